@@ -0,0 +1,235 @@
+#![warn(rust_2018_idioms)]
+
+//! Decodes the input with a selectable matcher and reports the throughput. The input is read fully
+//! into memory before the timed part, so this measures only the decoding.
+
+use minipb::field_reader::FieldReader;
+use minipb::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields};
+use minipb::{DecodingError, FieldId, ReadField, Reader, Status};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counts the allocations made through the global allocator.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    /// Skip every top level field
+    SkipAll,
+    /// Read every top level field as value or slice
+    ReadAll,
+    /// Recurse into every length delimited field which looks like a message
+    DecodeRaw,
+}
+
+fn usage(myself: &str) -> ! {
+    eprintln!(
+        "USAGE: {} [--iterations N] <skip-all|read-all|decode_raw> [FILE]\n\n\
+        Input is read from FILE or stdin.",
+        myself
+    );
+    std::process::exit(1);
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let myself = args.next().expect("zeroeth argument must be present");
+
+    let mut iterations = 1u32;
+    let mut mode = None;
+    let mut file = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iterations" => {
+                iterations = match args.next().and_then(|s| s.parse().ok()) {
+                    Some(n) if n > 0 => n,
+                    _ => usage(&myself),
+                }
+            }
+            "skip-all" if mode.is_none() => mode = Some(Mode::SkipAll),
+            "read-all" if mode.is_none() => mode = Some(Mode::ReadAll),
+            "decode_raw" if mode.is_none() => mode = Some(Mode::DecodeRaw),
+            _ if mode.is_some() && file.is_none() => file = Some(arg),
+            _ => usage(&myself),
+        }
+    }
+
+    let mode = mode.unwrap_or_else(|| usage(&myself));
+
+    let mut input = Vec::new();
+    match file {
+        Some(path) => std::fs::File::open(path)?.read_to_end(&mut input)?,
+        None => std::io::stdin().lock().read_to_end(&mut input)?,
+    };
+
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes_before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+
+    let mut fields = 0u64;
+    let mut elapsed = Duration::default();
+
+    for _ in 0..iterations {
+        let started = Instant::now();
+        fields += match mode {
+            Mode::SkipAll => run_matcher(&input, SkipAll)?,
+            Mode::ReadAll => run_matcher(&input, ReadAll)?,
+            Mode::DecodeRaw => decode_raw(&input)?,
+        };
+        elapsed += started.elapsed();
+    }
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    let allocated = ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes_before;
+
+    let total_bytes = input.len() as u64 * iterations as u64;
+    let secs = elapsed.as_secs_f64();
+
+    println!("mode:        {:?}", mode);
+    println!("iterations:  {}", iterations);
+    println!("bytes:       {}", total_bytes);
+    println!("elapsed:     {:?}", elapsed);
+    println!("throughput:  {:.2} MB/s", total_bytes as f64 / secs / 1e6);
+    println!("fields:      {}", fields);
+    println!("fields/s:    {:.0}", fields as f64 / secs);
+    println!("allocations: {} ({} bytes)", allocations, allocated);
+
+    Ok(())
+}
+
+fn run_matcher<M: Matcher>(input: &[u8], matcher: M) -> Result<u64, DecodingError> {
+    let mut fields = MatcherFields::new(matcher);
+    let mut buf = input;
+    let mut count = 0;
+    loop {
+        match fields.next(&mut buf)? {
+            Ok(_) => count += 1,
+            Err(Status::IdleAtEndOfBuffer) => return Ok(count),
+            Err(Status::NeedMoreBytes(_)) | Err(Status::CanSkip(_)) => {
+                eprintln!("input ended in the middle of a field");
+                return Ok(count);
+            }
+        }
+    }
+}
+
+struct SkipAll;
+
+impl Matcher for SkipAll {
+    type Tag = ();
+
+    fn decide_before(
+        &mut self,
+        _offset: usize,
+        _read: &ReadField<'_>,
+        _path: &[FieldId],
+    ) -> Result<Action<()>, DecodingError> {
+        Ok(Action::Skip(()))
+    }
+
+    fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+        None
+    }
+}
+
+struct ReadAll;
+
+impl Matcher for ReadAll {
+    type Tag = ();
+
+    fn decide_before(
+        &mut self,
+        _offset: usize,
+        read: &ReadField<'_>,
+        _path: &[FieldId],
+    ) -> Result<Action<()>, DecodingError> {
+        Ok(if read.is_length_delimited() {
+            Action::Continue(Cont::ReadSlice(()))
+        } else {
+            Action::Continue(Cont::ReadValue(()))
+        })
+    }
+
+    fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+        None
+    }
+}
+
+/// Walks the whole input recursing into anything which can be read as a message, returning the
+/// number of fields seen.
+fn decode_raw(buf: &[u8]) -> Result<u64, DecodingError> {
+    let mut reader = FieldReader::default();
+    let mut at = 0;
+    let mut count = 0;
+
+    while at < buf.len() {
+        let read = match reader.next(&buf[at..])? {
+            Ok(read) => read,
+            Err(_) => break,
+        };
+
+        count += 1;
+
+        let start = at + read.consumed();
+        let end = match start.checked_add(read.field_len()) {
+            Some(end) if end <= buf.len() => end,
+            _ => break,
+        };
+
+        if read.is_length_delimited() {
+            if let Some(nested) = try_decode_raw(&buf[start..end]) {
+                count += nested;
+            }
+        }
+
+        at = end;
+    }
+
+    Ok(count)
+}
+
+/// Returns the number of fields if all of the slice can be read as a message.
+fn try_decode_raw(buf: &[u8]) -> Option<u64> {
+    let mut reader = FieldReader::default();
+    let mut at = 0;
+
+    while at < buf.len() {
+        match reader.next(&buf[at..]) {
+            Ok(Ok(read))
+                if read.field_id() != 0
+                    && at
+                        .checked_add(read.bytes_to_skip())
+                        .is_some_and(|end| end <= buf.len()) =>
+            {
+                at += read.bytes_to_skip();
+            }
+            _ => return None,
+        }
+    }
+
+    if at == 0 {
+        None
+    } else {
+        decode_raw(buf).ok()
+    }
+}
@@ -0,0 +1,103 @@
+#![warn(rust_2018_idioms)]
+
+//! Testee for the protobuf conformance test runner, for example:
+//!
+//! ```text
+//! conformance_test_runner --enforce_recommended target/debug/examples/conformance
+//! ```
+//!
+//! Without a schema the payloads are only parsed as raw fields and serialized again, so the
+//! protobuf to protobuf tests are run and everything involving JSON or the text format is
+//! reported as skipped. The requests and responses are read from stdin and written to stdout, each
+//! prefixed with its length as a 4-byte little-endian integer.
+
+use minipb::message::{FieldData, Fields};
+use minipb::sink::{Scalar, Sink, VecSink};
+use std::io::{Read, Write};
+
+// ConformanceRequest
+const REQUEST_PROTOBUF_PAYLOAD: u32 = 1;
+const REQUEST_OUTPUT_FORMAT: u32 = 3;
+const WIRE_FORMAT_PROTOBUF: u64 = 1;
+
+// ConformanceResponse
+const RESPONSE_PARSE_ERROR: u32 = 1;
+const RESPONSE_PROTOBUF_PAYLOAD: u32 = 3;
+const RESPONSE_SKIPPED: u32 = 5;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut request = Vec::new();
+
+    loop {
+        let mut len = [0u8; 4];
+        match stdin.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        request.clear();
+        stdin
+            .by_ref()
+            .take(u32::from_le_bytes(len).into())
+            .read_to_end(&mut request)?;
+
+        let response = respond(&request)?;
+
+        stdout.write_all(&(response.len() as u32).to_le_bytes())?;
+        stdout.write_all(&response)?;
+        stdout.flush()?;
+    }
+}
+
+/// Returns the encoded ConformanceResponse for the ConformanceRequest.
+fn respond(request: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut payload = None;
+    let mut output_format = 0;
+
+    for field in Fields::new(request) {
+        let field = field?;
+        match (field.id, field.value) {
+            (REQUEST_PROTOBUF_PAYLOAD, FieldData::Bytes(bytes)) => payload = Some(bytes),
+            (REQUEST_OUTPUT_FORMAT, FieldData::Varint(x)) => output_format = x,
+            _ => {}
+        }
+    }
+
+    let mut response = VecSink::default();
+
+    match (payload, output_format) {
+        (Some(payload), WIRE_FORMAT_PROTOBUF) => match reserialize(payload) {
+            Ok(bytes) => response.write_slice(RESPONSE_PROTOBUF_PAYLOAD, &bytes)?,
+            Err(e) => response.write_slice(RESPONSE_PARSE_ERROR, e.to_string().as_bytes())?,
+        },
+        _ => response.write_slice(
+            RESPONSE_SKIPPED,
+            b"only protobuf input and output are supported",
+        )?,
+    }
+
+    Ok(response.into_inner())
+}
+
+/// Parses the fields of the message and encodes them again.
+fn reserialize(payload: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut out = VecSink::default();
+
+    for field in Fields::new(payload) {
+        let field = field?;
+        match field.value {
+            FieldData::Varint(x) => out.write_scalar(field.id, Scalar::Varint(x))?,
+            FieldData::Fixed64(x) => out.write_scalar(field.id, Scalar::Fixed64(x))?,
+            FieldData::Fixed32(x) => out.write_scalar(field.id, Scalar::Fixed32(x))?,
+            FieldData::Bytes(bytes) => out.write_slice(field.id, bytes)?,
+        }
+    }
+
+    Ok(out.into_inner())
+}
@@ -0,0 +1,241 @@
+#![warn(rust_2018_idioms)]
+
+//! Reads a protobuf message from stdin and prints out the fields as a tree, guessing which of the
+//! length delimited fields are nested messages, similar to `protoc --decode_raw`. With
+//! `--protoscope` the output is in the protoscope format instead, which the `encode` example
+//! turns back into the same bytes.
+
+use minipb::field_reader::FieldReader;
+use minipb::FieldValue;
+use std::fmt;
+use std::io::{IsTerminal, Read, Write};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let myself = args.next().expect("zeroeth argument must be present");
+
+    let mut color = ColorChoice::Auto;
+    let mut protoscope = false;
+
+    for arg in args {
+        color = match arg.as_str() {
+            "--protoscope" => {
+                protoscope = true;
+                continue;
+            }
+            "--color" | "--color=always" => ColorChoice::Always,
+            "--color=auto" => ColorChoice::Auto,
+            "--color=never" => ColorChoice::Never,
+            _ => {
+                eprintln!(
+                    "USAGE: {} [--color[=WHEN] | --protoscope]\n\n\
+                    Where: \n\
+                    WHEN is one of always, auto (default) or never. NO_COLOR is respected with auto.\n\n\
+                    Input is read from stdin.",
+                    myself
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let mut input = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut input)?;
+
+    if protoscope {
+        let mut out = String::new();
+        minipb::protoscope::write(&input, &mut out)?;
+        print!("{}", out);
+        return Ok(());
+    }
+
+    let stdout = std::io::stdout();
+    let palette = if color.enabled(stdout.is_terminal()) {
+        Palette::ANSI
+    } else {
+        Palette::PLAIN
+    };
+
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    print_message(&mut out, &palette, &input, 0, 0)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum ColorChoice {
+    Always,
+    Auto,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(&self, is_tty: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                is_tty && !no_color
+            }
+        }
+    }
+}
+
+/// Escape sequences to use for the different parts of the output.
+struct Palette {
+    offset: &'static str,
+    field_id: &'static str,
+    kind: &'static str,
+    string: &'static str,
+    reset: &'static str,
+}
+
+impl Palette {
+    const ANSI: Palette = Palette {
+        offset: "\x1b[2m",
+        field_id: "\x1b[1;36m",
+        kind: "\x1b[33m",
+        string: "\x1b[32m",
+        reset: "\x1b[0m",
+    };
+
+    const PLAIN: Palette = Palette {
+        offset: "",
+        field_id: "",
+        kind: "",
+        string: "",
+        reset: "",
+    };
+}
+
+struct Painted<'a, T>(&'a str, T, &'a str);
+
+impl<T: fmt::Display> fmt::Display for Painted<'_, T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}{}{}", self.0, self.1, self.2)
+    }
+}
+
+fn print_message(
+    out: &mut impl Write,
+    palette: &Palette,
+    buf: &[u8],
+    start_offset: usize,
+    depth: usize,
+) -> std::io::Result<()> {
+    let p = palette;
+    let mut reader = FieldReader::default();
+    let mut at = 0;
+
+    while at < buf.len() {
+        let read = match reader.next(&buf[at..]) {
+            Ok(Ok(read)) => read,
+            Ok(Err(_)) => {
+                writeln!(out, "{:indent$}(truncated)", "", indent = depth * 2)?;
+                return Ok(());
+            }
+            Err(e) => {
+                writeln!(out, "{:indent$}(error: {})", "", e, indent = depth * 2)?;
+                return Ok(());
+            }
+        };
+
+        let offset = start_offset + at;
+        let id = read.field_id();
+
+        write!(
+            out,
+            "{} {:indent$}{}: ",
+            Painted(p.offset, format_args!("@{:08}", offset), p.reset),
+            "",
+            Painted(p.field_id, id, p.reset),
+            indent = depth * 2
+        )?;
+
+        let value_at = at + read.consumed();
+
+        match read.value() {
+            FieldValue::Varint(x) => {
+                writeln!(out, "{} {}", Painted(p.kind, "varint", p.reset), x)?;
+            }
+            FieldValue::Fixed64(x) => {
+                writeln!(out, "{} {}", Painted(p.kind, "fixed64", p.reset), x)?;
+            }
+            FieldValue::Fixed32(x) => {
+                writeln!(out, "{} {}", Painted(p.kind, "fixed32", p.reset), x)?;
+            }
+            FieldValue::DataLength(_) => {
+                let end = match value_at.checked_add(read.field_len()) {
+                    Some(end) if end <= buf.len() => end,
+                    _ => {
+                        writeln!(out, "{}", Painted(p.kind, "(truncated)", p.reset))?;
+                        return Ok(());
+                    }
+                };
+
+                let slice = &buf[value_at..end];
+
+                if looks_like_message(slice) {
+                    writeln!(
+                        out,
+                        "{} ({} bytes) {{",
+                        Painted(p.kind, "message", p.reset),
+                        slice.len()
+                    )?;
+                    print_message(out, palette, slice, start_offset + value_at, depth + 1)?;
+                    writeln!(out, "{:indent$}}}", "", indent = 11 + depth * 2)?;
+                } else if let Some(s) = std::str::from_utf8(slice).ok().filter(|s| is_printable(s))
+                {
+                    writeln!(
+                        out,
+                        "{} {}",
+                        Painted(p.kind, "string", p.reset),
+                        Painted(p.string, format_args!("{:?}", s), p.reset)
+                    )?;
+                } else {
+                    write!(out, "{} ", Painted(p.kind, "bytes", p.reset))?;
+                    for b in slice {
+                        write!(out, "{:02x}", b)?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+            #[cfg(feature = "groups")]
+            FieldValue::StartGroup => writeln!(out, "{}", Painted(p.kind, "start group", p.reset))?,
+            #[cfg(feature = "groups")]
+            FieldValue::EndGroup => writeln!(out, "{}", Painted(p.kind, "end group", p.reset))?,
+        }
+
+        at = value_at + read.field_len();
+    }
+
+    Ok(())
+}
+
+/// Returns true if the whole slice can be read as fields, without anything left over.
+fn looks_like_message(mut buf: &[u8]) -> bool {
+    if buf.is_empty() {
+        return false;
+    }
+
+    let mut reader = FieldReader::default();
+
+    while !buf.is_empty() {
+        match reader.next(buf) {
+            Ok(Ok(read)) => {
+                if read.field_id() == 0 || read.bytes_to_skip() > buf.len() {
+                    return false;
+                }
+                buf = &buf[read.bytes_to_skip()..];
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+fn is_printable(s: &str) -> bool {
+    s.chars().all(|c| !c.is_control() || c == '\n' || c == '\t')
+}
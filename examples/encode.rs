@@ -0,0 +1,31 @@
+#![warn(rust_2018_idioms)]
+
+//! Reads the protoscope text format from stdin and writes the encoded bytes to stdout, for
+//! example to encode the hand-edited output of `decode_raw --protoscope`.
+
+use std::io::{Read, Write};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let myself = args.next().expect("zeroeth argument must be present");
+
+    if args.next().is_some() {
+        eprintln!(
+            "USAGE: {}\n\n\
+            Input is read from stdin, see minipb::protoscope for the supported syntax.",
+            myself
+        );
+        std::process::exit(1);
+    }
+
+    let mut text = String::new();
+    std::io::stdin().lock().read_to_string(&mut text)?;
+
+    let bytes = minipb::protoscope::parse(&text)?;
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    out.write_all(&bytes)?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+#![warn(rust_2018_idioms)]
+
+//! Guesses the schema of the message read from stdin, or of every message of a varint delimited
+//! stream with `--delimited`, printing the guesses with their confidences or with `--proto` a
+//! `.proto` skeleton for hand-editing. With `--compare BASELINE` the differences to the schema of
+//! the BASELINE file are printed instead.
+
+use minipb::framing::Framing;
+use minipb::infer::drift::{compare, Drift};
+use minipb::infer::{InferredMessage, SchemaInferrer};
+use minipb::io_ext::frames::FrameReader;
+use std::io::{Read, Write};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let myself = args.next().expect("zeroeth argument must be present");
+
+    let mut delimited = false;
+    let mut proto = false;
+    let mut baseline = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--delimited" => delimited = true,
+            "--proto" => proto = true,
+            "--compare" if baseline.is_none() => baseline = args.next(),
+            _ => {
+                eprintln!(
+                    "USAGE: {} [--delimited] [--proto | --compare BASELINE]\n\n\
+                    Input is read from stdin.",
+                    myself
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let schema = infer(std::io::stdin().lock(), delimited)?;
+
+    if let Some(baseline) = baseline {
+        let baseline = infer(std::fs::File::open(baseline)?, delimited)?;
+        for drift in compare(&baseline, &schema) {
+            print_drift(&drift);
+        }
+        return Ok(());
+    }
+
+    if proto {
+        let mut out = String::new();
+        schema.write_proto(&mut out, "Root")?;
+        print!("{}", out);
+        return Ok(());
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    print_message(&mut out, &schema, 0)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn infer<R: Read>(
+    mut read: R,
+    delimited: bool,
+) -> Result<InferredMessage, Box<dyn std::error::Error>> {
+    let mut inferrer = SchemaInferrer::default();
+    let mut skipped = 0;
+
+    if delimited {
+        let mut frames = FrameReader::new(std::io::BufReader::new(read), Framing::Varint);
+        while let Some((_, message)) = frames.next_frame()? {
+            if inferrer.add_message(message).is_err() {
+                skipped += 1;
+            }
+        }
+    } else {
+        let mut input = Vec::new();
+        read.read_to_end(&mut input)?;
+        inferrer.add_message(&input)?;
+    }
+
+    if skipped > 0 {
+        eprintln!("{} inputs could not be parsed as messages", skipped);
+    }
+
+    Ok(inferrer.infer())
+}
+
+fn print_drift(drift: &Drift) {
+    let path = drift
+        .path()
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    match drift {
+        Drift::Missing { kind, .. } => println!("/{}: missing, was {:?}", path, kind),
+        Drift::Added { kind, .. } => println!("/{}: added as {:?}", path, kind),
+        Drift::TypeChanged {
+            baseline, current, ..
+        } => println!(
+            "/{}: {:?} changed to {:?}{}",
+            path,
+            baseline,
+            current,
+            if drift.is_incompatible() {
+                " (incompatible)"
+            } else {
+                ""
+            }
+        ),
+        Drift::RepeatedChanged { current, .. } => println!(
+            "/{}: {}",
+            path,
+            if *current {
+                "became repeated"
+            } else {
+                "is no longer repeated"
+            }
+        ),
+    }
+}
+
+fn print_message<W: Write>(
+    out: &mut W,
+    message: &InferredMessage,
+    depth: usize,
+) -> std::io::Result<()> {
+    for field in &message.fields {
+        write!(
+            out,
+            "{:indent$}{}{}: ",
+            "",
+            field.id,
+            if field.repeated { "[]" } else { "" },
+            indent = depth * 2
+        )?;
+
+        for (i, candidate) in field.candidates.iter().enumerate() {
+            if i > 0 {
+                write!(out, ", ")?;
+            }
+            write!(out, "{:?} ({:.2})", candidate.kind, candidate.confidence)?;
+        }
+
+        writeln!(
+            out,
+            " in {}/{} messages",
+            field.present_in, message.instances
+        )?;
+
+        if let Some(nested) = field.message.as_ref() {
+            print_message(out, nested, depth + 1)?;
+        }
+    }
+    Ok(())
+}
@@ -6,7 +6,7 @@ use std::ops::Range;
 
 use minipb::gather_fields::{GatheredFields, Gatherer, Slicer};
 use minipb::io_ext::read::ReadWrapper;
-use minipb::matcher_fields::{Action, Cont, Matched, Matcher, Value};
+use minipb::matcher_fields::{Action, Cont, EndedMessage, Matched, Matcher, NestingTracker, Value};
 use minipb::{DecodingError, FieldId, ReadField};
 
 struct HexOnly<'a>(&'a [u8]);
@@ -22,29 +22,33 @@ impl<'a> fmt::Debug for HexOnly<'a> {
 
 fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     let stdin = std::io::stdin();
-    let gatherer = GatheredFields::new(MerkleDag::Top, PBLinkGatherer::default());
+    let gatherer = GatheredFields::new(MerkleDag::default(), PBLinkGatherer::default());
     let mut reader = ReadWrapper::new(stdin.lock(), gatherer);
 
-    while let Some(link) = reader.read_next()? {
-        println!("{:?}", link);
-    }
+    while reader.with_next(|link| println!("{:?}", link))?.is_some() {}
 
     Ok(())
 }
 
-/// States of the matcher, which recognizes the different fields in the document and tags them as
+/// The matcher, which recognizes the different fields in the document and tags them as
 /// `DagPbElement`.
+#[derive(Debug, Default)]
+struct MerkleDag {
+    nesting: NestingTracker<Nested>,
+}
+
+/// The nested messages of the document.
 #[derive(Debug)]
-enum MerkleDag {
-    Top,
-    Link { until: usize },
-    UserBytes { until: usize },
+enum Nested {
+    Link,
+    UserBytes,
 }
 
 /// The matched elements (all fields in a combined {dag-pb,unixfs}.proto, so not really "just" the
 /// DagPbElements but CombinedDagPbAndUnixFsElement, but this is shorter. After the interesting
 /// fields have been matched, a `Gatherer` can combine an `PBLink` out of them.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
 enum DagPbElement {
     StartPbLink,
     EndPbLink,
@@ -72,76 +76,51 @@ impl Matcher for MerkleDag {
         &mut self,
         offset: usize,
         read: &ReadField<'_>,
+        _path: &[FieldId],
     ) -> Result<Action<Self::Tag>, DecodingError> {
-        use MerkleDag::*;
-        //println!("decide({:?}, {}, {:?})", self, offset, read);
-        match self {
-            Top if read.field_id() == 1 => {
-                *self = UserBytes {
-                    until: offset + read.bytes_to_skip(),
-                };
-                return Ok(Action::Continue(Cont::Message(Some(
-                    DagPbElement::StartUserBytes,
-                ))));
-            }
-            Top if read.field_id() == 2 => {
-                *self = Link {
-                    until: offset + read.bytes_to_skip(),
-                };
-                return Ok(Action::Continue(Cont::Message(Some(
-                    DagPbElement::StartPbLink,
-                ))));
-            }
-            Top => {}
-            Link { until } => {
-                if offset >= *until {
-                    return Err(DecodingError::FailedMatcherNesting(offset, *until));
-                }
+        self.nesting.check(offset)?;
 
-                return Ok(match read.field_id() {
-                    1 => Action::Continue(Cont::ReadSlice(DagPbElement::PbLinkHash)),
-                    2 => Action::Continue(Cont::ReadSlice(DagPbElement::PbLinkName)),
-                    3 => Action::Continue(Cont::ReadValue(DagPbElement::PbLinkTotalSize)),
-                    x => Action::Skip(DagPbElement::PbLinkExtraField(x)),
-                });
+        Ok(match (self.nesting.current(), read.field_id()) {
+            (None, 1) => {
+                self.nesting.enter(offset, read, Nested::UserBytes)?;
+                Action::Continue(Cont::Message(Some(DagPbElement::StartUserBytes)))
             }
-            UserBytes { until } => {
-                if offset >= *until {
-                    return Err(DecodingError::FailedMatcherNesting(offset, *until));
-                }
-
-                return Ok(match read.field_id() {
-                    1 => Action::Continue(Cont::ReadValue(DagPbElement::UnixFsType)),
-                    2 => Action::Continue(Cont::ReadSlice(DagPbElement::UnixFsData)),
-                    3 => Action::Continue(Cont::ReadValue(DagPbElement::UnixFsFileSize)),
-                    4 => Action::Continue(Cont::ReadValue(DagPbElement::UnixFsBlockSize)),
-                    x => Action::Skip(DagPbElement::UnixFsField(x)),
-                });
+            (None, 2) => {
+                self.nesting.enter(offset, read, Nested::Link)?;
+                Action::Continue(Cont::Message(Some(DagPbElement::StartPbLink)))
             }
-        }
-
-        Ok(Action::Skip(DagPbElement::TopExtraField(read.field_id())))
+            (None, x) => Action::Skip(DagPbElement::TopExtraField(x)),
+            (Some(Nested::Link), x) => match x {
+                1 => Action::Continue(Cont::ReadSlice(DagPbElement::PbLinkHash)),
+                2 => Action::Continue(Cont::ReadSlice(DagPbElement::PbLinkName)),
+                3 => Action::Continue(Cont::ReadValue(DagPbElement::PbLinkTotalSize)),
+                x => Action::Skip(DagPbElement::PbLinkExtraField(x)),
+            },
+            (Some(Nested::UserBytes), x) => match x {
+                1 => Action::Continue(Cont::ReadValue(DagPbElement::UnixFsType)),
+                2 => Action::Continue(Cont::ReadSlice(DagPbElement::UnixFsData)),
+                3 => Action::Continue(Cont::ReadValue(DagPbElement::UnixFsFileSize)),
+                4 => Action::Continue(Cont::ReadValue(DagPbElement::UnixFsBlockSize)),
+                x => Action::Skip(DagPbElement::UnixFsField(x)),
+            },
+        })
     }
 
-    fn decide_after(&mut self, offset: usize) -> (bool, Option<Self::Tag>) {
-        use MerkleDag::*;
-        match self {
-            Link { until } if offset == *until => {
-                *self = Top;
-                (false, Some(DagPbElement::EndPbLink))
-            }
-            UserBytes { until } if offset == *until => {
-                *self = Top;
-                (false, Some(DagPbElement::EndUserBytes))
-            }
-            _ => (false, None),
-        }
+    fn decide_after(
+        &mut self,
+        offset: usize,
+        _ended: Option<EndedMessage<Self::Tag>>,
+    ) -> Option<Self::Tag> {
+        self.nesting.exit(offset).map(|nested| match nested {
+            Nested::Link => DagPbElement::EndPbLink,
+            Nested::UserBytes => DagPbElement::EndUserBytes,
+        })
     }
 }
 
 struct PBLink<'a> {
     /// File offset
-    offset: Range<u64>,
+    offset: Range<minipb::Offset>,
     hash: Cow<'a, [u8]>,
     name: Cow<'a, str>,
     total_size: u64,
@@ -151,7 +130,7 @@ impl fmt::Debug for PBLink<'_> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("PBLink")
             .field("offset", &format_args!("{:?}", self.offset))
-            .field("hash", &format_args!("{:?}", HexOnly(&*self.hash)))
+            .field("hash", &format_args!("{:?}", HexOnly(&self.hash)))
             .field("name", &self.name)
             .field("total_size", &self.total_size)
             .finish()
@@ -160,9 +139,9 @@ impl fmt::Debug for PBLink<'_> {
 
 #[derive(Default)]
 struct PBLinkGatherer {
-    start: Option<u64>,
-    hash: Option<Range<u64>>,
-    name: Option<Range<u64>>,
+    start: Option<minipb::Offset>,
+    hash: Option<Range<minipb::Offset>>,
+    name: Option<Range<minipb::Offset>>,
     total_size: Option<u64>,
 }
 
@@ -193,8 +172,7 @@ impl<'a> Gatherer<'a> for PBLinkGatherer {
 
                 if let (Some(start), Some(hr), Some(nr), Some(total_size)) = values {
                     let hash = Cow::Borrowed(slicer.as_slice(&hr));
-                    let name = slicer.as_slice(&nr);
-                    let name = Cow::Borrowed(std::str::from_utf8(name).unwrap());
+                    let name = Cow::Borrowed(slicer.as_str(&nr)?);
 
                     return Ok(Some(PBLink {
                         offset: start..offset,
@@ -250,7 +228,7 @@ impl<'a> Gatherer<'a> for PBLinkGatherer {
         Ok(None)
     }
 
-    fn min_offset(&self) -> Option<u64> {
+    fn min_offset(&self) -> Option<minipb::Offset> {
         let h = self.hash.as_ref().map(|r| r.start);
         let n = self.name.as_ref().map(|r| r.start);
         match (h, n) {
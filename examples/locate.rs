@@ -0,0 +1,114 @@
+#![warn(rust_2018_idioms)]
+
+//! Prints the field path spanning a byte offset of the message read from stdin, or of a varint
+//! delimited stream with `--delimited`, in which case the offset is from the start of the stream.
+
+use minipb::framing::Framing;
+use minipb::locate::{locate, Span};
+use minipb::message::FieldData;
+use std::io::Read;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let myself = args.next().expect("zeroeth argument must be present");
+
+    let mut delimited = false;
+    let mut offset = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--delimited" => delimited = true,
+            _ if offset.is_none() => match arg.replace('_', "").parse::<u64>() {
+                Ok(x) => offset = Some(x),
+                Err(_) => usage(&myself),
+            },
+            _ => usage(&myself),
+        }
+    }
+
+    let offset = offset.unwrap_or_else(|| usage(&myself));
+    let mut input = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut input)?;
+
+    if !delimited {
+        print_spans(&locate(&input, offset as usize)?, offset as usize, 0);
+        return Ok(());
+    }
+
+    // the whole stream is read to memory to have the exact offsets of the length prefixes
+    let mut start = 0;
+    let mut index = 0;
+
+    while start < input.len() {
+        let (consumed, header) = match Framing::Varint.read_prefix(&input[start..])? {
+            Ok(x) => x,
+            Err(_) => break,
+        };
+
+        let payload_start = start + consumed;
+        let end = payload_start.saturating_add(header.len as usize);
+
+        if (offset as usize) < end {
+            if (offset as usize) < payload_start {
+                println!("frame #{} at {}: in the length prefix", index, start);
+            } else {
+                println!("frame #{} at {}:", index, start);
+                let message = &input[payload_start..end.min(input.len())];
+                let relative = offset as usize - payload_start;
+                print_spans(&locate(message, relative)?, relative, payload_start);
+            }
+            return Ok(());
+        }
+
+        start = end;
+        index += 1;
+    }
+
+    eprintln!("{}: offset {} is past the end of the input", myself, offset);
+    std::process::exit(1);
+}
+
+fn usage(myself: &str) -> ! {
+    eprintln!(
+        "USAGE: {} [--delimited] <OFFSET>\n\n\
+        Input is read from stdin.",
+        myself
+    );
+    std::process::exit(1);
+}
+
+fn print_spans(spans: &[Span<'_>], offset: usize, base: usize) {
+    if spans.is_empty() {
+        println!("offset {} is past the end of the message", offset);
+        return;
+    }
+
+    let path = spans
+        .iter()
+        .map(|s| s.id.to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let last = spans.last().unwrap();
+
+    println!(
+        "/{}: field at {}..{}{}",
+        path,
+        base + last.start,
+        base + last.end,
+        if last.in_header(offset) {
+            ", in the tag or length"
+        } else {
+            ""
+        }
+    );
+
+    match last.value {
+        FieldData::Varint(x) | FieldData::Fixed64(x) => println!("value: {}", x),
+        FieldData::Fixed32(x) => println!("value: {}", x),
+        FieldData::Bytes(bytes) => match std::str::from_utf8(bytes) {
+            Ok(s) => println!("value: {:?}", s),
+            Err(_) => println!("value: {} bytes", bytes.len()),
+        },
+    }
+}
@@ -0,0 +1,121 @@
+#![warn(rust_2018_idioms)]
+
+//! Runs a jq-like query over the message read from stdin, or over every message of a varint
+//! delimited stream with `--delimited`. See `minipb::query` for the syntax.
+//!
+//! With `--schema FILE --type NAME` the fields can be named, where FILE is a descriptor set written
+//! by `protoc --include_imports --descriptor_set_out=FILE` and NAME the message type of the input.
+
+use minipb::framing::Framing;
+use minipb::io_ext::frames::FrameReader;
+use minipb::query::{Query, QueryValue};
+use minipb::schema::Schema;
+use std::io::{Read, Write};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let myself = args.next().expect("zeroeth argument must be present");
+
+    let mut delimited = false;
+    let mut query = None;
+    let mut schema = None;
+    let mut message_type = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--delimited" => delimited = true,
+            "--schema" if schema.is_none() => schema = args.next(),
+            "--type" if message_type.is_none() => message_type = args.next(),
+            _ if query.is_none() => query = Some(arg),
+            _ => usage(&myself),
+        }
+    }
+
+    let query = query.unwrap_or_else(|| usage(&myself));
+
+    let parsed = match (schema, message_type) {
+        (Some(schema), Some(message_type)) => {
+            let schema = Schema::decode(&std::fs::read(schema)?)?;
+            let message = match schema.message(&message_type) {
+                Some(message) => message,
+                None => {
+                    eprintln!("{}: message type not found: {}", myself, message_type);
+                    std::process::exit(1);
+                }
+            };
+            Query::parse_with_schema(&query, &schema, message)
+        }
+        (None, None) => Query::parse(&query),
+        _ => usage(&myself),
+    };
+
+    let query = match parsed {
+        Ok(q) => q,
+        Err(e) => {
+            eprintln!("{}: failed to parse {:?}: {}", myself, query, e);
+            std::process::exit(1);
+        }
+    };
+
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    let mut io_error = None;
+
+    let mut print = |value: QueryValue<'_>| {
+        if io_error.is_none() {
+            if let Err(e) = print_value(&mut out, value) {
+                io_error = Some(e);
+            }
+        }
+    };
+
+    if delimited {
+        let mut frames = FrameReader::new(std::io::BufReader::new(stdin), Framing::Varint);
+        while let Some((_, message)) = frames.next_frame()? {
+            query.run(message, &mut print)?;
+        }
+    } else {
+        let mut input = Vec::new();
+        stdin.read_to_end(&mut input)?;
+        query.run(&input, &mut print)?;
+    }
+
+    if let Some(e) = io_error {
+        return Err(e.into());
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn usage(myself: &str) -> ! {
+    eprintln!(
+        "USAGE: {} [--delimited] [--schema FILE --type NAME] <QUERY>\n\n\
+        Where: \n\
+        QUERY is for example '.2[] | select(.3 > 100) | .1'\n\
+        FILE is a descriptor set from protoc, NAME is the message type of the input\n\n\
+        Input is read from stdin.",
+        myself
+    );
+    std::process::exit(1);
+}
+
+/// Prints integers as is, length delimited fields as strings when they are valid UTF-8 and as hex
+/// otherwise.
+fn print_value<W: Write>(out: &mut W, value: QueryValue<'_>) -> std::io::Result<()> {
+    match value {
+        QueryValue::Varint(x) | QueryValue::Fixed64(x) => writeln!(out, "{}", x),
+        QueryValue::Fixed32(x) => writeln!(out, "{}", x),
+        QueryValue::Bytes(slice) => match std::str::from_utf8(slice) {
+            Ok(s) => writeln!(out, "{:?}", s),
+            Err(_) => {
+                for b in slice {
+                    write!(out, "{:02x}", b)?;
+                }
+                writeln!(out)
+            }
+        },
+    }
+}
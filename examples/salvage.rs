@@ -0,0 +1,59 @@
+#![warn(rust_2018_idioms)]
+
+//! Writes a cleanly truncated copy of a message read from stdin, or of a varint delimited stream
+//! with `--delimited`, a gRPC framed stream with `--grpc` or a 4-byte big-endian length prefixed
+//! stream with `--be32`, dropping the trailing bytes after the last complete field or frame. The
+//! amount of dropped bytes is reported to stderr.
+
+use minipb::framing::Framing;
+use minipb::salvage::{salvage_frames, salvage_message};
+use std::io::{Read, Write};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let myself = args.next().expect("zeroeth argument must be present");
+
+    let mut framing = None;
+
+    for arg in args {
+        framing = match arg.as_str() {
+            "--delimited" if framing.is_none() => Some(Framing::Varint),
+            "--grpc" if framing.is_none() => Some(Framing::Grpc),
+            "--be32" if framing.is_none() => Some(Framing::BigEndian32),
+            _ => {
+                eprintln!(
+                    "USAGE: {} [--delimited | --grpc | --be32]\n\n\
+                    Input is read from stdin and the truncated copy is written to stdout.",
+                    myself
+                );
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let mut input = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut input)?;
+
+    let salvage = match framing {
+        Some(framing) => salvage_frames(&input, framing),
+        None => salvage_message(&input),
+    };
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    out.write_all(&input[..salvage.kept])?;
+    out.flush()?;
+
+    eprintln!(
+        "kept {} bytes in {} {}, dropped {} trailing bytes",
+        salvage.kept,
+        salvage.items,
+        if framing.is_some() {
+            "frames"
+        } else {
+            "fields"
+        },
+        salvage.dropped
+    );
+    Ok(())
+}
@@ -0,0 +1,59 @@
+#![warn(rust_2018_idioms)]
+
+//! Writes the last N messages of a varint delimited file, or of a gRPC framed file with `--grpc` or
+//! a 4-byte big-endian length prefixed file with `--be32`, to stdout with the same framing. Only
+//! the length prefixes of the earlier messages are read, so this is quick even for large append
+//! only logs.
+
+use minipb::framing::Framing;
+use minipb::io_ext::index::tail;
+use std::io::Write;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args();
+    let myself = args.next().expect("zeroeth argument must be present");
+
+    let mut framing = Framing::Varint;
+    let mut n = None;
+    let mut file = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--grpc" => framing = Framing::Grpc,
+            "--be32" => framing = Framing::BigEndian32,
+            "-n" if n.is_none() => n = args.next().and_then(|n| n.parse::<usize>().ok()),
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(arg),
+            _ => usage(&myself),
+        }
+    }
+
+    let file = file.unwrap_or_else(|| usage(&myself));
+    let input = std::io::BufReader::new(std::fs::File::open(file)?);
+
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    let mut prefix = [0u8; 10];
+
+    for (entry, payload) in tail(input, framing, n.unwrap_or(10))? {
+        let len = framing
+            .encode_prefix(payload.len() as u64, &mut prefix)
+            .expect("the length was read with the same framing");
+        if entry.compressed {
+            // the compressed flag of the gRPC framing
+            prefix[0] = 1;
+        }
+        out.write_all(&prefix[..len])?;
+        out.write_all(&payload)?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn usage(myself: &str) -> ! {
+    eprintln!(
+        "USAGE: {} [--grpc | --be32] [-n N] FILE\n\nN defaults to 10.",
+        myself
+    );
+    std::process::exit(1);
+}
@@ -0,0 +1,296 @@
+//! `bytes::Bytes` support, enabled with the `bytes` feature.
+//!
+//! When the input is already held in a `Bytes`, the matched slices can be handed out as
+//! reference counted `Bytes` handles to the same allocation instead of borrowed slices, so they can
+//! be kept around after the read loop without copying.
+//!
+//! Input in any other `bytes::Buf`, for example a chain of frames, can be read with
+//! [`BytesMatcherFields::next_buf`]. The io wrappers read from `std::io::Read`, which a `Buf` can
+//! be turned into with `Buf::reader`.
+
+use crate::matcher_fields::{Matched, Matcher, MatcherFields, RawHeader, Value};
+use crate::{DecodingError, Introspect, Offset, Reader, Slicer, Stats, Status, WireType};
+use bytes::{Buf, Bytes, BytesMut};
+use std::ops::Range;
+
+/// [`MatcherFields`] but reading from `Bytes` and returning `BytesMatched`.
+pub struct BytesMatcherFields<M: Matcher> {
+    inner: MatcherFields<M>,
+    /// Bytes taken out of the `Buf` given to `next_buf` but not yet consumed
+    pending: Bytes,
+}
+
+/// An item tagged by a [`Matcher`] with `Value::Slice` turned into `Bytes`.
+#[derive(Debug, Clone)]
+pub struct BytesMatched<T> {
+    pub tag: T,
+    pub offset: Offset,
+    pub value: BytesValue,
+}
+
+/// Represents a matched value with the slice as `Bytes`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytesValue {
+    /// Value does not exist in the stream, but it represents a state change taken by the
+    /// [`Matcher`].
+    Marker,
+    /// Number read as a [`crate::WireType::Varint`]
+    Varint(u64),
+    /// Value read as a [`crate::WireType::Fixed64`]
+    Fixed64(u64),
+    /// Value read as a [`crate::WireType::Fixed32`]
+    Fixed32(u32),
+    /// A length delimited field sharing the allocation of the input.
+    Slice(Range<Offset>, Bytes),
+    /// A string validated as UTF-8, sharing the allocation of the input.
+    Str(Range<Offset>, Bytes),
+    /// Packed values of the wire type sharing the allocation of the input.
+    Packed(Range<Offset>, WireType, Bytes),
+    /// A captured field with the payload sharing the allocation of the input.
+    Raw(Range<Offset>, RawHeader, Bytes),
+    /// A chunk of a slice sharing the allocation of the input.
+    SliceChunk(Range<Offset>, Offset, Bytes),
+}
+
+impl From<BytesValue> for Value {
+    fn from(bv: BytesValue) -> Self {
+        match bv {
+            BytesValue::Marker => Self::Marker,
+            BytesValue::Varint(x) => Self::Varint(x),
+            BytesValue::Fixed64(x) => Self::Fixed64(x),
+            BytesValue::Fixed32(x) => Self::Fixed32(x),
+            BytesValue::Slice(range, _) => Self::Slice(range),
+            BytesValue::Str(range, _) => Self::Str(range),
+            BytesValue::Packed(range, wire_type, _) => Self::Packed(wire_type, range),
+            BytesValue::Raw(range, raw, _) => Self::Raw(raw, range),
+            BytesValue::SliceChunk(range, remaining, _) => Self::SliceChunk(range, remaining),
+        }
+    }
+}
+
+impl<M: Matcher> From<MatcherFields<M>> for BytesMatcherFields<M> {
+    fn from(inner: MatcherFields<M>) -> Self {
+        Self {
+            inner,
+            pending: Bytes::new(),
+        }
+    }
+}
+
+impl<M: Matcher> Introspect for BytesMatcherFields<M> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<M: Matcher> BytesMatcherFields<M> {
+    pub fn new(matcher: M) -> Self {
+        MatcherFields::new(matcher).into()
+    }
+
+    pub fn offset(&self) -> Offset {
+        self.inner.offset()
+    }
+
+    /// Returns the inner reader, dropping the bytes held for `next_buf`.
+    pub fn into_inner(self) -> MatcherFields<M> {
+        self.inner
+    }
+
+    /// Reads the next item from any `Buf`, which is advanced over the bytes taken from it. Unlike
+    /// with `next` the bytes not yet consumed are held by this reader, so the caller does not need
+    /// to keep them; once `Status::NeedMoreBytes` is returned the next call can be made with just
+    /// the following bytes.
+    ///
+    /// The chunks of the `Buf` are taken with `Buf::copy_to_bytes`, which does not copy for
+    /// `Bytes` and `BytesMut`. Only the items spanning two chunks are copied together.
+    #[allow(clippy::type_complexity)]
+    pub fn next_buf<B: Buf>(
+        &mut self,
+        buf: &mut B,
+    ) -> Result<Result<BytesMatched<M::Tag>, Status>, DecodingError> {
+        loop {
+            if !self.pending.is_empty() {
+                let mut pending = std::mem::take(&mut self.pending);
+                let ret = self.next(&mut pending);
+                self.pending = pending;
+
+                match ret? {
+                    Err(Status::NeedMoreBytes(_)) | Err(Status::CanSkip(_))
+                        if buf.has_remaining() => {}
+                    Err(Status::IdleAtEndOfBuffer) if buf.has_remaining() => {}
+                    ret => return Ok(ret),
+                }
+            } else if !buf.has_remaining() {
+                return self.next(&mut Bytes::new());
+            }
+
+            let chunk = buf.copy_to_bytes(buf.chunk().len());
+
+            self.pending = if self.pending.is_empty() {
+                chunk
+            } else {
+                let mut joined = BytesMut::with_capacity(self.pending.len() + chunk.len());
+                joined.extend_from_slice(&self.pending);
+                joined.extend_from_slice(&chunk);
+                joined.freeze()
+            };
+        }
+    }
+
+    /// Works like `Reader::next` by advancing the `buf` over the consumed bytes. As with the slice
+    /// based readers, the remaining bytes need to be kept and more bytes appended to them when
+    /// `Status::NeedMoreBytes` is returned.
+    #[allow(clippy::type_complexity)]
+    pub fn next(
+        &mut self,
+        buf: &mut Bytes,
+    ) -> Result<Result<BytesMatched<M::Tag>, Status>, DecodingError> {
+        let mut slice = &buf[..];
+        let ret = self.inner.next(&mut slice);
+        let consumed = buf.len() - slice.len();
+
+        let ret = match ret? {
+            Ok(Matched { tag, offset, value }) => Ok(BytesMatched {
+                tag,
+                offset,
+                value: match value {
+                    Value::Marker => BytesValue::Marker,
+                    Value::Varint(x) => BytesValue::Varint(x),
+                    Value::Fixed64(x) => BytesValue::Fixed64(x),
+                    Value::Fixed32(x) => BytesValue::Fixed32(x),
+                    Value::Slice(range) => {
+                        let slicer = Slicer::wrap(&buf[..consumed], self.inner.offset());
+                        let index = slicer.index_range(&range);
+                        BytesValue::Slice(range, buf.slice(index))
+                    }
+                    Value::Str(range) => {
+                        let slicer = Slicer::wrap(&buf[..consumed], self.inner.offset());
+                        let index = slicer.index_range(&range);
+                        BytesValue::Str(range, buf.slice(index))
+                    }
+                    Value::Packed(wire_type, range) => {
+                        let slicer = Slicer::wrap(&buf[..consumed], self.inner.offset());
+                        let index = slicer.index_range(&range);
+                        BytesValue::Packed(range, wire_type, buf.slice(index))
+                    }
+                    Value::Raw(raw, range) => {
+                        let slicer = Slicer::wrap(&buf[..consumed], self.inner.offset());
+                        let index = slicer.index_range(&range);
+                        BytesValue::Raw(range, raw, buf.slice(index))
+                    }
+                    Value::SliceChunk(range, remaining) => {
+                        let slicer = Slicer::wrap(&buf[..consumed], self.inner.offset());
+                        let index = slicer.index_range(&range);
+                        BytesValue::SliceChunk(range, remaining, buf.slice(index))
+                    }
+                },
+            }),
+            Err(e) => Err(e),
+        };
+
+        buf.advance(consumed);
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BytesMatcherFields, BytesValue};
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher};
+    use crate::{DecodingError, FieldId, FieldValue, ReadField, Status};
+    use bytes::Bytes;
+
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn slices_share_the_allocation() {
+        let mut input = Vec::new();
+        input.extend(FieldValue::Varint(1).output_with_field_id(1));
+        input.extend(FieldValue::DataLength(3).output_with_field_id(2));
+        input.extend_from_slice(b"abc");
+
+        let input = Bytes::from(input);
+        let mut buf = input.clone();
+
+        let mut fields = BytesMatcherFields::new(Slices);
+
+        let first = fields.next(&mut buf).unwrap().unwrap();
+        assert_eq!(first.value, BytesValue::Varint(1));
+
+        let second = fields.next(&mut buf).unwrap().unwrap();
+        match second.value {
+            BytesValue::Slice(range, bytes) => {
+                assert_eq!(range, 4..7);
+                assert_eq!(&bytes[..], b"abc");
+                assert_eq!(bytes.as_ptr(), input[4..].as_ptr());
+            }
+            x => unreachable!("{:?}", x),
+        }
+
+        assert!(buf.is_empty());
+        assert!(matches!(
+            fields.next(&mut buf).unwrap(),
+            Err(Status::IdleAtEndOfBuffer)
+        ));
+    }
+
+    #[test]
+    fn next_buf_reads_across_chunks() {
+        use bytes::Buf;
+
+        // 2: "abc", 1: 150, 2: "defg" split in the middle of the varint and the last slice
+        let first = Bytes::from_static(b"\x12\x03abc\x08\x96");
+        let second = Bytes::from_static(b"\x01\x12\x04de");
+        let third = Bytes::from_static(b"fg");
+
+        let mut fields = BytesMatcherFields::new(Slices);
+        let mut buf = first.clone().chain(second);
+
+        let mut values = Vec::new();
+        loop {
+            match fields.next_buf(&mut buf).unwrap() {
+                Ok(m) => values.push(m.value),
+                Err(Status::NeedMoreBytes(_)) => break,
+                Err(e) => unreachable!("{:?}", e),
+            }
+        }
+        assert!(!buf.has_remaining());
+
+        let mut buf = third;
+        values.push(fields.next_buf(&mut buf).unwrap().unwrap().value);
+        assert!(matches!(
+            fields.next_buf(&mut buf).unwrap(),
+            Err(Status::IdleAtEndOfBuffer)
+        ));
+
+        match &values[..] {
+            [BytesValue::Slice(_, abc), BytesValue::Varint(150), BytesValue::Slice(_, defg)] => {
+                assert_eq!(abc.as_ptr(), first[2..].as_ptr());
+                assert_eq!(&defg[..], b"defg");
+            }
+            x => unreachable!("{:?}", x),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Hashing messages in a canonical form, so that messages with the same content hash equal
+//! regardless of the field order or the varint encoding chosen by the producer.
+//!
+//! The canonical form orders the fields by their ids, keeping the order of the occurrences of the
+//! same field as that matters for repeated fields, and hashes the decoded values instead of the
+//! bytes, which makes overlong varints hash the same as minimal ones. The wire format does not tell
+//! which length delimited fields are messages, so without help they are hashed as bytes; use
+//! [`canonical_hash_with`] to have nested messages canonicalized as well.
+
+use crate::message::{Field, FieldData, Fields, MessageError};
+use crate::FieldId;
+use std::hash::Hasher;
+
+/// Hashes the top level fields of a complete message in the canonical form. Length delimited
+/// fields are hashed as bytes.
+pub fn canonical_hash<H: Hasher>(buf: &[u8], hasher: &mut H) -> Result<(), MessageError> {
+    canonical_hash_with(buf, hasher, |_| false)
+}
+
+/// Hashes a complete message in the canonical form, recursing into the length delimited fields
+/// for which `is_message` returns true when called with the path of field ids.
+pub fn canonical_hash_with<H, F>(
+    buf: &[u8],
+    hasher: &mut H,
+    mut is_message: F,
+) -> Result<(), MessageError>
+where
+    H: Hasher,
+    F: FnMut(&[FieldId]) -> bool,
+{
+    let mut path = Vec::new();
+    hash_message(buf, hasher, &mut is_message, &mut path)
+}
+
+fn hash_message<H: Hasher>(
+    buf: &[u8],
+    hasher: &mut H,
+    is_message: &mut dyn FnMut(&[FieldId]) -> bool,
+    path: &mut Vec<FieldId>,
+) -> Result<(), MessageError> {
+    let mut sorted = true;
+    let mut previous = 0;
+    for field in Fields::new(buf) {
+        let field = field?;
+        sorted &= previous <= field.id;
+        previous = field.id;
+    }
+
+    let mut count = 0u64;
+
+    if sorted {
+        // the common case needs no buffering
+        for field in Fields::new(buf) {
+            hash_field(&field?, hasher, is_message, path)?;
+            count += 1;
+        }
+    } else {
+        let mut fields = Fields::new(buf).collect::<Result<Vec<_>, _>>()?;
+        // stable sort keeps the repeated fields in order
+        fields.sort_by_key(|f| f.id);
+        for field in &fields {
+            hash_field(field, hasher, is_message, path)?;
+        }
+        count = fields.len() as u64;
+    }
+
+    // makes the nested messages unambiguous
+    hasher.write_u64(count);
+    Ok(())
+}
+
+fn hash_field<H: Hasher>(
+    field: &Field<'_>,
+    hasher: &mut H,
+    is_message: &mut dyn FnMut(&[FieldId]) -> bool,
+    path: &mut Vec<FieldId>,
+) -> Result<(), MessageError> {
+    hasher.write_u32(field.id);
+
+    match field.value {
+        FieldData::Varint(x) => {
+            hasher.write_u8(0);
+            hasher.write_u64(x);
+        }
+        FieldData::Fixed64(x) => {
+            hasher.write_u8(1);
+            hasher.write_u64(x);
+        }
+        FieldData::Fixed32(x) => {
+            hasher.write_u8(5);
+            hasher.write_u32(x);
+        }
+        FieldData::Bytes(bytes) => {
+            path.push(field.id);
+            let nested = is_message(path);
+            let ret = if nested {
+                // distinct from the bytes so that a message cannot collide with its encoding
+                hasher.write_u8(3);
+                hash_message(bytes, hasher, is_message, path)
+            } else {
+                hasher.write_u8(2);
+                hasher.write_u64(bytes.len() as u64);
+                hasher.write(bytes);
+                Ok(())
+            };
+            path.pop();
+            ret?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_hash, canonical_hash_with};
+    use hex_literal::hex;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    fn hash(buf: &[u8], nested: bool) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        if nested {
+            canonical_hash_with(buf, &mut hasher, |path| path == [3]).unwrap();
+        } else {
+            canonical_hash(buf, &mut hasher).unwrap();
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn equal_regardless_of_order_and_encoding() {
+        // { 1: 1, 2: "a", 2: "b", 3: { 1: 1, 2: 2 } }
+        let a = hex!("0801 120161 120162 1a04 0801 1002");
+        // same with an overlong varint and the fields reordered except for the repeated ones
+        let b = hex!("1a04 1002 0801 120161 088100 120162");
+
+        assert_ne!(hash(&a, false), hash(&b, false));
+        assert_eq!(hash(&a, true), hash(&b, true));
+
+        // repeated ones in a different order
+        let c = hex!("0801 120162 120161 1a04 0801 1002");
+        assert_ne!(hash(&a, true), hash(&c, true));
+    }
+}
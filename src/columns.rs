@@ -0,0 +1,430 @@
+//! Extracting selected fields of whole messages as rows, for tabular exports.
+//!
+//! Columns are selected with paths similar to the `extractor` example: `/1/2/3::string` navigates
+//! fields 1 and 2 as nested messages and picks field 3, converting it to a string. When a field
+//! occurs multiple times the last one wins, as with non-repeated fields in protobuf.
+//!
+//! With a [`Schema`] the path elements can also be field names or proto3 JSON names, and the leaf
+//! type can be left out, see [`Column::parse_with_schema`].
+
+use crate::field_reader::FieldReader;
+use crate::pb::decode_zigzag64;
+use crate::schema::{FieldType, MessageDescriptor, Schema};
+use crate::{DecodingError, FieldId, FieldValue, WireType};
+use std::convert::TryFrom;
+use std::fmt;
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod csv;
+
+/// The type to convert the leaf field into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Bytes,
+    String,
+    /// `uint32` or `uint64`
+    UInt64,
+    /// `int32` or `int64`, which are two's complement varints
+    Int64,
+    /// `sint32` or `sint64`, which are zigzag encoded varints
+    SInt64,
+    Fixed32,
+    Fixed64,
+    SFixed32,
+    SFixed64,
+    Float,
+    Double,
+    Bool,
+}
+
+impl From<FieldType> for ColumnType {
+    fn from(kind: FieldType) -> Self {
+        use FieldType::*;
+        match kind {
+            Double => ColumnType::Double,
+            Float => ColumnType::Float,
+            Int64 | Int32 | Enum => ColumnType::Int64,
+            UInt64 | UInt32 => ColumnType::UInt64,
+            SInt64 | SInt32 => ColumnType::SInt64,
+            Fixed64 => ColumnType::Fixed64,
+            Fixed32 => ColumnType::Fixed32,
+            SFixed64 => ColumnType::SFixed64,
+            SFixed32 => ColumnType::SFixed32,
+            Bool => ColumnType::Bool,
+            String => ColumnType::String,
+            Bytes | Message | Group => ColumnType::Bytes,
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for ColumnType {
+    type Error = ColumnParseError<'a>;
+
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+        use ColumnType::*;
+        Ok(match s {
+            "bytes" | "slice" => Bytes,
+            "string" | "str" => String,
+            "uint64" | "uint32" | "u64" | "u32" => UInt64,
+            "int64" | "int32" | "i64" | "i32" => Int64,
+            "sint64" | "sint32" => SInt64,
+            "fixed32" => Fixed32,
+            "fixed64" => Fixed64,
+            "sfixed32" => SFixed32,
+            "sfixed64" => SFixed64,
+            "float" | "f32" => Float,
+            "double" | "f64" => Double,
+            "bool" => Bool,
+            _ => return Err(ColumnParseError::UnsupportedLeafType(s)),
+        })
+    }
+}
+
+/// A single selected column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub path: Vec<FieldId>,
+    pub kind: ColumnType,
+}
+
+#[derive(Debug)]
+pub enum ColumnParseError<'a> {
+    InvalidField(&'a str),
+    /// The field name was not found in the schema
+    UnknownField(&'a str),
+    MissingLeafType,
+    UnsupportedLeafType(&'a str),
+    Empty,
+}
+
+impl fmt::Display for ColumnParseError<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ColumnParseError::*;
+        match self {
+            InvalidField(field) => write!(fmt, "invalid field: {:?}", field),
+            UnknownField(field) => write!(fmt, "unknown field: {:?}", field),
+            MissingLeafType => write!(fmt, "leaf type is required, for example `1/2::string`"),
+            UnsupportedLeafType(leaf_type) => write!(fmt, "unsupported leaf type: {:?}", leaf_type),
+            Empty => write!(fmt, "no path specified"),
+        }
+    }
+}
+
+impl std::error::Error for ColumnParseError<'_> {}
+
+impl Column {
+    /// Parses `/a/b/c::type` or `a/b/c::type`. The name of the column will be the path without
+    /// the type.
+    pub fn parse(s: &str) -> Result<Column, ColumnParseError<'_>> {
+        Self::parse_inner(s, None)
+    }
+
+    /// Parses the path like [`Column::parse`] but the elements can also be field names or proto3
+    /// JSON names of the fields of `message`, as in `/lines/productId`. The leaf type can be left
+    /// out in which case it is taken from the schema.
+    pub fn parse_with_schema<'a>(
+        s: &'a str,
+        schema: &Schema,
+        message: &MessageDescriptor,
+    ) -> Result<Column, ColumnParseError<'a>> {
+        Self::parse_inner(s, Some((schema, message)))
+    }
+
+    fn parse_inner<'a>(
+        s: &'a str,
+        schema: Option<(&Schema, &MessageDescriptor)>,
+    ) -> Result<Column, ColumnParseError<'a>> {
+        let mut split = s.splitn(2, "::");
+        let path_part = split.next().expect("there is always the first element");
+        let kind = match split.next() {
+            Some("") | None if schema.is_none() => return Err(ColumnParseError::MissingLeafType),
+            Some("") | None => None,
+            Some(kind) => Some(ColumnType::try_from(kind)?),
+        };
+
+        let trimmed = path_part.strip_prefix('/').unwrap_or(path_part);
+        if trimmed.is_empty() {
+            return Err(ColumnParseError::Empty);
+        }
+
+        let mut message = schema.map(|(_, message)| message);
+        let mut leaf = None;
+        let mut path = Vec::new();
+
+        for c in trimmed.split('/') {
+            let field = match c.parse::<FieldId>() {
+                Ok(id) => message.and_then(|m| m.field(id)),
+                Err(_) if schema.is_none() => return Err(ColumnParseError::InvalidField(c)),
+                Err(_) => Some(
+                    message
+                        .and_then(|m| m.field_by_name(c))
+                        .ok_or(ColumnParseError::UnknownField(c))?,
+                ),
+            };
+
+            path.push(match field {
+                Some(field) => field.number,
+                None => c
+                    .parse::<FieldId>()
+                    .map_err(|_| ColumnParseError::InvalidField(c))?,
+            });
+
+            message = match (schema, field) {
+                (Some((schema, _)), Some(field)) => schema.message_of(field),
+                _ => None,
+            };
+            leaf = field;
+        }
+
+        let kind = match (kind, leaf) {
+            (Some(kind), _) => kind,
+            (None, Some(leaf)) => ColumnType::from(leaf.kind),
+            // a number not found in the schema
+            (None, None) => return Err(ColumnParseError::MissingLeafType),
+        };
+
+        Ok(Column {
+            name: path_part.to_owned(),
+            path,
+            kind,
+        })
+    }
+}
+
+/// Converted value of a single column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell<'a> {
+    Bytes(&'a [u8]),
+    Str(&'a str),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+}
+
+#[derive(Debug)]
+pub enum ColumnError {
+    Decoding(DecodingError),
+    /// The message ended in the middle of a field
+    Truncated,
+    /// The leaf field had a wire type not compatible with the column type
+    UnexpectedWireType {
+        column: usize,
+        wire_type: WireType,
+    },
+    /// The leaf field for a string column was not valid UTF-8
+    InvalidUtf8 {
+        column: usize,
+    },
+}
+
+impl fmt::Display for ColumnError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ColumnError::*;
+        match self {
+            Decoding(e) => write!(fmt, "{}", e),
+            Truncated => write!(fmt, "message was truncated"),
+            UnexpectedWireType { column, wire_type } => write!(
+                fmt,
+                "unexpected wire type {:?} for column {}",
+                wire_type, column
+            ),
+            InvalidUtf8 { column } => write!(fmt, "invalid utf8 for column {}", column),
+        }
+    }
+}
+
+impl std::error::Error for ColumnError {}
+
+impl From<DecodingError> for ColumnError {
+    fn from(e: DecodingError) -> Self {
+        ColumnError::Decoding(e)
+    }
+}
+
+/// Extracts the columns out of a single complete message. Absent fields are returned as `None`.
+pub fn extract_row<'a>(
+    message: &'a [u8],
+    columns: &[Column],
+) -> Result<Vec<Option<Cell<'a>>>, ColumnError> {
+    let mut row = vec![None; columns.len()];
+    let candidates = (0..columns.len()).collect::<Vec<_>>();
+    walk(message, columns, &candidates, 0, &mut row)?;
+    Ok(row)
+}
+
+fn walk<'a>(
+    buf: &'a [u8],
+    columns: &[Column],
+    candidates: &[usize],
+    depth: usize,
+    row: &mut [Option<Cell<'a>>],
+) -> Result<(), ColumnError> {
+    let mut reader = FieldReader::default();
+    let mut at = 0;
+    let mut nested = Vec::new();
+
+    while at < buf.len() {
+        let read = match reader.next(&buf[at..])? {
+            Ok(read) => read,
+            Err(_) => return Err(ColumnError::Truncated),
+        };
+
+        #[cfg(feature = "groups")]
+        if matches!(read.value(), FieldValue::StartGroup | FieldValue::EndGroup) {
+            return Err(read.unsupported_group().into());
+        }
+
+        let start = at + read.consumed();
+        let end = match start.checked_add(read.field_len()) {
+            Some(end) if end <= buf.len() => end,
+            _ => return Err(ColumnError::Truncated),
+        };
+
+        let id = read.field_id();
+        nested.clear();
+
+        for &i in candidates {
+            let path = &columns[i].path;
+            if path[depth] != id {
+                continue;
+            }
+
+            if path.len() == depth + 1 {
+                row[i] = Some(convert(i, columns[i].kind, read.value(), &buf[start..end])?);
+            } else if read.is_length_delimited() {
+                nested.push(i);
+            }
+        }
+
+        if !nested.is_empty() {
+            // the walk cannot be done while reusing the `nested` so take it for the duration
+            let these = std::mem::take(&mut nested);
+            walk(&buf[start..end], columns, &these, depth + 1, row)?;
+            nested = these;
+        }
+
+        at = end;
+    }
+
+    Ok(())
+}
+
+fn convert<'a>(
+    column: usize,
+    kind: ColumnType,
+    value: &FieldValue,
+    slice: &'a [u8],
+) -> Result<Cell<'a>, ColumnError> {
+    use ColumnType as C;
+    use FieldValue as V;
+
+    Ok(match (kind, value) {
+        (C::Bytes, V::DataLength(_)) => Cell::Bytes(slice),
+        (C::String, V::DataLength(_)) => match std::str::from_utf8(slice) {
+            Ok(s) => Cell::Str(s),
+            Err(_) => return Err(ColumnError::InvalidUtf8 { column }),
+        },
+        (C::UInt64, V::Varint(x)) => Cell::U64(*x),
+        (C::Int64, V::Varint(x)) => Cell::I64(*x as i64),
+        (C::SInt64, V::Varint(x)) => Cell::I64(decode_zigzag64(*x)),
+        (C::Fixed32, V::Fixed32(x)) => Cell::U64(*x as u64),
+        (C::Fixed64, V::Fixed64(x)) => Cell::U64(*x),
+        (C::SFixed32, V::Fixed32(x)) => Cell::I64(*x as i32 as i64),
+        (C::SFixed64, V::Fixed64(x)) => Cell::I64(*x as i64),
+        (C::Float, V::Fixed32(x)) => Cell::F32(f32::from_bits(*x)),
+        (C::Double, V::Fixed64(x)) => Cell::F64(f64::from_bits(*x)),
+        (C::Bool, V::Varint(x)) => Cell::Bool(*x != 0),
+        (_, value) => {
+            let wire_type = match value {
+                V::Varint(_) => WireType::Varint,
+                V::Fixed64(_) => WireType::Fixed64,
+                V::Fixed32(_) => WireType::Fixed32,
+                V::DataLength(_) => WireType::LengthDelimited,
+                #[cfg(feature = "groups")]
+                V::StartGroup => WireType::StartGroup,
+                #[cfg(feature = "groups")]
+                V::EndGroup => WireType::EndGroup,
+            };
+            return Err(ColumnError::UnexpectedWireType { column, wire_type });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_row, Cell, Column, ColumnError, ColumnParseError, ColumnType};
+    use crate::schema::{tests::order_schema, Schema};
+    use hex_literal::hex;
+
+    #[test]
+    fn parse_column() {
+        let c = Column::parse("/1/2::sint64").unwrap();
+        assert_eq!(c.path, vec![1, 2]);
+        assert_eq!(c.kind, ColumnType::SInt64);
+        assert_eq!(c.name, "/1/2");
+
+        assert!(Column::parse("1/2").is_err());
+        assert!(Column::parse("::bool").is_err());
+        assert!(Column::parse("1/x::bool").is_err());
+    }
+
+    #[test]
+    fn parse_column_with_schema() {
+        let schema = Schema::decode(&order_schema()).unwrap();
+        let order = schema.message("test.Order").unwrap();
+
+        let c = Column::parse_with_schema("/lines/productId", &schema, order).unwrap();
+        assert_eq!((c.path, c.kind), (vec![2, 1], ColumnType::String));
+
+        let c = Column::parse_with_schema("totalCents", &schema, order).unwrap();
+        assert_eq!((c.path, c.kind), (vec![3], ColumnType::SInt64));
+
+        // the given type overrides
+        let c = Column::parse_with_schema("2/count::fixed64", &schema, order).unwrap();
+        assert_eq!((c.path, c.kind), (vec![2, 3], ColumnType::Fixed64));
+
+        assert!(matches!(
+            Column::parse_with_schema("lines/price", &schema, order),
+            Err(ColumnParseError::UnknownField("price"))
+        ));
+        assert!(matches!(
+            Column::parse_with_schema("9", &schema, order),
+            Err(ColumnParseError::MissingLeafType)
+        ));
+    }
+
+    #[test]
+    fn extracts_nested_and_last_wins() {
+        // 1: { 1: 4, 2: "ab" }, 2: 3 (zigzag -2), 1: { 1: 5 }
+        let input = hex!("0a06 0804 12026162 1003 0a02 0805");
+        let columns = ["1/1::uint64", "1/2::string", "2::sint64", "3::bool"]
+            .iter()
+            .map(|s| Column::parse(s).unwrap())
+            .collect::<Vec<_>>();
+
+        let row = extract_row(&input, &columns).unwrap();
+        assert_eq!(
+            row,
+            vec![
+                Some(Cell::U64(5)),
+                Some(Cell::Str("ab")),
+                Some(Cell::I64(-2)),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn unexpected_wire_type() {
+        let input = hex!("0804");
+        let columns = vec![Column::parse("1::string").unwrap()];
+        assert!(matches!(
+            extract_row(&input, &columns),
+            Err(ColumnError::UnexpectedWireType { column: 0, .. })
+        ));
+    }
+}
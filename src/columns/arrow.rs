@@ -0,0 +1,249 @@
+//! Exporting the selected columns into Arrow record batches, enabled with the `arrow` feature.
+//!
+//! The batches can be written out as Parquet with the `parquet` crates `ArrowWriter`.
+
+use super::{extract_row, Cell, Column, ColumnError, ColumnType};
+use crate::framing::Framing;
+use crate::io_ext::frames::FrameReader;
+use crate::ReadError;
+use arrow_array::builder::{
+    BinaryBuilder, BooleanBuilder, Float32Builder, Float64Builder, Int64Builder, StringBuilder,
+    UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum ExportError {
+    Column(ColumnError),
+    Read(ReadError),
+    Arrow(ArrowError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Column(e) => write!(fmt, "{}", e),
+            ExportError::Read(e) => write!(fmt, "{}", e),
+            ExportError::Arrow(e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<ColumnError> for ExportError {
+    fn from(e: ColumnError) -> Self {
+        ExportError::Column(e)
+    }
+}
+
+impl From<ReadError> for ExportError {
+    fn from(e: ReadError) -> Self {
+        ExportError::Read(e)
+    }
+}
+
+impl From<ArrowError> for ExportError {
+    fn from(e: ArrowError) -> Self {
+        ExportError::Arrow(e)
+    }
+}
+
+fn data_type(kind: ColumnType) -> DataType {
+    use ColumnType::*;
+    match kind {
+        Bytes => DataType::Binary,
+        String => DataType::Utf8,
+        UInt64 | Fixed32 | Fixed64 => DataType::UInt64,
+        Int64 | SInt64 | SFixed32 | SFixed64 => DataType::Int64,
+        Float => DataType::Float32,
+        Double => DataType::Float64,
+        Bool => DataType::Boolean,
+    }
+}
+
+enum Builder {
+    Binary(BinaryBuilder),
+    Utf8(StringBuilder),
+    UInt64(UInt64Builder),
+    Int64(Int64Builder),
+    Float32(Float32Builder),
+    Float64(Float64Builder),
+    Boolean(BooleanBuilder),
+}
+
+impl Builder {
+    fn new(kind: ColumnType) -> Self {
+        match data_type(kind) {
+            DataType::Binary => Builder::Binary(BinaryBuilder::new()),
+            DataType::Utf8 => Builder::Utf8(StringBuilder::new()),
+            DataType::UInt64 => Builder::UInt64(UInt64Builder::new()),
+            DataType::Int64 => Builder::Int64(Int64Builder::new()),
+            DataType::Float32 => Builder::Float32(Float32Builder::new()),
+            DataType::Float64 => Builder::Float64(Float64Builder::new()),
+            DataType::Boolean => Builder::Boolean(BooleanBuilder::new()),
+            _ => unreachable!("data_type returns only the above"),
+        }
+    }
+
+    fn append(&mut self, cell: Option<Cell<'_>>) {
+        match (self, cell) {
+            (Builder::Binary(b), Some(Cell::Bytes(x))) => b.append_value(x),
+            (Builder::Utf8(b), Some(Cell::Str(x))) => b.append_value(x),
+            (Builder::UInt64(b), Some(Cell::U64(x))) => b.append_value(x),
+            (Builder::Int64(b), Some(Cell::I64(x))) => b.append_value(x),
+            (Builder::Float32(b), Some(Cell::F32(x))) => b.append_value(x),
+            (Builder::Float64(b), Some(Cell::F64(x))) => b.append_value(x),
+            (Builder::Boolean(b), Some(Cell::Bool(x))) => b.append_value(x),
+            (Builder::Binary(b), _) => b.append_null(),
+            (Builder::Utf8(b), _) => b.append_null(),
+            (Builder::UInt64(b), _) => b.append_null(),
+            (Builder::Int64(b), _) => b.append_null(),
+            (Builder::Float32(b), _) => b.append_null(),
+            (Builder::Float64(b), _) => b.append_null(),
+            (Builder::Boolean(b), _) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Builder::Binary(b) => Arc::new(b.finish()),
+            Builder::Utf8(b) => Arc::new(b.finish()),
+            Builder::UInt64(b) => Arc::new(b.finish()),
+            Builder::Int64(b) => Arc::new(b.finish()),
+            Builder::Float32(b) => Arc::new(b.finish()),
+            Builder::Float64(b) => Arc::new(b.finish()),
+            Builder::Boolean(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Collects rows of messages into Arrow record batches of at most `batch_size` rows.
+pub struct RecordBatchExporter {
+    columns: Vec<Column>,
+    schema: SchemaRef,
+    builders: Vec<Builder>,
+    batch_size: usize,
+    rows: usize,
+}
+
+impl RecordBatchExporter {
+    pub fn new(columns: Vec<Column>, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be positive");
+        let fields = columns
+            .iter()
+            .map(|c| Field::new(c.name.clone(), data_type(c.kind), true))
+            .collect::<Vec<_>>();
+        let builders = columns.iter().map(|c| Builder::new(c.kind)).collect();
+        Self {
+            columns,
+            schema: Arc::new(Schema::new(fields)),
+            builders,
+            batch_size,
+            rows: 0,
+        }
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Adds a row from a complete message, returning a batch once `batch_size` rows have been
+    /// collected.
+    pub fn push_message(&mut self, message: &[u8]) -> Result<Option<RecordBatch>, ExportError> {
+        let row = extract_row(message, &self.columns)?;
+        for (builder, cell) in self.builders.iter_mut().zip(row) {
+            builder.append(cell);
+        }
+        self.rows += 1;
+
+        if self.rows == self.batch_size {
+            self.flush()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the rows collected so far as a batch, if there are any.
+    pub fn flush(&mut self) -> Result<Option<RecordBatch>, ExportError> {
+        if self.rows == 0 {
+            return Ok(None);
+        }
+        self.rows = 0;
+        let arrays = self.builders.iter_mut().map(|b| b.finish()).collect();
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), arrays)?))
+    }
+}
+
+/// Exports every message of a length delimited stream as a row, handing out the batches to
+/// `on_batch` as they fill up.
+pub fn export_delimited<R, F>(
+    read: R,
+    framing: Framing,
+    columns: Vec<Column>,
+    batch_size: usize,
+    mut on_batch: F,
+) -> Result<(), ExportError>
+where
+    R: std::io::Read,
+    F: FnMut(RecordBatch) -> Result<(), ExportError>,
+{
+    let mut frames = FrameReader::new(read, framing);
+    let mut exporter = RecordBatchExporter::new(columns, batch_size);
+
+    while let Some((_, message)) = frames.next_frame()? {
+        if let Some(batch) = exporter.push_message(message)? {
+            on_batch(batch)?;
+        }
+    }
+
+    if let Some(batch) = exporter.flush()? {
+        on_batch(batch)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::export_delimited;
+    use crate::columns::Column;
+    use crate::framing::Framing;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::UInt64Type;
+    use arrow_array::Array;
+    use hex_literal::hex;
+
+    #[test]
+    fn batches_from_delimited() {
+        // three messages: { 1: 1, 2: "a" }, { 1: 2 }, { 2: "c" }
+        let input = hex!("05 0801 120161 02 0802 03 120163");
+        let columns = vec![
+            Column::parse("1::uint64").unwrap(),
+            Column::parse("2::string").unwrap(),
+        ];
+
+        let mut batches = Vec::new();
+        export_delimited(&input[..], Framing::Varint, columns, 2, |b| {
+            batches.push(b);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].num_rows(), 2);
+        assert_eq!(batches[1].num_rows(), 1);
+
+        let ids = batches[0].column(0).as_primitive::<UInt64Type>();
+        assert_eq!(ids.value(1), 2);
+
+        let names = batches[0].column(1).as_string::<i32>();
+        assert_eq!(names.value(0), "a");
+        assert!(names.is_null(1));
+
+        assert!(batches[1].column(0).is_null(0));
+    }
+}
@@ -0,0 +1,230 @@
+//! Exporting the selected columns as CSV or TSV.
+
+use super::{extract_row, Cell, Column, ColumnError};
+use crate::framing::Framing;
+use crate::io_ext::frames::FrameReader;
+use crate::ReadError;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Options for [`CsvWriter`].
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    /// Field delimiter, `b','` for CSV and `b'\t'` for TSV.
+    pub delimiter: u8,
+    /// Written for absent fields.
+    pub null: String,
+    /// Write the column names as the first row.
+    pub header: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            null: String::new(),
+            header: true,
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn tsv() -> Self {
+        Self {
+            delimiter: b'\t',
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CsvError {
+    Column(ColumnError),
+    Read(ReadError),
+    IO(io::Error),
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::Column(e) => write!(fmt, "{}", e),
+            CsvError::Read(e) => write!(fmt, "{}", e),
+            CsvError::IO(e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+impl From<ColumnError> for CsvError {
+    fn from(e: ColumnError) -> Self {
+        CsvError::Column(e)
+    }
+}
+
+impl From<ReadError> for CsvError {
+    fn from(e: ReadError) -> Self {
+        CsvError::Read(e)
+    }
+}
+
+impl From<io::Error> for CsvError {
+    fn from(e: io::Error) -> Self {
+        CsvError::IO(e)
+    }
+}
+
+/// Writes one row per message. Values are quoted only when needed, bytes are written as hex.
+pub struct CsvWriter<W> {
+    inner: W,
+    columns: Vec<Column>,
+    options: CsvOptions,
+    header_written: bool,
+    /// Scratch space for formatting a single value
+    tmp: String,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(inner: W, columns: Vec<Column>, options: CsvOptions) -> Self {
+        let header_written = !options.header;
+        Self {
+            inner,
+            columns,
+            options,
+            header_written,
+            tmp: String::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Extracts the columns out of a complete message and writes them as a row.
+    pub fn write_message(&mut self, message: &[u8]) -> Result<(), CsvError> {
+        let row = extract_row(message, &self.columns)?;
+        self.write_row(&row)?;
+        Ok(())
+    }
+
+    /// Writes an already extracted row.
+    pub fn write_row(&mut self, row: &[Option<Cell<'_>>]) -> io::Result<()> {
+        use fmt::Write as _;
+
+        if !self.header_written {
+            self.header_written = true;
+            for i in 0..self.columns.len() {
+                if i > 0 {
+                    self.inner.write_all(&[self.options.delimiter])?;
+                }
+                let name = std::mem::take(&mut self.columns[i].name);
+                let ret = self.write_escaped(&name);
+                self.columns[i].name = name;
+                ret?;
+            }
+            self.inner.write_all(b"\n")?;
+        }
+
+        for (i, cell) in row.iter().enumerate() {
+            if i > 0 {
+                self.inner.write_all(&[self.options.delimiter])?;
+            }
+
+            let mut tmp = std::mem::take(&mut self.tmp);
+            tmp.clear();
+
+            match cell {
+                None => tmp.push_str(&self.options.null),
+                Some(Cell::Str(s)) => tmp.push_str(s),
+                Some(Cell::Bytes(b)) => {
+                    for byte in *b {
+                        write!(tmp, "{:02x}", byte).expect("writing to String cannot fail");
+                    }
+                }
+                Some(Cell::U64(x)) => write!(tmp, "{}", x).unwrap(),
+                Some(Cell::I64(x)) => write!(tmp, "{}", x).unwrap(),
+                Some(Cell::F32(x)) => write!(tmp, "{}", x).unwrap(),
+                Some(Cell::F64(x)) => write!(tmp, "{}", x).unwrap(),
+                Some(Cell::Bool(x)) => write!(tmp, "{}", x).unwrap(),
+            }
+
+            let ret = self.write_escaped(&tmp);
+            self.tmp = tmp;
+            ret?;
+        }
+
+        self.inner.write_all(b"\n")
+    }
+
+    fn write_escaped(&mut self, s: &str) -> io::Result<()> {
+        let delimiter = self.options.delimiter;
+        let needs_quoting = s
+            .bytes()
+            .any(|b| b == delimiter || b == b'"' || b == b'\n' || b == b'\r');
+
+        if !needs_quoting {
+            return self.inner.write_all(s.as_bytes());
+        }
+
+        self.inner.write_all(b"\"")?;
+        for (i, part) in s.split('"').enumerate() {
+            if i > 0 {
+                self.inner.write_all(b"\"\"")?;
+            }
+            self.inner.write_all(part.as_bytes())?;
+        }
+        self.inner.write_all(b"\"")
+    }
+}
+
+/// Writes every message of a length delimited stream as a row.
+pub fn export_delimited<R: io::Read, W: Write>(
+    read: R,
+    framing: Framing,
+    columns: Vec<Column>,
+    options: CsvOptions,
+    write: W,
+) -> Result<W, CsvError> {
+    let mut frames = FrameReader::new(read, framing);
+    let mut writer = CsvWriter::new(write, columns, options);
+
+    while let Some((_, message)) = frames.next_frame()? {
+        writer.write_message(message)?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_delimited, CsvOptions};
+    use crate::columns::Column;
+    use crate::framing::Framing;
+    use hex_literal::hex;
+
+    #[test]
+    fn quoting_and_nulls() {
+        // { 1: 1, 2: "a,\"b\"" }, { 1: 2 }
+        let mut input = hex!("09 0801 1205").to_vec();
+        input.extend_from_slice(b"a,\"b\"");
+        input.extend_from_slice(&hex!("02 0802"));
+
+        let columns = vec![
+            Column::parse("1::uint64").unwrap(),
+            Column::parse("2::string").unwrap(),
+        ];
+
+        let options = CsvOptions {
+            null: "NULL".into(),
+            ..CsvOptions::default()
+        };
+
+        let out =
+            export_delimited(&input[..], Framing::Varint, columns, options, Vec::new()).unwrap();
+
+        assert_eq!(
+            std::str::from_utf8(&out).unwrap(),
+            "1,2\n1,\"a,\"\"b\"\"\"\n2,NULL\n"
+        );
+    }
+}
@@ -1,73 +1,338 @@
 use std::convert::TryFrom;
+use std::io::{self, Seek, SeekFrom};
 
 use crate::{
-    pb::*, DecodingError, FieldInfo, FieldValue, NeedMoreBytes, ReadField, Status, WireType,
+    pb::*, DecodingError, DecodingErrorKind, FieldId, FieldInfo, FieldValue, NeedMoreBytes, Offset,
+    ReadField, Status, WireType,
 };
 
-#[derive(Default)]
+/// The longest field header: a 5-byte tag followed by a 10-byte varint.
+pub(crate) const MAX_HEADER: usize = 15;
+
+/// The longest length delimited field which, together with its header, fits into a `usize` and
+/// an [`Offset`]. The ends of the fields can still overflow at large offsets, which is checked by
+/// the callers.
+pub const DEFAULT_MAX_FIELD_LEN: u64 = {
+    let bits = if usize::BITS < Offset::BITS {
+        usize::BITS
+    } else {
+        Offset::BITS
+    };
+    (u64::MAX >> (u64::BITS - bits)) - MAX_HEADER as u64
+};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FieldReader {
+    /// The field last read, only borrowed by the returned `ReadField`
+    #[cfg_attr(feature = "serde", serde(skip))]
     field: Option<FieldInfo>,
+    strict: bool,
+    max_field_len: u64,
+    /// Stream offset of the data given on the next call
+    offset: u64,
+    /// The beginning of a header taken by `next_resumable`
+    partial: [u8; MAX_HEADER],
+    partial_len: usize,
+    /// Stream offset of the beginning of `partial`
+    partial_at: u64,
+}
+
+impl Default for FieldReader {
+    fn default() -> Self {
+        FieldReader {
+            field: None,
+            strict: false,
+            max_field_len: DEFAULT_MAX_FIELD_LEN,
+            offset: 0,
+            partial: [0; MAX_HEADER],
+            partial_len: 0,
+            partial_at: 0,
+        }
+    }
 }
 
 impl FieldReader {
+    /// With `strict` the varints which are not in the canonical form are rejected with
+    /// `DecodingErrorKind::NonCanonicalVarint`, for uses where the encoding needs to be unique such
+    /// as hashing or signatures.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Length delimited fields longer than `max` are rejected with
+    /// `DecodingErrorKind::FieldTooLarge`. The limit cannot be raised over
+    /// [`DEFAULT_MAX_FIELD_LEN`], which is the default.
+    pub fn set_max_field_len(&mut self, max: u64) {
+        self.max_field_len = max.min(DEFAULT_MAX_FIELD_LEN);
+    }
+
+    /// Sets the stream offset of the data given on the following calls, for the spans of the
+    /// returned `ReadField`s such as `ReadField::tag_span` and the offsets of the errors. By
+    /// default the offsets are relative to the data of each call.
+    pub fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
     /// Reads the first bytes as any field. After returning a length delimited field, the data must
     /// be skipped for 'ReadField::bytes_to_skip` to avoid interpreting the field as a nested message.
     pub fn next<'a>(
         &'a mut self,
         data: &[u8],
     ) -> Result<Result<ReadField<'a>, Status>, DecodingError> {
-        // TODO: this needs to work on Bytes, or two slices to support ring buffers
-        macro_rules! launder {
-            ($x:expr) => {
-                match $x {
-                    Ok(x) => x,
-                    Err(NeedMoreBytes) => return Ok(Err(Status::NeedMoreBytes)),
+        // the two slices of ring buffers are handled by copying the header in crate::split
+        let offset = self.offset;
+        match read_field(data, self.strict, self.max_field_len).map_err(|e| e.offset_by(offset))? {
+            Ok((consumed, info)) => Ok(Ok(ReadField {
+                consumed,
+                resumed: 0,
+                field: self.field.insert(info.at(self.offset)),
+            })),
+            Err(status) => Ok(Err(status)),
+        }
+    }
+
+    /// Reads the first bytes as any field like `next`, but on `Status::NeedMoreBytes` all of the
+    /// data has been taken and is kept by the reader, so the caller can discard it and continue
+    /// with only the following bytes. The `ReadField::consumed` is then the amount of bytes
+    /// consumed from the data of this call and `ReadField::resumed` the amount taken earlier.
+    ///
+    /// The offsets of the errors are relative to the beginning of the bytes taken earlier.
+    pub fn next_resumable<'a>(
+        &'a mut self,
+        data: &[u8],
+    ) -> Result<Result<ReadField<'a>, Status>, DecodingError> {
+        let resumed = self.partial_len;
+
+        if resumed == 0 {
+            let offset = self.offset;
+            let read = read_field(data, self.strict, self.max_field_len);
+            return match read.map_err(|e| e.offset_by(offset))? {
+                Ok((consumed, info)) => Ok(Ok(ReadField {
+                    consumed,
+                    resumed: 0,
+                    field: self.field.insert(info.at(self.offset)),
+                })),
+                Err(Status::NeedMoreBytes(_)) => {
+                    // less than a complete header
+                    self.partial[..data.len()].copy_from_slice(data);
+                    self.partial_len = data.len();
+                    self.partial_at = self.offset;
+                    Ok(Err(Status::NeedMoreBytes(None)))
                 }
+                Err(status) => Ok(Err(status)),
             };
         }
 
-        if data.is_empty() {
-            return Ok(Err(Status::IdleAtEndOfBuffer));
+        let mut tmp = self.partial;
+        let taken = data.len().min(MAX_HEADER - resumed);
+        tmp[resumed..resumed + taken].copy_from_slice(&data[..taken]);
+
+        match read_field(&tmp[..resumed + taken], self.strict, self.max_field_len) {
+            Ok(Ok((consumed, info))) => {
+                self.partial_len = 0;
+                Ok(Ok(ReadField {
+                    consumed: consumed - resumed,
+                    resumed,
+                    field: self.field.insert(info.at(self.partial_at)),
+                }))
+            }
+            Ok(Err(_)) => {
+                self.partial = tmp;
+                self.partial_len += taken;
+                Ok(Err(Status::NeedMoreBytes(None)))
+            }
+            Err(e) => {
+                self.partial_len = 0;
+                Err(e.offset_by(self.partial_at))
+            }
         }
+    }
 
-        let (consumed, tag) = launder!(read_varint32(data)?);
+    /// The first bytes of the header of the field just returned by `next_resumable` with the
+    /// `ReadField::resumed` amount.
+    pub(crate) fn resumed_bytes(&self, resumed: usize) -> &[u8] {
+        &self.partial[..resumed]
+    }
 
-        let data = &data[consumed..];
+    /// The amount of bytes taken by `next_resumable` for an incomplete header.
+    pub fn pending(&self) -> usize {
+        self.partial_len
+    }
 
-        let field = tag >> 3;
-        let kind = WireType::try_from(tag)?;
+    /// Skips the data of the length delimited field `read` with `seek` instead of reading it,
+    /// where `buffered` is the amount of bytes after the header which have already been read from
+    /// `seek`. The buffered bytes can extend past the end of the field, in which case the position
+    /// is moved back to the end of it. Returns the new position from `Seek::seek`.
+    ///
+    /// This does not take `self` as the `read` borrows the reader.
+    pub fn skip_field<S: Seek>(
+        read: &ReadField<'_>,
+        buffered: usize,
+        seek: &mut S,
+    ) -> io::Result<u64> {
+        let delta = i64::try_from(read.field_len())
+            .ok()
+            .zip(i64::try_from(buffered).ok())
+            .and_then(|(len, buffered)| len.checked_sub(buffered))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "field is too long"))?;
 
-        let (additional, value) = match &kind {
-            WireType::Varint => {
-                let (consumed, val) = launder!(read_varint64(data)?);
-                (consumed, FieldValue::Varint(val))
-            }
-            WireType::Fixed32 => {
-                let (consumed, val) = launder!(read_fixed32(data));
-                (consumed, FieldValue::Fixed32(val))
+        seek.seek(SeekFrom::Current(delta))
+    }
+
+    /// Reads all of the complete fields at the beginning of the data into `out`, including the
+    /// bytes of the length delimited fields, returning the amount of bytes they take. Reading
+    /// stops at the first field which does not fit the data, which needs to be given again with
+    /// more bytes. The length delimited fields are not read as nested messages.
+    ///
+    /// The bytes taken by `next_resumable` are not continued from.
+    pub fn next_many(
+        &mut self,
+        data: &[u8],
+        out: &mut Vec<OwnedReadField>,
+    ) -> Result<usize, DecodingError> {
+        let mut at = 0;
+
+        loop {
+            let (consumed, info) = match read_field(&data[at..], self.strict, self.max_field_len) {
+                Ok(Ok(read)) => read,
+                Ok(Err(_)) => return Ok(at),
+                Err(e) => return Err(e.offset_by(self.offset + at as u64)),
+            };
+
+            let len = consumed.checked_add(info.bytes_to_skip()).ok_or_else(|| {
+                let offset = self.offset + at as u64;
+                let kind = DecodingErrorKind::OffsetOverflow {
+                    offset,
+                    len: info.bytes_to_skip() as u64,
+                };
+                DecodingError::from(kind)
+                    .with_offset(offset)
+                    .with_field(info.id)
+            })?;
+            if data.len() - at < len {
+                return Ok(at);
             }
-            WireType::Fixed64 => {
-                let (consumed, val) = launder!(read_fixed64(data));
-                (consumed, FieldValue::Fixed64(val))
+
+            out.push(OwnedReadField {
+                offset: at,
+                consumed,
+                id: info.id,
+                wire_type: info.kind,
+                value: info.value,
+            });
+
+            at += len;
+        }
+    }
+}
+
+/// A field read by [`FieldReader::next_many`].
+#[derive(Debug)]
+pub struct OwnedReadField {
+    /// Offset of the tag in the data
+    pub offset: usize,
+    /// Length of the tag and the value, or the tag and the length for length delimited fields
+    pub consumed: usize,
+    pub id: FieldId,
+    pub wire_type: WireType,
+    pub value: FieldValue,
+}
+
+impl OwnedReadField {
+    /// The range of the bytes of a length delimited field in the data, or `None` for the other
+    /// fields and for the ranges which would end past `usize::MAX`, which `next_many` never
+    /// returns.
+    pub fn data_range(&self) -> Option<std::ops::Range<usize>> {
+        match self.value {
+            FieldValue::DataLength(len) => {
+                let start = self.offset.checked_add(self.consumed)?;
+                let end = start.checked_add(usize::try_from(len).ok()?)?;
+                Some(start..end)
             }
-            WireType::LengthDelimited => {
-                let (consumed, len) = launder!(read_varint32(data)?);
-                (consumed, FieldValue::DataLength(len))
+            _ => None,
+        }
+    }
+}
+
+/// Reads the header of the field at the beginning of the data, returning its length.
+fn read_field(
+    data: &[u8],
+    strict: bool,
+    max_field_len: u64,
+) -> Result<Result<(usize, FieldInfo), Status>, DecodingError> {
+    macro_rules! launder {
+        ($x:expr) => {
+            match $x {
+                Ok(x) => x,
+                Err(NeedMoreBytes) => return Ok(Err(Status::NeedMoreBytes(None))),
             }
         };
+    }
 
-        let consumed = consumed + additional;
+    if data.is_empty() {
+        return Ok(Err(Status::IdleAtEndOfBuffer));
+    }
 
-        self.field = Some(FieldInfo {
-            id: field,
-            kind,
-            value,
-        });
+    let canonical = |bytes: &[u8], max_bits, at: usize| {
+        if strict && !is_canonical_varint(bytes, max_bits) {
+            Err(DecodingError::from(DecodingErrorKind::NonCanonicalVarint).with_offset(at as u64))
+        } else {
+            Ok(())
+        }
+    };
 
-        let field = self.field.as_ref().unwrap();
+    let (consumed, tag) = launder!(read_varint32(data).map_err(|e| e.or_offset(0))?);
+    canonical(&data[..consumed], 32, 0)?;
 
-        Ok(Ok(ReadField { consumed, field }))
-    }
+    let tag_len = consumed;
+    let data = &data[consumed..];
+
+    let field = tag >> 3;
+    let in_field = |e: DecodingError| e.or_offset(tag_len as u64).or_field(field);
+    let kind = WireType::try_from(tag).map_err(|e| e.or_offset(0).or_field(field))?;
+
+    let (additional, value) = match &kind {
+        WireType::Varint => {
+            let (consumed, val) = launder!(read_varint64(data).map_err(in_field)?);
+            canonical(&data[..consumed], 64, tag_len).map_err(in_field)?;
+            (consumed, FieldValue::Varint(val))
+        }
+        WireType::Fixed32 => {
+            let (consumed, val) = launder!(read_fixed32(data));
+            (consumed, FieldValue::Fixed32(val))
+        }
+        WireType::Fixed64 => {
+            let (consumed, val) = launder!(read_fixed64(data));
+            (consumed, FieldValue::Fixed64(val))
+        }
+        WireType::LengthDelimited => {
+            let (consumed, len) = launder!(read_varint64(data).map_err(in_field)?);
+            canonical(&data[..consumed], 64, tag_len).map_err(in_field)?;
+            if len > max_field_len {
+                let kind = DecodingErrorKind::FieldTooLarge {
+                    len,
+                    limit: max_field_len,
+                };
+                return Err(DecodingError::from(kind).with_offset(0).with_field(field));
+            }
+            (consumed, FieldValue::DataLength(len))
+        }
+        #[cfg(feature = "groups")]
+        WireType::StartGroup => (0, FieldValue::StartGroup),
+        #[cfg(feature = "groups")]
+        WireType::EndGroup => (0, FieldValue::EndGroup),
+    };
+
+    let info = FieldInfo {
+        tag_at_offset: 0,
+        tag_len,
+        id: field,
+        kind,
+        value,
+    };
+
+    Ok(Ok((consumed + additional, info)))
 }
 
 #[cfg(test)]
@@ -88,13 +353,13 @@ mod tests {
             (Varint(34_359_738_369), 7, 0),
             (Varint(4_398_046_511_105), 8, 0),
             (Varint(562_949_953_421_313), 9, 0),
-            (Varint(u64::max_value()), 11, 0),
+            (Varint(u64::MAX), 11, 0),
             (Fixed64(1), 9, 0),
             (Fixed32(1), 5, 0),
             (DataLength(1), 2, 1),
             (DataLength(242), 3, 242),
             (DataLength(22242), 4, 22242),
-            (DataLength(u32::max_value()), 6, u32::max_value() as usize),
+            (DataLength(0xffff_fff0), 6, 0xffff_fff0),
             // longer fields are not supported
         ];
 
@@ -114,7 +379,7 @@ mod tests {
             // all those report back NeedMoreBytes
             for end_byte in 1..(buffer.len() - 1) {
                 let need_more = fr.next(&buffer[..end_byte]).unwrap().unwrap_err();
-                assert!(matches!(need_more, Status::NeedMoreBytes));
+                assert!(matches!(need_more, Status::NeedMoreBytes(_)));
             }
 
             buffer.clear();
@@ -162,4 +427,173 @@ mod tests {
             Ok("../../../arch/arm64/boot/dts")
         );
     }
+
+    #[test]
+    fn strict_rejects_non_canonical_varints() {
+        use crate::DecodingErrorKind;
+
+        let inputs: &[(&[u8], u64)] = &[
+            // overlong value
+            (&hex!("08 8000"), 1),
+            // overlong tag
+            (&hex!("8800 01"), 0),
+            // bits beyond 64
+            (&hex!("08 ffffffffffffffffff02"), 1),
+            // overlong length
+            (&hex!("12 8100 61"), 1),
+        ];
+
+        for (input, offset) in inputs {
+            let mut fr = FieldReader::default();
+            fr.next(input).unwrap().unwrap();
+
+            fr.set_strict(true);
+            match fr.next(input) {
+                Err(e) if matches!(e.kind(), DecodingErrorKind::NonCanonicalVarint) => {
+                    assert_eq!(e.offset(), Some(*offset))
+                }
+                other => panic!("{:02x?}: {:?}", input, other.map(|r| r.map(|r| r.consumed))),
+            }
+        }
+
+        let mut fr = FieldReader::default();
+        fr.set_strict(true);
+        fr.next(&hex!("08 ffffffffffffffff01")).unwrap().unwrap();
+    }
+
+    #[test]
+    fn long_fields_are_limited() {
+        use crate::DecodingErrorKind;
+
+        // 2: 5 GiB
+        let input = hex!("12 8080808014");
+        let mut fr = FieldReader::default();
+
+        if cfg!(all(target_pointer_width = "64", not(feature = "offset32"))) {
+            let read = fr.next(&input).unwrap().unwrap();
+            assert_eq!(read.field_len() as u64, 5 << 30);
+        } else {
+            assert!(fr.next(&input).is_err());
+        }
+
+        fr.set_max_field_len(1 << 30);
+        fr.set_offset(100);
+        let e = fr.next(&input).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::FieldTooLarge {
+                len: 0x1_4000_0000,
+                limit: 0x4000_0000,
+            }
+        ));
+        assert_eq!((e.offset(), e.field()), (Some(100), Some(2)));
+        assert_eq!(
+            e.to_string(),
+            "field of 5368709120 bytes is over the limit of 1073741824 in field 2 at offset 100"
+        );
+    }
+
+    #[test]
+    fn hostile_length_is_rejected() {
+        use crate::DecodingErrorKind;
+
+        // 1: u64::MAX - 1 bytes, which would overflow with the header
+        let input = hex!("0a feffffffffffffffff01");
+        let e = FieldReader::default().next(&input).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::FieldTooLarge {
+                len: 0xffff_ffff_ffff_fffe,
+                limit: super::DEFAULT_MAX_FIELD_LEN,
+            }
+        ));
+        assert_eq!((e.offset(), e.field()), (Some(0), Some(1)));
+    }
+
+    #[test]
+    fn next_many_reads_complete_fields() {
+        // 1: 150, 2: "ab", 3: 1, and the beginning of 2: "abc"
+        let input = hex!("089601 12026162 1801 1203 61");
+        let mut fr = FieldReader::default();
+        let mut out = Vec::new();
+
+        let consumed = fr.next_many(&input, &mut out).unwrap();
+        assert_eq!(consumed, 9);
+
+        let ids = out.iter().map(|f| (f.offset, f.id)).collect::<Vec<_>>();
+        assert_eq!(ids, [(0, 1), (3, 2), (7, 3)]);
+        assert!(matches!(out[0].value, FieldValue::Varint(150)));
+        assert_eq!(out[1].data_range(), Some(5..7));
+    }
+
+    #[test]
+    fn next_many_with_hostile_fields() {
+        use super::OwnedReadField;
+        use crate::{DecodingErrorKind, WireType};
+
+        // 1: 1, 2: "abc" with only "ab"
+        let input = hex!("0801 1203 6162");
+        let mut fr = FieldReader::default();
+        let mut out = Vec::new();
+        assert_eq!(fr.next_many(&input, &mut out).unwrap(), 2);
+        assert_eq!(out.len(), 1);
+
+        // 1: 1, 2: u64::MAX - 1 bytes
+        let input = hex!("0801 12feffffffffffffffff01");
+        fr.set_offset(100);
+        let e = fr.next_many(&input, &mut out).unwrap_err();
+        assert!(matches!(e.kind(), DecodingErrorKind::FieldTooLarge { .. }));
+        assert_eq!((e.offset(), e.field()), (Some(102), Some(2)));
+
+        let field = OwnedReadField {
+            offset: usize::MAX - 2,
+            consumed: 2,
+            id: 1,
+            wire_type: WireType::LengthDelimited,
+            value: FieldValue::DataLength(1),
+        };
+        assert_eq!(field.data_range(), None);
+    }
+
+    #[test]
+    fn spans_are_stream_offsets() {
+        // 1: 150, then 2: "abc" with the header split over two calls
+        let input = hex!("089601 1203616263");
+        let mut fr = FieldReader::default();
+        fr.set_offset(100);
+
+        let read = fr.next(&input).unwrap().unwrap();
+        assert_eq!(read.tag_span(), 100..101);
+        assert_eq!(read.length_span(), None);
+        assert_eq!(read.value_span(), 101..103);
+
+        fr.set_offset(103);
+        assert!(fr.next_resumable(&input[3..4]).unwrap().is_err());
+        fr.set_offset(104);
+        let read = fr.next_resumable(&input[4..]).unwrap().unwrap();
+        assert_eq!(read.tag_span(), 103..104);
+        assert_eq!(read.length_span(), Some(104..105));
+        assert_eq!(read.value_span(), 105..108);
+        assert_eq!(read.span(), 103..108);
+    }
+
+    #[test]
+    fn resumes_partial_headers() {
+        // 1: 300, fed one byte at a time
+        let input = hex!("08 ac02");
+        let mut fr = FieldReader::default();
+
+        for (i, b) in input[..2].iter().enumerate() {
+            assert!(matches!(
+                fr.next_resumable(std::slice::from_ref(b)),
+                Ok(Err(Status::NeedMoreBytes(_)))
+            ));
+            assert_eq!(fr.pending(), i + 1);
+        }
+
+        let read = fr.next_resumable(&input[2..]).unwrap().unwrap();
+        assert_eq!((read.consumed(), read.resumed()), (1, 2));
+        assert_eq!(read.field_id(), 1);
+        assert_eq!(fr.pending(), 0);
+    }
 }
@@ -0,0 +1,220 @@
+//! The writing counterpart of [`crate::field_reader::FieldReader`]: appends single fields to a
+//! buffer of the caller.
+//!
+//! [`FieldWriter`] knows nothing about the nesting of messages, a message field is written with
+//! `write_bytes` once its body has been encoded. [`MessageBuilder`] writes the nested messages in
+//! place and fills in their length prefix once they end.
+
+use crate::pb::{encode_varint, tag, varint_len, write_fixed32, write_fixed64, write_varint};
+use crate::sink::{Sink, SinkError};
+use crate::{FieldId, WireType};
+
+/// Appends fields in the wire format to a `Vec<u8>`.
+pub struct FieldWriter<'a> {
+    buf: &'a mut Vec<u8>,
+    /// Length of `buf` before the first field was written
+    start: usize,
+}
+
+impl<'a> FieldWriter<'a> {
+    /// Appends after any bytes already in `buf`.
+    pub fn new(buf: &'a mut Vec<u8>) -> Self {
+        let start = buf.len();
+        FieldWriter { buf, start }
+    }
+
+    /// Writes the tag of a field, to be followed by the value of the wire type. The other methods
+    /// write both the tag and the value.
+    pub fn write_header(&mut self, id: FieldId, wire_type: WireType) {
+        write_varint(tag(id, wire_type), self.buf);
+    }
+
+    /// Writes a varint field, for any of `int32`, `int64`, `uint32`, `uint64`, `bool` and `enum`.
+    ///
+    /// Negative `int32` and `int64` values are written as their 64-bit two's complement, which
+    /// takes 10 bytes, use `value as u64` and not `value as u32 as u64`.
+    pub fn write_varint(&mut self, id: FieldId, value: u64) {
+        self.write_header(id, WireType::Varint);
+        write_varint(value, self.buf);
+    }
+
+    /// Writes a zigzag encoded varint field, for `sint32` and `sint64`.
+    pub fn write_sint64(&mut self, id: FieldId, value: i64) {
+        self.write_varint(id, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub fn write_fixed32(&mut self, id: FieldId, value: u32) {
+        self.write_header(id, WireType::Fixed32);
+        write_fixed32(value, self.buf);
+    }
+
+    pub fn write_fixed64(&mut self, id: FieldId, value: u64) {
+        self.write_header(id, WireType::Fixed64);
+        write_fixed64(value, self.buf);
+    }
+
+    /// Writes a length delimited field, for `bytes`, `string`, packed repeated fields and
+    /// already encoded messages.
+    pub fn write_bytes(&mut self, id: FieldId, bytes: &[u8]) {
+        self.write_header(id, WireType::LengthDelimited);
+        write_varint(bytes.len() as u64, self.buf);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// The amount of bytes written by this writer.
+    pub fn written(&self) -> usize {
+        self.buf.len() - self.start
+    }
+}
+
+/// Writes nested messages with a [`FieldWriter`] without computing their sizes first.
+///
+/// A byte is reserved for the length prefix when a message begins and it is patched when the
+/// message ends. Only the bodies of 128 bytes or more need a longer prefix, for which the body is
+/// moved, once per nesting level.
+pub struct MessageBuilder<'a> {
+    writer: FieldWriter<'a>,
+    /// Positions of the reserved length prefixes of the open messages, the outermost first
+    open: Vec<usize>,
+}
+
+impl<'a> MessageBuilder<'a> {
+    /// Appends after any bytes already in `buf`.
+    pub fn new(buf: &'a mut Vec<u8>) -> Self {
+        MessageBuilder {
+            writer: FieldWriter::new(buf),
+            open: Vec::new(),
+        }
+    }
+
+    /// Writes the fields of the innermost open message, or the top level fields.
+    pub fn writer(&mut self) -> &mut FieldWriter<'a> {
+        &mut self.writer
+    }
+
+    /// Starts a message field, all fields written until the matching `end_message` are its body.
+    pub fn begin_message(&mut self, id: FieldId) {
+        self.writer.write_header(id, WireType::LengthDelimited);
+        self.open.push(self.writer.buf.len());
+        self.writer.buf.push(0);
+    }
+
+    /// Ends the latest message started with `begin_message`, writing its length prefix.
+    pub fn end_message(&mut self) -> Result<(), SinkError> {
+        let at = self.open.pop().ok_or(SinkError::UnbalancedEndMessage)?;
+        let buf = &mut *self.writer.buf;
+
+        let len = (buf.len() - at - 1) as u64;
+        let prefix = varint_len(len);
+        if prefix > 1 {
+            buf.splice(at + 1..at + 1, std::iter::repeat_n(0, prefix - 1));
+        }
+
+        let mut tmp = [0u8; 10];
+        encode_varint(len, &mut tmp);
+        buf[at..at + prefix].copy_from_slice(&tmp[..prefix]);
+        Ok(())
+    }
+
+    pub fn open_messages(&self) -> usize {
+        self.open.len()
+    }
+
+    /// The amount of bytes written, see `FieldWriter::written`. Until all messages have ended,
+    /// this includes the reserved bytes of the length prefixes.
+    pub fn written(&self) -> usize {
+        self.writer.written()
+    }
+}
+
+impl Sink for MessageBuilder<'_> {
+    type Error = SinkError;
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.writer.buf.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn begin_message(&mut self, id: FieldId) -> Result<(), Self::Error> {
+        MessageBuilder::begin_message(self, id);
+        Ok(())
+    }
+
+    fn end_message(&mut self) -> Result<(), Self::Error> {
+        MessageBuilder::end_message(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldWriter, MessageBuilder};
+    use crate::field_reader::FieldReader;
+    use crate::sink::{Sink, SinkError, VecSink};
+    use crate::FieldValue;
+    use hex_literal::hex;
+
+    #[test]
+    fn written_fields_are_read_back() {
+        let mut buf = vec![0xff];
+        let mut w = FieldWriter::new(&mut buf);
+        w.write_varint(1, 150);
+        w.write_sint64(2, -2);
+        w.write_fixed32(3, 7);
+        w.write_fixed64(4, u64::MAX);
+        w.write_bytes(5, b"abc");
+        assert_eq!(w.written(), 24);
+
+        assert_eq!(
+            buf[1..],
+            hex!("089601 1003 1d07000000 21ffffffffffffffff 2a03616263")
+        );
+
+        let mut out = Vec::new();
+        let consumed = FieldReader::default()
+            .next_many(&buf[1..], &mut out)
+            .unwrap();
+        assert_eq!(consumed, 24);
+
+        let ids = out.iter().map(|f| f.id).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2, 3, 4, 5]);
+        assert!(matches!(out[0].value, FieldValue::Varint(150)));
+        assert!(matches!(out[1].value, FieldValue::Varint(3)));
+        assert!(matches!(out[2].value, FieldValue::Fixed32(7)));
+        assert!(matches!(out[3].value, FieldValue::Fixed64(u64::MAX)));
+        assert_eq!(out[4].data_range(), Some(21..24));
+    }
+
+    #[test]
+    fn nested_lengths_are_patched() {
+        let mut buf = Vec::new();
+        let mut b = MessageBuilder::new(&mut buf);
+        let mut sink = VecSink::default();
+
+        b.begin_message(1);
+        sink.begin_message(1).unwrap();
+        b.writer().write_varint(1, 150);
+        sink.write_varint(8).unwrap();
+        sink.write_varint(150).unwrap();
+        b.begin_message(2);
+        sink.begin_message(2).unwrap();
+        // long enough for a two byte prefix for both messages
+        b.writer().write_bytes(3, &[0xaa; 200]);
+        sink.write_slice(3, &[0xaa; 200]).unwrap();
+        b.end_message().unwrap();
+        sink.end_message().unwrap();
+        b.writer().write_fixed32(4, 7);
+        sink.write_field_header(4, crate::WireType::Fixed32)
+            .unwrap();
+        sink.write_raw(&7u32.to_le_bytes()).unwrap();
+        b.end_message().unwrap();
+        sink.end_message().unwrap();
+
+        assert!(matches!(
+            b.end_message(),
+            Err(SinkError::UnbalancedEndMessage)
+        ));
+        assert_eq!(b.open_messages(), 0);
+        assert_eq!(buf, sink.into_inner());
+        assert_eq!(buf[..3], [0x0a, 0xd6, 0x01]);
+    }
+}
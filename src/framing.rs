@@ -0,0 +1,467 @@
+//! Length prefixed framing of multiple messages in a single stream.
+//!
+//! [`FramedMessages`] reads such a stream with a [`Reader`] per message, for example a
+//! [`crate::matcher_fields::MatcherFields`], so that the same matcher can be used as with a single
+//! message. [`crate::io_ext::frames::FrameWriter`] writes such streams.
+
+use crate::pb::{encode_varint, read_varint64};
+use crate::{DecodingError, DecodingErrorKind, Introspect, NeedMoreBytes, Reader, Stats, Status};
+use std::convert::TryFrom;
+
+/// The supported length prefixes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    /// Varint length prefix, as written by `writeDelimitedTo` and read by `parseDelimitedFrom`.
+    Varint,
+    /// gRPC message framing: one byte compressed flag followed by a 4-byte big-endian length.
+    Grpc,
+    /// Plain 4-byte big-endian length prefix, as used by many homegrown TCP protocols.
+    BigEndian32,
+}
+
+/// The decoded length prefix of a single frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameHeader {
+    /// Length of the payload following the prefix
+    pub len: u64,
+    /// True if the payload is compressed; only possible with `Framing::Grpc`
+    pub compressed: bool,
+}
+
+impl Framing {
+    /// The maximum length of the prefix in bytes.
+    pub fn max_prefix_len(&self) -> usize {
+        match self {
+            Framing::Varint => 10,
+            Framing::Grpc => 5,
+            Framing::BigEndian32 => 4,
+        }
+    }
+
+    /// Reads the length prefix from the beginning of `data`, returning the length of the prefix
+    /// and the header.
+    pub fn read_prefix(
+        &self,
+        data: &[u8],
+    ) -> Result<Result<(usize, FrameHeader), NeedMoreBytes>, DecodingError> {
+        match self {
+            Framing::Varint => {
+                Ok(read_varint64(data)
+                    .map_err(|e| e.or_offset(0))?
+                    .map(|(consumed, len)| {
+                        (
+                            consumed,
+                            FrameHeader {
+                                len,
+                                compressed: false,
+                            },
+                        )
+                    }))
+            }
+            Framing::Grpc => {
+                if data.len() < 5 {
+                    return Ok(Err(NeedMoreBytes));
+                }
+                let compressed = match data[0] {
+                    0 => false,
+                    1 => true,
+                    x => {
+                        let kind = DecodingErrorKind::InvalidGrpcCompressedFlag(x);
+                        return Err(DecodingError::from(kind).with_offset(0));
+                    }
+                };
+                let mut len = [0u8; 4];
+                len.copy_from_slice(&data[1..5]);
+                let len = u32::from_be_bytes(len) as u64;
+                Ok(Ok((5, FrameHeader { len, compressed })))
+            }
+            Framing::BigEndian32 => {
+                if data.len() < 4 {
+                    return Ok(Err(NeedMoreBytes));
+                }
+                let mut len = [0u8; 4];
+                len.copy_from_slice(&data[..4]);
+                let len = u32::from_be_bytes(len) as u64;
+                Ok(Ok((
+                    4,
+                    FrameHeader {
+                        len,
+                        compressed: false,
+                    },
+                )))
+            }
+        }
+    }
+
+    /// Encodes the length prefix for an uncompressed payload of `len` bytes, returning the length
+    /// of the prefix or `None` if the length cannot be represented.
+    pub fn encode_prefix(&self, len: u64, buf: &mut [u8; 10]) -> Option<usize> {
+        let header = FrameHeader {
+            len,
+            compressed: false,
+        };
+        self.encode_header(header, buf)
+    }
+
+    /// Encodes the prefix of the header, the inverse of `read_prefix`. Returns `None` also for a
+    /// compressed header with other than `Framing::Grpc`.
+    pub fn encode_header(&self, header: FrameHeader, buf: &mut [u8; 10]) -> Option<usize> {
+        let FrameHeader { len, compressed } = header;
+        match self {
+            _ if compressed && *self != Framing::Grpc => None,
+            Framing::Varint => Some(encode_varint(len, buf)),
+            Framing::Grpc => {
+                let len = u32::try_from(len).ok()?;
+                buf[0] = compressed as u8;
+                buf[1..5].copy_from_slice(&len.to_be_bytes());
+                Some(5)
+            }
+            Framing::BigEndian32 => {
+                let len = u32::try_from(len).ok()?;
+                buf[..4].copy_from_slice(&len.to_be_bytes());
+                Some(4)
+            }
+        }
+    }
+}
+
+/// An item of [`FramedMessages`].
+#[derive(Debug)]
+pub enum Framed<T> {
+    /// An item read from the current message
+    Item(T),
+    /// The message ended, with the number of the message starting from zero
+    End(u64),
+}
+
+/// Reads the length prefixed messages of a stream, each with a new [`Reader`] from `new_reader`,
+/// returning the items of the reader followed by [`Framed::End`] for every message.
+///
+/// The readers see only the bytes of their message, so their offsets are relative to the
+/// beginning of the message; the offsets of the errors are stream offsets. The length prefix is
+/// taken only once it is complete, so on `Status::NeedMoreBytes` for a prefix the bytes of it need
+/// to be given again. Compressed gRPC messages are rejected with
+/// `DecodingErrorKind::InvalidGrpcCompressedFlag`.
+pub struct FramedMessages<R, F> {
+    framing: Framing,
+    new_reader: F,
+    /// The reader of the current message and the amount of its bytes not yet given to it
+    current: Option<(R, u64)>,
+    /// Stream offset of the next byte
+    offset: u64,
+    max_frame_len: Option<u64>,
+    /// Messages ended so far
+    frames: u64,
+}
+
+impl<R, F: FnMut() -> R> FramedMessages<R, F> {
+    pub fn new(framing: Framing, new_reader: F) -> Self {
+        FramedMessages {
+            framing,
+            new_reader,
+            current: None,
+            offset: 0,
+            max_frame_len: None,
+            frames: 0,
+        }
+    }
+
+    /// Sets the maximum accepted message length. Longer messages produce
+    /// `DecodingErrorKind::FrameTooLarge` after reading only the length prefix.
+    pub fn set_max_frame_len(&mut self, limit: Option<u64>) {
+        self.max_frame_len = limit;
+    }
+
+    /// Offset of the next byte in the stream.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The reader of the current message, if one has been started.
+    pub fn current(&self) -> Option<&R> {
+        self.current.as_ref().map(|(reader, _)| reader)
+    }
+
+    fn start(&mut self, buf: &mut &[u8]) -> Result<Result<(), Status>, DecodingError> {
+        if buf.is_empty() {
+            return Ok(Err(Status::IdleAtEndOfBuffer));
+        }
+
+        let offset = self.offset;
+        let (consumed, header) = match read_header(self.framing, buf, offset, self.max_frame_len)? {
+            Ok(read) => read,
+            Err(NeedMoreBytes) => return Ok(Err(Status::NeedMoreBytes(None))),
+        };
+
+        if header.compressed {
+            let kind = DecodingErrorKind::InvalidGrpcCompressedFlag(1);
+            return Err(DecodingError::from(kind).with_offset(offset));
+        }
+
+        *buf = &buf[consumed..];
+        self.offset += consumed as u64;
+        self.current = Some(((self.new_reader)(), header.len));
+        Ok(Ok(()))
+    }
+}
+
+/// Reads the prefix at the stream `offset`, rejecting the frames over the limit like
+/// [`FramedMessages`].
+pub(crate) fn read_header(
+    framing: Framing,
+    data: &[u8],
+    offset: u64,
+    max_frame_len: Option<u64>,
+) -> Result<Result<(usize, FrameHeader), NeedMoreBytes>, DecodingError> {
+    let read = framing.read_prefix(data).map_err(|e| e.offset_by(offset))?;
+
+    if let (Ok((_, header)), Some(limit)) = (&read, max_frame_len) {
+        if header.len > limit {
+            let kind = DecodingErrorKind::FrameTooLarge {
+                len: header.len,
+                limit,
+            };
+            return Err(DecodingError::from(kind).with_offset(offset));
+        }
+    }
+    Ok(read)
+}
+
+impl<'a, R, F> Reader<'a> for FramedMessages<R, F>
+where
+    R: Reader<'a>,
+    F: FnMut() -> R,
+{
+    type Returned = Framed<R::Returned>;
+
+    fn next(
+        &mut self,
+        buf: &mut &'a [u8],
+    ) -> Result<Result<Framed<R::Returned>, Status>, DecodingError> {
+        if self.current.is_none() {
+            if let Err(status) = self.start(buf)? {
+                return Ok(Err(status));
+            }
+        }
+
+        let (reader, remaining) = self.current.as_mut().expect("started above");
+
+        let start = self.offset;
+        let take = usize::try_from(*remaining).map_or(buf.len(), |r| r.min(buf.len()));
+        let mut message = &buf[..take];
+        let ret = reader.next(&mut message).map_err(|e| e.offset_by(start));
+
+        let consumed = take - message.len();
+        *buf = &buf[consumed..];
+        *remaining -= consumed as u64;
+        self.offset += consumed as u64;
+
+        match ret? {
+            Ok(item) => Ok(Ok(Framed::Item(item))),
+            Err(Status::IdleAtEndOfBuffer) if *remaining == 0 => {
+                self.current = None;
+                self.frames += 1;
+                Ok(Ok(Framed::End(self.frames - 1)))
+            }
+            Err(status) if *remaining == 0 => {
+                // the field continues after the end of the message, by at least a byte
+                let needed = status.size_hint().map_or(1, |n| n.get());
+                let kind = match usize::try_from(self.offset)
+                    .ok()
+                    .and_then(|end| Some((end.checked_add(needed)?, end)))
+                {
+                    Some((past, end)) => DecodingErrorKind::FailedMatcherNesting(past, end),
+                    None => DecodingErrorKind::OffsetOverflow {
+                        offset: self.offset,
+                        len: needed as u64,
+                    },
+                };
+                Err(DecodingError::from(kind).with_offset(self.offset))
+            }
+            Err(_) => Ok(Err(Status::need_more(*remaining))),
+        }
+    }
+}
+
+/// The readers are given slices of the caller's buffer, so `buffered` is always zero. `items` is
+/// the amount of messages ended.
+impl<R, F> Introspect for FramedMessages<R, F> {
+    fn stats(&self) -> Stats {
+        Stats {
+            offset: self.offset,
+            mid_field: self.current.is_some(),
+            buffered: 0,
+            items: self.frames,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameHeader, Framed, FramedMessages, Framing};
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields, Value};
+    use crate::{DecodingError, DecodingErrorKind, FieldId, ReadField, Reader, Status};
+    use hex_literal::hex;
+
+    #[test]
+    fn big_endian_prefix() {
+        let input = hex!("00000102");
+        assert!(Framing::BigEndian32
+            .read_prefix(&input[..3])
+            .unwrap()
+            .is_err());
+        assert_eq!(
+            Framing::BigEndian32.read_prefix(&input).unwrap().unwrap(),
+            (
+                4,
+                FrameHeader {
+                    len: 258,
+                    compressed: false
+                }
+            )
+        );
+
+        let mut buf = [0u8; 10];
+        assert_eq!(Framing::BigEndian32.encode_prefix(258, &mut buf), Some(4));
+        assert_eq!(&buf[..4], &input);
+        assert_eq!(Framing::BigEndian32.encode_prefix(1 << 32, &mut buf), None);
+    }
+
+    #[test]
+    fn grpc_prefix() {
+        let input = hex!("0100000102");
+        for end in 0..input.len() {
+            assert!(Framing::Grpc.read_prefix(&input[..end]).unwrap().is_err());
+        }
+        assert_eq!(
+            Framing::Grpc.read_prefix(&input[..]).unwrap().unwrap(),
+            (
+                5,
+                FrameHeader {
+                    len: 258,
+                    compressed: true
+                }
+            )
+        );
+        let mut buf = [0u8; 10];
+        let header = FrameHeader {
+            len: 258,
+            compressed: true,
+        };
+        assert_eq!(Framing::Grpc.encode_header(header, &mut buf), Some(5));
+        assert_eq!(&buf[..5], &input);
+        assert_eq!(Framing::Varint.encode_header(header, &mut buf), None);
+
+        let e = Framing::Grpc.read_prefix(&hex!("0200000000")).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::InvalidGrpcCompressedFlag(2)
+        ));
+        assert_eq!(e.offset(), Some(0));
+    }
+
+    /// Reads the length delimited fields as slices and the others as values.
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = FieldId;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<FieldId>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(read.field_id()))
+            } else {
+                Action::Continue(Cont::ReadValue(read.field_id()))
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<FieldId>>,
+        ) -> Option<FieldId> {
+            None
+        }
+    }
+
+    // { 1: 1 }, {}, { 2: "a", 1: 2 }
+    const INPUT: [u8; 10] = hex!("02 0801 00 05 120161 0802");
+
+    #[test]
+    fn messages_of_a_stream() {
+        let mut buf = &INPUT[..];
+        let mut messages = FramedMessages::new(Framing::Varint, || MatcherFields::new(Slices));
+
+        let items = std::iter::from_fn(|| messages.next(&mut buf).unwrap().ok())
+            .map(|item| match item {
+                Framed::Item(m) => format!("{}@{}: {:?}", m.tag, m.offset, m.value),
+                Framed::End(n) => format!("end {}", n),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            items,
+            [
+                "1@0: Varint(1)",
+                "end 0",
+                "end 1",
+                "2@0: Slice(2..3)",
+                "1@3: Varint(2)",
+                "end 2"
+            ]
+        );
+        assert!(buf.is_empty());
+        assert_eq!(messages.offset(), 10);
+
+        // the same when given a byte at a time, keeping the incomplete prefixes
+        let mut given = 0;
+        let mut kept = 0;
+        let mut messages = FramedMessages::new(Framing::Varint, || MatcherFields::new(Slices));
+        let mut again = Vec::new();
+        while given < INPUT.len() {
+            given += 1;
+            let mut buf = &INPUT[kept..given];
+            loop {
+                match messages.next(&mut buf).unwrap() {
+                    Ok(Framed::Item(m)) => again.push(Some(m.value)),
+                    Ok(Framed::End(_)) => again.push(None),
+                    Err(_) => break,
+                }
+            }
+            kept = given - buf.len();
+        }
+        assert_eq!(again.len(), 6);
+        assert!(matches!(again[3], Some(Value::Slice(ref r)) if *r == (2..3)));
+    }
+
+    #[test]
+    fn field_over_the_end_of_a_message() {
+        // { 1: ... } with the varint continuing in the next message
+        let input = hex!("01 08 01 01");
+        let mut buf = &input[..];
+        let mut messages = FramedMessages::new(Framing::Varint, || MatcherFields::new(Slices));
+        let e = messages.next(&mut buf).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::FailedMatcherNesting(..)
+        ));
+        assert_eq!(e.offset(), Some(2));
+
+        // lengths over the limit are not read
+        let mut buf = &input[..];
+        let mut messages = FramedMessages::new(Framing::Varint, || MatcherFields::new(Slices));
+        messages.set_max_frame_len(Some(0));
+        assert!(matches!(
+            messages.next(&mut buf).unwrap_err().kind(),
+            DecodingErrorKind::FrameTooLarge { len: 1, limit: 0 }
+        ));
+        assert!(matches!(
+            messages.next(&mut &input[..0]),
+            Ok(Err(Status::IdleAtEndOfBuffer))
+        ));
+    }
+}
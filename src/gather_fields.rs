@@ -1,5 +1,12 @@
-use crate::matcher_fields::{Matched, Matcher, MatcherFields};
-use crate::{DecodingError, Status};
+use crate::matcher_fields::{
+    Matched, Matcher, MatcherFields, OwnedMatched, OwnedValue, Packed, SlicedMatched, SlicedValue,
+    Value,
+};
+use crate::memory::{MemoryReport, MemoryUsage, Peaks};
+use crate::split::Input;
+use crate::{DecodingError, DecodingErrorKind, Introspect, Offset, Stats, Status};
+use std::borrow::Cow;
+use std::marker::PhantomData;
 use std::ops::Range;
 
 /// Gathers multiple tagged values into single returned value. This is needed because the fields in
@@ -19,35 +26,188 @@ pub trait Gatherer<'a> {
     ) -> Result<Option<Self::Returned>, DecodingError>;
 
     /// Returns the minimum stored input offset or None
-    fn min_offset(&self) -> Option<u64>;
+    fn min_offset(&self) -> Option<Offset>;
+
+    /// Called by [`GatheredFields`] when the window retained for `min_offset` has grown over the
+    /// limit set with `GatheredFields::set_retention_limit`. The gatherer can copy the ranges it
+    /// still needs out of the `slicer`, for example with [`RetainedSlice::evict`], so that the
+    /// `min_offset` can advance and the buffer can be drained. By default nothing is copied and the
+    /// whole window is retained.
+    fn evict(&mut self, _slicer: Slicer<'a>) -> Result<(), DecodingError> {
+        Ok(())
+    }
+
+    /// Called by [`GatheredFields`] before reading the next field, to return more of the values
+    /// completed by an earlier `update`, for example one per entry of a message which just ended.
+    /// The ranges of the values not yet returned need to be held in the `min_offset` to still be
+    /// in the `slicer`. By default there are none.
+    fn next_completed(
+        &mut self,
+        _slicer: Slicer<'a>,
+    ) -> Result<Option<Self::Returned>, DecodingError> {
+        Ok(None)
+    }
+}
+
+/// A range of the input held by a gatherer, which is either still in the buffer or has been
+/// copied out of it by [`RetainedSlice::evict`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RetainedSlice {
+    Range(Range<Offset>),
+    Copied(Range<Offset>, Vec<u8>),
+}
+
+impl RetainedSlice {
+    pub fn range(&self) -> &Range<Offset> {
+        match self {
+            RetainedSlice::Range(range) | RetainedSlice::Copied(range, _) => range,
+        }
+    }
+
+    /// The start of the range if it still needs to be retained in the buffer.
+    pub fn min_offset(&self) -> Option<Offset> {
+        match self {
+            RetainedSlice::Range(range) => Some(range.start),
+            RetainedSlice::Copied(..) => None,
+        }
+    }
+
+    /// Copies the bytes out of the buffer, if not already copied.
+    pub fn evict(&mut self, slicer: &Slicer<'_>) {
+        if let RetainedSlice::Range(range) = self {
+            let bytes = slicer.get(range).into_owned();
+            *self = RetainedSlice::Copied(range.clone(), bytes);
+        }
+    }
+
+    /// Returns the bytes, borrowed from the buffer if they were not copied.
+    pub fn into_cow<'a>(self, slicer: &Slicer<'a>) -> Cow<'a, [u8]> {
+        match self {
+            RetainedSlice::Range(range) => slicer.get(&range),
+            RetainedSlice::Copied(_, bytes) => Cow::Owned(bytes),
+        }
+    }
+}
+
+impl From<Range<Offset>> for RetainedSlice {
+    fn from(range: Range<Offset>) -> Self {
+        RetainedSlice::Range(range)
+    }
 }
 
 /// Slicer helps to map the bytes in the current buffer into the offset ranges of Value::Slice.
+///
+/// With the two slices of a ring buffer the ranges can also span both of them, in which case they
+/// can only be accessed with [`Slicer::get`].
+#[derive(Clone, Copy)]
 pub struct Slicer<'a> {
     buffer: &'a [u8],
+    /// The continuation of `buffer` for ring buffers, otherwise empty
+    second: &'a [u8],
     // what file offset the buffer[0] corresponds to
-    first_offset: u64,
+    first_offset: Offset,
 }
 
 impl<'a> Slicer<'a> {
-    pub(crate) fn wrap(buffer: &'a [u8], last_offset: u64) -> Self {
-        let first_offset = last_offset.saturating_sub(buffer.len() as u64);
+    pub(crate) fn wrap(buffer: &'a [u8], last_offset: Offset) -> Self {
+        Self::wrap_split(buffer, &[], last_offset)
+    }
+
+    pub(crate) fn wrap_split(buffer: &'a [u8], second: &'a [u8], last_offset: Offset) -> Self {
+        let len = (buffer.len() + second.len()) as Offset;
         Self {
             buffer,
-            first_offset,
+            second,
+            first_offset: last_offset.saturating_sub(len),
+        }
+    }
+
+    /// Panics if the range spans the two slices of a ring buffer.
+    pub fn as_slice(&self, range: &Range<Offset>) -> &'a [u8] {
+        match self.get(range) {
+            Cow::Borrowed(bytes) => bytes,
+            Cow::Owned(_) => panic!("range {:?} spans the two slices", range),
+        }
+    }
+
+    /// Like [`Slicer::as_slice`] but `None` when the bytes of the range are not in the buffer or
+    /// span the two slices of a ring buffer.
+    pub fn get_slice(&self, range: &Range<Offset>) -> Option<&'a [u8]> {
+        if range.start > range.end || !self.contains(range) {
+            return None;
+        }
+
+        match self.get(range) {
+            Cow::Borrowed(bytes) => Some(bytes),
+            Cow::Owned(_) => None,
+        }
+    }
+
+    /// The bytes of the range validated as UTF-8, for the ranges of [`Value::Slice`] which are
+    /// expected to be strings. The error has the offset of the invalid bytes.
+    ///
+    /// Panics like [`Slicer::as_slice`].
+    pub fn as_str(&self, range: &Range<Offset>) -> Result<&'a str, DecodingError> {
+        crate::matcher_fields::to_str(self.as_slice(range), range.start)
+    }
+
+    /// Copies the bytes of the range into an array, for example of a fixed size hash. Fails with
+    /// `DecodingErrorKind::UnexpectedLength` when the range has some other length.
+    ///
+    /// Panics if the bytes of the range are not in the buffer.
+    pub fn as_array<const N: usize>(
+        &self,
+        range: &Range<Offset>,
+    ) -> Result<[u8; N], DecodingError> {
+        let len = crate::widen(range.end - range.start);
+        if len != N as u64 {
+            let kind = DecodingErrorKind::UnexpectedLength {
+                len,
+                wanted: N as u64,
+            };
+            return Err(DecodingError::from(kind).with_offset(crate::widen(range.start)));
         }
+
+        let mut out = [0u8; N];
+        out.copy_from_slice(&self.get(range));
+        Ok(out)
     }
 
-    pub fn as_slice(&self, range: &Range<u64>) -> &'a [u8] {
+    /// Returns the bytes, copying them only when the range spans the two slices of a ring
+    /// buffer.
+    pub fn get(&self, range: &Range<Offset>) -> Cow<'a, [u8]> {
+        let index = self.index_range(range);
+        let split = self.buffer.len();
+
+        if index.end <= split {
+            Cow::Borrowed(&self.buffer[index])
+        } else if index.start >= split {
+            Cow::Borrowed(&self.second[index.start - split..index.end - split])
+        } else {
+            let mut joined = self.buffer[index.start..].to_vec();
+            joined.extend_from_slice(&self.second[..index.end - split]);
+            Cow::Owned(joined)
+        }
+    }
+
+    /// True if the bytes of the range are in the buffer.
+    pub(crate) fn contains(&self, range: &Range<Offset>) -> bool {
+        let len = (self.buffer.len() + self.second.len()) as Offset;
+        range.start >= self.first_offset && range.end - self.first_offset <= len
+    }
+
+    /// Converts the offset range into an index range of the wrapped buffer, or of the two slices
+    /// one after the other.
+    pub(crate) fn index_range(&self, range: &Range<Offset>) -> Range<usize> {
         let start = (range.start - self.first_offset) as usize;
         let end = (range.end - self.first_offset) as usize;
         let adjusted_range = start..end;
         assert_eq!(
             range.end - range.start,
-            (adjusted_range.end - adjusted_range.start) as u64
+            (adjusted_range.end - adjusted_range.start) as Offset
         );
 
-        &self.buffer[adjusted_range]
+        adjusted_range
     }
 }
 
@@ -57,7 +217,12 @@ impl<'a> Slicer<'a> {
 pub struct GatheredFields<M: Matcher, G> {
     reader: MatcherFields<M>,
     gatherer: G,
-    cached_min_offset: Option<u64>,
+    cached_min_offset: Option<Offset>,
+    retention_limit: Option<Offset>,
+    max_retained: Option<Offset>,
+    /// Gathered values returned so far
+    items: u64,
+    peaks: Peaks,
 }
 
 impl<M: Matcher, G> GatheredFields<M, G>
@@ -69,6 +234,75 @@ where
             reader: MatcherFields::new(matcher),
             gatherer,
             cached_min_offset: None,
+            retention_limit: None,
+            max_retained: None,
+            items: 0,
+            peaks: Peaks::default(),
+        }
+    }
+
+    /// Sets the size of the retained window after which the gatherer is asked to copy out the
+    /// ranges it holds, see [`Gatherer::evict`].
+    pub fn set_retention_limit(&mut self, limit: Option<Offset>) {
+        self.retention_limit = limit;
+    }
+
+    /// Sets the size of the retained window over which reading fails with
+    /// `DecodingErrorKind::GatherWindowExceeded`, after the gatherer has been asked to copy out
+    /// the ranges with the `set_retention_limit`. Without it the window can grow without bounds
+    /// when a gatherer holds on to an early range.
+    pub fn set_max_retained(&mut self, limit: Option<Offset>) {
+        self.max_retained = limit;
+    }
+
+    /// Sets a function to be called whenever the peak retained window grows, with the
+    /// [`MemoryUsage`] at that point.
+    pub fn set_on_peak<F>(&mut self, on_peak: Option<F>)
+    where
+        F: FnMut(&MemoryUsage) + Send + 'static,
+    {
+        self.peaks.set_on_peak(on_peak);
+    }
+}
+
+impl<M: Matcher, G> GatheredFields<M, G> {
+    /// Tells that the caller has advanced its source over `amount` bytes after
+    /// `Status::CanSkip`, see [`MatcherFields::skipped`]. `Status::CanSkip` is only returned when
+    /// the gatherer retains nothing of the caller's buffer.
+    pub fn skipped(&mut self, amount: u64) {
+        self.reader.skipped(amount);
+    }
+
+    /// The window of the caller's buffer retained for the gatherer.
+    fn retained(&self) -> Offset {
+        self.cached_min_offset
+            .map(|min| self.reader.offset() - min)
+            .unwrap_or(0)
+    }
+}
+
+impl<M: Matcher, G> crate::Skip for GatheredFields<M, G> {
+    fn skipped(&mut self, amount: u64) {
+        GatheredFields::skipped(self, amount)
+    }
+}
+
+/// `retained` is the window of the caller's buffer retained for the gatherer, the
+/// `GatheredFields` has no buffer of its own.
+impl<M: Matcher, G> MemoryReport for GatheredFields<M, G> {
+    fn memory(&self) -> MemoryUsage {
+        self.peaks.usage(0, crate::widen(self.retained()))
+    }
+}
+
+/// `buffered` is the amount of bytes retained in the caller's buffer for the gatherer.
+impl<M: Matcher, G> Introspect for GatheredFields<M, G> {
+    fn stats(&self) -> Stats {
+        let inner = self.reader.stats();
+        Stats {
+            buffered: crate::widen(self.retained()),
+            items: self.items,
+            ..inner
         }
     }
 }
@@ -83,44 +317,1156 @@ where
         &mut self,
         buf: &mut &'a [u8],
     ) -> Result<Result<<G as Gatherer<'a>>::Returned, Status>, DecodingError> {
-        let mut tmp = if let Some(min) = self.cached_min_offset {
-            // this means that min is stored at buf[0] and buf[diff] is the next byte the inner
-            // reader(s) need to look at
-            let diff = (self.reader.offset() - min) as usize;
-            &buf[diff..]
-        } else {
-            &buf[..]
-        };
+        self.gather(buf)
+    }
+}
 
-        loop {
-            let ret = match self.reader.next(&mut tmp)? {
-                Ok(m) => {
-                    // FIXME: it's easy to not notice that tmp is passed to inner instead of buf
-                    // and even that was wrong in the case of reading more than 1 byte at a time!
+impl<M: Matcher, G> GatheredFields<M, G> {
+    /// Reads the next gathered value like `Reader::next` but from the two slices of a ring
+    /// buffer, see [`MatcherFields::next_split`]. The ranges retained by the gatherer can span the
+    /// two slices, so the gatherer needs to use [`Slicer::get`] instead of [`Slicer::as_slice`]
+    /// for them.
+    #[allow(clippy::type_complexity)]
+    pub fn next_split<'a>(
+        &mut self,
+        buf: &mut (&'a [u8], &'a [u8]),
+    ) -> Result<Result<<G as Gatherer<'a>>::Returned, Status>, DecodingError>
+    where
+        G: Gatherer<'a, Tag = M::Tag>,
+    {
+        self.gather(buf)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn gather<'a, I: Input<'a>>(
+        &mut self,
+        buf: &mut I,
+    ) -> Result<Result<<G as Gatherer<'a>>::Returned, Status>, DecodingError>
+    where
+        G: Gatherer<'a, Tag = M::Tag>,
+    {
+        // the caller's buffer starts at the retained window or, when nothing is retained, at the
+        // next byte to be read
+        let start = self
+            .cached_min_offset
+            .unwrap_or_else(|| self.reader.offset());
+        debug_assert!(start <= self.reader.offset());
 
-                    let end = buf.len() - tmp.len();
-                    let sliced_from = &buf[..end];
+        // the part of the buffer not yet read through the matcher
+        let mut rest = *buf;
+        rest.advance((self.reader.offset() - start) as usize);
 
-                    let slicer = Slicer::wrap(sliced_from, self.reader.offset());
-                    let ret = self.gatherer.update(m, slicer)?.map(|r| Ok(Ok(r)));
-                    // invalidate the cached value
-                    self.cached_min_offset.take();
-                    ret
+        loop {
+            let consumed = buf.remaining() - rest.remaining();
+            let slicer = buf.slicer(consumed, self.reader.offset());
+            if let Some(r) = self.gatherer.next_completed(slicer)? {
+                return self.returned(buf, rest, start, Ok(Ok(r)));
+            }
+
+            let ret = match self.reader.next_from(&mut rest)? {
+                Ok(m) => {
+                    // everything from the start up to the end of the match, which is where the
+                    // reader is now
+                    let consumed = buf.remaining() - rest.remaining();
+                    let slicer = buf.slicer(consumed, self.reader.offset());
+                    self.gatherer.update(m, slicer)?.map(|r| Ok(Ok(r)))
                 }
                 Err(e) => Some(Ok(Err(e))),
             };
 
             if let Some(ret) = ret {
-                self.cached_min_offset = self.gatherer.min_offset();
+                return self.returned(buf, rest, start, ret);
+            }
+        }
+    }
+
+    /// Advances the caller's buffer `buf` starting at offset `start` to the retained window, or
+    /// to `rest` when nothing is retained, before returning `ret`.
+    #[allow(clippy::type_complexity)]
+    fn returned<'a, I: Input<'a>>(
+        &mut self,
+        buf: &mut I,
+        rest: I,
+        start: Offset,
+        ret: Result<Result<<G as Gatherer<'a>>::Returned, Status>, DecodingError>,
+    ) -> Result<Result<<G as Gatherer<'a>>::Returned, Status>, DecodingError>
+    where
+        G: Gatherer<'a, Tag = M::Tag>,
+    {
+        let consumed = buf.remaining() - rest.remaining();
+        debug_assert_eq!(start + consumed as Offset, self.reader.offset());
+
+        let mut min_offset = self.gatherer.min_offset();
+
+        if let (Some(limit), Some(min)) = (self.retention_limit, min_offset) {
+            if self.reader.offset() - min > limit {
+                let slicer = buf.slicer(consumed, self.reader.offset());
+                self.gatherer.evict(slicer)?;
+                min_offset = self.gatherer.min_offset();
+            }
+        }
+
+        self.cached_min_offset = min_offset;
+        self.peaks.update(0, crate::widen(self.retained()));
+
+        if let (Some(limit), Some(min)) = (self.max_retained, min_offset) {
+            let retained = self.reader.offset() - min;
+            if retained > limit {
+                let kind = DecodingErrorKind::GatherWindowExceeded {
+                    retained: crate::widen(retained),
+                    limit: crate::widen(limit),
+                };
+                return Err(DecodingError::from(kind).with_offset(crate::widen(min)));
+            }
+        }
+
+        // the retained window needs to stay contiguous in the caller's buffer
+        let ret = match ret {
+            Ok(Err(Status::CanSkip(amount))) if min_offset.is_some() => {
+                Ok(Err(Status::need_more(amount)))
+            }
+            ret => ret,
+        };
+
+        match min_offset {
+            // nothing is retained, the next call starts from where the reader is
+            None => *buf = rest,
+            // keep only from the min_offset, which is where the next call expects buf to
+            // start
+            Some(min) => {
+                debug_assert!(min >= start, "{} < {}", min, start);
+                buf.advance(min.saturating_sub(start) as usize);
+            }
+        }
+
+        if matches!(ret, Ok(Ok(_))) {
+            self.items += 1;
+        }
+
+        ret
+    }
+}
+
+/// Gatherer which calls the callback with every match as it happens, slices included. Nothing is
+/// retained and nothing is ever returned, so there is no allocation per match; use it for counting
+/// or forwarding the matches.
+///
+/// As nothing is returned, `GatheredFields::next` processes all of the buffer in a single call.
+pub struct CallbackGatherer<T, F> {
+    callback: F,
+    tag: PhantomData<fn(T)>,
+}
+
+impl<T, F> CallbackGatherer<T, F>
+where
+    F: FnMut(SlicedMatched<'_, T>),
+{
+    pub fn new(callback: F) -> Self {
+        Self {
+            callback,
+            tag: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.callback
+    }
+}
+
+impl<'a, T: 'static, F> Gatherer<'a> for CallbackGatherer<T, F>
+where
+    F: FnMut(SlicedMatched<'_, T>),
+{
+    type Tag = T;
+    type Returned = ();
+
+    fn update(
+        &mut self,
+        matched: Matched<T>,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<()>, DecodingError> {
+        let Matched { tag, offset, value } = matched;
+        let value = match value {
+            Value::Marker => SlicedValue::Marker,
+            Value::Varint(x) => SlicedValue::Varint(x),
+            Value::Fixed64(x) => SlicedValue::Fixed64(x),
+            Value::Fixed32(x) => SlicedValue::Fixed32(x),
+            // a skipped field which was advanced over in the earlier calls
+            Value::Slice(range) if !slicer.contains(&range) => SlicedValue::Marker,
+            Value::Slice(range) => {
+                let bytes = slicer.as_slice(&range);
+                SlicedValue::Slice(range, bytes)
+            }
+            Value::Str(range) => {
+                let s = crate::matcher_fields::to_str(slicer.as_slice(&range), range.start)?;
+                SlicedValue::Str(range, s)
+            }
+            Value::Packed(wire_type, range) => {
+                let bytes = slicer.as_slice(&range);
+                let packed =
+                    Packed::new(wire_type, bytes).map_err(|e| e.or_offset(crate::widen(offset)))?;
+                SlicedValue::Packed(range, packed)
+            }
+            Value::Raw(raw, range) => {
+                let bytes = slicer.as_slice(&range);
+                SlicedValue::Raw(range, raw, bytes)
+            }
+            Value::SliceChunk(range, remaining) => {
+                let bytes = slicer.as_slice(&range);
+                SlicedValue::SliceChunk(range, remaining, bytes)
+            }
+        };
+
+        (self.callback)(SlicedMatched { tag, offset, value });
+        Ok(None)
+    }
+
+    fn min_offset(&self) -> Option<Offset> {
+        None
+    }
+}
+
+/// A gatherer which is given the matches with the values copied out of the buffer, adapted into a
+/// [`Gatherer`] with [`OwnedGatherer`].
+pub trait GatherOwned {
+    /// The marker type matched by this gatherer
+    type Tag: 'static;
+
+    /// Returned combined value from this gatherer
+    type Returned;
+
+    /// Records field matches required for `Returned` and possibly returns when needed.
+    fn update_owned(
+        &mut self,
+        matched: OwnedMatched<Self::Tag>,
+    ) -> Result<Option<Self::Returned>, DecodingError>;
+}
+
+/// Copies the slices of every match for a [`GatherOwned`], so that nothing of the caller's buffer
+/// is retained between the matches. For gatherers which would convert the slices right away, a
+/// copy of each is cheaper than keeping the buffer from the first slice on.
+///
+/// The fields skipped with `Action::Skip` over multiple calls are given as `OwnedValue::Marker`,
+/// as their bytes are no longer in the buffer.
+pub struct OwnedGatherer<G> {
+    inner: G,
+}
+
+impl<G: GatherOwned> OwnedGatherer<G> {
+    pub fn new(inner: G) -> Self {
+        Self { inner }
+    }
+
+    pub fn into_inner(self) -> G {
+        self.inner
+    }
+}
+
+impl<'a, G> Gatherer<'a> for OwnedGatherer<G>
+where
+    G: GatherOwned,
+    G::Returned: 'a,
+{
+    type Tag = G::Tag;
+    type Returned = G::Returned;
+
+    fn update(
+        &mut self,
+        matched: Matched<G::Tag>,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<G::Returned>, DecodingError> {
+        let Matched { tag, offset, value } = matched;
+        // `get` instead of `as_slice` as nothing is borrowed: the ranges can span a ring buffer
+        let value = match value {
+            Value::Marker => OwnedValue::Marker,
+            Value::Varint(x) => OwnedValue::Varint(x),
+            Value::Fixed64(x) => OwnedValue::Fixed64(x),
+            Value::Fixed32(x) => OwnedValue::Fixed32(x),
+            Value::Slice(range) if !slicer.contains(&range) => OwnedValue::Marker,
+            Value::Slice(range) => {
+                let bytes = slicer.get(&range).into_owned();
+                OwnedValue::Slice(range, bytes)
+            }
+            Value::Str(range) => {
+                let bytes = slicer.get(&range);
+                let s = crate::matcher_fields::to_str(&bytes, range.start)?.to_owned();
+                OwnedValue::Str(range, s)
+            }
+            Value::Packed(wire_type, range) => {
+                // validated while buffering
+                let bytes = slicer.get(&range).into_owned();
+                OwnedValue::Packed(range, wire_type, bytes)
+            }
+            Value::Raw(raw, range) => {
+                let bytes = slicer.get(&range).into_owned();
+                OwnedValue::Raw(range, raw, bytes)
+            }
+            Value::SliceChunk(range, remaining) => {
+                let bytes = slicer.get(&range).into_owned();
+                OwnedValue::SliceChunk(range, remaining, bytes)
+            }
+        };
+
+        self.inner.update_owned(OwnedMatched { tag, offset, value })
+    }
+
+    fn min_offset(&self) -> Option<Offset> {
+        None
+    }
+}
+
+/// A value collected by [`CollectAll`], with the offset of the field.
+#[derive(Debug, Clone)]
+pub enum Collected<'a> {
+    /// The bytes of a slice or a string, borrowed from the buffer or copied out of it
+    Slice(Offset, Cow<'a, [u8]>),
+    /// A number, including each of the packed values. The ranges of the other values are not
+    /// retained, so they are only good for their offsets.
+    Value(Offset, Value),
+}
+
+/// Collects every match of a tag, returning them when the match of another tag is seen, such as
+/// the marker returned by `Matcher::decide_after` for the end of the message containing the
+/// repeated field. The packed values are collected one by one.
+///
+/// By default the slices are kept as ranges of the caller's buffer, so the buffer is retained
+/// from the first slice on; with `set_copy_slices` they are copied out as they are matched.
+pub struct CollectAll<T> {
+    collect: T,
+    end: T,
+    copy_slices: bool,
+    collected: Vec<(Offset, Held)>,
+}
+
+/// What [`CollectAll`] holds until returning the [`Collected`] values.
+enum Held {
+    Slice(RetainedSlice),
+    Value(Value),
+}
+
+impl<T> CollectAll<T> {
+    /// Collects the matches tagged `collect` until the match tagged `end`.
+    pub fn new(collect: T, end: T) -> Self {
+        Self {
+            collect,
+            end,
+            copy_slices: false,
+            collected: Vec::new(),
+        }
+    }
+
+    /// Copies the slices as they are matched, so that nothing of the buffer is retained.
+    pub fn set_copy_slices(&mut self, copy: bool) {
+        self.copy_slices = copy;
+    }
+}
+
+impl<'a, T: PartialEq + 'static> Gatherer<'a> for CollectAll<T> {
+    type Tag = T;
+    type Returned = Vec<Collected<'a>>;
+
+    fn update(
+        &mut self,
+        matched: Matched<T>,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<Self::Returned>, DecodingError> {
+        let Matched { tag, offset, value } = matched;
+
+        if tag == self.end {
+            let collected = self
+                .collected
+                .drain(..)
+                .map(|(offset, held)| match held {
+                    Held::Slice(slice) => Collected::Slice(offset, slice.into_cow(&slicer)),
+                    Held::Value(value) => Collected::Value(offset, value),
+                })
+                .collect();
+            return Ok(Some(collected));
+        }
+
+        if tag != self.collect {
+            return Ok(None);
+        }
+
+        match value {
+            Value::Slice(range) | Value::Str(range) => {
+                let mut slice = RetainedSlice::from(range);
+                if self.copy_slices {
+                    slice.evict(&slicer);
+                }
+                self.collected.push((offset, Held::Slice(slice)));
+            }
+            Value::Packed(wire_type, range) => {
+                let bytes = slicer.get(&range);
+                let packed = Packed::new(wire_type, &bytes)
+                    .map_err(|e| e.or_offset(crate::widen(offset)))?;
+                self.collected
+                    .extend(packed.map(|value| (offset, Held::Value(value))));
+            }
+            value => self.collected.push((offset, Held::Value(value))),
+        }
+
+        Ok(None)
+    }
+
+    fn min_offset(&self) -> Option<Offset> {
+        self.collected.iter().find_map(|(_, held)| match held {
+            Held::Slice(slice) => slice.min_offset(),
+            Held::Value(_) => None,
+        })
+    }
+
+    fn evict(&mut self, slicer: Slicer<'a>) -> Result<(), DecodingError> {
+        for (_, held) in &mut self.collected {
+            if let Held::Slice(slice) = held {
+                slice.evict(&slicer);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Gives the same matches to two gatherers in a single pass, returning when either of them
+/// returns. More gatherers can be combined by nesting, for example
+/// `MultiGatherer::new(a, MultiGatherer::new(b, c))`. The buffer is retained from the smaller of
+/// the `min_offset`s.
+pub struct MultiGatherer<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> MultiGatherer<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<'a, T, A, B> Gatherer<'a> for MultiGatherer<A, B>
+where
+    T: Clone + 'static,
+    A: Gatherer<'a, Tag = T>,
+    B: Gatherer<'a, Tag = T>,
+{
+    type Tag = T;
+    type Returned = (Option<A::Returned>, Option<B::Returned>);
+
+    fn update(
+        &mut self,
+        matched: Matched<T>,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<Self::Returned>, DecodingError> {
+        let copy = Matched {
+            tag: matched.tag.clone(),
+            offset: matched.offset,
+            value: matched.value.clone(),
+        };
+
+        let first = self.first.update(copy, slicer)?;
+        let second = self.second.update(matched, slicer)?;
+
+        Ok(if first.is_some() || second.is_some() {
+            Some((first, second))
+        } else {
+            None
+        })
+    }
+
+    fn min_offset(&self) -> Option<Offset> {
+        match (self.first.min_offset(), self.second.min_offset()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn evict(&mut self, slicer: Slicer<'a>) -> Result<(), DecodingError> {
+        self.first.evict(slicer)?;
+        self.second.evict(slicer)
+    }
 
-                if self.cached_min_offset.is_none() {
-                    // advance to wherever the self.reader advanced to; we will not be using the
-                    // consumed bytes
-                    *buf = tmp;
+    fn next_completed(
+        &mut self,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<Self::Returned>, DecodingError> {
+        let first = self.first.next_completed(slicer)?;
+        let second = self.second.next_completed(slicer)?;
+
+        Ok(if first.is_some() || second.is_some() {
+            Some((first, second))
+        } else {
+            None
+        })
+    }
+}
+
+/// The tag of [`SubGatherer`], telling whether the match is for the parent or for the child
+/// gatherer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubTag<P, C> {
+    Parent(P),
+    Child(C),
+}
+
+/// A parent gatherer given the values returned by the gatherer of its nested messages, see
+/// [`SubGatherer`].
+pub trait ParentGatherer<'a, C>: Gatherer<'a> {
+    /// Records a value returned by the child gatherer. The values borrowing the buffer need to be
+    /// converted before returning, or their ranges held in the `min_offset`, as the buffer can be
+    /// different in the next call.
+    fn update_child(
+        &mut self,
+        child: C,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<Self::Returned>, DecodingError>;
+}
+
+/// Delegates the matches tagged with `SubTag::Child` to the child gatherer of the nested
+/// messages and gives its returned values to the parent gatherer, which is given the matches
+/// tagged with `SubTag::Parent`. Only the values of the parent are returned. Deeper nesting works
+/// by having another `SubGatherer` as the child.
+pub struct SubGatherer<P, C> {
+    parent: P,
+    child: C,
+}
+
+impl<P, C> SubGatherer<P, C> {
+    pub fn new(parent: P, child: C) -> Self {
+        Self { parent, child }
+    }
+
+    pub fn into_inner(self) -> (P, C) {
+        (self.parent, self.child)
+    }
+}
+
+impl<'a, P, C> Gatherer<'a> for SubGatherer<P, C>
+where
+    C: Gatherer<'a>,
+    P: ParentGatherer<'a, C::Returned>,
+{
+    type Tag = SubTag<P::Tag, C::Tag>;
+    type Returned = P::Returned;
+
+    fn update(
+        &mut self,
+        matched: Matched<Self::Tag>,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<Self::Returned>, DecodingError> {
+        let Matched { tag, offset, value } = matched;
+        match tag {
+            SubTag::Parent(tag) => self.parent.update(Matched { tag, offset, value }, slicer),
+            SubTag::Child(tag) => {
+                match self.child.update(Matched { tag, offset, value }, slicer)? {
+                    Some(child) => self.parent.update_child(child, slicer),
+                    None => Ok(None),
                 }
+            }
+        }
+    }
+
+    fn min_offset(&self) -> Option<Offset> {
+        match (self.parent.min_offset(), self.child.min_offset()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn evict(&mut self, slicer: Slicer<'a>) -> Result<(), DecodingError> {
+        self.parent.evict(slicer)?;
+        self.child.evict(slicer)
+    }
+
+    fn next_completed(
+        &mut self,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<Self::Returned>, DecodingError> {
+        if let Some(returned) = self.parent.next_completed(slicer)? {
+            return Ok(Some(returned));
+        }
 
-                return ret;
+        while let Some(child) = self.child.next_completed(slicer)? {
+            if let Some(returned) = self.parent.update_child(child, slicer)? {
+                return Ok(Some(returned));
             }
         }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CallbackGatherer, CollectAll, Collected, GatherOwned, GatheredFields, Gatherer,
+        MultiGatherer, OwnedGatherer, ParentGatherer, RetainedSlice, Slicer, SubGatherer, SubTag,
+    };
+    use crate::matcher_fields::{
+        Action, Cont, EndedMessage, Matched, Matcher, OwnedMatched, OwnedValue, SlicedValue, Value,
+    };
+    use crate::{
+        DecodingError, DecodingErrorKind, FieldId, Introspect, Offset, ReadField, Reader, Status,
+    };
+    use hex_literal::hex;
+    use std::borrow::Cow;
+    use std::ops::Range;
+
+    /// Reads all top level fields, tagged with their field id.
+    struct TopLevel;
+
+    impl Matcher for TopLevel {
+        type Tag = FieldId;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<FieldId>, DecodingError> {
+            Ok(match read.field_id() {
+                // skipped without buffering
+                9 => Action::Skip(9),
+                id if read.is_length_delimited() => Action::Continue(Cont::ReadSlice(id)),
+                id => Action::Continue(Cont::ReadValue(id)),
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<FieldId>>,
+        ) -> Option<FieldId> {
+            None
+        }
+    }
+
+    #[test]
+    fn callback_sees_slices() {
+        let input = hex!("0801 120161 1803 120162");
+        let mut seen = Vec::new();
+
+        let gatherer = CallbackGatherer::new(|m| match m.value {
+            SlicedValue::Slice(_, bytes) => seen.push((m.tag, bytes.to_vec())),
+            _ => seen.push((m.tag, Vec::new())),
+        });
+
+        let mut fields = GatheredFields::new(TopLevel, gatherer);
+        let mut buf = &input[..];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::IdleAtEndOfBuffer))
+        ));
+        assert!(buf.is_empty());
+
+        assert_eq!(
+            seen,
+            vec![
+                (1, vec![]),
+                (2, b"a".to_vec()),
+                (3, vec![]),
+                (2, b"b".to_vec())
+            ]
+        );
+    }
+
+    /// Holds field 1 until field 3 is seen.
+    #[derive(Default)]
+    struct HoldFirst {
+        first: Option<RetainedSlice>,
+    }
+
+    impl<'a> Gatherer<'a> for HoldFirst {
+        type Tag = FieldId;
+        type Returned = Cow<'a, [u8]>;
+
+        fn update(
+            &mut self,
+            matched: Matched<FieldId>,
+            slicer: Slicer<'a>,
+        ) -> Result<Option<Self::Returned>, DecodingError> {
+            Ok(match (matched.tag, matched.value) {
+                (1, Value::Slice(range)) => {
+                    self.first = Some(range.into());
+                    None
+                }
+                (3, _) => self.first.take().map(|first| first.into_cow(&slicer)),
+                _ => None,
+            })
+        }
+
+        fn min_offset(&self) -> Option<Offset> {
+            self.first.as_ref().and_then(|first| first.min_offset())
+        }
+
+        fn evict(&mut self, slicer: Slicer<'a>) -> Result<(), DecodingError> {
+            if let Some(first) = self.first.as_mut() {
+                first.evict(&slicer);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn retention_limit_copies_ranges() {
+        // 1: "abc", 9: 100 bytes, 3: 1
+        let mut input = hex!("0a03616263 4a64").to_vec();
+        input.extend(std::iter::repeat_n(0u8, 100));
+        input.extend(&hex!("1801"));
+
+        // without the limit everything from field 1 is retained
+        let mut fields = GatheredFields::new(TopLevel, HoldFirst::default());
+        let mut buf = &input[..60];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::NeedMoreBytes(_)))
+        ));
+        assert_eq!(buf.len(), 60 - 2);
+
+        let stats = fields.stats();
+        assert_eq!((stats.offset, stats.buffered, stats.items), (60, 58, 0));
+        assert!(stats.mid_field);
+
+        let mut buf = &input[2..];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Ok(Cow::Borrowed(b"abc")))
+        ));
+
+        // with the limit only the bytes of field 1 are
+        let mut fields = GatheredFields::new(TopLevel, HoldFirst::default());
+        fields.set_retention_limit(Some(16));
+        let mut buf = &input[..60];
+        // nothing is retained so the rest of field 9 could be seeked over
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::CanSkip(47)))
+        ));
+        assert!(buf.is_empty());
+
+        let mut buf = &input[60..];
+        match fields.next(&mut buf) {
+            Ok(Ok(Cow::Owned(bytes))) => assert_eq!(bytes, b"abc"),
+            x => panic!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn max_retained_window() {
+        // 1: "abc", 9: 100 bytes, 3: 1
+        let mut input = hex!("0a03616263 4a64").to_vec();
+        input.extend(std::iter::repeat_n(0u8, 100));
+        input.extend(&hex!("1801"));
+
+        // a window of the maximum is fine
+        let mut fields = GatheredFields::new(TopLevel, HoldFirst::default());
+        fields.set_max_retained(Some(58));
+        let mut buf = &input[..60];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::NeedMoreBytes(_)))
+        ));
+
+        // over it reading fails at the start of the window when the gatherer holds on to it
+        let mut fields = GatheredFields::new(TopLevel, HoldFirst::default());
+        fields.set_max_retained(Some(16));
+        let mut buf = &input[..60];
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::GatherWindowExceeded {
+                retained: 58,
+                limit: 16
+            }
+        ));
+        assert_eq!(e.offset(), Some(2));
+
+        // unless the ranges were copied out with the retention limit first
+        let mut fields = GatheredFields::new(TopLevel, HoldFirst::default());
+        fields.set_retention_limit(Some(16));
+        fields.set_max_retained(Some(16));
+        let mut buf = &input[..60];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::CanSkip(47)))
+        ));
+        let mut buf = &input[60..];
+        match fields.next(&mut buf) {
+            Ok(Ok(Cow::Owned(bytes))) => assert_eq!(bytes, b"abc"),
+            x => panic!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn slicer_accessors() {
+        // the buffer ends at offset 10
+        let slicer = Slicer::wrap(&hex!("6162ff 0102030405"), 10);
+
+        assert_eq!(slicer.get_slice(&(2..4)), Some(&b"ab"[..]));
+        assert_eq!(slicer.get_slice(&(0..4)), None);
+        assert_eq!(slicer.get_slice(&(9..11)), None);
+
+        assert_eq!(slicer.as_str(&(2..4)).unwrap(), "ab");
+        let e = slicer.as_str(&(2..5)).unwrap_err();
+        assert!(matches!(e.kind(), DecodingErrorKind::InvalidUtf8));
+        assert_eq!(e.offset(), Some(4));
+
+        assert_eq!(slicer.as_array::<4>(&(6..10)).unwrap(), [2, 3, 4, 5]);
+        let e = slicer.as_array::<4>(&(5..10)).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::UnexpectedLength { len: 5, wanted: 4 }
+        ));
+    }
+
+    #[test]
+    fn chunks_of_every_size() {
+        // 1: "abc", 9: "xyz", 2: 5, 3: 1, 1: "de", 9: "", 3: 2
+        let input = hex!("0a03616263 4a0378797a 1005 1801 0a026465 4a00 1802");
+
+        for chunk in 1..=input.len() {
+            let mut fields = GatheredFields::new(TopLevel, HoldFirst::default());
+            // the bytes given to the next call: the retained window and the unread bytes
+            let mut pending = Vec::new();
+            let mut gathered = Vec::new();
+
+            for bytes in input.chunks(chunk) {
+                pending.extend_from_slice(bytes);
+                loop {
+                    let mut buf = &pending[..];
+                    let ret = fields.next(&mut buf).unwrap();
+                    let kept = buf.len();
+                    let done = match ret {
+                        Ok(first) => {
+                            gathered.push(first.into_owned());
+                            false
+                        }
+                        Err(_) => true,
+                    };
+                    pending.drain(..pending.len() - kept);
+                    if done {
+                        break;
+                    }
+                }
+                // the field being read is kept in the pending bytes as well
+                assert!(fields.stats().buffered as usize <= pending.len());
+            }
+
+            assert_eq!(gathered, [&b"abc"[..], b"de"], "chunk {}", chunk);
+            assert!(pending.is_empty(), "chunk {}", chunk);
+        }
+    }
+
+    #[test]
+    fn callback_sees_skipped_fields() {
+        // 9: "xyz", 2: "a"
+        let input = hex!("4a0378797a 120161");
+        let mut seen = Vec::new();
+
+        let gatherer = CallbackGatherer::new(|m| match m.value {
+            SlicedValue::Slice(_, bytes) => seen.push((m.tag, Some(bytes.to_vec()))),
+            _ => seen.push((m.tag, None)),
+        });
+        let mut fields = GatheredFields::new(TopLevel, gatherer);
+
+        // the skipped field is advanced over before it completes
+        let mut buf = &input[..3];
+        assert!(matches!(fields.next(&mut buf), Ok(Err(Status::CanSkip(2)))));
+        assert!(buf.is_empty());
+
+        let mut buf = &input[3..];
+        assert!(fields.next(&mut buf).unwrap().is_err());
+        drop(fields);
+
+        assert_eq!(seen, [(9, None), (2, Some(b"a".to_vec()))]);
+    }
+
+    /// Holds the fields 2 and returns all of them one by one when field 3 is seen.
+    #[derive(Default)]
+    struct AllAtEnd {
+        held: std::collections::VecDeque<Range<Offset>>,
+        completed: usize,
+    }
+
+    impl<'a> Gatherer<'a> for AllAtEnd {
+        type Tag = FieldId;
+        type Returned = Cow<'a, [u8]>;
+
+        fn update(
+            &mut self,
+            matched: Matched<FieldId>,
+            slicer: Slicer<'a>,
+        ) -> Result<Option<Self::Returned>, DecodingError> {
+            match (matched.tag, matched.value) {
+                (2, Value::Slice(range)) => self.held.push_back(range),
+                (3, _) => {
+                    self.completed = self.held.len();
+                    return self.next_completed(slicer);
+                }
+                _ => {}
+            }
+            Ok(None)
+        }
+
+        fn min_offset(&self) -> Option<Offset> {
+            self.held.front().map(|range| range.start)
+        }
+
+        fn next_completed(
+            &mut self,
+            slicer: Slicer<'a>,
+        ) -> Result<Option<Self::Returned>, DecodingError> {
+            if self.completed == 0 {
+                return Ok(None);
+            }
+            self.completed -= 1;
+            let range = self.held.pop_front().expect("completed are held");
+            Ok(Some(slicer.get(&range)))
+        }
+    }
+
+    #[test]
+    fn many_values_from_one_update() {
+        // 2: "a", 2: "bc", 3: 1, 2: "d", 3: 1
+        let input = hex!("120161 12026263 1801 120164 1801");
+        let mut fields = GatheredFields::new(TopLevel, AllAtEnd::default());
+
+        let mut buf = &input[..];
+        let mut gathered = Vec::new();
+        while let Ok(bytes) = fields.next(&mut buf).unwrap() {
+            gathered.push(bytes.into_owned());
+        }
+
+        assert_eq!(gathered, [&b"a"[..], b"bc", b"d"]);
+        assert_eq!(fields.stats().items, 3);
+        assert!(buf.is_empty());
+    }
+
+    /// Tags the fields of the links in field 2 for the child and the others for the parent.
+    struct FileAndLinks;
+
+    impl Matcher for FileAndLinks {
+        type Tag = SubTag<FieldId, FieldId>;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            path: &[FieldId],
+        ) -> Result<Action<Self::Tag>, DecodingError> {
+            let id = read.field_id();
+            Ok(match (path.is_empty(), id) {
+                (true, 2) if read.is_length_delimited() => Action::Continue(Cont::Message(None)),
+                (true, _) if read.is_length_delimited() => {
+                    Action::Continue(Cont::ReadSlice(SubTag::Parent(id)))
+                }
+                (true, _) => Action::Continue(Cont::ReadValue(SubTag::Parent(id))),
+                (false, _) => Action::Continue(Cont::ReadValue(SubTag::Child(id))),
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            ended: Option<EndedMessage<Self::Tag>>,
+        ) -> Option<Self::Tag> {
+            // 0 for the end of a link
+            ended.map(|_| SubTag::Child(0))
+        }
+    }
+
+    /// Returns the sum of the values of field 1 at the end of a link.
+    #[derive(Default)]
+    struct LinkSize(u64);
+
+    impl<'a> Gatherer<'a> for LinkSize {
+        type Tag = FieldId;
+        type Returned = u64;
+
+        fn update(
+            &mut self,
+            matched: Matched<FieldId>,
+            _slicer: Slicer<'a>,
+        ) -> Result<Option<u64>, DecodingError> {
+            Ok(match (matched.tag, matched.value) {
+                (1, Value::Varint(x)) => {
+                    self.0 += x;
+                    None
+                }
+                (0, _) => Some(std::mem::take(&mut self.0)),
+                _ => None,
+            })
+        }
+
+        fn min_offset(&self) -> Option<Offset> {
+            None
+        }
+    }
+
+    /// Returns the name in field 1 with the sizes of the links when field 3 is seen.
+    #[derive(Default)]
+    struct File {
+        name: Option<Vec<u8>>,
+        sizes: Vec<u64>,
+    }
+
+    impl<'a> Gatherer<'a> for File {
+        type Tag = FieldId;
+        type Returned = (Vec<u8>, Vec<u64>);
+
+        fn update(
+            &mut self,
+            matched: Matched<FieldId>,
+            slicer: Slicer<'a>,
+        ) -> Result<Option<Self::Returned>, DecodingError> {
+            Ok(match (matched.tag, matched.value) {
+                (1, Value::Slice(range)) => {
+                    self.name = Some(slicer.get(&range).into_owned());
+                    None
+                }
+                (3, _) => Some((
+                    self.name.take().unwrap_or_default(),
+                    std::mem::take(&mut self.sizes),
+                )),
+                _ => None,
+            })
+        }
+
+        fn min_offset(&self) -> Option<Offset> {
+            None
+        }
+    }
+
+    impl<'a> ParentGatherer<'a, u64> for File {
+        fn update_child(
+            &mut self,
+            size: u64,
+            _slicer: Slicer<'a>,
+        ) -> Result<Option<Self::Returned>, DecodingError> {
+            self.sizes.push(size);
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn parent_gets_the_values_of_the_child() {
+        // 1: "f", 2: { 1: 3, 1: 4 }, 2: { 1: 5 }, 3: 0, 2: { 1: 1 }, 3: 0
+        let input = hex!("0a0166 1204 0803 0804 1202 0805 1800 1202 0801 1800");
+        let gatherer = SubGatherer::new(File::default(), LinkSize::default());
+        let mut fields = GatheredFields::new(FileAndLinks, gatherer);
+
+        let mut buf = &input[..];
+        let mut files = Vec::new();
+        while let Ok(file) = fields.next(&mut buf).unwrap() {
+            files.push(file);
+        }
+
+        assert_eq!(files, [(b"f".to_vec(), vec![7, 5]), (Vec::new(), vec![1])]);
+    }
+
+    /// Copies field 1 and returns it when field 3 is seen.
+    #[derive(Default)]
+    struct CopyFirst {
+        first: Option<Vec<u8>>,
+    }
+
+    impl GatherOwned for CopyFirst {
+        type Tag = FieldId;
+        type Returned = Vec<u8>;
+
+        fn update_owned(
+            &mut self,
+            matched: OwnedMatched<FieldId>,
+        ) -> Result<Option<Vec<u8>>, DecodingError> {
+            Ok(match (matched.tag, matched.value) {
+                (1, OwnedValue::Slice(_, bytes)) => {
+                    self.first = Some(bytes);
+                    None
+                }
+                (3, _) => self.first.take(),
+                _ => None,
+            })
+        }
+    }
+
+    #[test]
+    fn owned_gatherer_retains_nothing() {
+        // 1: "abc", 9: 100 bytes, 3: 1
+        let mut input = hex!("0a03616263 4a64").to_vec();
+        input.extend(std::iter::repeat_n(0u8, 100));
+        input.extend(&hex!("1801"));
+
+        let mut fields = GatheredFields::new(TopLevel, OwnedGatherer::new(CopyFirst::default()));
+        let mut buf = &input[..60];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::CanSkip(47)))
+        ));
+        assert!(buf.is_empty());
+        assert_eq!(fields.stats().buffered, 0);
+
+        let mut buf = &input[60..];
+        assert_eq!(fields.next(&mut buf).unwrap().unwrap(), b"abc");
+    }
+
+    #[test]
+    fn collect_all_until_the_end() {
+        // 2: "a", 1: 5, 2: "bc", 3: 1, 3: 1
+        let input = hex!("120161 0805 12026263 1801 1801");
+
+        for copy in [false, true] {
+            let mut gatherer = CollectAll::new(2, 3);
+            gatherer.set_copy_slices(copy);
+            let mut fields = GatheredFields::new(TopLevel, gatherer);
+
+            // field 2 is retained only without copying
+            let mut buf = &input[..5];
+            assert!(fields.next(&mut buf).unwrap().is_err());
+            assert_eq!(fields.stats().buffered, if copy { 0 } else { 3 });
+
+            let mut buf = &input[5 - buf.len()..];
+            let collected = fields.next(&mut buf).unwrap().unwrap();
+            let slices = collected
+                .iter()
+                .map(|c| match c {
+                    Collected::Slice(offset, bytes) => {
+                        assert_eq!(matches!(bytes, Cow::Owned(_)), copy);
+                        (*offset, bytes.to_vec())
+                    }
+                    other => panic!("unexpected {:?}", other),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(slices, [(0, b"a".to_vec()), (5, b"bc".to_vec())]);
+
+            // nothing is collected for the second end
+            assert!(fields.next(&mut buf).unwrap().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn multiple_gatherers_in_one_pass() {
+        // 2: "a", 1: 5, 2: "bc", 3: 1
+        let input = hex!("120161 0805 12026263 1801");
+        let mut matches = 0;
+
+        let gatherer = MultiGatherer::new(
+            CollectAll::new(2, 3),
+            CallbackGatherer::new(|_| matches += 1),
+        );
+        let mut fields = GatheredFields::new(TopLevel, gatherer);
+
+        // retained for the collected slice
+        let mut buf = &input[..5];
+        assert!(fields.next(&mut buf).unwrap().is_err());
+        assert_eq!(fields.stats().buffered, 3);
+
+        let mut buf = &input[5 - buf.len()..];
+        let (collected, nothing) = fields.next(&mut buf).unwrap().unwrap();
+        assert_eq!(collected.map(|c| c.len()), Some(2));
+        assert!(nothing.is_none());
+
+        drop(fields);
+        assert_eq!(matches, 4);
     }
 }
@@ -0,0 +1,168 @@
+//! gRPC message framing: a one byte compressed flag followed by a 4-byte big-endian length and the
+//! message.
+//!
+//! [`GrpcFrames`] splits a captured request or response body into its frames, keeping the
+//! compressed ones for the caller to decompress, so that the matchers can be run over the
+//! messages. [`write_frame`] writes a frame. To read the uncompressed frames of a stream with a
+//! reader per message use [`crate::framing::FramedMessages`] with `Framing::Grpc`.
+
+use crate::framing::{read_header, FrameHeader, Framing};
+use crate::DecodingError;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// The length of the frame header.
+pub const HEADER_LEN: usize = 5;
+
+/// A single frame of [`GrpcFrames`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrpcFrame<'a> {
+    /// Offset of the frame header in the body
+    pub offset: usize,
+    /// True if the message is compressed with the encoding given in the `grpc-encoding` header
+    pub compressed: bool,
+    pub message: &'a [u8],
+}
+
+/// Iterates the complete frames of a body, stopping at the first incomplete one which is left in
+/// `rest`. An invalid compressed flag is returned as
+/// `DecodingErrorKind::InvalidGrpcCompressedFlag` with the offset of the frame, after which the
+/// iteration ends, and so is a frame over the limit like with
+/// [`crate::framing::FramedMessages`].
+pub struct GrpcFrames<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    max_frame_len: Option<u64>,
+    failed: bool,
+}
+
+impl<'a> GrpcFrames<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        GrpcFrames {
+            buf,
+            offset: 0,
+            max_frame_len: None,
+            failed: false,
+        }
+    }
+
+    /// Sets the maximum accepted message length, see `FramedMessages::set_max_frame_len`.
+    pub fn set_max_frame_len(&mut self, limit: Option<u64>) {
+        self.max_frame_len = limit;
+    }
+
+    /// The bytes after the complete frames read so far: the incomplete frame at the end of a
+    /// partially captured body, or empty.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.buf[self.offset..]
+    }
+}
+
+impl<'a> Iterator for GrpcFrames<'a> {
+    type Item = Result<GrpcFrame<'a>, DecodingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let rest = self.rest();
+        let offset = self.offset as u64;
+        let (prefix, header) = match read_header(Framing::Grpc, rest, offset, self.max_frame_len) {
+            Ok(Ok(read)) => read,
+            Ok(Err(_)) => return None,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+
+        let len = usize::try_from(header.len).ok()?;
+        let message = rest.get(prefix..)?.get(..len)?;
+
+        let frame = GrpcFrame {
+            offset: self.offset,
+            compressed: header.compressed,
+            message,
+        };
+        self.offset += prefix + len;
+        Some(Ok(frame))
+    }
+}
+
+/// Writes a frame of the message, which the caller has compressed when `compressed` is true.
+/// Errors with `io::ErrorKind::InvalidInput` if the message is longer than the 4-byte length allows.
+pub fn write_frame<W: Write>(compressed: bool, message: &[u8], mut out: W) -> io::Result<()> {
+    let header = FrameHeader {
+        len: message.len() as u64,
+        compressed,
+    };
+    let mut prefix = [0u8; 10];
+    let len = Framing::Grpc
+        .encode_header(header, &mut prefix)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "message is too long"))?;
+
+    out.write_all(&prefix[..len])?;
+    out.write_all(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_frame, GrpcFrame, GrpcFrames};
+    use crate::DecodingErrorKind;
+    use hex_literal::hex;
+
+    #[test]
+    fn frames_of_a_partial_body() {
+        let mut body = Vec::new();
+        write_frame(false, &hex!("0801"), &mut body).unwrap();
+        write_frame(true, b"gz", &mut body).unwrap();
+        write_frame(false, &hex!("1203616263"), &mut body).unwrap();
+        assert_eq!(body[..7], hex!("0000000002 0801"));
+
+        let truncated = &body[..body.len() - 1];
+        let mut frames = GrpcFrames::new(truncated);
+        assert_eq!(
+            frames.by_ref().collect::<Result<Vec<_>, _>>().unwrap(),
+            [
+                GrpcFrame {
+                    offset: 0,
+                    compressed: false,
+                    message: &hex!("0801")[..],
+                },
+                GrpcFrame {
+                    offset: 7,
+                    compressed: true,
+                    message: b"gz",
+                },
+            ]
+        );
+        assert_eq!(frames.rest(), &body[14..body.len() - 1]);
+    }
+
+    #[test]
+    fn invalid_compressed_flag() {
+        let body = hex!("0000000000 0200000000");
+        let mut frames = GrpcFrames::new(&body);
+
+        assert!(frames.next().unwrap().is_ok());
+        let e = frames.next().unwrap().unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::InvalidGrpcCompressedFlag(2)
+        ));
+        assert_eq!(e.offset(), Some(5));
+        assert!(frames.next().is_none());
+
+        let body = hex!("0000000000 0100000003 677a");
+        let mut frames = GrpcFrames::new(&body);
+        frames.set_max_frame_len(Some(2));
+        assert!(frames.next().unwrap().is_ok());
+        let e = frames.next().unwrap().unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::FrameTooLarge { len: 3, limit: 2 }
+        ));
+        assert_eq!(e.offset(), Some(5));
+    }
+}
@@ -0,0 +1,503 @@
+//! Guessing the schema of messages from the wire data alone.
+//!
+//! The wire format carries only the field numbers and wire types, so the types are guessed from
+//! the values seen over a corpus of messages: length delimited fields which parse as messages are
+//! likely submessages and printable UTF-8 is likely a string, varints holding only zeroes and ones
+//! are likely booleans and fixed width values which make sense as floating point numbers are
+//! likely floats or doubles. Every guess comes with a confidence between 0 and 1.
+//!
+//! Note that there is no way to tell `uint64` and `sint64` apart on the wire; the zigzag guess is
+//! only made more likely by values with the lowest bit set, as the negative values map to odd
+//! numbers.
+
+use crate::field_reader::FieldReader;
+use crate::pb::read_varint64;
+use crate::{FieldId, FieldValue};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub mod drift;
+
+/// How deep the nested messages are inspected.
+const MAX_DEPTH: usize = 32;
+
+/// The guessed type of a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum InferredType {
+    Message,
+    String,
+    Bytes,
+    /// Length delimited field which parses fully as varints
+    PackedVarint,
+    UInt64,
+    Int64,
+    SInt64,
+    Bool,
+    Fixed32,
+    Float,
+    Fixed64,
+    Double,
+}
+
+/// A single guess for the type of a field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub kind: InferredType,
+    /// Between 0 and 1, higher is more likely
+    pub confidence: f32,
+}
+
+/// The inferred structure of a message.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct InferredMessage {
+    /// How many instances of this message were inspected
+    pub instances: u64,
+    /// Fields ordered by the field id
+    pub fields: Vec<InferredField>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InferredField {
+    pub id: FieldId,
+    /// Total amount of times this field was seen
+    pub occurrences: u64,
+    /// Amount of message instances which had this field at least once
+    pub present_in: u64,
+    /// True if any message instance had this field more than once
+    pub repeated: bool,
+    /// The guesses ordered from the most likely, never empty
+    pub candidates: Vec<Candidate>,
+    /// The nested structure when the most likely type is [`InferredType::Message`]
+    pub message: Option<InferredMessage>,
+}
+
+impl InferredField {
+    /// The most likely type.
+    pub fn kind(&self) -> InferredType {
+        self.candidates[0].kind
+    }
+}
+
+/// The input passed to [`SchemaInferrer::add_message`] could not be parsed as a message.
+#[derive(Debug, PartialEq)]
+pub struct NotAMessage;
+
+impl fmt::Display for NotAMessage {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "input could not be parsed as a message")
+    }
+}
+
+impl std::error::Error for NotAMessage {}
+
+/// Collects statistics over complete messages for guessing their schema.
+#[derive(Debug, Default)]
+pub struct SchemaInferrer {
+    root: MessageStats,
+}
+
+#[derive(Debug, Default)]
+struct MessageStats {
+    instances: u64,
+    fields: BTreeMap<FieldId, FieldStats>,
+}
+
+#[derive(Debug, Default)]
+struct FieldStats {
+    present_in: u64,
+    repeated_in: u64,
+    occurrences: u64,
+
+    varints: u64,
+    varint_max: u64,
+    /// Varints with the highest bit set, which are negative `int64` or `int32`
+    varint_negative: u64,
+    varint_odd: u64,
+
+    fixed32: u64,
+    plausible_f32: u64,
+
+    fixed64: u64,
+    plausible_f64: u64,
+
+    length_delimited: u64,
+    messages: u64,
+    strings: u64,
+    packed: u64,
+
+    /// Statistics of the occurrences which parsed as messages
+    nested: MessageStats,
+}
+
+impl SchemaInferrer {
+    /// Adds a complete message to the corpus.
+    pub fn add_message(&mut self, message: &[u8]) -> Result<(), NotAMessage> {
+        if !is_message(message) {
+            return Err(NotAMessage);
+        }
+        self.root.add(message, 0);
+        Ok(())
+    }
+
+    /// Amount of messages added.
+    pub fn messages(&self) -> u64 {
+        self.root.instances
+    }
+
+    /// Guesses the schema from the messages added so far.
+    pub fn infer(&self) -> InferredMessage {
+        self.root.infer()
+    }
+}
+
+impl MessageStats {
+    /// Adds a message which has already been checked with `is_message`.
+    fn add(&mut self, buf: &[u8], depth: usize) {
+        self.instances += 1;
+
+        let mut reader = FieldReader::default();
+        let mut at = 0;
+        let mut counts = BTreeMap::<FieldId, u64>::new();
+
+        while at < buf.len() {
+            let read = match reader.next(&buf[at..]) {
+                Ok(Ok(read)) => read,
+                _ => unreachable!("message was checked before"),
+            };
+
+            let start = at + read.consumed();
+            let end = start
+                .checked_add(read.field_len())
+                .expect("message was checked before");
+
+            *counts.entry(read.field_id()).or_default() += 1;
+            let stats = self.fields.entry(read.field_id()).or_default();
+            stats.occurrences += 1;
+
+            match *read.value() {
+                FieldValue::Varint(x) => {
+                    stats.varints += 1;
+                    stats.varint_max = stats.varint_max.max(x);
+                    stats.varint_negative += x >> 63;
+                    stats.varint_odd += x & 1;
+                }
+                FieldValue::Fixed32(x) => {
+                    stats.fixed32 += 1;
+                    stats.plausible_f32 += plausible_float(f32::from_bits(x) as f64) as u64;
+                }
+                FieldValue::Fixed64(x) => {
+                    stats.fixed64 += 1;
+                    stats.plausible_f64 += plausible_float(f64::from_bits(x)) as u64;
+                }
+                FieldValue::DataLength(_) => {
+                    let slice = &buf[start..end];
+                    stats.length_delimited += 1;
+                    stats.strings += is_text(slice) as u64;
+                    stats.packed += is_packed_varints(slice) as u64;
+
+                    if depth < MAX_DEPTH && is_message(slice) {
+                        stats.messages += 1;
+                        stats.nested.add(slice, depth + 1);
+                    }
+                }
+                #[cfg(feature = "groups")]
+                FieldValue::StartGroup | FieldValue::EndGroup => {
+                    unreachable!("message was checked before")
+                }
+            }
+
+            at = end;
+        }
+
+        for (id, count) in counts {
+            let stats = self.fields.get_mut(&id).expect("inserted above");
+            stats.present_in += 1;
+            stats.repeated_in += (count > 1) as u64;
+        }
+    }
+
+    fn infer(&self) -> InferredMessage {
+        InferredMessage {
+            instances: self.instances,
+            fields: self
+                .fields
+                .iter()
+                .map(|(id, stats)| stats.infer(*id))
+                .collect(),
+        }
+    }
+}
+
+impl FieldStats {
+    fn infer(&self, id: FieldId) -> InferredField {
+        use InferredType::*;
+
+        let mut candidates = Vec::new();
+        let total = self.occurrences as f32;
+        let mut push = |kind, confidence: f32| {
+            if confidence > 0.0 {
+                candidates.push(Candidate { kind, confidence });
+            }
+        };
+
+        if self.varints > 0 {
+            let share = self.varints as f32 / total;
+            if self.varint_max <= 1 {
+                push(Bool, share);
+                push(UInt64, share * 0.5);
+            } else if self.varint_negative > 0 {
+                push(Int64, share);
+                push(UInt64, share * 0.3);
+            } else {
+                let odd = self.varint_odd as f32 / self.varints as f32;
+                push(UInt64, share * (1.0 - odd / 2.0));
+                push(SInt64, share * odd / 2.0);
+            }
+        }
+
+        if self.fixed32 > 0 {
+            let share = self.fixed32 as f32 / total;
+            let floats = self.plausible_f32 as f32 / self.fixed32 as f32;
+            push(Float, share * floats);
+            push(Fixed32, share * (1.0 - floats));
+        }
+
+        if self.fixed64 > 0 {
+            let share = self.fixed64 as f32 / total;
+            let floats = self.plausible_f64 as f32 / self.fixed64 as f32;
+            push(Double, share * floats);
+            push(Fixed64, share * (1.0 - floats));
+        }
+
+        if self.length_delimited > 0 {
+            let share = self.length_delimited as f32 / total;
+            let len = self.length_delimited as f32;
+            let messages = self.messages as f32 / len;
+            let strings = self.strings as f32 / len;
+            push(Message, share * messages);
+            push(String, share * strings);
+            // ascii text and many messages parse as varints as well
+            push(PackedVarint, share * self.packed as f32 / len * 0.5);
+            // anything can be bytes
+            push(Bytes, share * (1.0 - messages.max(strings)).max(0.1));
+        }
+
+        // stable sort keeps the more specific guess first on ties
+        candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+        let message = if candidates[0].kind == Message {
+            Some(self.nested.infer())
+        } else {
+            None
+        };
+
+        InferredField {
+            id,
+            occurrences: self.occurrences,
+            present_in: self.present_in,
+            repeated: self.repeated_in > 0,
+            candidates,
+            message,
+        }
+    }
+}
+
+impl InferredType {
+    /// The type to use in a `.proto` file.
+    pub fn proto_type(&self) -> &'static str {
+        use InferredType::*;
+        match self {
+            Message => "message",
+            String => "string",
+            Bytes => "bytes",
+            PackedVarint | UInt64 => "uint64",
+            Int64 => "int64",
+            SInt64 => "sint64",
+            Bool => "bool",
+            Fixed32 => "fixed32",
+            Float => "float",
+            Fixed64 => "fixed64",
+            Double => "double",
+        }
+    }
+}
+
+impl InferredMessage {
+    /// Writes a best effort `proto3` definition of this message with the given name. The fields
+    /// are named after their ids and the nested messages are defined within their parents; the
+    /// less likely guesses are left as comments.
+    pub fn write_proto<W: fmt::Write>(&self, out: &mut W, name: &str) -> fmt::Result {
+        writeln!(out, "syntax = \"proto3\";")?;
+        writeln!(out)?;
+        self.write_message(out, name, 0)
+    }
+
+    fn write_message<W: fmt::Write>(&self, out: &mut W, name: &str, depth: usize) -> fmt::Result {
+        let indent = depth * 2;
+        writeln!(out, "{:indent$}message {} {{", "", name, indent = indent)?;
+
+        for field in &self.fields {
+            if let Some(nested) = field.message.as_ref() {
+                nested.write_message(out, &format!("Message{}", field.id), depth + 1)?;
+            }
+        }
+
+        for field in &self.fields {
+            let kind = field.kind();
+            let type_name = match kind {
+                InferredType::Message => format!("Message{}", field.id),
+                other => other.proto_type().to_owned(),
+            };
+            let repeated = field.repeated || kind == InferredType::PackedVarint;
+
+            write!(
+                out,
+                "{:indent$}{}{} field_{} = {};",
+                "",
+                if repeated { "repeated " } else { "" },
+                type_name,
+                field.id,
+                field.id,
+                indent = indent + 2
+            )?;
+
+            write!(out, " // {:.2}", field.candidates[0].confidence)?;
+            for candidate in &field.candidates[1..] {
+                write!(out, ", {:?} {:.2}", candidate.kind, candidate.confidence)?;
+            }
+            writeln!(out)?;
+        }
+
+        writeln!(out, "{:indent$}}}", "", indent = indent)
+    }
+}
+
+/// Returns true if all of the buffer parses as fields, recursing no further.
+pub(crate) fn is_message(buf: &[u8]) -> bool {
+    let mut reader = FieldReader::default();
+    let mut at = 0;
+
+    while at < buf.len() {
+        match reader.next(&buf[at..]) {
+            #[cfg(feature = "groups")]
+            Ok(Ok(read))
+                if matches!(read.value(), FieldValue::StartGroup | FieldValue::EndGroup) =>
+            {
+                return false
+            }
+            Ok(Ok(read))
+                if read.field_id() != 0
+                    && at
+                        .checked_add(read.bytes_to_skip())
+                        .is_some_and(|end| end <= buf.len()) =>
+            {
+                at += read.bytes_to_skip();
+            }
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+pub(crate) fn is_text(buf: &[u8]) -> bool {
+    match std::str::from_utf8(buf) {
+        Ok(s) => !s
+            .chars()
+            .any(|c| c.is_control() && c != '\n' && c != '\r' && c != '\t'),
+        Err(_) => false,
+    }
+}
+
+fn is_packed_varints(mut buf: &[u8]) -> bool {
+    if buf.is_empty() {
+        return false;
+    }
+    while !buf.is_empty() {
+        match read_varint64(buf) {
+            Ok(Ok((consumed, _))) => buf = &buf[consumed..],
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Floating point values which are zero or of moderate magnitude are likely floats, while
+/// integers reinterpreted as floats tend to be tiny or huge.
+fn plausible_float(x: f64) -> bool {
+    x == 0.0 || (x.is_finite() && (1e-9..1e12).contains(&x.abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InferredType, SchemaInferrer};
+    use hex_literal::hex;
+
+    #[test]
+    fn infers_nested_and_repeated() {
+        let mut inferrer = SchemaInferrer::default();
+        // { 1: "hello", 2: { 1: 1, 1: 0 }, 3: -1 as int64, 4: 1.5f64 }
+        inferrer
+            .add_message(&hex!(
+                "0a0568656c6c6f 1204 0801 0800 18ffffffffffffffffff01 21000000000000f83f"
+            ))
+            .unwrap();
+        // { 1: "world", 2: { 1: 0 } }
+        inferrer
+            .add_message(&hex!("0a05776f726c64 1202 0800"))
+            .unwrap();
+
+        assert!(inferrer.add_message(&hex!("0a05")).is_err());
+        assert_eq!(inferrer.messages(), 2);
+
+        let schema = inferrer.infer();
+        let kinds = schema
+            .fields
+            .iter()
+            .map(|f| (f.id, f.kind()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                (1, InferredType::String),
+                (2, InferredType::Message),
+                (3, InferredType::Int64),
+                (4, InferredType::Double),
+            ]
+        );
+
+        assert_eq!(schema.fields[0].present_in, 2);
+        assert_eq!(schema.fields[3].present_in, 1);
+
+        let nested = schema.fields[1].message.as_ref().unwrap();
+        assert_eq!(nested.instances, 2);
+        assert_eq!(nested.fields[0].kind(), InferredType::Bool);
+        assert!(nested.fields[0].repeated);
+        assert!(!schema.fields[0].repeated);
+    }
+
+    #[test]
+    fn proto_skeleton() {
+        let mut inferrer = SchemaInferrer::default();
+        // { 1: "a", 2: { 1: 1, 1: 300 } }
+        inferrer
+            .add_message(&hex!("0a0161 1205 0801 08ac02"))
+            .unwrap();
+
+        let mut out = String::new();
+        inferrer.infer().write_proto(&mut out, "Root").unwrap();
+
+        let expected = "\
+syntax = \"proto3\";
+
+message Root {
+  message Message2 {
+    repeated uint64 field_1 = 1; // 0.75, SInt64 0.25
+  }
+  string field_1 = 1; // 1.00, PackedVarint 0.50, Bytes 0.10
+  Message2 field_2 = 2; // 1.00, PackedVarint 0.50, Bytes 0.10
+}
+";
+        assert_eq!(out, expected);
+    }
+}
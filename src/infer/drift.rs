@@ -0,0 +1,191 @@
+//! Comparing the inferred schemas of two corpora, for example of the messages produced yesterday
+//! and today.
+
+use super::{InferredMessage, InferredType};
+use crate::{FieldId, WireType};
+use std::cmp::Ordering;
+
+/// A single difference between the baseline and the current schema.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Drift {
+    /// The field was seen in the baseline but not in the current corpus
+    Missing {
+        path: Vec<FieldId>,
+        kind: InferredType,
+    },
+    /// The field was not seen in the baseline
+    Added {
+        path: Vec<FieldId>,
+        kind: InferredType,
+    },
+    /// The most likely type differs
+    TypeChanged {
+        path: Vec<FieldId>,
+        baseline: InferredType,
+        current: InferredType,
+    },
+    /// The field became repeated or stopped being repeated
+    RepeatedChanged {
+        path: Vec<FieldId>,
+        baseline: bool,
+        current: bool,
+    },
+}
+
+impl Drift {
+    pub fn path(&self) -> &[FieldId] {
+        match self {
+            Drift::Missing { path, .. }
+            | Drift::Added { path, .. }
+            | Drift::TypeChanged { path, .. }
+            | Drift::RepeatedChanged { path, .. } => path,
+        }
+    }
+
+    /// True if the type changed so that the values can no longer be decoded the same way; guesses
+    /// within the same wire type, such as `bool` and `uint64`, are compatible.
+    pub fn is_incompatible(&self) -> bool {
+        match self {
+            Drift::TypeChanged {
+                baseline, current, ..
+            } => baseline.wire_type() != current.wire_type(),
+            _ => false,
+        }
+    }
+}
+
+impl InferredType {
+    /// The wire type the values of this type are encoded with.
+    pub fn wire_type(&self) -> WireType {
+        use InferredType::*;
+        match self {
+            Message | String | Bytes | PackedVarint => WireType::LengthDelimited,
+            UInt64 | Int64 | SInt64 | Bool => WireType::Varint,
+            Fixed32 | Float => WireType::Fixed32,
+            Fixed64 | Double => WireType::Fixed64,
+        }
+    }
+}
+
+/// Compares the two schemas recursively, returning the differences ordered by path.
+pub fn compare(baseline: &InferredMessage, current: &InferredMessage) -> Vec<Drift> {
+    let mut ret = Vec::new();
+    let mut path = Vec::new();
+    compare_messages(baseline, current, &mut path, &mut ret);
+    ret
+}
+
+fn compare_messages(
+    baseline: &InferredMessage,
+    current: &InferredMessage,
+    path: &mut Vec<FieldId>,
+    out: &mut Vec<Drift>,
+) {
+    let mut a = baseline.fields.iter().peekable();
+    let mut b = current.fields.iter().peekable();
+
+    // both are ordered by the field id
+    loop {
+        let order = match (a.peek(), b.peek()) {
+            (None, None) => return,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(x), Some(y)) => x.id.cmp(&y.id),
+        };
+
+        let (x, y) = match order {
+            Ordering::Less => {
+                let x = a.next().unwrap();
+                path.push(x.id);
+                out.push(Drift::Missing {
+                    path: path.clone(),
+                    kind: x.kind(),
+                });
+                path.pop();
+                continue;
+            }
+            Ordering::Greater => {
+                let y = b.next().unwrap();
+                path.push(y.id);
+                out.push(Drift::Added {
+                    path: path.clone(),
+                    kind: y.kind(),
+                });
+                path.pop();
+                continue;
+            }
+            Ordering::Equal => (a.next().unwrap(), b.next().unwrap()),
+        };
+
+        path.push(x.id);
+
+        if x.kind() != y.kind() {
+            out.push(Drift::TypeChanged {
+                path: path.clone(),
+                baseline: x.kind(),
+                current: y.kind(),
+            });
+        }
+
+        if x.repeated != y.repeated {
+            out.push(Drift::RepeatedChanged {
+                path: path.clone(),
+                baseline: x.repeated,
+                current: y.repeated,
+            });
+        }
+
+        if let (Some(x), Some(y)) = (x.message.as_ref(), y.message.as_ref()) {
+            compare_messages(x, y, path, out);
+        }
+
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, Drift};
+    use crate::infer::{InferredType, SchemaInferrer};
+    use hex_literal::hex;
+
+    #[test]
+    fn reports_drift() {
+        let mut baseline = SchemaInferrer::default();
+        // { 1: "a", 2: { 1: 1 }, 3: 1 }
+        baseline.add_message(&hex!("0a0161 12020801 1801")).unwrap();
+
+        let mut current = SchemaInferrer::default();
+        // { 1: 5, 2: { 1: 1, 1: 0, 2: 1.0f32 } }
+        current
+            .add_message(&hex!("0805 1209 0801 0800 150000803f"))
+            .unwrap();
+
+        let drift = compare(&baseline.infer(), &current.infer());
+        assert_eq!(
+            drift,
+            vec![
+                Drift::TypeChanged {
+                    path: vec![1],
+                    baseline: InferredType::String,
+                    current: InferredType::UInt64,
+                },
+                Drift::RepeatedChanged {
+                    path: vec![2, 1],
+                    baseline: false,
+                    current: true,
+                },
+                Drift::Added {
+                    path: vec![2, 2],
+                    kind: InferredType::Float,
+                },
+                Drift::Missing {
+                    path: vec![3],
+                    kind: InferredType::Bool,
+                },
+            ]
+        );
+        assert!(drift[0].is_incompatible());
+        assert!(!drift[1].is_incompatible());
+    }
+}
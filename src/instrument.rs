@@ -0,0 +1,426 @@
+//! Wrapping [`Matcher`] adapter for finding out where the time and bytes go.
+
+use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher};
+use crate::{DecodingError, FieldId, ReadField};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::Discriminant;
+use std::time::{Duration, Instant};
+
+/// Wraps a [`Matcher`] and accumulates statistics per tag variant (and decision) of the wrapped
+/// matcher. Tags are grouped by their enum variant, so `Tag::Extra(1)` and `Tag::Extra(2)` end up
+/// in the same bucket, labeled after the first one seen.
+///
+/// Two kinds of time are recorded:
+///
+///  * time spent inside the wrapped matcher methods
+///  * wall clock time from a decision until the next call to the matcher, which will include any
+///    buffering, skipping and the time spent by the caller processing the matched value
+///
+/// The latter is the interesting one when looking for accidentally buffered giant fields.
+pub struct InstrumentedMatcher<M: Matcher> {
+    inner: M,
+    stats: HashMap<Key<M::Tag>, TagStats>,
+    /// The previous decision and the time it was made at
+    pending: Option<(Key<M::Tag>, Instant)>,
+}
+
+/// Without a `Hash` bound on the `Matcher::Tag` the best we can do is to group by variant.
+type Key<T> = (Decision, Option<Discriminant<T>>);
+
+/// The kind of decision a tag was returned with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Decision {
+    Message,
+    ReadSlice,
+    ReadString,
+    ReadPartialSlice,
+    ReadValue,
+    ReadPacked,
+    CaptureRaw,
+    Skip,
+    /// Returned from `Matcher::decide_after`
+    After,
+    /// Returned from `Matcher::end_group`
+    #[cfg(feature = "groups")]
+    EndGroup,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Decision::*;
+        let s = match self {
+            Message => "message",
+            ReadSlice => "slice",
+            ReadString => "string",
+            ReadPartialSlice => "partial_slice",
+            ReadValue => "value",
+            ReadPacked => "packed",
+            CaptureRaw => "raw",
+            Skip => "skip",
+            After => "after",
+            #[cfg(feature = "groups")]
+            EndGroup => "end_group",
+        };
+        fmt.write_str(s)
+    }
+}
+
+/// Accumulated statistics for a single tag variant and decision.
+#[derive(Debug, Clone)]
+pub struct TagStats {
+    /// `Debug` formatting of the first tag seen, or `None` for untagged messages
+    pub label: Option<String>,
+    /// The decision this tag was returned with
+    pub decision: Decision,
+    /// How many times this tag was returned
+    pub count: u64,
+    /// Total bytes of the decided fields, including the tag and the length prefix. Nested
+    /// messages only count their header bytes, as their contents are accounted for by the nested
+    /// fields.
+    pub bytes: u64,
+    /// Time spent inside the wrapped matcher
+    pub in_matcher: Duration,
+    /// Time from the decision until the next call to the matcher
+    pub elapsed: Duration,
+}
+
+impl<M: Matcher> InstrumentedMatcher<M>
+where
+    M::Tag: fmt::Debug,
+{
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            stats: HashMap::new(),
+            pending: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Returns the statistics gathered so far, the most time consuming first.
+    pub fn summary(&self) -> Summary {
+        let mut entries = self.stats.values().cloned().collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.elapsed.cmp(&a.elapsed).then(b.bytes.cmp(&a.bytes)));
+        Summary { entries }
+    }
+
+    /// Attributes the time since the last decision for the last decision.
+    fn close_pending(&mut self, now: Instant) {
+        if let Some((key, since)) = self.pending.take() {
+            if let Some(stats) = self.stats.get_mut(&key) {
+                stats.elapsed += now.duration_since(since);
+            }
+        }
+    }
+
+    fn record(
+        &mut self,
+        decision: Decision,
+        tag: Option<&M::Tag>,
+        bytes: u64,
+        in_matcher: Duration,
+        now: Instant,
+    ) {
+        let key = (decision, tag.map(std::mem::discriminant));
+        let stats = self.stats.entry(key).or_insert_with(|| TagStats {
+            label: tag.map(|t| format!("{:?}", t)),
+            decision,
+            count: 0,
+            bytes: 0,
+            in_matcher: Duration::default(),
+            elapsed: Duration::default(),
+        });
+        stats.count += 1;
+        stats.bytes += bytes;
+        stats.in_matcher += in_matcher;
+        self.pending = Some((key, now));
+    }
+}
+
+impl<M: Matcher> Matcher for InstrumentedMatcher<M>
+where
+    M::Tag: fmt::Debug,
+{
+    type Tag = M::Tag;
+
+    fn decide_before(
+        &mut self,
+        offset: usize,
+        read: &ReadField<'_>,
+        path: &[FieldId],
+    ) -> Result<Action<Self::Tag>, DecodingError> {
+        let started = Instant::now();
+        self.close_pending(started);
+
+        let action = self.inner.decide_before(offset, read, path)?;
+
+        let now = Instant::now();
+        let in_matcher = now.duration_since(started);
+
+        let (decision, tag, bytes) = match &action {
+            Action::Continue(Cont::Message(tag)) => {
+                (Decision::Message, tag.as_ref(), read.consumed())
+            }
+            Action::Continue(Cont::ReadSlice(tag)) => {
+                (Decision::ReadSlice, Some(tag), read.bytes_to_skip())
+            }
+            Action::Continue(Cont::ReadString(tag)) => {
+                (Decision::ReadString, Some(tag), read.bytes_to_skip())
+            }
+            Action::Continue(Cont::ReadPartialSlice(tag)) => {
+                (Decision::ReadPartialSlice, Some(tag), read.bytes_to_skip())
+            }
+            Action::Continue(Cont::ReadValue(tag)) => {
+                (Decision::ReadValue, Some(tag), read.bytes_to_skip())
+            }
+            Action::Continue(Cont::ReadPacked(tag, _)) => {
+                (Decision::ReadPacked, Some(tag), read.bytes_to_skip())
+            }
+            Action::Continue(Cont::CaptureRaw(tag)) => {
+                (Decision::CaptureRaw, Some(tag), read.bytes_to_skip())
+            }
+            Action::Skip(tag) => (Decision::Skip, Some(tag), read.bytes_to_skip()),
+        };
+
+        self.record(decision, tag, bytes as u64, in_matcher, now);
+
+        Ok(action)
+    }
+
+    fn decide_after(
+        &mut self,
+        offset: usize,
+        ended: Option<EndedMessage<Self::Tag>>,
+    ) -> Option<Self::Tag> {
+        let started = Instant::now();
+        self.close_pending(started);
+
+        let tag = self.inner.decide_after(offset, ended);
+
+        let now = Instant::now();
+        if tag.is_some() {
+            self.record(
+                Decision::After,
+                tag.as_ref(),
+                0,
+                now.duration_since(started),
+                now,
+            );
+        }
+
+        tag
+    }
+
+    #[cfg(feature = "groups")]
+    fn end_group(&mut self, offset: usize, field: FieldId) -> Option<Self::Tag> {
+        use crate::pb::{tag, varint_len};
+        use crate::WireType;
+
+        let started = Instant::now();
+        self.close_pending(started);
+
+        let marker = self.inner.end_group(offset, field);
+
+        let now = Instant::now();
+        if marker.is_some() {
+            // the end group tag, like the start group tag is counted for `Decision::Message`
+            let bytes = varint_len(tag(field, WireType::EndGroup));
+            self.record(
+                Decision::EndGroup,
+                marker.as_ref(),
+                bytes as u64,
+                now.duration_since(started),
+                now,
+            );
+        }
+
+        marker
+    }
+}
+
+/// Statistics gathered by [`InstrumentedMatcher`], ordered by the most time consuming tag first.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    entries: Vec<TagStats>,
+}
+
+impl Summary {
+    pub fn entries(&self) -> &[TagStats] {
+        &self.entries
+    }
+
+    pub fn total_elapsed(&self) -> Duration {
+        self.entries.iter().map(|e| e.elapsed).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(|e| e.bytes).sum()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            fmt,
+            "{:>12} {:>12} {:>14} {:>14}  {:<8} tag",
+            "count", "bytes", "elapsed", "in matcher", "decision"
+        )?;
+        for e in &self.entries {
+            writeln!(
+                fmt,
+                "{:>12} {:>12} {:>14?} {:>14?}  {:<8} {}",
+                e.count,
+                e.bytes,
+                e.elapsed,
+                e.in_matcher,
+                e.decision,
+                e.label.as_deref().unwrap_or("(untagged)")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decision, InstrumentedMatcher};
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields};
+    use crate::{DecodingError, FieldId, FieldValue, ReadField, Reader};
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Tag {
+        Value,
+        Slice,
+        Ignored(u32),
+    }
+
+    struct Flat;
+
+    impl Matcher for Flat {
+        type Tag = Tag;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<Tag>, DecodingError> {
+            Ok(match read.field_id() {
+                1 => Action::Continue(Cont::ReadValue(Tag::Value)),
+                2 => Action::Continue(Cont::ReadSlice(Tag::Slice)),
+                x => Action::Skip(Tag::Ignored(x)),
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<Tag>>,
+        ) -> Option<Tag> {
+            None
+        }
+    }
+
+    #[test]
+    fn groups_by_variant_and_counts_bytes() {
+        let mut input = Vec::new();
+        input.extend(FieldValue::Varint(150).output_with_field_id(1));
+        input.extend(FieldValue::DataLength(3).output_with_field_id(2));
+        input.extend_from_slice(b"abc");
+        input.extend(FieldValue::Fixed32(1).output_with_field_id(3));
+        input.extend(FieldValue::Fixed64(1).output_with_field_id(4));
+
+        let mut fields = MatcherFields::new(InstrumentedMatcher::new(Flat));
+        let mut buf = &input[..];
+
+        let mut count = 0;
+        while let Ok(_matched) = fields.next(&mut buf).unwrap() {
+            count += 1;
+        }
+        assert_eq!(count, 4);
+
+        let (_, matcher) = fields.into_parts();
+        let summary = matcher.summary();
+
+        assert_eq!(summary.entries().len(), 3);
+        assert_eq!(summary.total_bytes(), input.len() as u64);
+
+        let ignored = summary
+            .entries()
+            .iter()
+            .find(|e| e.decision == Decision::Skip)
+            .unwrap();
+
+        assert_eq!(ignored.count, 2);
+        assert_eq!(ignored.bytes, 5 + 9);
+        assert_eq!(ignored.label.as_deref(), Some("Ignored(3)"));
+    }
+
+    /// Enters the groups of field 1, reading the rest as values.
+    #[cfg(feature = "groups")]
+    struct Groups;
+
+    #[cfg(feature = "groups")]
+    impl Matcher for Groups {
+        type Tag = &'static str;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<Self::Tag>, DecodingError> {
+            Ok(if read.is_start_group() {
+                Action::Continue(Cont::Message(Some("start")))
+            } else {
+                Action::Continue(Cont::ReadValue("value"))
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<Self::Tag>>,
+        ) -> Option<Self::Tag> {
+            None
+        }
+
+        fn end_group(&mut self, _offset: usize, _field: FieldId) -> Option<Self::Tag> {
+            Some("end")
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "groups")]
+    fn group_ends_are_forwarded() {
+        // 1: { 1: 1 }, 2: 2
+        let input = hex_literal::hex!("0b 0801 0c 1002");
+
+        let mut fields = MatcherFields::new(InstrumentedMatcher::new(Groups));
+        let mut buf = &input[..];
+        let mut tags = Vec::new();
+        while let Ok(matched) = fields.next(&mut buf).unwrap() {
+            tags.push(matched.tag);
+        }
+        assert_eq!(tags, ["start", "value", "end", "value"]);
+
+        let (_, matcher) = fields.into_parts();
+        let summary = matcher.summary();
+        let ends = summary
+            .entries()
+            .iter()
+            .find(|e| e.decision == Decision::EndGroup)
+            .unwrap();
+        assert_eq!((ends.count, ends.bytes), (1, 1));
+        assert_eq!(ends.label.as_deref(), Some("\"end\""));
+        assert_eq!(summary.total_bytes(), input.len() as u64);
+    }
+}
@@ -1,2 +1,45 @@
 // std::io::Read support
 pub mod read;
+
+// std::io::Read support with a caller provided fixed size buffer
+pub mod fixed;
+
+// std::io::BufRead support reading through its buffer
+pub mod buf_read;
+
+// std::io::Write support for writing fields
+pub mod write;
+
+// the matched slices concatenated as a std::io::Read
+pub mod concat;
+
+// length prefixed frames over std::io::Read and std::io::Write
+pub mod frames;
+
+// ordered sequence of std::io::Read sources as one stream
+pub mod segments;
+
+// std::io::Seek support
+pub mod index;
+
+// gathering from std::io::Read + std::io::Seek, seeking over the skipped fields
+pub mod seek;
+
+// sorting length prefixed streams with temporary files
+pub mod sort;
+
+// embedded_io::Read and embedded_io::Write support
+#[cfg(feature = "embedded-io")]
+pub mod embedded;
+
+// embedded_io_async::Read and embedded_io_async::Write support
+#[cfg(feature = "embedded-io-async")]
+pub mod embedded_async;
+
+// futures::io::AsyncRead support
+#[cfg(feature = "futures")]
+pub mod futures;
+
+// tokio_util::codec::Decoder and Encoder support
+#[cfg(feature = "tokio-util")]
+pub mod codec;
@@ -0,0 +1,261 @@
+use crate::{Introspect, ReadError, Reader, Stats, Status};
+use std::io::BufRead;
+
+/// Like [`super::read::ReadWrapper`] but reads through the buffer of a `std::io::BufRead` with
+/// `fill_buf` and `consume`, so the bytes are not copied into a second buffer.
+///
+/// Only when the reader needs more bytes at once than are available in the buffer of the
+/// `std::io::BufRead`, for example a slice longer than it or the window retained by a gatherer,
+/// the needed bytes are copied into an own buffer until the reader has advanced over them.
+pub struct BufReadWrapper<IO, R> {
+    inner: IO,
+    reader: R,
+    /// The bytes still needed by the reader which did not fit the buffer of `inner`, otherwise
+    /// empty
+    spill: Vec<u8>,
+    /// Where in the spill the reader got to
+    at_offset: usize,
+    /// When true, the spill needs more bytes before the reader can advance
+    needs_more: bool,
+    eof: bool,
+}
+
+/// `buffered` is the amount of bytes copied out of the buffer of the `std::io::BufRead`.
+impl<IO, R: Introspect> Introspect for BufReadWrapper<IO, R> {
+    fn stats(&self) -> Stats {
+        Stats {
+            buffered: self.spill.len() as u64,
+            ..self.reader.stats()
+        }
+    }
+}
+
+impl<IO, R> BufReadWrapper<IO, R>
+where
+    IO: BufRead,
+    R: for<'a> Reader<'a>,
+{
+    pub fn new(inner: IO, reader: R) -> Self {
+        BufReadWrapper {
+            inner,
+            reader,
+            spill: Vec::new(),
+            at_offset: 0,
+            needs_more: false,
+            eof: false,
+        }
+    }
+
+    /// Returns the next item of a reader whose items do not borrow the buffer, see `with_next`
+    /// for the others.
+    pub fn read_next<T>(&mut self) -> Result<Option<T>, ReadError>
+    where
+        R: for<'a> Reader<'a, Returned = T>,
+    {
+        self.with_next(|item| item)
+    }
+
+    /// Calls `f` with the next item, which can borrow the buffer, returning what `f` returned or
+    /// `None` at the end of the input.
+    pub fn with_next<T, F>(&mut self, f: F) -> Result<Option<T>, ReadError>
+    where
+        F: for<'a> FnOnce(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut f = Some(f);
+        loop {
+            let ret = if self.spill.is_empty() {
+                self.next_direct(&mut |item| (f.take().expect("called at most once"))(item))?
+            } else {
+                self.next_spilled(&mut |item| (f.take().expect("called at most once"))(item))?
+            };
+
+            if let Some(ret) = ret {
+                return Ok(ret);
+            }
+        }
+    }
+
+    /// Runs the reader over the buffer of `inner`, moving the bytes the reader did not advance
+    /// over into the spill.
+    fn next_direct<T, F>(&mut self, f: &mut F) -> Result<Option<Option<T>>, ReadError>
+    where
+        F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+    {
+        let available = self.inner.fill_buf()?;
+        let mut buf = available;
+
+        // the item is given to `f` while the buffer of `inner` is borrowed
+        let ret = match self.reader.next(&mut buf) {
+            Ok(Ok(m)) => Ok(Ok(f(m))),
+            Ok(Err(status)) => Ok(Err(status)),
+            Err(e) => Err(e),
+        };
+
+        let eof = available.is_empty();
+        let consumed = available.len() - buf.len();
+
+        let status = match ret {
+            Ok(Ok(ret)) => {
+                self.inner.consume(consumed);
+                return Ok(Some(Some(ret)));
+            }
+            Ok(Err(status)) => Ok(status),
+            Err(e) => Err(e),
+        };
+
+        // the reader needs these bytes again with the following ones
+        let kept = !buf.is_empty();
+        self.spill.extend_from_slice(buf);
+        let len = available.len();
+        self.inner.consume(len);
+        self.needs_more = kept;
+
+        self.ended(status?, eof)
+    }
+
+    /// Runs the reader over the spill, first reading more into it if needed.
+    fn next_spilled<T, F>(&mut self, f: &mut F) -> Result<Option<Option<T>>, ReadError>
+    where
+        F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+    {
+        if self.needs_more && !self.eof {
+            self.spill.drain(..self.at_offset);
+            self.at_offset = 0;
+
+            let available = self.inner.fill_buf()?;
+            let len = available.len();
+            self.spill.extend_from_slice(available);
+            self.inner.consume(len);
+
+            self.eof = len == 0;
+            self.needs_more = false;
+        }
+
+        let mut buf = &self.spill[self.at_offset..];
+        let original_len = buf.len();
+
+        let ret = match self.reader.next(&mut buf) {
+            Ok(Ok(m)) => Ok(Ok(f(m))),
+            Ok(Err(status)) => Ok(Err(status)),
+            Err(e) => Err(e),
+        };
+
+        self.at_offset += original_len - buf.len();
+
+        let status = match ret {
+            Ok(Ok(ret)) => {
+                self.drop_consumed_spill();
+                return Ok(Some(Some(ret)));
+            }
+            Ok(Err(status)) => Ok(status),
+            Err(e) => Err(e),
+        };
+
+        self.needs_more = self.at_offset < self.spill.len();
+        self.drop_consumed_spill();
+
+        let eof = self.eof;
+        self.ended(status?, eof)
+    }
+
+    /// Returns to reading from the buffer of `inner` once the spill has been read through.
+    fn drop_consumed_spill(&mut self) {
+        if self.at_offset == self.spill.len() {
+            self.spill.clear();
+            self.at_offset = 0;
+        }
+    }
+
+    fn ended<T>(&mut self, status: Status, eof: bool) -> Result<Option<Option<T>>, ReadError> {
+        match status {
+            Status::IdleAtEndOfBuffer if eof && self.spill.is_empty() => Ok(Some(None)),
+            _ if eof => Err(ReadError::UnexpectedEndOfFile),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn into_parts(self) -> (IO, R) {
+        (self.inner, self.reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufReadWrapper;
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields, SlicedValue};
+    use crate::{DecodingError, FieldId, Introspect, ReadError, ReadField};
+    use hex_literal::hex;
+    use std::io::BufReader;
+
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    // 1: "abcdef", 2: 150, 1: "gh"
+    const INPUT: [u8; 15] = hex!("0a06616263646566 109601 0a026768");
+
+    #[test]
+    fn slices_through_buffers_of_every_size() {
+        for capacity in 1..=INPUT.len() + 1 {
+            let inner = BufReader::with_capacity(capacity, &INPUT[..]);
+            let mut rw = BufReadWrapper::new(inner, MatcherFields::new(Slices).into_sliced());
+
+            let mut slices = Vec::new();
+            while let Some(slice) = rw
+                .with_next(|m| match m.value {
+                    SlicedValue::Slice(_, bytes) => Some(bytes.to_vec()),
+                    _ => None,
+                })
+                .unwrap()
+            {
+                slices.extend(slice);
+            }
+
+            assert_eq!(slices, [&b"abcdef"[..], b"gh"], "capacity {}", capacity);
+            assert_eq!(rw.stats().buffered, 0);
+        }
+    }
+
+    #[test]
+    fn nothing_is_copied_when_the_fields_fit() {
+        let inner = BufReader::with_capacity(64, &INPUT[..]);
+        let mut rw = BufReadWrapper::new(inner, MatcherFields::new(Slices).into_sliced());
+
+        let mut copied = 0;
+        while rw.with_next(|_| ()).unwrap().is_some() {
+            copied = copied.max(rw.stats().buffered);
+        }
+        assert_eq!(copied, 0);
+    }
+
+    #[test]
+    fn truncated_input() {
+        let inner = BufReader::with_capacity(4, &INPUT[..10]);
+        let mut rw = BufReadWrapper::new(inner, MatcherFields::new(Slices));
+
+        assert!(rw.read_next().unwrap().is_some());
+        assert!(matches!(
+            rw.read_next(),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+    }
+}
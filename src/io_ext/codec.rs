@@ -0,0 +1,178 @@
+//! `tokio_util::codec` support, so that the readers can be used with `FramedRead` and `Framed`.
+//!
+//! [`MatcherDecoder`] decodes the items of any [`Reader`] out of the `BytesMut` of the framing,
+//! and encodes the messages built with a [`VecSink`].
+
+use crate::sink::{SinkError, VecSink};
+use crate::{ReadError, Reader, Status};
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Decodes the items of the reader, converted with the given function so that they do not borrow
+/// the buffer, for example with `|m| m.into_owned()` for [`crate::matcher_fields::SlicedMatched`].
+///
+/// The bytes are consumed from the `BytesMut` as the reader advances over them, the ones retained
+/// by the reader stay in the `BytesMut` until they are no longer needed.
+pub struct MatcherDecoder<R, F> {
+    reader: R,
+    convert: F,
+    /// The status of the latest call which did not produce an item
+    status: Status,
+}
+
+impl<R, F> MatcherDecoder<R, F> {
+    pub fn new<T>(reader: R, convert: F) -> Self
+    where
+        R: for<'a> Reader<'a>,
+        F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+    {
+        MatcherDecoder {
+            reader,
+            convert,
+            status: Status::IdleAtEndOfBuffer,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R, F, T> Decoder for MatcherDecoder<R, F>
+where
+    R: for<'a> Reader<'a>,
+    F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+{
+    type Item = T;
+    type Error = ReadError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<T>, ReadError> {
+        let mut buf = &src[..];
+        let original_len = buf.len();
+
+        let ret = match self.reader.next(&mut buf) {
+            Ok(Ok(m)) => Ok(Ok((self.convert)(m))),
+            Ok(Err(status)) => Ok(Err(status)),
+            Err(e) => Err(e),
+        };
+
+        let consumed = original_len - buf.len();
+        src.advance(consumed);
+
+        match ret? {
+            Ok(item) => Ok(Some(item)),
+            Err(status) => {
+                self.status = status;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Errors with `ReadError::UnexpectedEndOfFile` if the input ends in the middle of a field.
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<T>, ReadError> {
+        match self.decode(src)? {
+            Some(item) => Ok(Some(item)),
+            None if src.is_empty() && matches!(self.status, Status::IdleAtEndOfBuffer) => Ok(None),
+            None => Err(ReadError::UnexpectedEndOfFile),
+        }
+    }
+}
+
+/// Appends the bytes of the messages built with a [`VecSink`]. Any messages still open in the sink
+/// will be missing their length prefix, like with `VecSink::into_inner`.
+impl<R, F> Encoder<VecSink> for MatcherDecoder<R, F> {
+    type Error = SinkError;
+
+    fn encode(&mut self, item: VecSink, dst: &mut BytesMut) -> Result<(), SinkError> {
+        dst.extend_from_slice(&item.into_inner());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MatcherDecoder;
+    use crate::matcher_fields::{
+        Action, Cont, EndedMessage, Matcher, MatcherFields, OwnedValue, SlicedMatched,
+    };
+    use crate::sink::{Scalar, Sink, VecSink};
+    use crate::{DecodingError, FieldId, ReadError, ReadField};
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    fn decoder() -> MatcherDecoder<
+        crate::matcher_fields::SlicedMatcherFields<Slices>,
+        impl for<'a> FnMut(SlicedMatched<'a, ()>) -> OwnedValue,
+    > {
+        MatcherDecoder::new(
+            MatcherFields::new(Slices).into_sliced(),
+            |m: SlicedMatched<'_, ()>| m.into_owned().value,
+        )
+    }
+
+    #[test]
+    fn encoded_fields_are_decoded_in_pieces() {
+        let mut sink = VecSink::default();
+        sink.write_slice(1, b"abc").unwrap();
+        sink.write_scalar(2, Scalar::Varint(150)).unwrap();
+
+        let mut codec = decoder();
+        let mut encoded = BytesMut::new();
+        codec.encode(sink, &mut encoded).unwrap();
+
+        // the bytes arrive one at a time
+        let mut src = BytesMut::new();
+        let mut values = Vec::new();
+        for byte in encoded.iter() {
+            src.extend_from_slice(&[*byte]);
+            while let Some(value) = codec.decode(&mut src).unwrap() {
+                values.push(value);
+            }
+        }
+        assert_eq!(codec.decode_eof(&mut src).unwrap(), None);
+
+        assert_eq!(
+            values,
+            [
+                OwnedValue::Slice(2..5, b"abc".to_vec()),
+                OwnedValue::Varint(150)
+            ]
+        );
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn truncated_field_at_eof() {
+        let mut codec = decoder();
+        let mut src = BytesMut::from(&b"\x0a\x03ab"[..]);
+
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        assert!(matches!(
+            codec.decode_eof(&mut src),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+    }
+}
@@ -0,0 +1,141 @@
+use super::read::ReadWrapper;
+use crate::matcher_fields::{Matcher, MatcherFields, SlicedMatcherFields, SlicedValue};
+use crate::ReadError;
+use std::io::{self, Read};
+
+/// Exposes the bytes of every slice matched with a selected tag, in the stream order, as one
+/// continuous `std::io::Read`.
+///
+/// For example with UnixFS the `Data` fields of the successive blocks concatenated are the
+/// content of the file. The matcher needs to return `Cont::ReadSlice` for the wanted fields,
+/// which are buffered by the inner [`ReadWrapper`] one at a time.
+///
+/// The errors of the reading are returned as `io::ErrorKind::InvalidData`, except for the errors
+/// of the inner `std::io::Read` which are returned as is.
+pub struct ConcatSlices<IO, M: Matcher, F> {
+    inner: ReadWrapper<IO, SlicedMatcherFields<M>>,
+    select: F,
+    /// The currently read slice and how much of it has been read
+    pending: Vec<u8>,
+    at: usize,
+}
+
+impl<IO, M, F> ConcatSlices<IO, M, F>
+where
+    IO: Read,
+    M: Matcher,
+    F: FnMut(&M::Tag) -> bool,
+{
+    pub fn new(inner: IO, matcher: M, select: F) -> Self {
+        ConcatSlices {
+            inner: ReadWrapper::new(inner, MatcherFields::new(matcher).into_sliced()),
+            select,
+            pending: Vec::new(),
+            at: 0,
+        }
+    }
+
+    /// Fills the pending slice with the next selected one, returning false at the end of the
+    /// input.
+    fn next_slice(&mut self) -> Result<bool, ReadError> {
+        let ConcatSlices {
+            inner,
+            select,
+            pending,
+            at,
+        } = self;
+
+        loop {
+            let selected = inner.with_next(|m| match m.value {
+                SlicedValue::Slice(_, bytes) if !bytes.is_empty() && select(&m.tag) => {
+                    pending.clear();
+                    pending.extend_from_slice(bytes);
+                    *at = 0;
+                    true
+                }
+                _ => false,
+            })?;
+
+            match selected {
+                Some(true) => return Ok(true),
+                Some(false) => {}
+                None => return Ok(false),
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> IO {
+        self.inner.into_inner()
+    }
+}
+
+impl<IO, M, F> Read for ConcatSlices<IO, M, F>
+where
+    IO: Read,
+    M: Matcher,
+    F: FnMut(&M::Tag) -> bool,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.at == self.pending.len() {
+            match self.next_slice() {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                Err(ReadError::IO(e)) => return Err(e),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len() - self.at);
+        buf[..len].copy_from_slice(&self.pending[self.at..self.at + len]);
+        self.at += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConcatSlices;
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher};
+    use crate::{DecodingError, FieldId, ReadField};
+    use hex_literal::hex;
+    use std::io::Read;
+
+    /// Tags the slices with their field ids.
+    struct ById;
+
+    impl Matcher for ById {
+        type Tag = FieldId;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<FieldId>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(read.field_id()))
+            } else {
+                Action::Skip(read.field_id())
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<FieldId>>,
+        ) -> Option<FieldId> {
+            None
+        }
+    }
+
+    #[test]
+    fn concatenates_selected() {
+        // 1: "ab", 2: "xx", 3: 1, 1: "", 1: "cde"
+        let input = hex!("0a026162 12027878 1801 0a00 0a03636465");
+        let mut read = ConcatSlices::new(&input[..], ById, |&id| id == 1);
+
+        let mut out = String::new();
+        read.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "abcde");
+    }
+}
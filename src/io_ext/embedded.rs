@@ -0,0 +1,357 @@
+//! Reading and writing through the `embedded_io` traits instead of `std::io`, so that the
+//! streaming decoder can be used with the UART and TCP abstractions of the embedded HALs.
+//!
+//! As the targets rarely have an allocator to spare, the reads happen into a caller provided
+//! buffer like with [`super::fixed::FixedReadWrapper`].
+
+use crate::sink::Sink;
+use crate::{DecodingError, FieldId, Introspect, Reader, Stats, Status};
+use std::fmt;
+
+/// Errors which can happen when reading from an `embedded_io::Read`.
+#[derive(Debug)]
+pub enum EmbeddedReadError<E> {
+    /// More bytes could not be read from the source but were expected
+    UnexpectedEndOfFile,
+    /// Decoding the input failed
+    Decoding(DecodingError),
+    /// Reading from the source failed
+    IO(E),
+    /// The buffer was full of bytes still needed by the reader, for example because a field was
+    /// longer than the buffer
+    BufferTooSmall { capacity: usize },
+}
+
+impl<E: fmt::Debug> fmt::Display for EmbeddedReadError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbeddedReadError::UnexpectedEndOfFile => write!(fmt, "unexpected end of file"),
+            EmbeddedReadError::Decoding(e) => write!(fmt, "{}", e),
+            EmbeddedReadError::IO(e) => write!(fmt, "read failed: {:?}", e),
+            EmbeddedReadError::BufferTooSmall { capacity } => write!(
+                fmt,
+                "buffer of {} bytes is too small for the field being read",
+                capacity
+            ),
+        }
+    }
+}
+
+impl<E: fmt::Debug> std::error::Error for EmbeddedReadError<E> {}
+
+impl<E> From<DecodingError> for EmbeddedReadError<E> {
+    fn from(e: DecodingError) -> Self {
+        EmbeddedReadError::Decoding(e)
+    }
+}
+
+/// The caller provided buffer and the bookkeeping of the bytes in it.
+pub(crate) struct Window<'b> {
+    buffer: &'b mut [u8],
+    /// The amount of bytes read into the buffer
+    filled: usize,
+    /// Where in the buffer did we last get to
+    at_offset: usize,
+    /// When true, need to read more bytes
+    exhausted: bool,
+    /// When true, any bytes in the buffer represent the last bytes of the input stream.
+    eof_after_buffer: bool,
+}
+
+impl<'b> Window<'b> {
+    pub(crate) fn new(buffer: &'b mut [u8]) -> Self {
+        Window {
+            buffer,
+            filled: 0,
+            at_offset: 0,
+            exhausted: false,
+            eof_after_buffer: false,
+        }
+    }
+
+    /// Returns the room to read more bytes into, if more bytes are needed. Consumed bytes are
+    /// shifted out from the beginning when the buffer is full.
+    pub(crate) fn room<E>(&mut self) -> Result<Option<&mut [u8]>, EmbeddedReadError<E>> {
+        if !self.exhausted || self.eof_after_buffer {
+            return Ok(None);
+        }
+
+        if self.filled == self.buffer.len() {
+            if self.at_offset == 0 {
+                return Err(EmbeddedReadError::BufferTooSmall {
+                    capacity: self.buffer.len(),
+                });
+            }
+
+            self.buffer.copy_within(self.at_offset..self.filled, 0);
+            self.filled -= self.at_offset;
+            self.at_offset = 0;
+        }
+
+        Ok(Some(&mut self.buffer[self.filled..]))
+    }
+
+    /// Records the amount of bytes read into the room, zero meaning the end of the input.
+    pub(crate) fn filled(&mut self, bytes: usize) {
+        self.eof_after_buffer = bytes == 0;
+        self.exhausted = false;
+        self.filled += bytes;
+    }
+
+    /// Runs the reader on the buffered bytes, giving the item to `f`, returning `None` if more
+    /// bytes need to be read. The item is given to `f` while the buffer is borrowed, see
+    /// `ReadWrapper::with_next`.
+    pub(crate) fn read<R, E, T, F>(
+        &mut self,
+        reader: &mut R,
+        f: &mut F,
+    ) -> Result<Option<Option<T>>, EmbeddedReadError<E>>
+    where
+        R: for<'a> Reader<'a>,
+        F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut buf = &self.buffer[self.at_offset..self.filled];
+        let original_len = buf.len();
+
+        let status = match reader.next(&mut buf) {
+            Ok(Ok(m)) => {
+                self.at_offset += original_len - buf.len();
+                return Ok(Some(Some(f(m))));
+            }
+            Ok(Err(status)) => Ok(status),
+            Err(e) => Err(e),
+        };
+
+        self.at_offset += original_len - buf.len();
+
+        match status? {
+            Status::IdleAtEndOfBuffer if self.eof_after_buffer => Ok(Some(None)),
+            Status::NeedMoreBytes(_) | Status::CanSkip(_) if self.eof_after_buffer => {
+                Err(EmbeddedReadError::UnexpectedEndOfFile)
+            }
+            Status::IdleAtEndOfBuffer | Status::NeedMoreBytes(_) | Status::CanSkip(_) => {
+                self.exhausted = true;
+                Ok(None)
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.filled
+    }
+}
+
+/// Reads from an `embedded_io::Read` into a caller provided buffer, which needs to be at least as
+/// large as the longest slice read plus the longest varint tag and length.
+pub struct EmbeddedReadWrapper<'b, IO, R> {
+    inner: IO,
+    window: Window<'b>,
+    matcher: R,
+}
+
+impl<'b, IO, R> EmbeddedReadWrapper<'b, IO, R>
+where
+    IO: embedded_io::Read,
+    R: for<'a> Reader<'a>,
+{
+    pub fn new(inner: IO, buffer: &'b mut [u8], matcher: R) -> Self {
+        Self {
+            inner,
+            window: Window::new(buffer),
+            matcher,
+        }
+    }
+
+    /// Returns the next item of a reader whose items do not borrow the buffer, or `None` at the
+    /// end of the input.
+    pub fn read_next<T>(&mut self) -> Result<Option<T>, EmbeddedReadError<IO::Error>>
+    where
+        R: for<'a> Reader<'a, Returned = T>,
+    {
+        self.with_next(|item| item)
+    }
+
+    /// Calls `f` with the next item, which can borrow the buffer, returning what `f` returned or
+    /// `None` at the end of the input.
+    pub fn with_next<T, F>(&mut self, f: F) -> Result<Option<T>, EmbeddedReadError<IO::Error>>
+    where
+        F: for<'a> FnOnce(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut f = Some(f);
+        loop {
+            if let Some(room) = self.window.room()? {
+                let bytes = self.inner.read(room).map_err(EmbeddedReadError::IO)?;
+                self.window.filled(bytes);
+            }
+
+            let ret = self.window.read(&mut self.matcher, &mut |item| {
+                (f.take().expect("called at most once"))(item)
+            })?;
+            if let Some(ret) = ret {
+                return Ok(ret);
+            }
+        }
+    }
+}
+
+impl<IO, R> EmbeddedReadWrapper<'_, IO, R> {
+    pub fn into_parts(self) -> (IO, R) {
+        (self.inner, self.matcher)
+    }
+}
+
+impl<IO, R: Introspect> Introspect for EmbeddedReadWrapper<'_, IO, R> {
+    fn stats(&self) -> Stats {
+        Stats {
+            buffered: self.window.len() as u64,
+            ..self.matcher.stats()
+        }
+    }
+}
+
+/// Errors from [`EmbeddedSink`].
+#[derive(Debug)]
+pub enum EmbeddedSinkError<E> {
+    /// `end_message` was called without a matching `begin_message`
+    UnbalancedEndMessage,
+    /// Writing to the underlying `embedded_io::Write` failed
+    IO(E),
+}
+
+impl<E: fmt::Debug> fmt::Display for EmbeddedSinkError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbeddedSinkError::UnbalancedEndMessage => {
+                write!(fmt, "end_message called without begin_message")
+            }
+            EmbeddedSinkError::IO(e) => write!(fmt, "write failed: {:?}", e),
+        }
+    }
+}
+
+impl<E: fmt::Debug> std::error::Error for EmbeddedSinkError<E> {}
+
+/// Writes to an `embedded_io::Write`. Like [`crate::sink::WriteSink`] the nested messages are
+/// buffered until the outermost message ends.
+pub struct EmbeddedSink<W> {
+    inner: W,
+    /// Field id and the buffered body of the open messages, the outermost first
+    open: Vec<(FieldId, Vec<u8>)>,
+}
+
+impl<W: embedded_io::Write> EmbeddedSink<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            open: Vec::new(),
+        }
+    }
+
+    /// Flushes the inner writer.
+    pub fn flush(&mut self) -> Result<(), EmbeddedSinkError<W::Error>> {
+        self.inner.flush().map_err(EmbeddedSinkError::IO)
+    }
+
+    /// Returns the inner writer, discarding any still open messages.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub fn open_messages(&self) -> usize {
+        self.open.len()
+    }
+}
+
+impl<W: embedded_io::Write> Sink for EmbeddedSink<W> {
+    type Error = EmbeddedSinkError<W::Error>;
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        match self.open.last_mut() {
+            Some((_, body)) => body.extend_from_slice(bytes),
+            None => self.inner.write_all(bytes).map_err(EmbeddedSinkError::IO)?,
+        }
+        Ok(())
+    }
+
+    fn begin_message(&mut self, id: FieldId) -> Result<(), Self::Error> {
+        self.open.push((id, Vec::new()));
+        Ok(())
+    }
+
+    fn end_message(&mut self) -> Result<(), Self::Error> {
+        let (id, body) = self
+            .open
+            .pop()
+            .ok_or(EmbeddedSinkError::UnbalancedEndMessage)?;
+        self.write_slice(id, &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmbeddedReadError, EmbeddedReadWrapper, EmbeddedSink};
+    use crate::matcher_fields::{
+        Action, Cont, EndedMessage, Matched, Matcher, MatcherFields, Value,
+    };
+    use crate::sink::{Scalar, Sink};
+    use crate::{DecodingError, FieldId, ReadField};
+    use hex_literal::hex;
+
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn reads_and_writes_through_embedded_io() {
+        let mut written = [0u8; 16];
+        let len = {
+            let mut out = &mut written[..];
+            let mut sink = EmbeddedSink::new(&mut out);
+            sink.write_scalar(1, Scalar::Varint(1)).unwrap();
+            sink.begin_message(2).unwrap();
+            sink.write_slice(1, b"ab").unwrap();
+            sink.end_message().unwrap();
+            drop(sink);
+            16 - out.len()
+        };
+        assert_eq!(&written[..len], &hex!("0801 1204 0a026162"));
+
+        let mut buffer = [0u8; 6];
+        let mut rw =
+            EmbeddedReadWrapper::new(&written[..len], &mut buffer, MatcherFields::new(Slices));
+
+        let mut values = Vec::new();
+        loop {
+            match rw.read_next() {
+                Ok(Some(Matched { value, .. })) => values.push(value),
+                Ok(None) => break,
+                Err(EmbeddedReadError::IO(e)) => match e {},
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+
+        assert!(matches!(
+            values.as_slice(),
+            [Value::Varint(1), Value::Slice(r)] if r.start == 4 && r.end == 8
+        ));
+    }
+}
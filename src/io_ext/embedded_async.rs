@@ -0,0 +1,166 @@
+//! Reading and writing through the `embedded_io_async` traits, for the async embedded executors
+//! such as embassy. The decoding is the same as in [`super::embedded`], only the reads and writes
+//! are awaited.
+
+use super::embedded::{EmbeddedReadError, EmbeddedSinkError, Window};
+use crate::{Introspect, Reader, Stats};
+
+/// Reads from an `embedded_io_async::Read` into a caller provided buffer, which needs to be at
+/// least as large as the longest slice read plus the longest varint tag and length.
+pub struct AsyncEmbeddedReadWrapper<'b, IO, R> {
+    inner: IO,
+    window: Window<'b>,
+    matcher: R,
+}
+
+impl<'b, IO, R> AsyncEmbeddedReadWrapper<'b, IO, R>
+where
+    IO: embedded_io_async::Read,
+    R: for<'a> Reader<'a>,
+{
+    pub fn new(inner: IO, buffer: &'b mut [u8], matcher: R) -> Self {
+        Self {
+            inner,
+            window: Window::new(buffer),
+            matcher,
+        }
+    }
+
+    /// Returns the next item of a reader whose items do not borrow the buffer, or `None` at the
+    /// end of the input. Dropping the future while it is waiting for more bytes is safe, the bytes
+    /// already read are kept.
+    pub async fn read_next<T>(&mut self) -> Result<Option<T>, EmbeddedReadError<IO::Error>>
+    where
+        R: for<'a> Reader<'a, Returned = T>,
+    {
+        self.with_next(|item| item).await
+    }
+
+    /// Calls `f` with the next item, which can borrow the buffer, returning what `f` returned or
+    /// `None` at the end of the input, see `read_next`.
+    pub async fn with_next<T, F>(&mut self, f: F) -> Result<Option<T>, EmbeddedReadError<IO::Error>>
+    where
+        F: for<'a> FnOnce(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut f = Some(f);
+        loop {
+            if let Some(room) = self.window.room()? {
+                let bytes = self.inner.read(room).await.map_err(EmbeddedReadError::IO)?;
+                self.window.filled(bytes);
+            }
+
+            let ret = self.window.read(&mut self.matcher, &mut |item| {
+                (f.take().expect("called at most once"))(item)
+            })?;
+            if let Some(ret) = ret {
+                return Ok(ret);
+            }
+        }
+    }
+}
+
+impl<IO, R> AsyncEmbeddedReadWrapper<'_, IO, R> {
+    pub fn into_parts(self) -> (IO, R) {
+        (self.inner, self.matcher)
+    }
+}
+
+impl<IO, R: Introspect> Introspect for AsyncEmbeddedReadWrapper<'_, IO, R> {
+    fn stats(&self) -> Stats {
+        Stats {
+            buffered: self.window.len() as u64,
+            ..self.matcher.stats()
+        }
+    }
+}
+
+/// Writes a length delimited field with the already encoded `body` to an
+/// `embedded_io_async::Write`.
+///
+/// The [`crate::sink::Sink`] trait is synchronous, so messages can be built with a
+/// [`crate::sink::VecSink`] and written with this.
+pub async fn write_slice<W: embedded_io_async::Write>(
+    out: &mut W,
+    id: crate::FieldId,
+    body: &[u8],
+) -> Result<(), EmbeddedSinkError<W::Error>> {
+    let mut tmp = [0u8; 10];
+    for value in [(u64::from(id) << 3) | 2, body.len() as u64] {
+        let len = crate::pb::encode_varint(value, &mut tmp);
+        out.write_all(&tmp[..len])
+            .await
+            .map_err(EmbeddedSinkError::IO)?;
+    }
+    out.write_all(body).await.map_err(EmbeddedSinkError::IO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_slice, AsyncEmbeddedReadWrapper};
+    use crate::matcher_fields::{
+        Action, Cont, EndedMessage, Matched, Matcher, MatcherFields, Value,
+    };
+    use crate::{DecodingError, FieldId, ReadField};
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(ret) = future.as_mut().poll(&mut cx) {
+                return ret;
+            }
+        }
+    }
+
+    struct Varints;
+
+    impl Matcher for Varints {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Skip(())
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn awaits_fields() {
+        let mut written = [0u8; 16];
+        let mut out = &mut written[..];
+        block_on(write_slice(&mut out, 2, b"abc")).unwrap();
+        let len = 16 - out.len();
+
+        let mut input = written[..len].to_vec();
+        input.extend_from_slice(&[0x08, 0x96, 0x01]);
+
+        let mut buffer = [0u8; 4];
+        let mut rw =
+            AsyncEmbeddedReadWrapper::new(&input[..], &mut buffer, MatcherFields::new(Varints));
+
+        let mut values = Vec::new();
+        while let Some(Matched { value, .. }) = block_on(rw.read_next()).unwrap() {
+            values.push(value);
+        }
+
+        // the skipped field is reported with its range, without reading it into the buffer
+        assert!(matches!(
+            values.as_slice(),
+            [Value::Slice(r), Value::Varint(150)] if *r == (2..5)
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::memory::{MemoryReport, MemoryUsage, Peaks};
+use crate::{Introspect, ReadError, Reader, Stats, Status};
+
+/// Like [`super::read::ReadWrapper`] but uses a caller provided fixed size buffer instead of a
+/// growing `Vec`, so no allocations are made.
+///
+/// Consumed bytes are shifted out from the beginning of the buffer when more room is needed. If
+/// the buffer is full of bytes the reader still needs, for example a field longer than the
+/// buffer is being read as a slice, `ReadError::BufferTooSmall` is returned. The buffer needs to
+/// be at least as large as the longest slice read plus the longest varint tag and length.
+pub struct FixedReadWrapper<'b, IO, R> {
+    inner: IO,
+    buffer: &'b mut [u8],
+    /// The amount of bytes read into the buffer
+    filled: usize,
+    matcher: R,
+    /// Where in the buffer did we last get to
+    at_offset: usize,
+    /// When true, need to read more bytes
+    exhausted: bool,
+    /// When true, any bytes in the buffer represent the last bytes of the input stream.
+    eof_after_buffer: bool,
+    peaks: Peaks,
+}
+
+impl<'b, IO, R> FixedReadWrapper<'b, IO, R>
+where
+    IO: std::io::Read,
+{
+    pub fn new(inner: IO, buffer: &'b mut [u8], matcher: R) -> Self {
+        Self {
+            inner,
+            buffer,
+            filled: 0,
+            matcher,
+            at_offset: 0,
+            exhausted: false,
+            eof_after_buffer: false,
+            peaks: Peaks::default(),
+        }
+    }
+
+    /// Sets a function to be called whenever the peak retained size grows,
+    /// with the [`MemoryUsage`] at that point.
+    pub fn set_on_peak<F>(&mut self, on_peak: Option<F>)
+    where
+        F: FnMut(&MemoryUsage) + Send + 'static,
+    {
+        self.peaks.set_on_peak(on_peak);
+    }
+
+    /// See [`super::read::ReadWrapper::read_next`] for the items which do not borrow the buffer
+    /// and for the handling of interruptions.
+    pub fn read_next<T>(&mut self) -> Result<Option<T>, ReadError>
+    where
+        R: for<'a> Reader<'a, Returned = T>,
+    {
+        self.with_next(|item| item)
+    }
+
+    /// See [`super::read::ReadWrapper::with_next`].
+    pub fn with_next<T, F>(&mut self, f: F) -> Result<Option<T>, ReadError>
+    where
+        R: for<'a> Reader<'a>,
+        F: for<'a> FnOnce(<R as Reader<'a>>::Returned) -> T,
+    {
+        loop {
+            self.maybe_fill()?;
+
+            let mut buf = &self.buffer[self.at_offset..self.filled];
+            let original_len = buf.len();
+
+            // see ReadWrapper::next_buffered for why the item is given to `f` here
+            let status = match self.matcher.next(&mut buf) {
+                Ok(Ok(m)) => {
+                    self.at_offset += original_len - buf.len();
+                    return Ok(Some(f(m)));
+                }
+                Ok(Err(status)) => Ok(status),
+                Err(e) => Err(e),
+            };
+
+            self.at_offset += original_len - buf.len();
+
+            match status? {
+                Status::IdleAtEndOfBuffer if self.eof_after_buffer => return Ok(None),
+                Status::NeedMoreBytes(_) | Status::CanSkip(_) if self.eof_after_buffer => {
+                    return Err(ReadError::UnexpectedEndOfFile)
+                }
+                Status::IdleAtEndOfBuffer | Status::NeedMoreBytes(_) | Status::CanSkip(_) => {
+                    self.exhausted = true
+                }
+            }
+        }
+    }
+
+    fn maybe_fill(&mut self) -> Result<(), ReadError> {
+        if !self.exhausted || self.eof_after_buffer {
+            return Ok(());
+        }
+
+        if self.filled == self.buffer.len() {
+            if self.at_offset == 0 {
+                return Err(ReadError::BufferTooSmall {
+                    capacity: self.buffer.len(),
+                });
+            }
+
+            // shift the still needed bytes to the beginning to make room
+            self.buffer.copy_within(self.at_offset..self.filled, 0);
+            self.filled -= self.at_offset;
+            self.at_offset = 0;
+        }
+
+        let bytes = match self.inner.read(&mut self.buffer[self.filled..]) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return Err(match e.kind() {
+                    std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => {
+                        ReadError::TimedOut
+                    }
+                    _ => e.into(),
+                })
+            }
+        };
+
+        self.eof_after_buffer = bytes == 0;
+        self.exhausted = false;
+        self.filled += bytes;
+        self.peaks.update(
+            self.buffer.len() as u64,
+            (self.filled - self.at_offset) as u64,
+        );
+        Ok(())
+    }
+}
+
+impl<IO, R> FixedReadWrapper<'_, IO, R> {
+    pub fn into_parts(self) -> (IO, R) {
+        (self.inner, self.matcher)
+    }
+}
+
+/// `buffered` is the amount of bytes in the buffer, which includes the bytes retained for the
+/// wrapped reader and the bytes read ahead.
+impl<IO, R: Introspect> Introspect for FixedReadWrapper<'_, IO, R> {
+    fn stats(&self) -> Stats {
+        Stats {
+            buffered: self.filled as u64,
+            ..self.matcher.stats()
+        }
+    }
+}
+
+/// `capacity` is the length of the caller provided buffer.
+impl<IO, R> MemoryReport for FixedReadWrapper<'_, IO, R> {
+    fn memory(&self) -> MemoryUsage {
+        self.peaks.usage(
+            self.buffer.len() as u64,
+            (self.filled - self.at_offset) as u64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FixedReadWrapper;
+    use crate::matcher_fields::{
+        Action, Cont, EndedMessage, Matched, Matcher, MatcherFields, Value,
+    };
+    use crate::{DecodingError, FieldId, ReadError, ReadField};
+    use hex_literal::hex;
+
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn reads_through_small_buffer() {
+        // 1: 1, 2: "abcd", 1: 2, 2: "abcdefgh"
+        let input = hex!("0801 120461626364 0802 12086162636465666768");
+        let mut buffer = [0u8; 6];
+        let mut rw = FixedReadWrapper::new(&input[..], &mut buffer, MatcherFields::new(Slices));
+
+        let mut values = Vec::new();
+        loop {
+            match rw.read_next() {
+                Ok(Some(Matched { value, .. })) => values.push(value),
+                Ok(None) => unreachable!("the last field does not fit"),
+                Err(ReadError::BufferTooSmall { capacity: 6 }) => break,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+
+        assert!(matches!(
+            values.as_slice(),
+            [Value::Varint(1), Value::Slice(_), Value::Varint(2)]
+        ));
+    }
+}
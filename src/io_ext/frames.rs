@@ -0,0 +1,342 @@
+use crate::field_writer::MessageBuilder;
+use crate::framing::{FrameHeader, Framing};
+use crate::memory::{MemoryReport, MemoryUsage, Peaks};
+use crate::sink::SinkError;
+use crate::{Introspect, ReadError, Stats};
+use std::io::{self, Read, Write};
+
+/// Reads complete length prefixed frames out of an `std::io::Read` into an internal buffer.
+///
+/// The length prefix is read a byte at a time, so the reader should be buffered, for example with
+/// `std::io::BufReader`.
+///
+/// The buffer is never allocated up front from the length prefix, it grows only as the payload
+/// bytes arrive. Use `set_max_frame_len` to reject the frames over a limit before reading them.
+pub struct FrameReader<R> {
+    inner: R,
+    framing: Framing,
+    buffer: Vec<u8>,
+    /// Offset of the next length prefix
+    offset: u64,
+    max_frame_len: Option<u64>,
+    /// Frames read so far
+    frames: u64,
+    peaks: Peaks,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R, framing: Framing) -> Self {
+        Self {
+            inner,
+            framing,
+            buffer: Vec::new(),
+            offset: 0,
+            max_frame_len: None,
+            frames: 0,
+            peaks: Peaks::default(),
+        }
+    }
+
+    /// Sets the maximum accepted payload length. Longer frames produce
+    /// `DecodingErrorKind::FrameTooLarge` after reading only the length prefix.
+    pub fn set_max_frame_len(&mut self, limit: Option<u64>) {
+        self.max_frame_len = limit;
+    }
+
+    /// Sets a function to be called whenever the peak capacity or the peak retained size grows,
+    /// with the [`MemoryUsage`] at that point.
+    pub fn set_on_peak<F>(&mut self, on_peak: Option<F>)
+    where
+        F: FnMut(&MemoryUsage) + Send + 'static,
+    {
+        self.peaks.set_on_peak(on_peak);
+    }
+
+    /// Offset of the next length prefix in the stream.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads the next frame, returning the header and the payload, or `None` at the end of the
+    /// input.
+    pub fn next_frame(&mut self) -> Result<Option<(FrameHeader, &[u8])>, ReadError> {
+        let mut tmp = [0u8; 10];
+        let mut filled = 0;
+
+        let (consumed, header) = loop {
+            if filled == self.framing.max_prefix_len() {
+                // read_prefix has already failed for the maximum length
+                let kind = crate::DecodingErrorKind::TooManyVarint64Bytes;
+                return Err(crate::DecodingError::from(kind)
+                    .with_offset(self.offset)
+                    .into());
+            }
+
+            match self.inner.read(&mut tmp[filled..filled + 1]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(ReadError::UnexpectedEndOfFile),
+                Ok(_) => filled += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            let offset = self.offset;
+            if let Ok(x) = self
+                .framing
+                .read_prefix(&tmp[..filled])
+                .map_err(|e| e.offset_by(offset))?
+            {
+                break x;
+            }
+        };
+
+        if let Some(limit) = self.max_frame_len {
+            if header.len > limit {
+                let kind = crate::DecodingErrorKind::FrameTooLarge {
+                    len: header.len,
+                    limit,
+                };
+                return Err(crate::DecodingError::from(kind)
+                    .with_offset(self.offset)
+                    .into());
+            }
+        }
+
+        self.buffer.clear();
+
+        // the buffer grows only as the bytes arrive, instead of trusting the length prefix
+        let read = (&mut self.inner)
+            .take(header.len)
+            .read_to_end(&mut self.buffer)?;
+
+        if (read as u64) < header.len {
+            return Err(ReadError::UnexpectedEndOfFile);
+        }
+
+        self.offset += consumed as u64 + header.len;
+        self.frames += 1;
+        self.peaks
+            .update(self.buffer.capacity() as u64, self.buffer.len() as u64);
+
+        Ok(Some((header, &self.buffer[..])))
+    }
+}
+
+/// Frames are read completely within `next_frame`, so `mid_field` is always false. `buffered` is
+/// the length of the latest frame.
+impl<R> Introspect for FrameReader<R> {
+    fn stats(&self) -> Stats {
+        Stats {
+            offset: self.offset,
+            mid_field: false,
+            buffered: self.buffer.len() as u64,
+            items: self.frames,
+        }
+    }
+}
+
+/// `retained` is the length of the latest frame.
+impl<R> MemoryReport for FrameReader<R> {
+    fn memory(&self) -> MemoryUsage {
+        self.peaks
+            .usage(self.buffer.capacity() as u64, self.buffer.len() as u64)
+    }
+}
+
+/// Writes length prefixed frames into an `std::io::Write`, the counterpart of [`FrameReader`].
+///
+/// With `Framing::Varint` the output can be read with Java `parseDelimitedFrom` or Go
+/// `protodelim.UnmarshalFrom`.
+pub struct FrameWriter<W> {
+    inner: W,
+    framing: Framing,
+    /// Offset of the next length prefix
+    offset: u64,
+    /// The message built with `write_with`
+    scratch: Vec<u8>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(inner: W, framing: Framing) -> Self {
+        Self {
+            inner,
+            framing,
+            offset: 0,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Amount of bytes written so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes the length prefix followed by the complete message.
+    pub fn write_frame(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut prefix = [0u8; 10];
+        let prefix_len = self
+            .framing
+            .encode_prefix(message.len() as u64, &mut prefix)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+
+        self.inner.write_all(&prefix[..prefix_len])?;
+        self.inner.write_all(message)?;
+        self.offset += (prefix_len + message.len()) as u64;
+        Ok(())
+    }
+
+    /// Writes a frame of the message built with `f`, which is buffered until `f` returns. The
+    /// messages nested in it which `f` leaves open are ended.
+    pub fn write_with<F>(&mut self, f: F) -> Result<(), SinkError>
+    where
+        F: FnOnce(&mut MessageBuilder<'_>) -> Result<(), SinkError>,
+    {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+
+        let mut builder = MessageBuilder::new(&mut scratch);
+        let built = f(&mut builder).and_then(|_| {
+            while builder.open_messages() > 0 {
+                builder.end_message()?;
+            }
+            Ok(())
+        });
+        let ret = built.and_then(|_| Ok(self.write_frame(&scratch)?));
+
+        self.scratch = scratch;
+        ret
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameReader, FrameWriter};
+    use crate::framing::Framing;
+    use crate::{DecodingErrorKind, Introspect, ReadError};
+    use hex_literal::hex;
+
+    #[test]
+    fn reads_frames() {
+        let input = hex!("03616263 00 0201ff 02");
+        let mut reader = FrameReader::new(&input[..], Framing::Varint);
+
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, b"abc");
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, b"");
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, &[0x01, 0xff]);
+        assert_eq!(reader.offset(), 8);
+        assert!(matches!(
+            reader.next_frame(),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+    }
+
+    #[test]
+    fn stats_after_frames() {
+        let input = hex!("03616263 0201ff");
+        let mut reader = FrameReader::new(&input[..], Framing::Varint);
+        let stats = reader.stats();
+        assert_eq!((stats.offset, stats.buffered, stats.items), (0, 0, 0));
+
+        reader.next_frame().unwrap().unwrap();
+        let stats = reader.stats();
+        assert_eq!(
+            (stats.offset, stats.mid_field, stats.buffered, stats.items),
+            (4, false, 3, 1)
+        );
+
+        reader.next_frame().unwrap().unwrap();
+        assert!(reader.next_frame().unwrap().is_none());
+        let stats = reader.stats();
+        assert_eq!((stats.offset, stats.buffered, stats.items), (7, 2, 2));
+    }
+
+    #[test]
+    fn roundtrip() {
+        for &framing in &[Framing::Varint, Framing::Grpc, Framing::BigEndian32] {
+            let long = vec![0xaau8; 300];
+            let messages: [&[u8]; 3] = [b"abc", b"", &long];
+
+            let mut writer = FrameWriter::new(Vec::new(), framing);
+            for m in messages.iter() {
+                writer.write_frame(m).unwrap();
+            }
+            let written = writer.into_inner();
+
+            let mut reader = FrameReader::new(&written[..], framing);
+            for m in messages.iter() {
+                assert_eq!(reader.next_frame().unwrap().unwrap().1, *m);
+            }
+            assert!(reader.next_frame().unwrap().is_none());
+            assert_eq!(reader.offset(), written.len() as u64);
+        }
+    }
+
+    #[test]
+    fn built_messages_are_written() {
+        let mut writer = FrameWriter::new(Vec::new(), Framing::Varint);
+        writer.write_frame(&hex!("0801")).unwrap();
+        writer
+            .write_with(|b| {
+                b.begin_message(2);
+                b.writer().write_bytes(1, b"a");
+                b.writer().write_varint(1, 2);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(writer.offset(), 11);
+
+        // the open message was ended
+        let written = writer.into_inner();
+        assert_eq!(written, hex!("02 0801 07 1205 0a0161 0802"));
+
+        let mut reader = FrameReader::new(&written[..], Framing::Varint);
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, hex!("0801"));
+        assert_eq!(
+            reader.next_frame().unwrap().unwrap().1,
+            hex!("1205 0a0161 0802")
+        );
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn adversarial_length_prefix() {
+        // claims u32::MAX bytes but has only three
+        let input = hex!("ffffffff0f 616263");
+
+        let mut reader = FrameReader::new(&input[..], Framing::Varint);
+        assert!(matches!(
+            reader.next_frame(),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+        assert!(reader.buffer.capacity() < 1024);
+
+        let mut reader = FrameReader::new(&input[..], Framing::Varint);
+        reader.set_max_frame_len(Some(1024));
+        match reader.next_frame() {
+            Err(ReadError::Decoding(e)) => assert!(matches!(
+                e.kind(),
+                DecodingErrorKind::FrameTooLarge {
+                    len: 0xffff_ffff,
+                    limit: 1024
+                }
+            )),
+            other => panic!("unexpected {:?}", other.map(|x| x.map(|(h, _)| h))),
+        }
+    }
+}
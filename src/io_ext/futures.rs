@@ -0,0 +1,283 @@
+//! Reading through the `futures::io::AsyncRead` trait, for the executors other than tokio such as
+//! smol and async-std. The buffering is like in [`super::read::ReadWrapper`], only the reads are
+//! awaited.
+
+use crate::{Introspect, ReadError, Reader, Stats, Status};
+use futures_io::AsyncRead;
+use std::future::poll_fn;
+use std::pin::Pin;
+
+/// Reads from a `futures::io::AsyncRead` into a growing buffer.
+///
+/// Like with [`super::read::ReadWrapper`], the buffer grows only when the buffered bytes have
+/// been read and the reader still needs more.
+pub struct AsyncReadWrapper<IO, R> {
+    inner: IO,
+    /// Bytes up to `filled` have been read, the rest is room for the next read
+    buffer: Vec<u8>,
+    filled: usize,
+    matcher: R,
+    grow_by: usize,
+    /// Where in the buffer did we last get to
+    at_offset: usize,
+    /// When true, need to read more bytes
+    exhausted: bool,
+    /// When true, the bytes in the buffer are the last bytes of the input stream
+    eof_after_buffer: bool,
+}
+
+/// `buffered` is the amount of bytes in the buffer, which includes the bytes retained for the
+/// wrapped reader and the bytes read ahead.
+impl<IO, R: Introspect> Introspect for AsyncReadWrapper<IO, R> {
+    fn stats(&self) -> Stats {
+        Stats {
+            buffered: self.filled as u64,
+            ..self.matcher.stats()
+        }
+    }
+}
+
+impl<IO, R> AsyncReadWrapper<IO, R>
+where
+    IO: AsyncRead + Unpin,
+    R: for<'a> Reader<'a>,
+{
+    pub fn new(inner: IO, matcher: R) -> Self {
+        let grow_by = 8192;
+        Self {
+            inner,
+            buffer: vec![0; grow_by],
+            filled: 0,
+            matcher,
+            grow_by,
+            at_offset: 0,
+            exhausted: false,
+            eof_after_buffer: false,
+        }
+    }
+
+    /// Returns the next item of a reader whose items do not borrow the buffer, or `None` at the
+    /// end of the input. Dropping the future while it is waiting for more bytes is safe, the bytes
+    /// already read are kept.
+    ///
+    /// Like with `ReadWrapper`, Interrupted errors are **not** ignored.
+    pub async fn read_next<T>(&mut self) -> Result<Option<T>, ReadError>
+    where
+        R: for<'a> Reader<'a, Returned = T>,
+    {
+        self.with_next(|item| item).await
+    }
+
+    /// Calls `f` with the next item, which can borrow the buffer, returning what `f` returned or
+    /// `None` at the end of the input, see `read_next`.
+    pub async fn with_next<T, F>(&mut self, f: F) -> Result<Option<T>, ReadError>
+    where
+        F: for<'a> FnOnce(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut f = Some(f);
+        loop {
+            if self.exhausted && !self.eof_after_buffer {
+                self.fill().await?;
+            }
+
+            let ret =
+                self.next_buffered(&mut |item| (f.take().expect("called at most once"))(item))?;
+            if let Some(ret) = ret {
+                return Ok(ret);
+            }
+        }
+    }
+
+    /// Runs the matcher over the buffered bytes, returning `None` if more bytes need to be read
+    /// and `Some(None)` at the end of the input.
+    fn next_buffered<T, F>(&mut self, f: &mut F) -> Result<Option<Option<T>>, ReadError>
+    where
+        F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut buf = &self.buffer[self.at_offset..self.filled];
+        let original_len = buf.len();
+
+        let status = match self.matcher.next(&mut buf) {
+            Ok(Ok(m)) => {
+                self.at_offset += original_len - buf.len();
+                return Ok(Some(Some(f(m))));
+            }
+            Ok(Err(status)) => Ok(status),
+            Err(e) => Err(e),
+        };
+
+        self.at_offset += original_len - buf.len();
+
+        match status? {
+            Status::IdleAtEndOfBuffer if self.eof_after_buffer => Ok(Some(None)),
+            _ if self.eof_after_buffer => Err(ReadError::UnexpectedEndOfFile),
+            _ => {
+                self.exhausted = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<IO, R> AsyncReadWrapper<IO, R>
+where
+    IO: AsyncRead + Unpin,
+{
+    /// Reads more bytes after the filled part of the buffer, first moving the unused bytes to the
+    /// front or growing the buffer if there is no room. Nothing is changed until the read
+    /// completes, so the future can be dropped at any point.
+    async fn fill(&mut self) -> Result<(), ReadError> {
+        if self.filled == self.buffer.len() {
+            // these first bytes haven't been needed for a long time
+            self.buffer.copy_within(self.at_offset..self.filled, 0);
+            self.filled -= self.at_offset;
+            self.at_offset = 0;
+        }
+
+        if self.filled == self.buffer.len() {
+            let len = self.buffer.len() + self.grow_by;
+            self.buffer.resize(len, 0);
+        }
+
+        let AsyncReadWrapper {
+            inner,
+            buffer,
+            filled,
+            ..
+        } = self;
+        let room = &mut buffer[*filled..];
+        let bytes = poll_fn(|cx| Pin::new(&mut *inner).poll_read(cx, room)).await?;
+
+        self.filled += bytes;
+        self.eof_after_buffer = bytes == 0;
+        self.exhausted = false;
+        Ok(())
+    }
+
+    pub fn into_parts(self) -> (IO, R) {
+        (self.inner, self.matcher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncReadWrapper;
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields, SlicedValue};
+    use crate::{DecodingError, FieldId, ReadError, ReadField};
+    use futures_io::AsyncRead;
+    use hex_literal::hex;
+    use std::future::Future;
+    use std::pin::{pin, Pin};
+    use std::task::{Context, Poll, Waker};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(ret) = future.as_mut().poll(&mut cx) {
+                return ret;
+            }
+        }
+    }
+
+    /// Returns one byte at a time, every other poll being pending.
+    struct Trickle<'a> {
+        input: &'a [u8],
+        ready: bool,
+    }
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = self.input.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    // 1: "abc", 2: 150, 1: "de"
+    const INPUT: [u8; 12] = hex!("0a03616263 109601 0a026465");
+
+    #[test]
+    fn awaits_sliced_fields() {
+        let inner = Trickle {
+            input: &INPUT,
+            ready: false,
+        };
+        let mut rw = AsyncReadWrapper::new(inner, MatcherFields::new(Slices).into_sliced());
+
+        let mut slices = Vec::new();
+        while let Some(slice) = block_on(rw.with_next(|m| match m.value {
+            SlicedValue::Slice(_, bytes) => Some(bytes.to_vec()),
+            _ => None,
+        }))
+        .unwrap()
+        {
+            slices.extend(slice);
+        }
+
+        assert_eq!(slices, [&b"abc"[..], b"de"]);
+    }
+
+    #[test]
+    fn dropped_read_keeps_the_bytes() {
+        let inner = Trickle {
+            input: &INPUT,
+            ready: false,
+        };
+        let mut rw = AsyncReadWrapper::new(inner, MatcherFields::new(Slices));
+
+        // poll once, which only gets the pending read, and drop
+        {
+            let mut future = pin!(rw.read_next());
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+        }
+
+        let mut count = 0;
+        while block_on(rw.read_next()).unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 3);
+
+        let mut rw = AsyncReadWrapper::new(&INPUT[..10], MatcherFields::new(Slices));
+        assert!(block_on(rw.read_next()).unwrap().is_some());
+        assert!(block_on(rw.read_next()).unwrap().is_some());
+        assert!(matches!(
+            block_on(rw.read_next()),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+    }
+}
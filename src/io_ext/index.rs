@@ -0,0 +1,507 @@
+use super::read::ReadWrapper;
+use crate::field_reader::FieldReader;
+use crate::framing::Framing;
+use crate::matcher_fields::{Matcher, MatcherFields};
+use crate::{DecodingError, DecodingErrorKind, FieldId, FieldValue, ReadError, WireType};
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom, Take};
+use std::ops::Range;
+
+/// Location of a single message in a length delimited stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    /// Zero based index of the message in the stream
+    pub index: usize,
+    /// Offset of the length prefix
+    pub prefix_offset: u64,
+    /// Offsets of the message payload
+    pub payload: Range<u64>,
+    /// True if the payload was marked as compressed by the framing
+    pub compressed: bool,
+}
+
+/// Builds an index of the messages in a length delimited stream by reading only the length
+/// prefixes and seeking over the payloads.
+///
+/// Starts from the current position of the stream.
+pub struct DelimitedIndexer<R> {
+    inner: R,
+    framing: Framing,
+    /// Offset of the next length prefix
+    offset: u64,
+    /// Length of the whole stream
+    end: u64,
+    index: usize,
+    failed: bool,
+}
+
+impl<R: Read + Seek> DelimitedIndexer<R> {
+    pub fn new(mut inner: R, framing: Framing) -> Result<Self, ReadError> {
+        let offset = inner.stream_position()?;
+        let end = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            inner,
+            framing,
+            offset,
+            end,
+            index: 0,
+            failed: false,
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_entry(&mut self) -> Result<Option<IndexEntry>, ReadError> {
+        if self.offset == self.end {
+            return Ok(None);
+        }
+
+        let mut tmp = [0u8; 10];
+        let max = self.framing.max_prefix_len();
+        let mut filled = 0;
+
+        // read as much as the longest prefix could be, the extra is seeked over below
+        while filled < max {
+            match self.inner.read(&mut tmp[filled..max]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let offset = self.offset;
+        let (consumed, header) = match self
+            .framing
+            .read_prefix(&tmp[..filled])
+            .map_err(|e| e.offset_by(offset))?
+        {
+            Ok(x) => x,
+            Err(_) if filled < max => return Err(ReadError::UnexpectedEndOfFile),
+            Err(_) => {
+                let kind = DecodingErrorKind::TooManyVarint64Bytes;
+                return Err(DecodingError::from(kind).with_offset(offset).into());
+            }
+        };
+
+        let start = self.offset + consumed as u64;
+        let end = start
+            .checked_add(header.len)
+            .filter(|end| *end <= self.end)
+            .ok_or(ReadError::UnexpectedEndOfFile)?;
+
+        self.inner.seek(SeekFrom::Start(end))?;
+
+        let entry = IndexEntry {
+            index: self.index,
+            prefix_offset: self.offset,
+            payload: start..end,
+            compressed: header.compressed,
+        };
+
+        self.offset = end;
+        self.index += 1;
+
+        Ok(Some(entry))
+    }
+}
+
+impl<R: Read + Seek> Iterator for DelimitedIndexer<R> {
+    type Item = Result<IndexEntry, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let ret = self.read_entry().transpose();
+        self.failed = matches!(ret, Some(Err(_)));
+        ret
+    }
+}
+
+/// Location of a single top level field of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldEntry {
+    pub field: FieldId,
+    pub wire_type: WireType,
+    /// Offset of the tag
+    pub offset: u64,
+    /// Offsets of the bytes of a length delimited field
+    pub data: Option<Range<u64>>,
+    /// Offset after the field
+    pub end: u64,
+}
+
+/// Builds an index of the top level fields of a message by reading only the field headers and
+/// seeking over the length delimited fields with [`FieldReader::skip_field`].
+///
+/// Starts from the current position of the stream, and the message continues to the end of it.
+pub struct FieldIndexer<R> {
+    inner: R,
+    reader: FieldReader,
+    /// Offset of the next field
+    offset: u64,
+    /// Length of the whole stream
+    end: u64,
+    failed: bool,
+}
+
+impl<R: Read + Seek> FieldIndexer<R> {
+    pub fn new(mut inner: R) -> Result<Self, ReadError> {
+        let offset = inner.stream_position()?;
+        let end = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            inner,
+            reader: FieldReader::default(),
+            offset,
+            end,
+            failed: false,
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_entry(&mut self) -> Result<Option<FieldEntry>, ReadError> {
+        if self.offset == self.end {
+            return Ok(None);
+        }
+
+        let (entry, _) =
+            read_field_entry(&mut self.inner, &mut self.reader, self.offset, self.end)?;
+        self.offset = entry.end;
+
+        Ok(Some(entry))
+    }
+}
+
+/// Reads the header of the field at `offset`, which is the current position of `inner`, leaving
+/// the position at the end of the field.
+fn read_field_entry<R: Read + Seek>(
+    inner: &mut R,
+    reader: &mut FieldReader,
+    offset: u64,
+    stream_end: u64,
+) -> Result<(FieldEntry, FieldValue), ReadError> {
+    let mut tmp = [0u8; 15];
+    let max = (stream_end.saturating_sub(offset)).min(tmp.len() as u64) as usize;
+    let mut filled = 0;
+
+    // read as much as the longest header could be, the extra is seeked back over
+    while filled < max {
+        match inner.read(&mut tmp[filled..max]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    reader.set_offset(offset);
+    let read = match reader.next(&tmp[..filled])? {
+        Ok(read) => read,
+        Err(_) => return Err(ReadError::UnexpectedEndOfFile),
+    };
+
+    let start = offset + read.consumed() as u64;
+    let end = start
+        .checked_add(read.field_len() as u64)
+        .filter(|end| *end <= stream_end)
+        .ok_or(ReadError::UnexpectedEndOfFile)?;
+
+    FieldReader::skip_field(&read, filled - read.consumed(), inner)?;
+
+    let entry = FieldEntry {
+        field: read.field_id(),
+        wire_type: read.wire_type(),
+        offset,
+        data: Some(start..end).filter(|_| read.is_length_delimited()),
+        end,
+    };
+
+    Ok((entry, read.value().clone()))
+}
+
+/// Decodes single fields at the offsets of a message in a seekable stream, for example the ones
+/// of the [`FieldEntry`] collected earlier with [`FieldIndexer`], without reading the stream from
+/// the start.
+pub struct SeekDecoder<R> {
+    inner: R,
+    reader: FieldReader,
+    /// Length of the whole stream
+    end: u64,
+}
+
+impl<R: Read + Seek> SeekDecoder<R> {
+    pub fn new(mut inner: R) -> Result<Self, ReadError> {
+        let end = inner.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            inner,
+            reader: FieldReader::default(),
+            end,
+        })
+    }
+
+    /// Reads the field with the tag at `offset`, returning its location and the value, which for
+    /// the length delimited fields is only the length, see `data` and `message`.
+    pub fn field_at(&mut self, offset: u64) -> Result<(FieldEntry, FieldValue), ReadError> {
+        if offset >= self.end {
+            return Err(ReadError::UnexpectedEndOfFile);
+        }
+
+        self.inner.seek(SeekFrom::Start(offset))?;
+        read_field_entry(&mut self.inner, &mut self.reader, offset, self.end)
+    }
+
+    /// Reads the bytes of the length delimited field.
+    ///
+    /// # Panics
+    ///
+    /// If the field is not length delimited.
+    pub fn data(&mut self, entry: &FieldEntry) -> Result<Vec<u8>, ReadError> {
+        let range = entry.data.clone().expect("not a length delimited field");
+        self.inner.seek(SeekFrom::Start(range.start))?;
+
+        let mut data = Vec::new();
+        (&mut self.inner)
+            .take(range.end - range.start)
+            .read_to_end(&mut data)?;
+        if data.len() as u64 != range.end - range.start {
+            return Err(ReadError::UnexpectedEndOfFile);
+        }
+        Ok(data)
+    }
+
+    /// Decodes the length delimited field as a message with `matcher`, reading only the bytes of
+    /// the field. The offsets of the matched values are relative to the start of the message,
+    /// `entry.data.start`.
+    ///
+    /// # Panics
+    ///
+    /// If the field is not length delimited.
+    pub fn message<M: Matcher>(
+        &mut self,
+        entry: &FieldEntry,
+        matcher: M,
+    ) -> Result<ReadWrapper<Take<&mut R>, MatcherFields<M>>, ReadError> {
+        let range = entry.data.clone().expect("not a length delimited field");
+        self.inner.seek(SeekFrom::Start(range.start))?;
+
+        let inner = (&mut self.inner).take(range.end - range.start);
+        Ok(ReadWrapper::new(inner, MatcherFields::new(matcher)))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> Iterator for FieldIndexer<R> {
+    type Item = Result<FieldEntry, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let ret = self.read_entry().transpose();
+        self.failed = matches!(ret, Some(Err(_)));
+        ret
+    }
+}
+
+/// Collects the complete index of the stream starting from the current position.
+pub fn build_index<R: Read + Seek>(
+    inner: R,
+    framing: Framing,
+) -> Result<Vec<IndexEntry>, ReadError> {
+    DelimitedIndexer::new(inner, framing)?.collect()
+}
+
+/// Reads the payloads of the last `n` messages of the stream starting from the current position.
+/// Only the length prefixes of the earlier messages are read.
+pub fn tail<R: Read + Seek>(
+    inner: R,
+    framing: Framing,
+    n: usize,
+) -> Result<Vec<(IndexEntry, Vec<u8>)>, ReadError> {
+    let mut indexer = DelimitedIndexer::new(inner, framing)?;
+    let mut last = VecDeque::with_capacity(n.min(1024));
+
+    for entry in &mut indexer {
+        if n == 0 {
+            break;
+        }
+        if last.len() == n {
+            last.pop_front();
+        }
+        last.push_back(entry?);
+    }
+
+    let mut inner = indexer.into_inner();
+    let mut ret = Vec::with_capacity(last.len());
+
+    for entry in last {
+        inner.seek(SeekFrom::Start(entry.payload.start))?;
+        let mut payload = Vec::new();
+        (&mut inner)
+            .take(entry.payload.end - entry.payload.start)
+            .read_to_end(&mut payload)?;
+        if payload.len() as u64 != entry.payload.end - entry.payload.start {
+            return Err(ReadError::UnexpectedEndOfFile);
+        }
+        ret.push((entry, payload));
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_index, tail};
+    use crate::framing::Framing;
+    use crate::ReadError;
+    use hex_literal::hex;
+    use std::io::Cursor;
+
+    #[test]
+    fn varint_delimited() {
+        let input = hex!("03616263 00 0201ff");
+        let index = build_index(Cursor::new(&input[..]), Framing::Varint).unwrap();
+        let payloads = index.iter().map(|e| e.payload.clone()).collect::<Vec<_>>();
+        assert_eq!(payloads, vec![1..4, 5..5, 6..8]);
+        assert_eq!(index[2].index, 2);
+        assert_eq!(index[2].prefix_offset, 5);
+    }
+
+    #[test]
+    fn grpc_delimited() {
+        let input = hex!("0000000001ff 0100000000");
+        let index = build_index(Cursor::new(&input[..]), Framing::Grpc).unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[0].payload, 5..6);
+        assert!(!index[0].compressed);
+        assert_eq!(index[1].payload, 11..11);
+        assert!(index[1].compressed);
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let input = hex!("03616263 0561");
+        let e = build_index(Cursor::new(&input[..]), Framing::Varint).unwrap_err();
+        assert!(matches!(e, ReadError::UnexpectedEndOfFile), "{:?}", e);
+    }
+
+    #[test]
+    fn fields_are_seeked_over() {
+        use super::FieldIndexer;
+        use crate::WireType;
+
+        // 1: 150, 2: "abc", 3: ""
+        let input = hex!("089601 1203616263 1a00");
+        let entries = FieldIndexer::new(Cursor::new(&input[..]))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let ids = entries
+            .iter()
+            .map(|e| (e.field, e.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [(1, 0), (2, 3), (3, 8)]);
+        assert_eq!(entries[0].wire_type, WireType::Varint);
+        assert_eq!(entries[0].data, None);
+        assert_eq!(entries[1].data, Some(5..8));
+        assert_eq!(entries[2].end, 10);
+
+        let e = FieldIndexer::new(Cursor::new(&input[..6]))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        assert!(matches!(e, ReadError::UnexpectedEndOfFile), "{:?}", e);
+    }
+
+    #[test]
+    fn fields_are_fetched_at_the_indexed_offsets() {
+        use super::{FieldIndexer, SeekDecoder};
+        use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, Value};
+        use crate::{DecodingError, FieldId, FieldValue, ReadField};
+
+        struct Values;
+
+        impl Matcher for Values {
+            type Tag = FieldId;
+
+            fn decide_before(
+                &mut self,
+                _offset: usize,
+                read: &ReadField<'_>,
+                _path: &[FieldId],
+            ) -> Result<Action<FieldId>, DecodingError> {
+                Ok(Action::Continue(Cont::ReadValue(read.field_id())))
+            }
+
+            fn decide_after(
+                &mut self,
+                _offset: usize,
+                _ended: Option<EndedMessage<FieldId>>,
+            ) -> Option<FieldId> {
+                None
+            }
+        }
+
+        // 1: 150, 2: { 3: 1, 4: 2 }, 5: "ab"
+        let input = hex!("089601 1204 1801 2002 2a026162");
+        let entries = FieldIndexer::new(Cursor::new(&input[..]))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut decoder = SeekDecoder::new(Cursor::new(&input[..])).unwrap();
+
+        // in any order
+        let (last, _) = decoder.field_at(entries[2].offset).unwrap();
+        assert_eq!(last, entries[2]);
+        assert_eq!(decoder.data(&last).unwrap(), b"ab");
+
+        let (first, value) = decoder.field_at(0).unwrap();
+        assert_eq!(first, entries[0]);
+        assert!(matches!(value, FieldValue::Varint(150)));
+
+        let mut message = decoder.message(&entries[1], Values).unwrap();
+        let mut values = Vec::new();
+        while let Some(m) = message.read_next().unwrap() {
+            values.push((m.tag, m.value));
+        }
+        assert!(
+            matches!(&values[..], [(3, Value::Varint(1)), (4, Value::Varint(2))]),
+            "{:?}",
+            values
+        );
+
+        let e = decoder.field_at(input.len() as u64).unwrap_err();
+        assert!(matches!(e, ReadError::UnexpectedEndOfFile), "{:?}", e);
+    }
+
+    #[test]
+    fn tail_reads_last() {
+        let input = hex!("03616263 00 0201ff");
+        let last = tail(Cursor::new(&input[..]), Framing::Varint, 2).unwrap();
+        let payloads = last.iter().map(|(_, p)| &p[..]).collect::<Vec<_>>();
+        assert_eq!(payloads, vec![&[][..], &[0x01, 0xff][..]]);
+        assert_eq!(last[1].0.index, 2);
+
+        assert_eq!(
+            tail(Cursor::new(&input[..]), Framing::Varint, 5)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+}
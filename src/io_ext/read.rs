@@ -1,102 +1,564 @@
-use crate::{ReadError, Reader, Status};
+use crate::memory::{MemoryReport, MemoryUsage, Peaks};
+use crate::Skip;
+use crate::{Introspect, ReadError, Reader, Stats, Status};
+use std::convert::TryFrom;
+use std::io::{IoSliceMut, Seek, SeekFrom};
+use std::time::{Duration, Instant};
 
 /// A poor mans `std::io::BufRead` but with a growing buffer.
+///
+/// The buffer grows only when the buffered bytes have been read and the matcher still needs more,
+/// never directly from the length of a field, so a hostile length prefix alone does not cause
+/// large allocations. The growth can be configured and capped with [`ReadWrapperBuilder`].
 pub struct ReadWrapper<IO, R> {
     /// The wrapped reader
     inner: IO,
-    /// Growable byte buffer. Growth happens by `grow_by` amount at a time.
+    /// Growable byte buffer. Growth happens as directed by `growth`.
     buffer: Vec<u8>,
     /// Processes the bytes read into the buffer.
     matcher: R,
-    /// The amount to grow the buffer by. It will need to be grown for larger fields, as there
-    /// currently isn't a way to read fields as slices.
-    grow_by: usize,
+    /// How to grow the buffer. It will need to be grown for the fields longer than the buffer
+    /// read as slices, or for the bytes retained by a gatherer.
+    growth: Growth,
+    /// The buffer is never grown beyond this
+    max_capacity: Option<usize>,
+    /// Second segment for the vectored reads, empty when not reading vectored. The bytes read
+    /// past the buffer are kept in `pending` until there is room for them in the buffer.
+    spare: Vec<u8>,
+    pending: std::ops::Range<usize>,
+    /// Set when constructed with `new_seeking` or `build_seeking`
+    seeking: Option<Seeking<IO, R>>,
+    /// Bytes read from the inner reader
+    read: u64,
+    /// Called after every read from the inner reader
+    on_fill: Option<OnFill>,
     /// Where in the buffer did we last get to
     at_offset: usize,
     /// When true, need to read more bytes
     exhausted: bool,
     /// When true, any bytes in the buffer represent the last bytes of the input stream.
     eof_after_buffer: bool,
+    /// Absolute point in time after which no more reads are started.
+    deadline: Option<Instant>,
+    /// Per `read_next` call budget, after which no more reads are started.
+    time_budget: Option<Duration>,
+    /// Called before every read to wait for the inner reader to become readable.
+    poll: Option<Box<dyn FnMut() -> std::io::Result<bool> + Send>>,
+    peaks: Peaks,
 }
 
-impl<'a, IO, R> ReadWrapper<IO, R>
-where
-    IO: std::io::Read,
-    R: Reader<'a>,
-{
-    pub fn new(inner: IO, matcher: R) -> Self {
-        let grow_by = 8192;
-        Self {
+/// `buffered` is the amount of bytes in the buffer, which includes the bytes retained for the
+/// wrapped reader and the bytes read ahead.
+impl<IO, R: Introspect> Introspect for ReadWrapper<IO, R> {
+    fn stats(&self) -> Stats {
+        Stats {
+            buffered: (self.buffer.len() + self.pending.len()) as u64,
+            ..self.matcher.stats()
+        }
+    }
+}
+
+/// `retained` is the amount of bytes in the buffer not yet consumed by the wrapped reader.
+impl<IO, R> MemoryReport for ReadWrapper<IO, R> {
+    fn memory(&self) -> MemoryUsage {
+        self.peaks.usage(
+            (self.buffer.capacity() + self.spare.len()) as u64,
+            (self.buffer.len() - self.at_offset) as u64,
+        )
+    }
+}
+
+/// The `std::io::Seek` of the inner reader and the `Skip` of the matcher, captured when they are
+/// known to be implemented.
+struct Seeking<IO, R> {
+    seek: fn(&mut IO, SeekFrom) -> std::io::Result<u64>,
+    skipped: fn(&mut R, u64),
+    /// Length of the stream when last checked
+    end: Option<u64>,
+    /// Bytes seeked over instead of reading
+    seeked: u64,
+}
+
+type OnFill = Box<dyn FnMut(&Progress) + Send>;
+
+/// How far a [`ReadWrapper`] has got in the inner reader, see `ReadWrapper::progress`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Position in the inner reader: the bytes read from it and seeked over
+    pub stream_offset: u64,
+    /// Bytes the matcher has advanced over
+    pub consumed: u64,
+    /// Bytes read from the inner reader but not yet advanced over by the matcher, including the
+    /// bytes retained by it
+    pub buffered: u64,
+}
+
+/// How the buffer of a [`ReadWrapper`] grows once it is full of bytes still needed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Growth {
+    /// Grows by this many bytes at a time
+    Additive(usize),
+    /// Doubles the capacity
+    Doubling,
+}
+
+impl Growth {
+    fn grow(&self, capacity: usize) -> usize {
+        match *self {
+            Growth::Additive(amount) => capacity.saturating_add(amount),
+            Growth::Doubling => capacity.saturating_mul(2).max(1),
+        }
+    }
+}
+
+/// Configures the buffer of a [`ReadWrapper`]. The defaults are the ones of `ReadWrapper::new`: an
+/// initial capacity of 8192 bytes, growing by the same amount without a limit.
+#[derive(Debug, Clone)]
+pub struct ReadWrapperBuilder {
+    initial_capacity: usize,
+    growth: Growth,
+    max_capacity: Option<usize>,
+    spare_capacity: usize,
+}
+
+impl Default for ReadWrapperBuilder {
+    fn default() -> Self {
+        ReadWrapperBuilder {
+            initial_capacity: 8192,
+            growth: Growth::Additive(8192),
+            max_capacity: None,
+            spare_capacity: 0,
+        }
+    }
+}
+
+impl ReadWrapperBuilder {
+    pub fn initial_capacity(mut self, capacity: usize) -> Self {
+        self.initial_capacity = capacity;
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If the growth is `Growth::Additive(0)`.
+    pub fn growth(mut self, growth: Growth) -> Self {
+        assert_ne!(growth, Growth::Additive(0), "growth cannot be zero");
+        self.growth = growth;
+        self
+    }
+
+    /// Sets the capacity the buffer is never grown beyond. When the buffer of this size is full of
+    /// bytes still needed, for example for a field longer than it, reading errors with
+    /// `ReadError::BufferTooSmall`.
+    pub fn max_capacity(mut self, max: Option<usize>) -> Self {
+        self.max_capacity = max;
+        self
+    }
+
+    /// Fills the buffer with `std::io::Read::read_vectored`, with a second segment of this many
+    /// bytes after the room in the buffer, so that the sources supporting the vectored reads such
+    /// as `TcpStream` can read more with one call when the buffer is almost full. The bytes read
+    /// into the second segment are copied into the buffer as it makes room. Zero, the default,
+    /// reads with `std::io::Read::read`.
+    pub fn spare_capacity(mut self, capacity: usize) -> Self {
+        self.spare_capacity = capacity;
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If the initial capacity is over the maximum capacity.
+    pub fn build<IO: std::io::Read, R>(self, inner: IO, matcher: R) -> ReadWrapper<IO, R> {
+        if let Some(max) = self.max_capacity {
+            assert!(
+                self.initial_capacity <= max,
+                "initial capacity cannot be over the maximum capacity"
+            );
+        }
+
+        ReadWrapper {
             inner,
-            buffer: Vec::with_capacity(grow_by),
+            buffer: Vec::with_capacity(self.initial_capacity),
             matcher,
-            grow_by,
+            growth: self.growth,
+            max_capacity: self.max_capacity,
+            spare: vec![0; self.spare_capacity],
+            pending: 0..0,
+            seeking: None,
+            read: 0,
+            on_fill: None,
             at_offset: 0,
             exhausted: false,
             eof_after_buffer: false,
+            deadline: None,
+            time_budget: None,
+            poll: None,
+            peaks: Peaks::default(),
+        }
+    }
+
+    /// Like `build` but the fields skipped by the matcher are seeked over, see
+    /// `ReadWrapper::new_seeking`.
+    pub fn build_seeking<IO, R>(self, inner: IO, matcher: R) -> ReadWrapper<IO, R>
+    where
+        IO: std::io::Read + Seek,
+        R: Skip,
+    {
+        let mut rw = self.build(inner, matcher);
+        rw.seeking = Some(Seeking {
+            seek: <IO as Seek>::seek,
+            skipped: <R as Skip>::skipped,
+            end: None,
+            seeked: 0,
+        });
+        rw
+    }
+}
+
+impl<IO, R> ReadWrapper<IO, R>
+where
+    IO: std::io::Read + Seek,
+    R: Skip,
+{
+    /// Reads from a seekable source, seeking over the bytes of `Status::CanSkip` with
+    /// `SeekFrom::Current` instead of reading them. Seeking past the end of the stream is
+    /// reported as `ReadError::UnexpectedEndOfFile`.
+    pub fn new_seeking(inner: IO, matcher: R) -> Self {
+        ReadWrapperBuilder::default().build_seeking(inner, matcher)
+    }
+}
+
+impl<IO, R> ReadWrapper<IO, R>
+where
+    IO: std::io::Read,
+{
+    pub fn new(inner: IO, matcher: R) -> Self {
+        ReadWrapperBuilder::default().build(inner, matcher)
+    }
+
+    /// The amount of bytes seeked over instead of reading them, always zero if not constructed
+    /// with `new_seeking` or `build_seeking`.
+    pub fn seeked(&self) -> u64 {
+        self.seeking.as_ref().map(|s| s.seeked).unwrap_or(0)
+    }
+
+    /// Position in the inner reader, relative to where it was when wrapped.
+    pub fn stream_offset(&self) -> u64 {
+        self.read + self.seeked()
+    }
+
+    /// Bytes read from the inner reader but not yet advanced over by the matcher. Unlike
+    /// `Stats::buffered` this does not include the bytes already advanced over but not yet
+    /// dropped from the buffer.
+    pub fn bytes_buffered(&self) -> u64 {
+        (self.buffer.len() - self.at_offset + self.pending.len()) as u64
+    }
+
+    /// Bytes of the inner reader the matcher has advanced over, including the seeked ones.
+    pub fn bytes_consumed(&self) -> u64 {
+        self.stream_offset() - self.bytes_buffered()
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            stream_offset: self.stream_offset(),
+            consumed: self.bytes_consumed(),
+            buffered: self.bytes_buffered(),
         }
     }
 
+    /// Sets a function to be called with the [`Progress`] after every read from the inner
+    /// `std::io::Read`, for example to report the progress of a long running extraction.
+    pub fn set_on_fill<F>(&mut self, on_fill: Option<F>)
+    where
+        F: FnMut(&Progress) + Send + 'static,
+    {
+        self.on_fill = on_fill.map(|f| Box::new(f) as Box<_>);
+    }
+
+    /// Sets an absolute deadline after which `read_next` returns `ReadError::DeadlineExceeded`
+    /// instead of reading more from the inner `std::io::Read`. Items which can be produced from the
+    /// already buffered bytes are still returned.
+    ///
+    /// The deadline is only checked in between the reads; a blocking read cannot be interrupted.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Sets a time budget for each call to `read_next`, see `set_deadline` for details.
+    pub fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    /// Sets a function to be called before every read from the inner `std::io::Read`, which should
+    /// wait until the reader becomes readable or a timeout of its choosing expires, returning
+    /// `false` on the expiry. An expiry results in `ReadError::TimedOut`.
+    ///
+    /// Read timeouts configured on the inner reader, such as `TcpStream::set_read_timeout`, are
+    /// reported as `ReadError::TimedOut` even without a poll function.
+    pub fn set_poll<F>(&mut self, poll: Option<F>)
+    where
+        F: FnMut() -> std::io::Result<bool> + Send + 'static,
+    {
+        self.poll = poll.map(|f| Box::new(f) as Box<_>);
+    }
+
+    /// Sets a function to be called whenever the peak capacity or the peak retained size grows,
+    /// with the [`MemoryUsage`] at that point.
+    pub fn set_on_peak<F>(&mut self, on_peak: Option<F>)
+    where
+        F: FnMut(&MemoryUsage) + Send + 'static,
+    {
+        self.peaks.set_on_peak(on_peak);
+    }
+
+    /// True if the buffer is filled with vectored reads, see
+    /// `ReadWrapperBuilder::spare_capacity`.
+    pub fn is_read_vectored(&self) -> bool {
+        !self.spare.is_empty()
+    }
+
+    fn call_deadline(&self) -> Option<Instant> {
+        let budgeted = self.time_budget.map(|budget| Instant::now() + budget);
+        match (self.deadline, budgeted) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (Some(x), None) | (None, Some(x)) => Some(x),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the inner reader, discarding the bytes already read from it into the buffer, see
+    /// `into_parts`.
+    pub fn into_inner(self) -> IO {
+        self.inner
+    }
+
+    /// Returns the inner reader, the bytes already read from it but not advanced over by the
+    /// matcher, and the matcher. The bytes are the ones the next read from the inner reader would
+    /// have followed, so the stream can be handed over to another parser without losing any.
+    ///
+    /// The bytes retained by the matcher, such as the window of a gatherer or a partially buffered
+    /// slice, are included.
+    pub fn into_parts(self) -> (IO, Vec<u8>, R) {
+        let ReadWrapper {
+            inner,
+            mut buffer,
+            matcher,
+            at_offset,
+            spare,
+            pending,
+            ..
+        } = self;
+
+        buffer.drain(..at_offset);
+        buffer.extend_from_slice(&spare[pending]);
+        (inner, buffer, matcher)
+    }
+}
+
+/// The reader needs to work with any lifetime of the buffer, as the buffer is borrowed only for
+/// the duration of each call; the items borrowing the buffer are given to a function with
+/// `with_next` and `next_batch`.
+impl<IO, R> ReadWrapper<IO, R>
+where
+    IO: std::io::Read,
+    R: for<'a> Reader<'a>,
+{
+    /// Returns the next item of a reader whose items do not borrow the buffer, such as
+    /// `MatcherFields`, see `with_next` for the others.
+    ///
     /// There might be Interrupted errors while reading, which are **not** ignored like the
     /// `std::io::BufRead` does for example. After the interruption the next can be called again
     /// only if the inner `std::io::Read` can continue reading where it was left off.
     ///
-    /// # Safety
+    /// After a `ReadError::DeadlineExceeded` or `ReadError::TimedOut` all of the state is retained
+    /// and the call can be retried.
+    pub fn read_next<T>(&mut self) -> Result<Option<T>, ReadError>
+    where
+        R: for<'a> Reader<'a, Returned = T>,
+    {
+        self.with_next(|item| item)
+    }
+
+    /// Calls `f` with the next item, which can borrow the buffer, returning what `f` returned or
+    /// `None` at the end of the input. See `read_next` for the errors.
+    pub fn with_next<T, F>(&mut self, f: F) -> Result<Option<T>, ReadError>
+    where
+        F: for<'a> FnOnce(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut f = Some(f);
+        self.read_one(&mut |item| (f.take().expect("called at most once"))(item))
+    }
+
+    /// Reads up to `max` items into `out`, converting them with `convert`, for example with
+    /// `|m| m.into_owned()` to have items which do not borrow the buffer. Only the first item reads
+    /// more from the inner `std::io::Read` if needed, the rest are the ones which can be produced
+    /// from the already buffered bytes.
     ///
-    /// The current implementation requires the use of `unsafe`, so this method might not be sound.
-    /// Any obvious cases dont seem to compile, please see `tests/ui` for those and report an issue
-    /// if you find a new case which shouldn't work!
-    pub fn read_next(&'a mut self) -> Result<Option<R::Returned>, ReadError> {
-        use std::mem::transmute;
+    /// Returns the number of items appended, zero meaning the end of the input. On an error the
+    /// items read before it are left in `out`, see `read_next` for the errors.
+    pub fn next_batch<T, F>(
+        &mut self,
+        out: &mut Vec<T>,
+        max: usize,
+        mut convert: F,
+    ) -> Result<usize, ReadError>
+    where
+        F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut count = 0;
+        while count < max {
+            let next = if count == 0 {
+                self.read_one(&mut convert)?
+            } else {
+                match self.next_buffered(&mut convert)? {
+                    Some(next) => next,
+                    None => break,
+                }
+            };
+
+            match next {
+                Some(item) => {
+                    out.push(item);
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        Ok(count)
+    }
+
+    fn read_one<T, F>(&mut self, f: &mut F) -> Result<Option<T>, ReadError>
+    where
+        F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+    {
+        let deadline = self.call_deadline();
         loop {
+            if let Some(deadline) = deadline {
+                if self.needs_read() && Instant::now() >= deadline {
+                    return Err(ReadError::DeadlineExceeded);
+                }
+            }
+
             self.maybe_fill()?;
 
-            unsafe {
-                // We are trying to work around a compiler "bug" which would otherwise grow the
-                // region of shared borrow of `buf` to whole function (NLL limitation). I think
-                // this is NLL problem #3 in Nikos polonius/NLL related blog posts and different
-                // variants of it have been reported and have the tag 'NLL-polonius'.
-                //
-                // The shared borrow is changed into 'static for the *duration* of matcher.next
-                // call so that it doesn't become hit the NLL limitation. When we cast it back to
-                // the "shorter" lifetime right before returning, it should have the same effect as
-                // using the `'a` the whole time.
-                //
-                // I am a bit worried of someone coming over and writing a Gatherer<'static>.. But
-                // not sure if that could fit here, wouldn't &'static mut self become an issue?
-                let mut buf = transmute::<&'_ _, &'static [u8]>(&self.buffer[self.at_offset..]);
-
-                // the matcher might advance this
-                let original_len = buf.len();
-
-                let ret = self.matcher.next(&mut buf);
-
-                let buf_len = buf.len();
-                let consumed = original_len - buf_len;
-
-                // consumed can be zero, in case the gatherer would only need more buffer
-                self.at_offset += consumed;
-
-                match ret? {
-                    Ok(m) => return Ok(Some(m)),
-                    Err(Status::IdleAtEndOfBuffer) if self.eof_after_buffer => return Ok(None),
-                    Err(Status::NeedMoreBytes) if self.eof_after_buffer => {
-                        return Err(ReadError::UnexpectedEndOfFile)
-                    }
-                    Err(Status::IdleAtEndOfBuffer) | Err(Status::NeedMoreBytes) => {
-                        self.exhausted = true
-                    }
+            if let Some(ret) = self.next_buffered(f)? {
+                return Ok(ret);
+            }
+        }
+    }
+
+    /// Runs the matcher over the buffered bytes, returning `None` if more bytes need to be read
+    /// and `Some(None)` at the end of the input.
+    fn next_buffered<T, F>(&mut self, f: &mut F) -> Result<Option<Option<T>>, ReadError>
+    where
+        F: for<'a> FnMut(<R as Reader<'a>>::Returned) -> T,
+    {
+        let mut buf = &self.buffer[self.at_offset..];
+
+        // the matcher might advance this
+        let original_len = buf.len();
+
+        // the item is given to `f` while the buffer is borrowed, so that the borrow ends here when
+        // more bytes need to be read into the buffer
+        let status = match self.matcher.next(&mut buf) {
+            Ok(Ok(m)) => {
+                self.at_offset += original_len - buf.len();
+                return Ok(Some(Some(f(m))));
+            }
+            Ok(Err(status)) => Ok(status),
+            Err(e) => Err(e),
+        };
+
+        // consumed can be zero, in case the gatherer would only need more buffer
+        self.at_offset += original_len - buf.len();
+
+        match status? {
+            Status::IdleAtEndOfBuffer if self.eof_after_buffer => Ok(Some(None)),
+            Status::NeedMoreBytes(_) | Status::CanSkip(_) if self.eof_after_buffer => {
+                Err(ReadError::UnexpectedEndOfFile)
+            }
+            Status::CanSkip(amount) if self.seeking.is_some() => {
+                self.seek_over(amount)?;
+                self.exhausted = true;
+                Ok(None)
+            }
+            Status::IdleAtEndOfBuffer | Status::NeedMoreBytes(_) | Status::CanSkip(_) => {
+                self.exhausted = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<IO, R> ReadWrapper<IO, R>
+where
+    IO: std::io::Read,
+{
+    /// Skips the bytes read ahead into the spare segment and seeks over the rest. If the seek
+    /// fails, the matcher has not been told and will ask to skip again.
+    fn seek_over(&mut self, amount: u64) -> Result<(), ReadError> {
+        let seeking = self
+            .seeking
+            .as_mut()
+            .expect("only seeking when constructed so");
+
+        // nothing is retained by the matcher so the buffer was read through
+        debug_assert_eq!(self.at_offset, self.buffer.len());
+
+        let from_pending = (self.pending.len() as u64).min(amount);
+        let rest = amount - from_pending;
+
+        if rest > 0 {
+            let offset = i64::try_from(rest).map_err(|_| ReadError::UnexpectedEndOfFile)?;
+            let position = (seeking.seek)(&mut self.inner, SeekFrom::Current(offset))?;
+
+            let end = match seeking.end {
+                Some(end) if position <= end => end,
+                // the stream could have grown since
+                _ => {
+                    let end = (seeking.seek)(&mut self.inner, SeekFrom::End(0))?;
+                    (seeking.seek)(&mut self.inner, SeekFrom::Start(position))?;
+                    seeking.end = Some(end);
+                    end
                 }
+            };
+
+            if position > end {
+                return Err(ReadError::UnexpectedEndOfFile);
             }
         }
+
+        self.pending.start += from_pending as usize;
+        (seeking.skipped)(&mut self.matcher, amount);
+        seeking.seeked += rest;
+
+        self.buffer.clear();
+        self.at_offset = 0;
+        Ok(())
+    }
+
+    fn needs_fill(&self) -> bool {
+        self.exhausted && !self.eof_after_buffer
+    }
+
+    /// True if the next fill needs to read from the inner reader.
+    fn needs_read(&self) -> bool {
+        self.needs_fill() && self.pending.is_empty()
     }
 
     fn maybe_fill(&mut self) -> Result<(), ReadError> {
-        use std::iter::repeat;
+        use std::iter::repeat_n;
+
+        if self.needs_fill() {
+            // the allocation can be larger than asked for
+            let capacity = match self.max_capacity {
+                Some(max) => self.buffer.capacity().min(max),
+                None => self.buffer.capacity(),
+            };
 
-        if self.exhausted && !self.eof_after_buffer {
             let mut len_before = self.buffer.len();
-            let mut needed_zeroes = self.buffer.capacity() - len_before;
+            let mut needed_zeroes = capacity - len_before;
 
             if needed_zeroes == 0 {
                 // we are at capacity; try draining any unused bytes if that'd help
@@ -106,29 +568,467 @@ where
                 self.at_offset = 0;
                 len_before = self.buffer.len();
 
-                needed_zeroes = self.buffer.capacity() - len_before;
+                needed_zeroes = capacity - len_before;
             }
 
             if needed_zeroes == 0 {
                 // growing only after we are certain there's no other way might cause some
                 // reprocessing but might be the optimal strategy, or silly either way
-                needed_zeroes += self.grow_by;
+                let grown = self.growth.grow(capacity);
+                let grown = match self.max_capacity {
+                    Some(max) => grown.min(max),
+                    None => grown,
+                };
+
+                if grown == capacity {
+                    return Err(ReadError::BufferTooSmall { capacity });
+                }
+
+                needed_zeroes = grown - len_before;
+                self.buffer.reserve_exact(needed_zeroes);
             }
 
             // only read N bytes at a time
             //needed_zeroes = needed_zeroes.min(8);
 
-            self.buffer.extend(repeat(0).take(needed_zeroes));
+            if !self.pending.is_empty() {
+                // read earlier into the spare segment
+                let bytes = needed_zeroes.min(self.pending.len());
+                let from = self.pending.start..self.pending.start + bytes;
+                self.buffer.extend_from_slice(&self.spare[from]);
+                self.pending.start += bytes;
+
+                self.exhausted = false;
+                self.peaks.update(
+                    (self.buffer.capacity() + self.spare.len()) as u64,
+                    (self.buffer.len() - self.at_offset) as u64,
+                );
+                return Ok(());
+            }
+
+            if let Some(poll) = self.poll.as_mut() {
+                if !poll()? {
+                    return Err(ReadError::TimedOut);
+                }
+            }
+
+            self.buffer.extend(repeat_n(0, needed_zeroes));
+
+            let read = if self.spare.is_empty() {
+                self.inner.read(&mut self.buffer[len_before..])
+            } else {
+                let mut segments = [
+                    IoSliceMut::new(&mut self.buffer[len_before..]),
+                    IoSliceMut::new(&mut self.spare),
+                ];
+                self.inner.read_vectored(&mut segments)
+            };
 
-            let bytes = self.inner.read(&mut self.buffer[len_before..])?;
+            let bytes = match read {
+                Ok(bytes) if bytes > needed_zeroes => {
+                    self.pending = 0..bytes - needed_zeroes;
+                    needed_zeroes
+                }
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    // don't leave the zeroes around for a retry to find
+                    self.buffer.truncate(len_before);
+                    return Err(match e.kind() {
+                        // unix reports the socket read timeouts as WouldBlock
+                        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => {
+                            ReadError::TimedOut
+                        }
+                        _ => e.into(),
+                    });
+                }
+            };
 
             self.eof_after_buffer = bytes == 0;
+            self.exhausted = false;
             self.buffer.truncate(len_before + bytes);
+            self.read += (bytes + self.pending.len()) as u64;
+            self.peaks.update(
+                (self.buffer.capacity() + self.spare.len()) as u64,
+                (self.buffer.len() - self.at_offset) as u64,
+            );
+
+            if self.on_fill.is_some() {
+                let progress = self.progress();
+                if let Some(on_fill) = self.on_fill.as_mut() {
+                    on_fill(&progress);
+                }
+            }
         }
         Ok(())
     }
+}
 
-    pub fn into_inner(self) -> IO {
-        self.inner
+#[cfg(test)]
+mod tests {
+    use super::ReadWrapper;
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields, Value};
+    use crate::{DecodingError, FieldId, FieldValue, Introspect, ReadError, ReadField};
+    use std::time::Instant;
+
+    struct AllValues;
+
+    impl Matcher for AllValues {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn deadline_exceeded_is_retryable() {
+        let mut input = Vec::new();
+        input.extend(FieldValue::Varint(1).output_with_field_id(1));
+        input.extend(FieldValue::Varint(2).output_with_field_id(1));
+
+        let mut rw = ReadWrapper::new(&input[..], MatcherFields::new(AllValues));
+        rw.set_deadline(Some(Instant::now()));
+
+        let e = rw.read_next().unwrap_err();
+        assert!(matches!(e, ReadError::DeadlineExceeded), "{:?}", e);
+        assert!(e.is_retryable());
+
+        rw.set_deadline(None);
+
+        let mut values = Vec::new();
+        while let Some(m) = rw.read_next().unwrap() {
+            values.push(m.value);
+        }
+
+        assert!(
+            matches!(&values[..], [Value::Varint(1), Value::Varint(2)]),
+            "{:?}",
+            values
+        );
+    }
+
+    #[test]
+    fn adversarial_length_does_not_allocate() {
+        use hex_literal::hex;
+
+        // field 1 claims almost u32::MAX bytes, still within the offsets of offset32, but has
+        // only three
+        let input = hex!("0a f0ffffff0f 616263");
+
+        let mut rw = ReadWrapper::new(&input[..], MatcherFields::new(AllValues));
+        assert!(matches!(
+            rw.read_next(),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+        assert!(rw.buffer.capacity() <= 8192);
+    }
+
+    #[test]
+    fn wrapper_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<ReadWrapper<std::fs::File, MatcherFields<AllValues>>>();
+    }
+
+    #[test]
+    fn timed_out_poll_keeps_state() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        // a field split over two reads, with the poll expiring in between
+        let mut input = Vec::new();
+        input.extend(FieldValue::Varint(300).output_with_field_id(1));
+        input.extend(FieldValue::Varint(2).output_with_field_id(1));
+
+        struct Chunked<'a>(&'a [u8]);
+
+        impl std::io::Read for Chunked<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(2);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let ready = Arc::new(AtomicBool::new(true));
+        let polled = ready.clone();
+
+        let mut rw = ReadWrapper::new(Chunked(&input), MatcherFields::new(AllValues));
+        rw.set_poll(Some(move || Ok(polled.swap(false, Ordering::SeqCst))));
+
+        let e = rw.read_next().unwrap_err();
+        assert!(matches!(e, ReadError::TimedOut), "{:?}", e);
+        assert!(e.is_retryable());
+
+        ready.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            rw.read_next().unwrap(),
+            Some(crate::matcher_fields::Matched {
+                value: Value::Varint(300),
+                ..
+            })
+        ));
+
+        let stats = rw.stats();
+        assert_eq!((stats.offset, stats.items, stats.mid_field), (3, 1, false));
+    }
+
+    #[test]
+    fn next_batch_into_owned() {
+        use crate::matcher_fields::OwnedValue;
+
+        let mut input = Vec::new();
+        for _ in 0..3 {
+            input.extend_from_slice(&[0x12, 0x01, b'a']);
+        }
+
+        let mut rw = ReadWrapper::new(&input[..], MatcherFields::new(AllValues).into_sliced());
+
+        let mut out = Vec::new();
+        loop {
+            match rw.next_batch(&mut out, 2, |m| m.into_owned()).unwrap() {
+                0 => break,
+                n => assert!(n <= 2),
+            }
+        }
+
+        assert_eq!(out.len(), 3);
+        assert!(out
+            .iter()
+            .all(|m| matches!(&m.value, OwnedValue::Slice(_, bytes) if bytes == b"a")));
+    }
+
+    #[test]
+    fn vectored_reads_fill_the_spare_segment() {
+        use super::ReadWrapperBuilder;
+        use std::io::IoSliceMut;
+
+        /// Counts the calls, reading into as many segments as given.
+        struct Vectored<'a>(&'a [u8], usize);
+
+        impl std::io::Read for Vectored<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.read_vectored(&mut [IoSliceMut::new(buf)])
+            }
+
+            fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> std::io::Result<usize> {
+                self.1 += 1;
+                let mut total = 0;
+                for buf in bufs {
+                    let n = self.0.len().min(buf.len());
+                    buf[..n].copy_from_slice(&self.0[..n]);
+                    self.0 = &self.0[n..];
+                    total += n;
+                }
+                Ok(total)
+            }
+        }
+
+        let mut input = Vec::new();
+        for i in 0..10 {
+            input.extend(FieldValue::Varint(i).output_with_field_id(1));
+        }
+
+        let read_all = |spare| {
+            let mut rw = ReadWrapperBuilder::default()
+                .initial_capacity(4)
+                .spare_capacity(spare)
+                .build(Vectored(&input, 0), MatcherFields::new(AllValues));
+            assert_eq!(rw.is_read_vectored(), spare > 0);
+
+            let mut values = Vec::new();
+            while let Some(m) = rw.read_next().unwrap() {
+                if let Value::Varint(x) = m.value {
+                    values.push(x);
+                }
+            }
+            assert_eq!(values, (0..10).collect::<Vec<_>>());
+            rw.into_inner().1
+        };
+
+        // 20 bytes four at a time, and the zero read at the end
+        assert_eq!(read_all(0), 6);
+        assert_eq!(read_all(16), 2);
+    }
+
+    #[test]
+    fn skipped_fields_are_seeked_over() {
+        use super::ReadWrapperBuilder;
+        use std::io::{Cursor, Read};
+
+        /// Reads field 1 and skips the others.
+        struct OnlyFirst;
+
+        impl Matcher for OnlyFirst {
+            type Tag = ();
+
+            fn decide_before(
+                &mut self,
+                _offset: usize,
+                read: &ReadField<'_>,
+                _path: &[FieldId],
+            ) -> Result<Action<()>, DecodingError> {
+                Ok(match read.field_id() {
+                    1 => Action::Continue(Cont::ReadValue(())),
+                    _ => Action::Skip(()),
+                })
+            }
+
+            fn decide_after(
+                &mut self,
+                _offset: usize,
+                _ended: Option<EndedMessage<()>>,
+            ) -> Option<()> {
+                None
+            }
+        }
+
+        // 1: 1, 2: 1000 bytes, 1: 2
+        let mut input = Vec::new();
+        input.extend(FieldValue::Varint(1).output_with_field_id(1));
+        input.extend(FieldValue::DataLength(1000).output_with_field_id(2));
+        input.extend(std::iter::repeat_n(0xff, 1000));
+        input.extend(FieldValue::Varint(2).output_with_field_id(1));
+
+        let mut rw = ReadWrapperBuilder::default()
+            .initial_capacity(16)
+            .build_seeking(Cursor::new(&input), MatcherFields::new(OnlyFirst));
+
+        let mut values = Vec::new();
+        while let Some(m) = rw.read_next().unwrap() {
+            values.push(m.value);
+        }
+
+        // the skipped field is reported once it has been seeked over
+        assert!(
+            matches!(
+                &values[..],
+                [Value::Varint(1), Value::Slice(r), Value::Varint(2)] if *r == (5..1005)
+            ),
+            "{:?}",
+            values
+        );
+        // the first read had 11 bytes of the skipped field
+        assert_eq!(rw.seeked(), 1000 - 11);
+        assert_eq!(rw.stats().offset, input.len() as u64);
+
+        // claims more than there is left
+        let mut truncated = Cursor::new(&input[..100]);
+        truncated.read_exact(&mut [0; 2]).unwrap();
+        let mut rw = ReadWrapper::new_seeking(truncated, MatcherFields::new(OnlyFirst));
+        assert!(matches!(
+            rw.read_next(),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+    }
+
+    #[test]
+    fn progress_is_reported_per_fill() {
+        use super::{Progress, ReadWrapperBuilder};
+        use std::sync::{Arc, Mutex};
+
+        let mut input = Vec::new();
+        for i in 0..6 {
+            input.extend(FieldValue::Varint(i).output_with_field_id(1));
+        }
+
+        let mut rw = ReadWrapperBuilder::default()
+            .initial_capacity(4)
+            .build(&input[..], MatcherFields::new(AllValues));
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let on_fill = reported.clone();
+        rw.set_on_fill(Some(move |p: &Progress| on_fill.lock().unwrap().push(*p)));
+
+        assert!(rw.read_next().unwrap().is_some());
+        assert_eq!(
+            rw.progress(),
+            Progress {
+                stream_offset: 4,
+                consumed: 2,
+                buffered: 2
+            }
+        );
+
+        while rw.read_next().unwrap().is_some() {}
+
+        let reported = reported.lock().unwrap();
+        // three reads of four bytes, and the one at the end
+        assert_eq!(reported.len(), 4);
+        assert_eq!(reported[0].stream_offset, 4);
+        assert_eq!(
+            reported.last().copied(),
+            Some(Progress {
+                stream_offset: 12,
+                consumed: 12,
+                buffered: 0
+            })
+        );
+        assert_eq!(rw.bytes_consumed(), 12);
+    }
+
+    #[test]
+    fn into_parts_keeps_the_residue() {
+        use super::ReadWrapperBuilder;
+        use std::io::Read;
+
+        let mut input = Vec::new();
+        input.extend(FieldValue::Varint(1).output_with_field_id(1));
+        input.extend(FieldValue::Varint(2).output_with_field_id(1));
+        input.extend_from_slice(b"trailer");
+
+        let mut rw = ReadWrapperBuilder::default()
+            .initial_capacity(4)
+            .spare_capacity(4)
+            .build(&input[..], MatcherFields::new(AllValues));
+
+        assert!(rw.read_next().unwrap().is_some());
+
+        let (mut rest, residue, _) = rw.into_parts();
+        // the second field and the start of the trailer read into the spare segment
+        assert_eq!(residue, [0x08, 0x02, b't', b'r', b'a', b'i']);
+
+        let mut trailer = residue[2..].to_vec();
+        rest.read_to_end(&mut trailer).unwrap();
+        assert_eq!(trailer, b"trailer");
+    }
+
+    #[test]
+    fn doubling_up_to_the_max_capacity() {
+        use super::{Growth, ReadWrapperBuilder};
+        use hex_literal::hex;
+
+        // 1: "abcde", 1: 10 bytes
+        let input = hex!("0a056162636465 0a0a 00010203040506070809");
+
+        let mut rw = ReadWrapperBuilder::default()
+            .initial_capacity(2)
+            .growth(Growth::Doubling)
+            .max_capacity(Some(8))
+            .build(&input[..], MatcherFields::new(AllValues).into_sliced());
+
+        let first = rw.with_next(|m| m.into_owned()).unwrap().unwrap();
+        assert!(
+            matches!(&first.value, crate::matcher_fields::OwnedValue::Slice(_, bytes) if bytes == b"abcde")
+        );
+        assert_eq!(rw.buffer.capacity(), 8);
+
+        assert!(matches!(
+            rw.with_next(|m| m.into_owned()),
+            Err(ReadError::BufferTooSmall { capacity: 8 })
+        ));
     }
 }
@@ -0,0 +1,241 @@
+use crate::gather_fields::{GatheredFields, Gatherer};
+use crate::matcher_fields::Matcher;
+use crate::{Introspect, ReadError, Reader, Stats, Status};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Reads the values of [`GatheredFields`] from a seekable source, seeking over the fields the
+/// matcher skips instead of reading them. The skipped fields can only be seeked over when the
+/// gatherer retains nothing of the buffer, see `Status::CanSkip`, so the fields in between the
+/// ranges held by the gatherer are read as usual.
+///
+/// The values returned by the gatherer cannot borrow the buffer, use for example
+/// [`OwnedGatherer`](crate::gather_fields::OwnedGatherer).
+///
+/// Starts from the current position of the stream.
+pub struct GatheredSeekFields<IO, M: Matcher, G> {
+    inner: IO,
+    fields: GatheredFields<M, G>,
+    /// The bytes read from the source, ending at `position`
+    buffer: Vec<u8>,
+    /// Where in the buffer the next call to the fields starts
+    at_offset: usize,
+    /// How many bytes to read at a time
+    read_size: usize,
+    /// Position of the source
+    position: u64,
+    /// Length of the whole stream
+    end: u64,
+    /// Bytes seeked over instead of reading
+    seeked: u64,
+    eof: bool,
+}
+
+/// `buffered` is the amount of bytes in the buffer, which includes the bytes retained for the
+/// gatherer and the bytes read ahead.
+impl<IO, M: Matcher, G> Introspect for GatheredSeekFields<IO, M, G> {
+    fn stats(&self) -> Stats {
+        Stats {
+            buffered: self.buffer.len() as u64,
+            ..self.fields.stats()
+        }
+    }
+}
+
+impl<IO, M, G> GatheredSeekFields<IO, M, G>
+where
+    IO: Read + Seek,
+    M: Matcher,
+{
+    pub fn new(mut inner: IO, fields: GatheredFields<M, G>) -> Result<Self, ReadError> {
+        let position = inner.stream_position()?;
+        let end = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(position))?;
+        Ok(Self {
+            inner,
+            fields,
+            buffer: Vec::new(),
+            at_offset: 0,
+            read_size: 8192,
+            position,
+            end,
+            seeked: 0,
+            eof: false,
+        })
+    }
+
+    /// Sets how many bytes are read at a time. With a smaller size less is read ahead of the fields
+    /// which could be seeked over.
+    ///
+    /// # Panics
+    ///
+    /// If the size is zero.
+    pub fn set_read_size(&mut self, size: usize) {
+        assert!(size > 0, "read size cannot be zero");
+        self.read_size = size;
+    }
+
+    /// The amount of bytes seeked over instead of reading them.
+    pub fn seeked(&self) -> u64 {
+        self.seeked
+    }
+
+    /// Returns the next gathered value, or `None` at the end of the stream.
+    pub fn read_next<T>(&mut self) -> Result<Option<T>, ReadError>
+    where
+        G: for<'a> Gatherer<'a, Tag = M::Tag, Returned = T>,
+    {
+        loop {
+            let mut buf = &self.buffer[self.at_offset..];
+            let before = buf.len();
+            let ret = self.fields.next(&mut buf)?;
+            self.at_offset += before - buf.len();
+
+            match ret {
+                Ok(value) => return Ok(Some(value)),
+                Err(Status::IdleAtEndOfBuffer) if self.eof => return Ok(None),
+                Err(_) if self.eof => return Err(ReadError::UnexpectedEndOfFile),
+                Err(Status::CanSkip(amount)) => self.seek_over(amount)?,
+                Err(Status::IdleAtEndOfBuffer) | Err(Status::NeedMoreBytes(_)) => self.fill()?,
+            }
+        }
+    }
+
+    fn seek_over(&mut self, amount: u64) -> Result<(), ReadError> {
+        // nothing is retained for the gatherer so the buffer was read through
+        debug_assert_eq!(self.at_offset, self.buffer.len());
+
+        let target = self
+            .position
+            .checked_add(amount)
+            .filter(|target| *target <= self.end)
+            .ok_or(ReadError::UnexpectedEndOfFile)?;
+
+        self.inner.seek(SeekFrom::Start(target))?;
+        self.fields.skipped(amount);
+
+        self.buffer.clear();
+        self.at_offset = 0;
+        self.position = target;
+        self.seeked += amount;
+        Ok(())
+    }
+
+    fn fill(&mut self) -> Result<(), ReadError> {
+        // the bytes before are not needed by the fields anymore
+        self.buffer.drain(..self.at_offset);
+        self.at_offset = 0;
+
+        let len_before = self.buffer.len();
+        self.buffer.resize(len_before + self.read_size, 0);
+
+        let bytes = loop {
+            match self.inner.read(&mut self.buffer[len_before..]) {
+                Ok(bytes) => break bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buffer.truncate(len_before);
+                    return Err(e.into());
+                }
+            }
+        };
+
+        self.buffer.truncate(len_before + bytes);
+        self.position += bytes as u64;
+        self.eof = bytes == 0;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> IO {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GatheredSeekFields;
+    use crate::gather_fields::{GatherOwned, GatheredFields, OwnedGatherer};
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, OwnedMatched, OwnedValue};
+    use crate::{DecodingError, FieldId, Introspect, ReadError, ReadField};
+    use hex_literal::hex;
+    use std::io::Cursor;
+
+    /// Reads field 1 as a slice and skips the others.
+    struct OnlyFirst;
+
+    impl Matcher for OnlyFirst {
+        type Tag = FieldId;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<FieldId>, DecodingError> {
+            Ok(match read.field_id() {
+                1 if read.is_length_delimited() => Action::Continue(Cont::ReadSlice(1)),
+                id => Action::Skip(id),
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<FieldId>>,
+        ) -> Option<FieldId> {
+            None
+        }
+    }
+
+    struct Firsts;
+
+    impl GatherOwned for Firsts {
+        type Tag = FieldId;
+        type Returned = Vec<u8>;
+
+        fn update_owned(
+            &mut self,
+            matched: OwnedMatched<FieldId>,
+        ) -> Result<Option<Vec<u8>>, DecodingError> {
+            Ok(match matched.value {
+                OwnedValue::Slice(_, bytes) if matched.tag == 1 => Some(bytes),
+                _ => None,
+            })
+        }
+    }
+
+    fn fields() -> GatheredFields<OnlyFirst, OwnedGatherer<Firsts>> {
+        GatheredFields::new(OnlyFirst, OwnedGatherer::new(Firsts))
+    }
+
+    #[test]
+    fn skipped_fields_are_seeked_over() {
+        // 1: "ab", 2: 100 bytes, 1: "c"
+        let mut input = hex!("0a026162 1264").to_vec();
+        input.extend(std::iter::repeat_n(0xff, 100));
+        input.extend(&hex!("0a0163"));
+
+        let mut reader = GatheredSeekFields::new(Cursor::new(&input), fields()).unwrap();
+        reader.set_read_size(8);
+
+        let mut firsts = Vec::new();
+        while let Some(first) = reader.read_next().unwrap() {
+            firsts.push(first);
+        }
+
+        assert_eq!(firsts, [b"ab".to_vec(), b"c".to_vec()]);
+        assert_eq!(reader.seeked(), 98);
+        assert_eq!(reader.stats().offset, input.len() as u64);
+    }
+
+    #[test]
+    fn seeking_past_the_end() {
+        // 2: claims 100 bytes but has 3
+        let input = hex!("1264 ffffff");
+
+        let mut reader = GatheredSeekFields::new(Cursor::new(&input), fields()).unwrap();
+        assert!(matches!(
+            reader.read_next(),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::{self, Read};
+
+/// Reads an ordered sequence of `Read` sources, for example rotated log segments or the parts of
+/// a download, as one continuous stream.
+///
+/// Wrapping this in [`super::read::ReadWrapper`] carries the decoder state across the segment
+/// boundaries, so a field split over two segments is decoded like any other field, and the
+/// offsets of the matched fields are global offsets over all of the segments. These can be mapped
+/// back to the segments with [`SegmentedRead::locate`].
+///
+/// The segments are opened lazily by the iterator, so for example the files can be opened only
+/// once the previous one has been read.
+pub struct SegmentedRead<I, R> {
+    segments: I,
+    current: Option<R>,
+    /// Global offsets of the beginnings of the segments opened so far
+    starts: Vec<u64>,
+    offset: u64,
+}
+
+impl<I, R> SegmentedRead<I, R>
+where
+    I: Iterator<Item = io::Result<R>>,
+    R: Read,
+{
+    pub fn new<T: IntoIterator<IntoIter = I>>(segments: T) -> Self {
+        SegmentedRead {
+            segments: segments.into_iter(),
+            current: None,
+            starts: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// The global offset of the next byte to be read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Index of the segment currently being read, or the last segment once all have been read.
+    /// `None` before the first segment has been opened.
+    pub fn segment(&self) -> Option<usize> {
+        self.starts.len().checked_sub(1)
+    }
+
+    /// Maps a global offset already read to the index of the segment and the offset within it.
+    pub fn locate(&self, offset: u64) -> Option<(usize, u64)> {
+        if offset >= self.offset {
+            return None;
+        }
+        // empty segments share their start with the next one, which is the one holding the byte
+        let index = self.starts.partition_point(|&start| start <= offset) - 1;
+        Some((index, offset - self.starts[index]))
+    }
+
+    pub fn into_inner(self) -> (I, Option<R>) {
+        (self.segments, self.current)
+    }
+}
+
+impl<I, R> Read for SegmentedRead<I, R>
+where
+    I: Iterator<Item = io::Result<R>>,
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let current = match self.current.as_mut() {
+                Some(current) => current,
+                None => match self.segments.next() {
+                    Some(next) => {
+                        self.starts.push(self.offset);
+                        self.current.insert(next?)
+                    }
+                    None => return Ok(0),
+                },
+            };
+
+            let bytes = current.read(buf)?;
+            if bytes == 0 {
+                self.current = None;
+                continue;
+            }
+
+            self.offset += bytes as u64;
+            return Ok(bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SegmentedRead;
+    use crate::io_ext::read::ReadWrapper;
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields, Value};
+    use crate::{DecodingError, FieldId, ReadField};
+    use hex_literal::hex;
+    use std::io;
+
+    struct AllValues;
+
+    impl Matcher for AllValues {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn field_split_over_segments() {
+        // 1: 150, 2: "abc" split in the middle of the varint and the slice, with an empty segment
+        let input = hex!("0896 01 1203 61 6263");
+        let segments = vec![&input[..2], &input[2..6], &[][..], &input[6..]];
+
+        let read = SegmentedRead::new(segments.into_iter().map(io::Result::Ok));
+        let mut rw = ReadWrapper::new(read, MatcherFields::new(AllValues));
+
+        let mut values = Vec::new();
+        while let Some(m) = rw.read_next().unwrap() {
+            values.push((m.offset, m.value));
+        }
+
+        assert!(matches!(
+            values.as_slice(),
+            [(0, Value::Varint(150)), (3, Value::Slice(r))] if *r == (5..8)
+        ));
+
+        let read = rw.into_inner();
+        assert_eq!(read.segment(), Some(3));
+        assert_eq!(read.locate(1), Some((0, 1)));
+        assert_eq!(read.locate(6), Some((3, 0)));
+        assert_eq!(read.locate(8), None);
+    }
+}
@@ -0,0 +1,317 @@
+//! Sorting a length prefixed stream of messages by a key field with bounded memory.
+//!
+//! The messages are collected into runs of at most the configured amount of bytes, each of which
+//! is sorted and written to a temporary file, and finally the runs are merged. The message bytes
+//! are copied verbatim. If the whole stream fits into a single run no files are created.
+
+use crate::framing::Framing;
+use crate::io_ext::frames::{FrameReader, FrameWriter};
+use crate::message::{FieldData, Fields, MessageError};
+use crate::{FieldId, ReadError};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the runs of the concurrent sorts within the process.
+static NEXT_RUN: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub enum SortError {
+    /// Reading the input or a run failed
+    Read(ReadError),
+    /// A message could not be read while looking for the key
+    Message(MessageError),
+    /// Writing the output or a run failed
+    IO(io::Error),
+}
+
+impl fmt::Display for SortError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortError::Read(e) => write!(fmt, "reading failed: {}", e),
+            SortError::Message(e) => write!(fmt, "{}", e),
+            SortError::IO(e) => write!(fmt, "writing failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SortError {}
+
+impl From<ReadError> for SortError {
+    fn from(e: ReadError) -> Self {
+        SortError::Read(e)
+    }
+}
+
+impl From<MessageError> for SortError {
+    fn from(e: MessageError) -> Self {
+        SortError::Message(e)
+    }
+}
+
+impl From<io::Error> for SortError {
+    fn from(e: io::Error) -> Self {
+        SortError::IO(e)
+    }
+}
+
+/// The key of a message, ordered like [`crate::rewrite::sort_repeated`] orders them: messages
+/// without the key first, then by the wire type and then by the value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Absent,
+    Varint(u64),
+    Fixed32(u32),
+    Fixed64(u64),
+    Bytes(Vec<u8>),
+}
+
+impl Key {
+    fn len(&self) -> usize {
+        match self {
+            Key::Bytes(bytes) => bytes.len(),
+            _ => 0,
+        }
+    }
+}
+
+/// Sorts a stream of messages by the field at `key`, see the module documentation.
+///
+/// The key is navigated like the column paths: every element but the last is a nested message and
+/// the last one is the key field, and when a field occurs multiple times the last one is used.
+/// Integer keys compare by their wire value and length delimited keys as bytes. The sort is
+/// stable.
+pub struct ExternalSort {
+    framing: Framing,
+    key: Vec<FieldId>,
+    max_run_bytes: usize,
+    temp_dir: PathBuf,
+}
+
+impl ExternalSort {
+    pub fn new(framing: Framing, key: Vec<FieldId>) -> Self {
+        assert!(!key.is_empty(), "key must contain at least the key field");
+        ExternalSort {
+            framing,
+            key,
+            max_run_bytes: 64 * 1024 * 1024,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+
+    /// Sets the amount of message bytes collected into a single run, defaults to 64MiB. The
+    /// memory used is somewhat more because of the keys and the bookkeeping.
+    pub fn set_max_run_bytes(&mut self, max: usize) {
+        self.max_run_bytes = max;
+    }
+
+    /// Sets the directory for the runs, defaults to `std::env::temp_dir()`.
+    pub fn set_temp_dir(&mut self, dir: PathBuf) {
+        self.temp_dir = dir;
+    }
+
+    /// Reads all of the messages from `read` and writes them sorted to `write` using the same
+    /// framing, returning the number of messages.
+    pub fn sort<R: Read, W: Write>(&self, read: R, write: W) -> Result<u64, SortError> {
+        let mut frames = FrameReader::new(read, self.framing);
+        let mut out = FrameWriter::new(write, self.framing);
+
+        let mut run = Run::default();
+        let mut files = Vec::new();
+        let mut count = 0;
+
+        while let Some((_, message)) = frames.next_frame()? {
+            let key = key_of(message, &self.key)?;
+            run.push(key, message);
+            count += 1;
+
+            if run.bytes >= self.max_run_bytes {
+                files.push(self.write_run(&mut run)?);
+            }
+        }
+
+        if files.is_empty() {
+            run.sort();
+            for (_, message) in run.iter() {
+                out.write_frame(message)?;
+            }
+        } else {
+            if !run.keys.is_empty() {
+                files.push(self.write_run(&mut run)?);
+            }
+            self.merge(&files, &mut out)?;
+        }
+
+        out.flush()?;
+        Ok(count)
+    }
+
+    fn write_run(&self, run: &mut Run) -> Result<TempFile, SortError> {
+        run.sort();
+
+        let path = self.temp_dir.join(format!(
+            "minipb-sort-{}-{}",
+            std::process::id(),
+            NEXT_RUN.fetch_add(1, Ordering::Relaxed)
+        ));
+        // created first so that the file is removed even if writing fails
+        let file = TempFile { path };
+        let mut writer = FrameWriter::new(BufWriter::new(File::create(&file.path)?), self.framing);
+
+        for (_, message) in run.iter() {
+            writer.write_frame(message)?;
+        }
+
+        writer.flush()?;
+        run.clear();
+        Ok(file)
+    }
+
+    fn merge<W: Write>(
+        &self,
+        files: &[TempFile],
+        out: &mut FrameWriter<W>,
+    ) -> Result<(), SortError> {
+        let mut readers = files
+            .iter()
+            .map(|f| File::open(&f.path).map(|f| FrameReader::new(BufReader::new(f), self.framing)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut heads = vec![Vec::new(); readers.len()];
+        // the run index breaks the ties, which keeps the sort stable
+        let mut heap = BinaryHeap::new();
+
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some((_, message)) = reader.next_frame()? {
+                heap.push(Reverse((key_of(message, &self.key)?, i)));
+                heads[i] = message.to_vec();
+            }
+        }
+
+        while let Some(Reverse((_, i))) = heap.pop() {
+            out.write_frame(&heads[i])?;
+
+            if let Some((_, message)) = readers[i].next_frame()? {
+                heap.push(Reverse((key_of(message, &self.key)?, i)));
+                heads[i].clear();
+                heads[i].extend_from_slice(message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Messages of a run stored back to back in a single buffer.
+#[derive(Default)]
+struct Run {
+    buffer: Vec<u8>,
+    /// Key and the range in the buffer of each message
+    keys: Vec<(Key, usize, usize)>,
+    bytes: usize,
+}
+
+impl Run {
+    fn push(&mut self, key: Key, message: &[u8]) {
+        let start = self.buffer.len();
+        self.buffer.extend_from_slice(message);
+        self.bytes += message.len() + key.len();
+        self.keys.push((key, start, self.buffer.len()));
+    }
+
+    fn sort(&mut self) {
+        // stable
+        self.keys.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&Key, &[u8])> + '_ {
+        self.keys
+            .iter()
+            .map(move |(key, start, end)| (key, &self.buffer[*start..*end]))
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.keys.clear();
+        self.bytes = 0;
+    }
+}
+
+struct TempFile {
+    path: PathBuf,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn key_of(message: &[u8], path: &[FieldId]) -> Result<Key, MessageError> {
+    let (&id, rest) = path.split_first().expect("key is never empty");
+
+    let mut last = None;
+    for field in Fields::new(message) {
+        let field = field?;
+        if field.id == id {
+            last = Some(field.value);
+        }
+    }
+
+    Ok(match (last, rest.is_empty()) {
+        (None, _) => Key::Absent,
+        (Some(FieldData::Bytes(nested)), false) => key_of(nested, rest)?,
+        // a scalar cannot be navigated
+        (Some(_), false) => Key::Absent,
+        (Some(FieldData::Varint(x)), true) => Key::Varint(x),
+        (Some(FieldData::Fixed32(x)), true) => Key::Fixed32(x),
+        (Some(FieldData::Fixed64(x)), true) => Key::Fixed64(x),
+        (Some(FieldData::Bytes(bytes)), true) => Key::Bytes(bytes.to_vec()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExternalSort;
+    use crate::framing::Framing;
+    use hex_literal::hex;
+
+    #[test]
+    fn sorts_over_runs() {
+        // { 1: { 2: k } } or without the key, each with a discriminator in field 3
+        let messages: Vec<&[u8]> = vec![
+            &hex!("0a02 1005 1800"),
+            &hex!("0a02 1001 1801"),
+            &hex!("1802"),
+            &hex!("0a02 1005 1803"),
+            &hex!("0a02 1003 1804"),
+            &hex!("0a02 1001 1805"),
+        ];
+
+        let mut input = Vec::new();
+        for message in &messages {
+            input.push(message.len() as u8);
+            input.extend_from_slice(message);
+        }
+
+        let expected_order = [2, 1, 5, 4, 0, 3];
+        let mut expected = Vec::new();
+        for &i in &expected_order {
+            expected.push(messages[i].len() as u8);
+            expected.extend_from_slice(messages[i]);
+        }
+
+        for &max in &[usize::MAX, 1, 10] {
+            let mut sort = ExternalSort::new(Framing::Varint, vec![1, 2]);
+            sort.set_max_run_bytes(max);
+
+            let mut output = Vec::new();
+            assert_eq!(sort.sort(&input[..], &mut output).unwrap(), 6);
+            assert_eq!(output, expected, "max_run_bytes = {}", max);
+        }
+    }
+}
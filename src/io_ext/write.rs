@@ -0,0 +1,193 @@
+use crate::matcher_fields::RawHeader;
+use crate::sink::{Sink, SinkError};
+use crate::FieldId;
+use std::io::Write;
+
+/// Writes fields to an `std::io::Write` through a small buffer of its own, the writing
+/// counterpart of [`super::read::ReadWrapper`].
+///
+/// The fields are written with the [`Sink`] methods, or copied as they were captured with
+/// `Cont::CaptureRaw` by `write_captured`, so that a stream can be read, modified and written
+/// field by field. Like with [`crate::sink::WriteSink`], nested messages are buffered until the
+/// outermost message ends.
+///
+/// The buffered bytes are only written by `flush` and `into_inner`, not when dropped.
+pub struct WriteWrapper<W> {
+    inner: W,
+    /// Bytes of complete fields not yet written to `inner`
+    buffer: Vec<u8>,
+    capacity: usize,
+    /// Field id and the buffered body of the open messages, the outermost first
+    open: Vec<(FieldId, Vec<u8>)>,
+}
+
+impl<W: Write> WriteWrapper<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(8192, inner)
+    }
+
+    /// Writes to `inner` whenever more than `capacity` bytes would be buffered. Writes of at least
+    /// `capacity` bytes go to `inner` directly.
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        WriteWrapper {
+            inner,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            open: Vec::new(),
+        }
+    }
+
+    /// Writes a field captured with `Cont::CaptureRaw`: the header and the payload as they were in
+    /// the input.
+    pub fn write_captured(&mut self, header: &RawHeader, payload: &[u8]) -> Result<(), SinkError> {
+        self.write_raw(header.as_bytes())?;
+        self.write_raw(payload)
+    }
+
+    /// Writes the buffered bytes of the complete fields and flushes `inner`. The fields of the
+    /// open messages are written once the outermost message ends.
+    pub fn flush(&mut self) -> Result<(), SinkError> {
+        self.flush_buffer()?;
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    fn flush_buffer(&mut self) -> Result<(), SinkError> {
+        if !self.buffer.is_empty() {
+            self.inner.write_all(&self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// The amount of bytes of the complete fields not yet written to `inner`.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn open_messages(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writes the buffered bytes and returns the inner writer, discarding any still open messages.
+    pub fn into_inner(mut self) -> Result<W, SinkError> {
+        self.flush_buffer()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Sink for WriteWrapper<W> {
+    type Error = SinkError;
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        if let Some((_, body)) = self.open.last_mut() {
+            body.extend_from_slice(bytes);
+            return Ok(());
+        }
+
+        if self.buffer.len() + bytes.len() > self.capacity {
+            self.flush_buffer()?;
+        }
+
+        if bytes.len() >= self.capacity {
+            self.inner.write_all(bytes)?;
+        } else {
+            self.buffer.extend_from_slice(bytes);
+        }
+        Ok(())
+    }
+
+    fn begin_message(&mut self, id: FieldId) -> Result<(), Self::Error> {
+        self.open.push((id, Vec::new()));
+        Ok(())
+    }
+
+    fn end_message(&mut self) -> Result<(), Self::Error> {
+        let (id, body) = self.open.pop().ok_or(SinkError::UnbalancedEndMessage)?;
+        self.write_slice(id, &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteWrapper;
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields, SlicedValue};
+    use crate::sink::{Scalar, Sink};
+    use crate::{DecodingError, FieldId, ReadField, Reader};
+    use hex_literal::hex;
+
+    /// Reads the varints of field 2 and captures all the other fields.
+    struct CaptureOthers;
+
+    impl Matcher for CaptureOthers {
+        type Tag = FieldId;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<FieldId>, DecodingError> {
+            Ok(match read.field_id() {
+                2 if !read.is_length_delimited() => Action::Continue(Cont::ReadValue(2)),
+                id => Action::Continue(Cont::CaptureRaw(id)),
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<FieldId>>,
+        ) -> Option<FieldId> {
+            None
+        }
+    }
+
+    #[test]
+    fn read_modify_write() {
+        // 1: "abc", 2: 150, 3: fixed32 7, 2: 1
+        let input = hex!("0a03616263 109601 1d07000000 1001");
+        let mut fields = MatcherFields::new(CaptureOthers).into_sliced();
+        let mut out = WriteWrapper::with_capacity(4, Vec::new());
+
+        let mut buf = &input[..];
+        while let Ok(m) = fields.next(&mut buf).unwrap() {
+            match m.value {
+                SlicedValue::Varint(x) => out.write_scalar(2, Scalar::Varint(x + 1)).unwrap(),
+                SlicedValue::Raw(_, header, payload) => {
+                    out.write_captured(&header, payload).unwrap()
+                }
+                other => unreachable!("{:?}", other),
+            }
+        }
+
+        let written = out.into_inner().unwrap();
+        assert_eq!(written, hex!("0a03616263 109701 1d07000000 1002"));
+    }
+
+    #[test]
+    fn writes_only_when_the_buffer_fills() {
+        let mut out = WriteWrapper::with_capacity(8, Vec::new());
+
+        out.write_scalar(1, Scalar::Varint(150)).unwrap();
+        out.begin_message(2).unwrap();
+        out.write_slice(1, b"abc").unwrap();
+        assert_eq!((out.get_ref().len(), out.buffered()), (0, 3));
+
+        // the body of the ended message does not fit after its tag and length
+        out.end_message().unwrap();
+        assert_eq!((out.get_ref().len(), out.buffered()), (5, 5));
+
+        // the payload as long as the capacity goes directly after the buffered bytes
+        out.write_slice(3, &[0xff; 8]).unwrap();
+        assert_eq!((out.get_ref().len(), out.buffered()), (20, 0));
+
+        out.write_scalar(4, Scalar::Varint(1)).unwrap();
+        out.flush().unwrap();
+        assert_eq!(out.get_ref()[20..], hex!("2001"));
+    }
+}
@@ -1,20 +1,102 @@
+#![forbid(unsafe_code)]
+
 use std::convert::TryFrom;
 use std::fmt;
+use std::num::NonZeroUsize;
+use std::ops::Range;
 
+pub mod canonical;
+pub mod columns;
 pub mod field_reader;
+pub mod field_writer;
+pub mod framing;
 pub mod gather_fields;
+pub mod grpc;
+pub mod infer;
+pub mod instrument;
+pub mod locate;
+pub mod map;
 pub mod matcher_fields;
+pub mod memory;
+pub mod message;
+pub mod message_set;
+pub mod path;
+pub mod protoscope;
+pub mod query;
+pub mod rewrite;
+pub mod salvage;
+pub mod schema;
+pub mod sink;
+pub mod stats;
+pub mod trace;
+pub mod transcode;
 
 pub mod io_ext;
 
+#[cfg(feature = "bytes")]
+pub mod bytes_ext;
+
 pub use gather_fields::Slicer;
 
-pub(crate) mod pb;
+/// Offsets into the input stream, as tracked by the matchers and gatherers.
+///
+/// With the `offset32` feature this is `u32`, which spares the small 32-bit targets from 64-bit
+/// arithmetic, but limits the streams to 4GiB.
+#[cfg(not(feature = "offset32"))]
+pub type Offset = u64;
+
+/// Offsets into the input stream, as tracked by the matchers and gatherers.
+///
+/// With the `offset32` feature this is `u32`, which spares the small 32-bit targets from 64-bit
+/// arithmetic, but limits the streams to 4GiB.
+#[cfg(feature = "offset32")]
+pub type Offset = u32;
+
+/// Converts an [`Offset`] to `u64`, which is a no-op without the `offset32` feature.
+#[allow(clippy::useless_conversion)]
+pub(crate) fn widen(offset: Offset) -> u64 {
+    offset.into()
+}
+
+/// Converts an [`Offset`] to the `usize` of the [`matcher_fields::Matcher`] methods, erroring with
+/// `DecodingErrorKind::OffsetOverflow` when it does not fit, which is possible on the 32-bit
+/// targets without the `offset32` feature.
+pub(crate) fn offset_to_usize(offset: Offset) -> Result<usize, DecodingError> {
+    usize::try_from(offset).map_err(|_| {
+        let kind = DecodingErrorKind::OffsetOverflow {
+            offset: widen(offset),
+            len: 0,
+        };
+        DecodingError::from(kind).with_offset(widen(offset))
+    })
+}
+
+/// Advances an [`Offset`] over `amount` bytes, erroring with `DecodingErrorKind::OffsetOverflow`
+/// past the largest offset.
+pub(crate) fn advance_offset(offset: Offset, amount: u64) -> Result<Offset, DecodingError> {
+    Offset::try_from(amount)
+        .ok()
+        .and_then(|amount| offset.checked_add(amount))
+        .ok_or_else(|| {
+            let kind = DecodingErrorKind::OffsetOverflow {
+                offset: widen(offset),
+                len: amount,
+            };
+            DecodingError::from(kind).with_offset(widen(offset))
+        })
+}
+
+pub mod pb;
+
+pub(crate) mod split;
 
 #[derive(Debug)]
 pub struct ReadField<'a> {
     /// How many bytes were consumed from the beginning of the buffer
     consumed: usize,
+    /// How many bytes of the header were taken on the earlier calls with
+    /// `FieldReader::next_resumable`
+    resumed: usize,
     /// The actual read field, which can be used to skip the field.
     field: &'a FieldInfo,
 }
@@ -24,6 +106,12 @@ impl ReadField<'_> {
         self.consumed
     }
 
+    /// The amount of bytes of the header taken on the earlier calls to
+    /// [`field_reader::FieldReader::next_resumable`], zero otherwise.
+    pub fn resumed(&self) -> usize {
+        self.resumed
+    }
+
     pub fn field_id(&self) -> FieldId {
         self.field.id
     }
@@ -41,21 +129,77 @@ impl ReadField<'_> {
     }
 
     pub fn is_length_delimited(&self) -> bool {
-        match self.field.kind {
-            WireType::LengthDelimited => true,
-            _ => false,
-        }
+        matches!(self.field.kind, WireType::LengthDelimited)
+    }
+
+    /// Returns true for the start group tag of a group, which is read as a nested message.
+    #[cfg(feature = "groups")]
+    pub fn is_start_group(&self) -> bool {
+        matches!(self.field.kind, WireType::StartGroup)
+    }
+
+    /// The error for the group tags where groups are not supported even with the `groups`
+    /// feature.
+    #[cfg(feature = "groups")]
+    pub(crate) fn unsupported_group(&self) -> DecodingError {
+        let lowest_bits = if self.is_start_group() { 3 } else { 4 };
+        DecodingError::from(DecodingErrorKind::UnsupportedGroupWireType(
+            self.field.id << 3 | lowest_bits,
+        ))
+        .with_field(self.field.id)
     }
 
     pub fn value(&self) -> &FieldValue {
         &self.field.value
     }
+
+    /// Offset where the tag starts, see [`field_reader::FieldReader::set_offset`].
+    pub fn tag_offset(&self) -> u64 {
+        self.field.tag_at_offset
+    }
+
+    /// Offsets of the tag bytes.
+    pub fn tag_span(&self) -> Range<u64> {
+        let start = self.field.tag_at_offset;
+        start..start + self.field.tag_len as u64
+    }
+
+    /// Offsets of the length prefix of a length delimited field.
+    pub fn length_span(&self) -> Option<Range<u64>> {
+        if self.is_length_delimited() {
+            Some(self.tag_span().end..self.header_end())
+        } else {
+            None
+        }
+    }
+
+    /// Offsets of the value: the bytes of a varint or a fixed width value, the payload of a length
+    /// delimited field, or an empty span after the tag of a group tag.
+    pub fn value_span(&self) -> Range<u64> {
+        if self.is_length_delimited() {
+            let start = self.header_end();
+            start..start + self.field_len() as u64
+        } else {
+            self.tag_span().end..self.header_end()
+        }
+    }
+
+    /// Offsets of the whole field, from the tag to the end of the value.
+    pub fn span(&self) -> Range<u64> {
+        self.field.tag_at_offset..self.value_span().end
+    }
+
+    fn header_end(&self) -> u64 {
+        self.field.tag_at_offset + (self.resumed + self.consumed) as u64
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct FieldInfo {
     /// Offset where the field tag (index and wiretype) starts.
-    // tag_at_offset: u64,
+    tag_at_offset: u64,
+    /// Length of the tag.
+    tag_len: usize,
     /// Field identifier as in the .proto file.
     id: FieldId,
     /// Kind of the field, determines the kind of `value` field.
@@ -71,12 +215,19 @@ struct FieldInfo {
 
 pub type FieldId = u32;
 
-/// Supported protobuf wire types. Note, that BeginGroup and EndGroup **are not supported**.
+/// Supported protobuf wire types. Note, that StartGroup and EndGroup are only supported with the
+/// `groups` feature.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum WireType {
     Varint,
     Fixed64,
     LengthDelimited,
+    #[cfg(feature = "groups")]
+    StartGroup,
+    #[cfg(feature = "groups")]
+    EndGroup,
     Fixed32,
 }
 
@@ -88,23 +239,39 @@ impl TryFrom<u32> for WireType {
             0 => WireType::Varint,
             1 => WireType::Fixed64,
             2 => WireType::LengthDelimited,
-            3 | 4 => return Err(DecodingError::UnsupportedGroupWireType(tag)),
+            #[cfg(feature = "groups")]
+            3 => WireType::StartGroup,
+            #[cfg(feature = "groups")]
+            4 => WireType::EndGroup,
+            #[cfg(not(feature = "groups"))]
+            3 | 4 => return Err(DecodingErrorKind::UnsupportedGroupWireType(tag).into()),
             5 => WireType::Fixed32,
-            _ => return Err(DecodingError::UnknownWireType(tag)),
+            _ => return Err(DecodingErrorKind::UnknownWireType(tag).into()),
         })
     }
 }
 
 impl FieldInfo {
+    fn at(self, tag_at_offset: u64) -> Self {
+        FieldInfo {
+            tag_at_offset,
+            ..self
+        }
+    }
+
     fn bytes_to_skip(&self) -> usize {
         match self.value {
-            FieldValue::DataLength(x) => x as usize,
+            // with the header still within usize, see field_reader::DEFAULT_MAX_FIELD_LEN
+            FieldValue::DataLength(x) => {
+                usize::try_from(x).expect("length is limited by the field reader")
+            }
             _ => 0,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum FieldValue {
     Varint(u64),
     Fixed64(u64),
@@ -112,99 +279,256 @@ pub enum FieldValue {
     /// Length delimited data starts after two varints, tag and length. This offset points to the
     /// byte which starts the actual field, and continues for the length specified in the
     /// `FieldInfo::offset`.
-    DataLength(u32),
+    DataLength(u64),
+    /// Start of a group, which continues until the matching `EndGroup`.
+    #[cfg(feature = "groups")]
+    StartGroup,
+    #[cfg(feature = "groups")]
+    EndGroup,
 }
 
 #[cfg(test)]
 impl FieldValue {
-    fn output_with_field_id(&self, id: FieldId) -> impl Iterator<Item = u8> {
-        use either::Either;
-        use stackvector::StackVec;
+    /// Encodes the field with the value; for `DataLength` only the tag and the length.
+    fn output_with_field_id(&self, id: FieldId) -> Vec<u8> {
         use FieldValue::*;
 
-        // sorry, this is some maniac code right here. wanted to try this out
-        let lowest_bits = match self {
-            Varint(_) => 0,
-            Fixed64(_) => 1,
-            DataLength(_) => 2,
-            Fixed32(_) => 5,
-        };
+        let mut out = Vec::new();
 
-        let id = (id << 3) | lowest_bits;
-
-        let field_bytes = VarintBytes(id as u64);
-
-        let mut tmp = StackVec::<[u8; 8]>::new();
-
-        let payload = match self {
-            Varint(x) => Either::Left(VarintBytes(*x)),
+        match self {
+            Varint(x) => {
+                pb::write_tag(id, WireType::Varint, &mut out);
+                pb::write_varint(*x, &mut out);
+            }
             Fixed64(x) => {
-                tmp.extend_from_slice(&x.to_le_bytes());
-                Either::Right(tmp.into_iter())
+                pb::write_tag(id, WireType::Fixed64, &mut out);
+                pb::write_fixed64(*x, &mut out);
             }
             Fixed32(x) => {
-                tmp.extend_from_slice(&x.to_le_bytes());
-                Either::Right(tmp.into_iter())
+                pb::write_tag(id, WireType::Fixed32, &mut out);
+                pb::write_fixed32(*x, &mut out);
             }
-            DataLength(x) => Either::Left(VarintBytes(*x as u64)),
-        };
-
-        field_bytes.chain(payload)
-    }
-}
-
-#[cfg(test)]
-struct VarintBytes(u64);
-
-#[cfg(test)]
-impl Iterator for VarintBytes {
-    type Item = u8;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.0 == 0 {
-            None
-        } else {
-            let ret = (self.0 & 0x7f) as u8;
-            self.0 >>= 7;
-            Some(if self.0 != 0 { ret | 0x80 } else { ret })
+            DataLength(x) => {
+                pb::write_tag(id, WireType::LengthDelimited, &mut out);
+                pb::write_varint(*x, &mut out);
+            }
+            #[cfg(feature = "groups")]
+            StartGroup => pb::write_tag(id, WireType::StartGroup, &mut out),
+            #[cfg(feature = "groups")]
+            EndGroup => pb::write_tag(id, WireType::EndGroup, &mut out),
         }
-    }
-}
 
-#[test]
-fn test_varint_bytes() {
-    assert_eq!(&VarintBytes(227).collect::<Vec<_>>(), &[0xe3, 0x01]);
-    assert_eq!(&VarintBytes(242).collect::<Vec<_>>(), &[0xf2, 0x01]);
+        out
+    }
 }
 
 /// All of the bytes still remaining in the buffer need to be kept, but more bytes should be read.
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct NeedMoreBytes;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Status {
     /// Would like to read the next item but there are no more bytes in the buffer. This could be
     /// because the input has been fully exhausted (end of file).
     IdleAtEndOfBuffer,
     /// Reading a variable length integer, for example the field id and type or the value or the
-    /// length.
-    NeedMoreBytes,
+    /// length. With `FieldReader::next_resumable` and `MatcherFields` the partially read header
+    /// has already been taken and only the following bytes need to be given.
+    ///
+    /// Has the amount of additional bytes needed to complete the current field when it is known,
+    /// which is when buffering, streaming or skipping a length delimited field.
+    NeedMoreBytes(Option<NonZeroUsize>),
+    /// Skipping a field of which this many bytes remain. They can be given like with
+    /// `NeedMoreBytes`, or the caller can advance its source over them without reading, for
+    /// example with `std::io::Seek`, and then tell the amount advanced to
+    /// `MatcherFields::skipped`.
+    CanSkip(u64),
+}
+
+impl Status {
+    /// `NeedMoreBytes` with the amount of additional bytes needed.
+    pub(crate) fn need_more(amount: u64) -> Self {
+        Status::NeedMoreBytes(usize::try_from(amount).ok().and_then(NonZeroUsize::new))
+    }
+
+    /// The amount of additional bytes needed, if known.
+    pub fn size_hint(&self) -> Option<NonZeroUsize> {
+        match self {
+            Status::NeedMoreBytes(hint) => *hint,
+            Status::CanSkip(amount) => usize::try_from(*amount).ok().and_then(NonZeroUsize::new),
+            Status::IdleAtEndOfBuffer => None,
+        }
+    }
 }
 
-/// Represents either a bug in this crate, or an error in the protobuf bytes.
+/// Represents either a bug in this crate, or an error in the protobuf bytes, with the stream
+/// offset and the field id where known.
 #[derive(Debug)]
-pub enum DecodingError {
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DecodingError {
+    kind: DecodingErrorKind,
+    offset: Option<u64>,
+    field: Option<FieldId>,
+}
+
+/// What went wrong, see [`DecodingError::kind`].
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodingErrorKind {
     UnsupportedGroupWireType(u32),
     UnknownWireType(u32),
     TooManyVarint32Bytes,
     TooManyVarint64Bytes,
     InvalidUtf8,
     FailedMatcherNesting(usize, usize),
+    /// MessageSet item group was missing the type_id or message, or had a mismatched end group
+    InvalidMessageSetItem,
+    /// gRPC frame had a compressed flag other than 0 or 1
+    InvalidGrpcCompressedFlag(u8),
+    /// Length prefix of a frame was over the configured limit
+    FrameTooLarge {
+        len: u64,
+        limit: u64,
+    },
+    /// The matcher decided to process a field in a way which is not possible with its wire type,
+    /// for example `Cont::ReadValue` for a length delimited field
+    InvalidDecision {
+        wire_type: WireType,
+    },
+    /// Packed repeated field did not consist of whole values of the wire type
+    InvalidPacked {
+        wire_type: WireType,
+    },
+    /// End group tag did not match the latest started group
+    UnmatchedEndGroup,
+    /// Varint was overlong or had bits set beyond its width, only returned in the strict mode
+    NonCanonicalVarint,
+    /// Length of a length delimited field was over the configured limit, see
+    /// `FieldReader::set_max_field_len`
+    FieldTooLarge {
+        len: u64,
+        limit: u64,
+    },
+    /// Length of a field the matcher decided to buffer was over the configured limit, see
+    /// `MatcherFields::set_max_slice_len`
+    SliceLimitExceeded {
+        field: FieldId,
+        len: u64,
+        limit: u64,
+    },
+    /// The window of the input retained for a gatherer grew over the configured limit, see
+    /// `GatheredFields::set_max_retained`
+    GatherWindowExceeded {
+        retained: u64,
+        limit: u64,
+    },
+    /// The field at the offset would end past the largest [`Offset`], which with the `offset32`
+    /// feature is at 4GiB
+    OffsetOverflow {
+        offset: u64,
+        len: u64,
+    },
+    /// Slice was not of the fixed length wanted, see `Slicer::as_array`
+    UnexpectedLength {
+        len: u64,
+        wanted: u64,
+    },
+    /// Value of a field could not be converted into the wanted type, for example by the
+    /// converters of `map::MapGatherer`
+    Conversion(matcher_fields::ConversionError),
+}
+
+impl DecodingError {
+    pub fn new(kind: DecodingErrorKind) -> Self {
+        DecodingError {
+            kind,
+            offset: None,
+            field: None,
+        }
+    }
+
+    pub fn kind(&self) -> &DecodingErrorKind {
+        &self.kind
+    }
+
+    pub fn into_kind(self) -> DecodingErrorKind {
+        self.kind
+    }
+
+    /// The offset of the erroneous bytes, usually of the tag of the field being processed. The
+    /// offsets are from the beginning of the stream for the readers which know it, otherwise from
+    /// the beginning of the data given to the reader.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// The id of the field being processed.
+    pub fn field(&self) -> Option<FieldId> {
+        self.field
+    }
+
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn with_field(mut self, field: FieldId) -> Self {
+        self.field = Some(field);
+        self
+    }
+
+    /// Sets the offset unless it is already known.
+    pub(crate) fn or_offset(mut self, offset: u64) -> Self {
+        self.offset.get_or_insert(offset);
+        self
+    }
+
+    /// Sets the field unless it is already known.
+    pub(crate) fn or_field(mut self, field: FieldId) -> Self {
+        self.field.get_or_insert(field);
+        self
+    }
+
+    /// Moves the offset relative to the beginning of a buffer to be relative to the stream, where
+    /// the buffer starts at `offset`.
+    pub(crate) fn offset_by(mut self, offset: u64) -> Self {
+        if let Some(at) = self.offset.as_mut() {
+            *at += offset;
+        }
+        self
+    }
+}
+
+impl From<DecodingErrorKind> for DecodingError {
+    fn from(kind: DecodingErrorKind) -> Self {
+        DecodingError::new(kind)
+    }
+}
+
+impl From<matcher_fields::ConversionError> for DecodingError {
+    fn from(e: matcher_fields::ConversionError) -> Self {
+        DecodingError::new(DecodingErrorKind::Conversion(e))
+    }
 }
 
 impl fmt::Display for DecodingError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use DecodingError::*;
+        write!(fmt, "{}", self.kind)?;
+        if let Some(field) = self.field {
+            write!(fmt, " in field {}", field)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(fmt, " at offset {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DecodingErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use DecodingErrorKind::*;
         match *self {
             UnsupportedGroupWireType(tag) => write!(fmt, "groups are not supported: {:02x}", tag),
             UnknownWireType(tag) => write!(
@@ -221,12 +545,75 @@ impl fmt::Display for DecodingError {
                 "nested field was read until {}, should had ended at {}",
                 offset, limit
             ),
+            InvalidMessageSetItem => write!(fmt, "invalid MessageSet item"),
+            InvalidGrpcCompressedFlag(flag) => {
+                write!(fmt, "invalid gRPC compressed flag: {:02x}", flag)
+            }
+            FrameTooLarge { len, limit } => {
+                write!(fmt, "frame of {} bytes is over the limit of {}", len, limit)
+            }
+            FieldTooLarge { len, limit } => {
+                write!(fmt, "field of {} bytes is over the limit of {}", len, limit)
+            }
+            SliceLimitExceeded { field, len, limit } => write!(
+                fmt,
+                "buffering field {} of {} bytes is over the limit of {}",
+                field, len, limit
+            ),
+            GatherWindowExceeded { retained, limit } => write!(
+                fmt,
+                "{} bytes retained for the gatherer is over the limit of {}",
+                retained, limit
+            ),
+            OffsetOverflow { offset, len } => write!(
+                fmt,
+                "field of {} bytes at offset {} ends past the largest supported offset",
+                len, offset
+            ),
+            UnexpectedLength { len, wanted } => {
+                write!(fmt, "slice of {} bytes, wanted {}", len, wanted)
+            }
+            Conversion(ref e) => write!(fmt, "{}", e),
+            InvalidDecision { wire_type } => write!(
+                fmt,
+                "matcher decision is invalid for wire type {:?}",
+                wire_type
+            ),
+            InvalidPacked { wire_type } => {
+                write!(fmt, "packed field has partial values of {:?}", wire_type)
+            }
+            UnmatchedEndGroup => write!(fmt, "end group does not match a started group"),
+            NonCanonicalVarint => write!(fmt, "non-canonical varint"),
         }
     }
 }
 
 impl std::error::Error for DecodingError {}
 
+/// A snapshot of the progress of a reader, for introspecting long running or stuck decoders.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    /// Offset of the next byte to be processed in the input stream
+    pub offset: u64,
+    /// True when stopped in the middle of a field, for example while buffering a slice or skipping
+    pub mid_field: bool,
+    /// Bytes held in a buffer owned by the reader, or retained in the caller's buffer
+    pub buffered: u64,
+    /// Items returned so far
+    pub items: u64,
+}
+
+/// Readers and wrappers which can report their [`Stats`].
+pub trait Introspect {
+    fn stats(&self) -> Stats;
+}
+
+/// Readers which can be told that the caller advanced its source over the bytes of
+/// `Status::CanSkip` instead of giving them, see [`matcher_fields::MatcherFields::skipped`].
+pub trait Skip {
+    fn skipped(&mut self, amount: u64);
+}
+
 // a single method trait would allow easy extension adapters, still not 100% convinced this *can't*
 // work but it'll take some iterations
 pub trait Reader<'a> {
@@ -244,6 +631,27 @@ pub enum ReadError {
     Decoding(DecodingError),
     /// An IO error occured
     IO(std::io::Error),
+    /// The configured deadline or time budget was exceeded before more bytes were read. The state
+    /// is preserved and reading can be retried.
+    DeadlineExceeded,
+    /// Reading from the source timed out, either because of a read timeout on the source or
+    /// because the supplied poll function reported no readiness. The state is preserved and
+    /// reading can be retried.
+    TimedOut,
+    /// A fixed size buffer, or a buffer grown to its maximum capacity, was full of bytes still
+    /// needed by the reader, for example because a field was longer than the buffer
+    BufferTooSmall { capacity: usize },
+}
+
+impl ReadError {
+    /// Returns true if the operation can be retried without losing any state.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ReadError::DeadlineExceeded | ReadError::TimedOut => true,
+            ReadError::IO(e) => e.kind() == std::io::ErrorKind::Interrupted,
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for ReadError {
@@ -253,6 +661,11 @@ impl fmt::Display for ReadError {
             UnexpectedEndOfFile => write!(fmt, "unexpected end of file"),
             Decoding(e) => write!(fmt, "decoding failed: {}", e),
             IO(e) => write!(fmt, "{}", e),
+            DeadlineExceeded => write!(fmt, "deadline exceeded"),
+            TimedOut => write!(fmt, "read timed out"),
+            BufferTooSmall { capacity } => {
+                write!(fmt, "buffer of {} bytes is too small", capacity)
+            }
         }
     }
 }
@@ -0,0 +1,147 @@
+//! Finding the field which spans a byte offset of a message, for turning an offset from an error
+//! message or a hex editor into a field path.
+//!
+//! The wire format does not tell which length delimited fields are messages. [`locate`] descends
+//! into the ones which parse completely as messages, which can be fooled by strings and bytes that
+//! happen to parse; [`locate_with`] lets the caller decide.
+
+use crate::message::{FieldData, Fields, MessageError};
+use crate::FieldId;
+
+/// A field containing the offset, with offsets relative to the start of the outermost message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span<'a> {
+    pub id: FieldId,
+    /// Offset of the tag
+    pub start: usize,
+    /// Offset of the value, after the tag and any length prefix
+    pub value_start: usize,
+    /// Offset one past the last byte of the value
+    pub end: usize,
+    pub value: FieldData<'a>,
+}
+
+impl Span<'_> {
+    /// Returns true if the offset is within the tag or the length prefix of the field.
+    pub fn in_header(&self, offset: usize) -> bool {
+        offset < self.value_start
+    }
+}
+
+/// Returns the fields which contain the `offset`, from the outermost to the innermost, descending
+/// into the length delimited fields which are complete messages. The returned path is empty when
+/// the offset is past the end of `buf`.
+///
+/// Errors are returned only from the outermost message, when a field before or at the offset
+/// cannot be read.
+pub fn locate(buf: &[u8], offset: usize) -> Result<Vec<Span<'_>>, MessageError> {
+    locate_with(buf, offset, |_, payload| {
+        !payload.is_empty() && Fields::new(payload).all(|f| f.is_ok())
+    })
+}
+
+/// Like [`locate`] but descends into the length delimited fields for which `is_message` returns
+/// true when called with the path of field ids and the payload.
+pub fn locate_with<'a, F>(
+    buf: &'a [u8],
+    offset: usize,
+    mut is_message: F,
+) -> Result<Vec<Span<'a>>, MessageError>
+where
+    F: FnMut(&[FieldId], &[u8]) -> bool,
+{
+    let mut spans = Vec::new();
+    let mut path = Vec::new();
+    let mut message = buf;
+    let mut base = 0;
+
+    'outer: loop {
+        for field in Fields::new(message) {
+            let field = match field {
+                Ok(field) => field,
+                Err(e) if spans.is_empty() => return Err(e),
+                // descended into something which was not a message after all
+                Err(_) => break 'outer,
+            };
+
+            let start = base + field.offset;
+            let end = start + field.raw.len();
+
+            if offset >= end {
+                continue;
+            }
+
+            let value_len = match field.value {
+                FieldData::Bytes(payload) => payload.len(),
+                FieldData::Varint(_) => field.raw.len() - tag_len(field.raw),
+                FieldData::Fixed64(_) => 8,
+                FieldData::Fixed32(_) => 4,
+            };
+
+            let span = Span {
+                id: field.id,
+                start,
+                value_start: end - value_len,
+                end,
+                value: field.value,
+            };
+            spans.push(span);
+            path.push(field.id);
+
+            match field.value {
+                FieldData::Bytes(payload)
+                    if !span.in_header(offset) && is_message(&path, payload) =>
+                {
+                    message = payload;
+                    base = span.value_start;
+                    continue 'outer;
+                }
+                _ => break 'outer,
+            }
+        }
+        break;
+    }
+
+    Ok(spans)
+}
+
+fn tag_len(raw: &[u8]) -> usize {
+    raw.iter()
+        .position(|b| b & 0x80 == 0)
+        .map(|i| i + 1)
+        .unwrap_or(raw.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::locate;
+    use crate::message::FieldData;
+    use hex_literal::hex;
+
+    #[test]
+    fn locates_nested_field() {
+        // 1: 150, 2: { 1: "abc", 2: 7 }, 3: "x y"
+        let input = hex!("089601 1207 0a03616263 1007 1a03782079");
+
+        let spans = locate(&input, 1).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!((spans[0].id, spans[0].value_start), (1, 1));
+        assert_eq!(spans[0].value, FieldData::Varint(150));
+
+        // the "c" in the nested "abc"
+        let spans = locate(&input, 9).unwrap();
+        let path = spans.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(path, vec![2, 1]);
+        assert_eq!((spans[1].start, spans[1].end), (5, 10));
+        assert_eq!(spans[1].value, FieldData::Bytes(b"abc"));
+
+        // in the length prefix of field 2
+        let spans = locate(&input, 4).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].in_header(4));
+
+        // "x y" does not parse as a message
+        assert_eq!(locate(&input, 15).unwrap().len(), 1);
+        assert!(locate(&input, 17).unwrap().is_empty());
+    }
+}
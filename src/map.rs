@@ -0,0 +1,251 @@
+//! Reading the entries of `map<K, V>` fields, which are on the wire repeated nested messages with
+//! the key as field 1 and the value as field 2.
+//!
+//! [`MapMatcher`] enters the entries of the map field at a path of field ids and
+//! [`MapGatherer`] returns the entries converted into `(K, V)` pairs with the converters given to
+//! it, such as [`string`] and [`uint64`].
+
+use crate::gather_fields::{Gatherer, Slicer};
+use crate::matcher_fields::{
+    to_str, Action, Cont, ConversionError, EndedMessage, Matched, Matcher, Value,
+};
+use crate::{DecodingError, FieldId, Offset, ReadField};
+use std::marker::PhantomData;
+
+/// Marks the fields read by [`MapMatcher`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapTag {
+    /// An entry of the map was entered
+    EntryStart,
+    /// The key of the current entry
+    Key,
+    /// The value of the current entry
+    Value,
+    /// The current entry ended
+    EntryEnd,
+    /// An entry without the key and the value, which was not entered
+    EmptyEntry,
+    /// A field not of the map was skipped
+    Ignored,
+}
+
+/// Matches the entries of the map field at the end of the path, the other parts of the path are
+/// the messages containing the map.
+pub struct MapMatcher {
+    path: Vec<FieldId>,
+}
+
+impl MapMatcher {
+    /// # Panics
+    ///
+    /// If the path is empty.
+    pub fn new(path: Vec<FieldId>) -> Self {
+        assert!(
+            !path.is_empty(),
+            "path needs to have at least the map field"
+        );
+        MapMatcher { path }
+    }
+}
+
+impl Matcher for MapMatcher {
+    type Tag = MapTag;
+
+    fn decide_before(
+        &mut self,
+        _offset: usize,
+        read: &ReadField<'_>,
+        path: &[FieldId],
+    ) -> Result<Action<MapTag>, DecodingError> {
+        // only the messages on the path and the entries are entered
+        let depth = path.len();
+
+        let decision = if depth == self.path.len() {
+            match read.field_id() {
+                1 if read.is_length_delimited() => Action::Continue(Cont::ReadSlice(MapTag::Key)),
+                1 => Action::Continue(Cont::ReadValue(MapTag::Key)),
+                2 if read.is_length_delimited() => Action::Continue(Cont::ReadSlice(MapTag::Value)),
+                2 => Action::Continue(Cont::ReadValue(MapTag::Value)),
+                _ => Action::Skip(MapTag::Ignored),
+            }
+        } else if read.field_id() != self.path[depth] || !read.is_length_delimited() {
+            Action::Skip(MapTag::Ignored)
+        } else if depth + 1 < self.path.len() {
+            Action::Continue(Cont::Message(None))
+        } else if read.field_len() > 0 {
+            Action::Continue(Cont::Message(Some(MapTag::EntryStart)))
+        } else {
+            Action::Skip(MapTag::EmptyEntry)
+        };
+
+        Ok(decision)
+    }
+
+    fn decide_after(
+        &mut self,
+        _offset: usize,
+        ended: Option<EndedMessage<MapTag>>,
+    ) -> Option<MapTag> {
+        match ended {
+            Some(EndedMessage {
+                tag: Some(MapTag::EntryStart),
+                ..
+            }) => Some(MapTag::EntryEnd),
+            _ => None,
+        }
+    }
+}
+
+/// Gathers the key and the value of each entry matched by [`MapMatcher`] and returns them
+/// converted with the key and value converters at the end of the entry. The converters are given
+/// `None` when the entry has no key or value, which means the default value of the type.
+///
+/// The slices of the key and the value are retained until the end of the entry.
+pub struct MapGatherer<K, V, FK, FV> {
+    key_fn: FK,
+    value_fn: FV,
+    key: Option<(Offset, Value)>,
+    value: Option<(Offset, Value)>,
+    entry: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V, FK, FV> MapGatherer<K, V, FK, FV>
+where
+    FK: FnMut(Option<Value>, Slicer<'_>) -> Result<K, DecodingError>,
+    FV: FnMut(Option<Value>, Slicer<'_>) -> Result<V, DecodingError>,
+{
+    pub fn new(key_fn: FK, value_fn: FV) -> Self {
+        MapGatherer {
+            key_fn,
+            value_fn,
+            key: None,
+            value: None,
+            entry: PhantomData,
+        }
+    }
+}
+
+impl<'a, K: 'a, V: 'a, FK, FV> Gatherer<'a> for MapGatherer<K, V, FK, FV>
+where
+    FK: FnMut(Option<Value>, Slicer<'_>) -> Result<K, DecodingError>,
+    FV: FnMut(Option<Value>, Slicer<'_>) -> Result<V, DecodingError>,
+{
+    type Tag = MapTag;
+    type Returned = (K, V);
+
+    fn update(
+        &mut self,
+        matched: Matched<MapTag>,
+        slicer: Slicer<'a>,
+    ) -> Result<Option<(K, V)>, DecodingError> {
+        let Matched { tag, offset, value } = matched;
+        match tag {
+            MapTag::EntryStart => {
+                self.key = None;
+                self.value = None;
+            }
+            MapTag::Key => self.key = Some((offset, value)),
+            MapTag::Value => self.value = Some((offset, value)),
+            MapTag::EntryEnd | MapTag::EmptyEntry => {
+                let key = convert(&mut self.key_fn, self.key.take(), slicer)?;
+                let value = convert(&mut self.value_fn, self.value.take(), slicer)?;
+                return Ok(Some((key, value)));
+            }
+            MapTag::Ignored => {}
+        }
+        Ok(None)
+    }
+
+    fn min_offset(&self) -> Option<Offset> {
+        let start = |held: &Option<(Offset, Value)>| match held {
+            Some((_, Value::Slice(range))) => Some(range.start),
+            _ => None,
+        };
+
+        match (start(&self.key), start(&self.value)) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Calls the converter, the error pointing at the field when there was one.
+fn convert<T>(
+    f: &mut impl FnMut(Option<Value>, Slicer<'_>) -> Result<T, DecodingError>,
+    held: Option<(Offset, Value)>,
+    slicer: Slicer<'_>,
+) -> Result<T, DecodingError> {
+    match held {
+        Some((offset, value)) => {
+            f(Some(value), slicer).map_err(|e| e.or_offset(crate::widen(offset)))
+        }
+        None => f(None, slicer),
+    }
+}
+
+/// Converts a `string` key or value, see [`MapGatherer`].
+pub fn string(value: Option<Value>, slicer: Slicer<'_>) -> Result<String, DecodingError> {
+    match value {
+        Some(Value::Slice(range)) => Ok(to_str(&slicer.get(&range), range.start)?.to_owned()),
+        Some(_) => Err(ConversionError::UnexpectedValue { wanted: "string" }.into()),
+        None => Ok(String::new()),
+    }
+}
+
+/// Converts a `bytes` key or value, see [`MapGatherer`].
+pub fn bytes(value: Option<Value>, slicer: Slicer<'_>) -> Result<Vec<u8>, DecodingError> {
+    match value {
+        Some(Value::Slice(range)) => Ok(slicer.get(&range).into_owned()),
+        Some(_) => Err(ConversionError::UnexpectedValue { wanted: "bytes" }.into()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Converts an `uint64`, `uint32`, `fixed64` or `fixed32` key or value, see [`MapGatherer`].
+pub fn uint64(value: Option<Value>, _slicer: Slicer<'_>) -> Result<u64, DecodingError> {
+    Ok(value.map(|v| v.as_u64()).transpose()?.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{string, uint64, MapGatherer, MapMatcher};
+    use crate::gather_fields::GatheredFields;
+    use crate::{DecodingErrorKind, Reader};
+    use hex_literal::hex;
+
+    #[test]
+    fn entries_of_a_nested_map() {
+        // 1: { 3: { 1: "a", 2: 5 }, 3: {}, 4: 1, 3: { 2: 7, 1: "bc" } }
+        let input = hex!("0a13 1a05 0a0161 1005 1a00 2001 1a06 1007 0a026263");
+        let matcher = MapMatcher::new(vec![1, 3]);
+        let mut fields = GatheredFields::new(matcher, MapGatherer::new(string, uint64));
+
+        let mut buf = &input[..];
+        let mut entries = Vec::new();
+        while let Ok(entry) = fields.next(&mut buf).unwrap() {
+            entries.push(entry);
+        }
+
+        assert_eq!(
+            entries,
+            [
+                ("a".to_string(), 5),
+                (String::new(), 0),
+                ("bc".to_string(), 7)
+            ]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn unconvertible_value() {
+        // 3: { 1: "a", 2: "x" }
+        let input = hex!("1a06 0a0161 120178");
+        let mut fields =
+            GatheredFields::new(MapMatcher::new(vec![3]), MapGatherer::new(string, uint64));
+
+        let e = fields.next(&mut &input[..]).unwrap_err();
+        assert!(matches!(e.kind(), DecodingErrorKind::Conversion(_)));
+        assert_eq!(e.offset(), Some(5));
+    }
+}
@@ -1,29 +1,52 @@
-use crate::field_reader::FieldReader;
-use crate::{DecodingError, FieldValue, ReadField, Slicer, Status};
+use crate::field_reader::{FieldReader, MAX_HEADER};
+use crate::pb::{decode_zigzag32, decode_zigzag64, read_fixed32, read_fixed64, read_varint64};
+use crate::split::Input;
+use crate::{
+    DecodingError, DecodingErrorKind, FieldId, FieldValue, Introspect, NeedMoreBytes, Offset,
+    ReadField, Slicer, Stats, Status, WireType,
+};
+use std::convert::TryFrom;
+use std::fmt;
 use std::ops::Range;
 
 /// State machine one needs to write in order to know how to handle nested fields.
 pub trait Matcher {
-    /// Tag describing to caller how to process the field
-    type Tag: 'static;
+    /// Tag describing to caller how to process the field. Cloned for the chunks of
+    /// `Cont::ReadPartialSlice`.
+    type Tag: Clone + 'static;
 
     /// Advance the matcher on a new field read.
     ///
     /// Returns the direction to take with the field with either Cont or Skip. Cont'd fields need
-    /// to be tagged.
+    /// to be tagged. The `path` has the field ids of the messages entered with `Cont::Message`
+    /// which contain the field, outermost first.
     fn decide_before(
         &mut self,
         offset: usize,
         read: &ReadField<'_>,
+        path: &[FieldId],
     ) -> Result<Action<Self::Tag>, DecodingError>;
 
-    /// Advance the matcher after a field has been processed. Depending on the return value this
-    /// can be called many times in order for the Matcher ot highligh which objects have ended at
-    /// this location with valueless tags.
+    /// Advance the matcher after a field has been processed, at the offset after it. When nested
+    /// messages entered with `Cont::Message` end at the offset this is called once for each of
+    /// them, innermost first, with the [`EndedMessage`]; otherwise once with `None`.
     ///
-    /// Return `(true, _)` if this method needs to be called again on the same offset, `(false, _)`
-    /// otherwise.
-    fn decide_after(&mut self, offset: usize) -> (bool, Option<Self::Tag>);
+    /// The returned tag is output as a valueless marker, for example to highlight the end of an
+    /// object.
+    fn decide_after(
+        &mut self,
+        offset: usize,
+        ended: Option<EndedMessage<Self::Tag>>,
+    ) -> Option<Self::Tag>;
+
+    /// Advance the matcher after the end group tag of a group entered with `Cont::Message`, at
+    /// the offset after the tag. The returned tag is output as a valueless marker. `decide_after`
+    /// is called next as with the other fields.
+    #[cfg(feature = "groups")]
+    fn end_group(&mut self, offset: usize, field: FieldId) -> Option<Self::Tag> {
+        let _ = (offset, field);
+        None
+    }
 }
 
 /// The action to take, with a tag.
@@ -32,43 +55,283 @@ pub enum Action<T> {
     /// Continue reading the field.
     Continue(Cont<T>),
     /// Represents an instruction to skip the current field. Good default.
+    ///
+    /// The skipped field is given as a `Value::Slice` of its range once skipped over. The sliced
+    /// readers give it as `SlicedValue::Marker` when its bytes were advanced over in the earlier
+    /// calls.
     Skip(T),
 }
 
 /// Instruction to process the field as follows, with the given tag.
 #[derive(Debug)]
 pub enum Cont<T> {
-    /// Start processing the field as a nested message. Outputs the given tag to mark this.
+    /// Start processing the field as a nested message. Outputs the given tag to mark this. With
+    /// the `groups` feature this is also valid for groups, which end with
+    /// [`Matcher::end_group`].
     Message(Option<T>),
     /// Process the field as an opaque slice. Bytes will be buffered until there's at least this
     /// amount available. This will require the caller to buffer this much data.
     ReadSlice(T),
-    // FIXME: here could be a ReadPartialSlice to stream bytes when they arrive, that will require
-    // though cloneable tags, which wouldn't be a huge deal.
+    /// Process the field as a string. Bytes will be buffered like with `ReadSlice` and validated
+    /// as UTF-8, returning [`Value::Str`] or [`DecodingErrorKind::InvalidUtf8`] at the offset of
+    /// the first invalid byte.
+    ReadString(T),
+    /// Process the field as an opaque slice returned in chunks as the bytes arrive, as
+    /// [`Value::SliceChunk`] with a clone of the tag, so that the caller does not need to buffer
+    /// the whole field. A field of zero length is returned as a single empty chunk.
+    ReadPartialSlice(T),
     /// Process the field as non-length delimited field with the given tag.
     ReadValue(T),
+    /// Process the field as packed repeated values of the given wire type, which needs to be
+    /// `Varint`, `Fixed64` or `Fixed32`. Bytes will be buffered like with `ReadSlice` and the
+    /// values are returned as [`Value::Packed`].
+    ReadPacked(T, WireType),
+    /// Process the field as the complete bytes of it, including the tag and the length prefix,
+    /// for preserving unknown fields byte-exactly. Bytes will be buffered like with `ReadSlice`
+    /// and the field is returned as [`Value::Raw`]. Not valid for groups.
+    CaptureRaw(T),
+}
+
+/// A nested message entered with `Cont::Message` which ended, given to
+/// [`Matcher::decide_after`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EndedMessage<T> {
+    /// Field id of the message
+    pub field: FieldId,
+    /// Offset of the tag of the field
+    pub offset: usize,
+    /// The tag given with `Cont::Message`
+    pub tag: Option<T>,
+}
+
+/// The bookkeeping of the entered nested messages for [`Matcher`] implementations which need to
+/// know more than the message path given to `decide_before`: the offsets where the messages end,
+/// each with a value describing the message.
+///
+/// Push the field with [`NestingTracker::enter`] when returning `Cont::Message` for it, and call
+/// [`NestingTracker::exit`] in `decide_after` to pop the message which ended:
+///
+/// ```
+/// # use minipb::matcher_fields::{Action, Cont, EndedMessage, Matcher, NestingTracker};
+/// # use minipb::{DecodingError, FieldId, ReadField};
+/// /// Reads the field 1 of the messages in the field 2.
+/// #[derive(Default)]
+/// struct Links(NestingTracker);
+///
+/// #[derive(Debug, Clone)]
+/// enum Tag {
+///     Start,
+///     Hash,
+///     End,
+/// }
+///
+/// impl Matcher for Links {
+///     type Tag = Tag;
+///
+///     fn decide_before(
+///         &mut self,
+///         offset: usize,
+///         read: &ReadField<'_>,
+///         _path: &[FieldId],
+///     ) -> Result<Action<Tag>, DecodingError> {
+///         self.0.check(offset)?;
+///         Ok(match (self.0.depth(), read.field_id()) {
+///             (0, 2) if read.is_length_delimited() => {
+///                 self.0.enter(offset, read, ())?;
+///                 Action::Continue(Cont::Message(Some(Tag::Start)))
+///             }
+///             (1, 1) if read.is_length_delimited() => Action::Continue(Cont::ReadSlice(Tag::Hash)),
+///             _ => Action::Skip(Tag::End),
+///         })
+///     }
+///
+///     fn decide_after(&mut self, offset: usize, _: Option<EndedMessage<Tag>>) -> Option<Tag> {
+///         self.0.exit(offset).map(|()| Tag::End)
+///     }
+/// }
+/// ```
+///
+/// Only for length delimited messages, as the end of a group is only known once the end group
+/// tag is read.
+#[derive(Debug, Clone)]
+pub struct NestingTracker<T = ()> {
+    /// The end offsets of the entered messages, innermost last
+    stack: Vec<(usize, T)>,
+}
+
+impl<T> Default for NestingTracker<T> {
+    fn default() -> Self {
+        NestingTracker { stack: Vec::new() }
+    }
+}
+
+impl<T> NestingTracker<T> {
+    /// Amount of entered messages which have not yet ended.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// The value of the innermost entered message.
+    pub fn current(&self) -> Option<&T> {
+        self.stack.last().map(|(_, value)| value)
+    }
+
+    pub fn current_mut(&mut self) -> Option<&mut T> {
+        self.stack.last_mut().map(|(_, value)| value)
+    }
+
+    /// The values of the entered messages, outermost first.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.stack.iter().map(|(_, value)| value)
+    }
+
+    /// Offset where the innermost entered message ends.
+    pub fn current_end(&self) -> Option<usize> {
+        self.stack.last().map(|(end, _)| *end)
+    }
+
+    /// Enters the field `read` at `offset` as a nested message, to be called in
+    /// `decide_before` when returning `Cont::Message`.
+    ///
+    /// Returns `DecodingErrorKind::FailedMatcherNesting` if the field would end after the innermost
+    /// entered message.
+    pub fn enter(
+        &mut self,
+        offset: usize,
+        read: &ReadField<'_>,
+        value: T,
+    ) -> Result<(), DecodingError> {
+        let end = offset.checked_add(read.bytes_to_skip()).ok_or_else(|| {
+            let kind = DecodingErrorKind::OffsetOverflow {
+                offset: offset as u64,
+                len: read.field_len() as u64,
+            };
+            DecodingError::from(kind)
+        })?;
+        match self.current_end() {
+            Some(limit) if end > limit => {
+                Err(DecodingErrorKind::FailedMatcherNesting(end, limit).into())
+            }
+            _ => {
+                self.stack.push((end, value));
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that a field at `offset` is within the innermost entered message, to be called in
+    /// `decide_before`.
+    pub fn check(&self, offset: usize) -> Result<(), DecodingError> {
+        match self.current_end() {
+            Some(limit) if offset >= limit => {
+                Err(DecodingErrorKind::FailedMatcherNesting(offset, limit).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Exits the innermost entered message if it ends at `offset`, to be called in
+    /// `decide_after` which is called once for each of the messages ending at the same offset.
+    /// Returns the value of the message.
+    ///
+    /// # Panics
+    ///
+    /// If the innermost message ended before `offset`, which `MatcherFields` never does.
+    pub fn exit(&mut self, offset: usize) -> Option<T> {
+        let end = self.current_end()?;
+        assert!(
+            end >= offset,
+            "got up to {} but should had stopped at {}",
+            offset,
+            end
+        );
+
+        if end != offset {
+            return None;
+        }
+
+        self.stack.pop().map(|(_, value)| value)
+    }
+}
+
+/// What is buffered in `State::Buffering`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Buffered {
+    Slice,
+    Str,
+    Packed(WireType),
+    Raw(RawHeader),
 }
 
 /// Uses an [`Matcher`] to match tagged fields from a [`FieldReader`].
 pub struct MatcherFields<M: Matcher> {
-    offset: u64,
+    offset: Offset,
     reader: FieldReader,
     matcher: M,
     state: State<M::Tag>,
+    /// Matched items returned so far
+    items: u64,
+    /// Longest field to be buffered, see `set_max_slice_len`
+    max_slice_len: Option<u64>,
+    /// The end offsets of the length delimited messages entered, innermost last
+    nested: Vec<(Offset, EndedMessage<M::Tag>)>,
+    /// Field ids of all of the messages entered, given to `Matcher::decide_before`
+    path: Vec<FieldId>,
+    /// Field ids of the groups entered as nested messages
+    #[cfg(feature = "groups")]
+    groups: Vec<FieldId>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum State<T> {
     /// Initial state and where we can also stop because of EOF.
     Ready,
     /// Entered after processing a field value (nested message, slice, or scalar) and stayed as
-    /// long as matcher.decide_after returns `(true, _)` as multiple nested messages will stop on
-    /// the same byte offset.
+    /// long as there are nested messages ending at the offset, as multiple nested messages can
+    /// stop on the same byte offset.
     DecidingAfter,
-    /// Entered to buffer up a complete slice (bytes or str).
-    Buffering(T, u64, u64, u64),
+    /// Entered to buffer up a complete slice (bytes or str), or packed values of the wire type.
+    Buffering(T, Buffered, Offset, Offset, Offset),
+    /// Returning the chunks of a slice, with the remaining amount.
+    Streaming(T, Offset, Offset),
     /// Skipping a complete field, which can be long.
-    Skipping(T, u64, u64, u64),
+    Skipping(T, Offset, Offset, Offset),
+    /// Skipping a complete group with the field ids of the open groups, and the remaining bytes
+    /// of the length delimited field being skipped within it.
+    #[cfg(feature = "groups")]
+    SkippingGroup(T, Offset, Offset, Vec<FieldId>, Offset),
+}
+
+/// The state of [`MatcherFields`] between the calls, the matcher included, see
+/// [`MatcherFields::checkpoint`]. With the `serde` feature it can be serialized, for example to
+/// continue a long read after a restart instead of starting over.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checkpoint<M, T> {
+    offset: Offset,
+    reader: FieldReader,
+    matcher: M,
+    state: State<T>,
+    items: u64,
+    max_slice_len: Option<u64>,
+    nested: Vec<(Offset, EndedMessage<T>)>,
+    path: Vec<FieldId>,
+    #[cfg(feature = "groups")]
+    groups: Vec<FieldId>,
+}
+
+impl<M, T> Checkpoint<M, T> {
+    /// The offset of the input to continue from, as in [`MatcherFields::offset`].
+    pub fn offset(&self) -> Offset {
+        self.offset
+    }
+
+    pub fn matcher(&self) -> &M {
+        &self.matcher
+    }
 }
 
 impl<M: Matcher> MatcherFields<M> {
@@ -78,40 +341,252 @@ impl<M: Matcher> MatcherFields<M> {
             reader: FieldReader::default(),
             matcher,
             state: State::Ready,
+            items: 0,
+            max_slice_len: None,
+            nested: Vec::new(),
+            path: Vec::new(),
+            #[cfg(feature = "groups")]
+            groups: Vec::new(),
         }
     }
 
-    pub fn offset(&self) -> u64 {
+    pub fn offset(&self) -> Offset {
         self.offset
     }
 
-    pub fn is_idle(&self) -> bool {
-        match self.state {
-            State::Ready => true,
-            _ => false,
+    /// Snapshot of the state between the calls. Continue with [`MatcherFields::from_checkpoint`]
+    /// by giving the input from [`Checkpoint::offset`] on; the bytes before it have been taken,
+    /// including a partially read field header.
+    pub fn checkpoint(&self) -> Checkpoint<M, M::Tag>
+    where
+        M: Clone,
+    {
+        Checkpoint {
+            offset: self.offset,
+            reader: self.reader.clone(),
+            matcher: self.matcher.clone(),
+            state: self.state.clone(),
+            items: self.items,
+            max_slice_len: self.max_slice_len,
+            nested: self.nested.clone(),
+            path: self.path.clone(),
+            #[cfg(feature = "groups")]
+            groups: self.groups.clone(),
+        }
+    }
+
+    /// Continues from the state of [`MatcherFields::checkpoint`].
+    pub fn from_checkpoint(checkpoint: Checkpoint<M, M::Tag>) -> Self {
+        let Checkpoint {
+            offset,
+            reader,
+            matcher,
+            state,
+            items,
+            max_slice_len,
+            nested,
+            path,
+            #[cfg(feature = "groups")]
+            groups,
+        } = checkpoint;
+
+        Self {
+            offset,
+            reader,
+            matcher,
+            state,
+            items,
+            max_slice_len,
+            nested,
+            path,
+            #[cfg(feature = "groups")]
+            groups,
         }
     }
 
+    /// Rejects the varints which are not in the canonical form, see
+    /// [`FieldReader::set_strict`]. The offsets of the errors are stream offsets.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.reader.set_strict(strict);
+    }
+
+    /// Rejects the length delimited fields longer than `max`, see
+    /// [`FieldReader::set_max_field_len`].
+    pub fn set_max_field_len(&mut self, max: u64) {
+        self.reader.set_max_field_len(max);
+    }
+
+    /// Rejects the fields which would need buffering more than `max` bytes, that is
+    /// `Cont::ReadSlice`, `Cont::ReadString`, `Cont::ReadPacked` and `Cont::CaptureRaw`, with
+    /// `DecodingErrorKind::SliceLimitExceeded`. Skipped fields and the chunks of
+    /// `Cont::ReadPartialSlice` are not limited. By default there is no limit, so a length prefix
+    /// can make the caller buffer up to [`FieldReader::set_max_field_len`] bytes.
+    pub fn set_max_slice_len(&mut self, max: u64) {
+        self.max_slice_len = Some(max);
+    }
+
+    /// Tells that the caller has advanced its source over `amount` bytes after
+    /// `Status::CanSkip` instead of giving them.
+    ///
+    /// # Panics
+    ///
+    /// If not skipping a field, or if `amount` is more than the amount of the `Status::CanSkip`.
+    pub fn skipped(&mut self, amount: u64) {
+        let amount = Offset::try_from(amount).expect("more than the remaining amount");
+        let remaining = match &mut self.state {
+            State::Skipping(_, _, _, remaining) => remaining,
+            #[cfg(feature = "groups")]
+            State::SkippingGroup(_, _, _, _, remaining) => remaining,
+            _ => panic!("not skipping a field"),
+        };
+        assert!(amount <= *remaining, "more than the remaining amount");
+        *remaining -= amount;
+        self.offset += amount;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        matches!(self.state, State::Ready) && self.reader.pending() == 0
+    }
+
     #[allow(clippy::type_complexity)]
-    fn advance<'a>(
+    fn advance<'a, I: Input<'a>>(
         &mut self,
-        buf: &mut &'a [u8],
+        buf: &mut I,
     ) -> Result<Result<Option<Matched<M::Tag>>, Status>, DecodingError> {
-        match &mut self.state {
-            State::Ready => match self.reader.next(buf)? {
+        self.reader.set_offset(crate::widen(self.offset));
+
+        // the state is taken out and replaced with the next state in every branch; on errors the
+        // state is left Ready
+        match std::mem::replace(&mut self.state, State::Ready) {
+            State::Ready => match buf.read_field(&mut self.reader)? {
+                Err(Status::NeedMoreBytes(_)) => {
+                    // the partial header was taken by the reader
+                    let taken = buf.remaining();
+                    self.offset = crate::advance_offset(self.offset, taken as u64)?;
+                    buf.advance(taken);
+                    Ok(Err(Status::NeedMoreBytes(None)))
+                }
                 Err(s) => Ok(Err(s)),
                 Ok(read) => {
                     let consumed = read.consumed();
-                    let _decoded = &buf[..consumed];
-                    *buf = &buf[consumed..];
-                    let read_at = self.offset;
-                    self.offset += consumed as u64;
+                    let read_at = self.offset - read.resumed() as Offset;
+
+                    // the offsets up to the end of the field are then known to fit
+                    let end = field_end(self.offset, read_at, &read)?;
+                    let at = crate::offset_to_usize(read_at)?;
+
+                    // for Cont::CaptureRaw
+                    let header = *buf;
+                    buf.advance(consumed);
+                    self.offset += consumed as Offset;
+
+                    if let Some((limit, _)) = self.nested.last() {
+                        if end > *limit {
+                            let kind = DecodingErrorKind::FailedMatcherNesting(
+                                crate::offset_to_usize(end)?,
+                                crate::offset_to_usize(*limit)?,
+                            );
+                            return Err(DecodingError::from(kind)
+                                .with_offset(crate::widen(read_at))
+                                .with_field(read.field_id()));
+                        }
+                    }
+
+                    #[cfg(feature = "groups")]
+                    if read.wire_type() == WireType::EndGroup {
+                        let field = read.field_id();
+                        if self.groups.pop() != Some(field) {
+                            return Err(DecodingError::from(DecodingErrorKind::UnmatchedEndGroup)
+                                .with_offset(crate::widen(read_at))
+                                .with_field(field));
+                        }
+
+                        self.path.pop();
+                        self.state = State::DecidingAfter;
+
+                        let tag = self
+                            .matcher
+                            .end_group(crate::offset_to_usize(self.offset)?, field);
+                        return Ok(Ok(tag.map(|tag| Matched {
+                            tag,
+                            offset: self.offset,
+                            value: Value::Marker,
+                        })));
+                    }
+
+                    #[cfg(feature = "groups")]
+                    let group = read.is_start_group();
+                    #[cfg(not(feature = "groups"))]
+                    let group = false;
 
                     // when possibly going deeper, only one decision is enough.
-                    let decision = self.matcher.decide_before(read_at as usize, &read)?;
+                    let decision =
+                        self.matcher
+                            .decide_before(at, &read, &self.path)
+                            .map_err(|e| {
+                                e.or_offset(crate::widen(read_at)).or_field(read.field_id())
+                            })?;
+
+                    let invalid = DecodingError::from(DecodingErrorKind::InvalidDecision {
+                        wire_type: read.wire_type(),
+                    })
+                    .with_offset(crate::widen(read_at))
+                    .with_field(read.field_id());
 
                     let ret = match decision {
+                        Action::Continue(Cont::Message(_))
+                            if !read.is_length_delimited() && !group =>
+                        {
+                            return Err(invalid);
+                        }
+                        Action::Continue(Cont::ReadSlice(_))
+                        | Action::Continue(Cont::ReadString(_))
+                        | Action::Continue(Cont::ReadPartialSlice(_))
+                        | Action::Continue(Cont::ReadPacked(..))
+                            if !read.is_length_delimited() =>
+                        {
+                            return Err(invalid);
+                        }
+                        Action::Continue(Cont::ReadPacked(_, WireType::LengthDelimited)) => {
+                            return Err(invalid);
+                        }
+                        Action::Continue(Cont::CaptureRaw(_)) if group => {
+                            return Err(invalid);
+                        }
+                        Action::Continue(Cont::ReadSlice(_))
+                        | Action::Continue(Cont::ReadString(_))
+                        | Action::Continue(Cont::ReadPacked(..))
+                        | Action::Continue(Cont::CaptureRaw(_))
+                            if self
+                                .max_slice_len
+                                .is_some_and(|limit| read.field_len() as u64 > limit) =>
+                        {
+                            let kind = DecodingErrorKind::SliceLimitExceeded {
+                                field: read.field_id(),
+                                len: read.field_len() as u64,
+                                limit: self.max_slice_len.unwrap_or_default(),
+                            };
+                            return Err(DecodingError::from(kind)
+                                .with_offset(crate::widen(read_at))
+                                .with_field(read.field_id()));
+                        }
                         Action::Continue(Cont::Message(maybe_tag)) => {
+                            #[cfg(feature = "groups")]
+                            if group {
+                                self.groups.push(read.field_id());
+                            }
+
+                            self.path.push(read.field_id());
+
+                            if !group {
+                                let ended = EndedMessage {
+                                    field: read.field_id(),
+                                    offset: at,
+                                    tag: maybe_tag.clone(),
+                                };
+                                self.nested.push((end, ended));
+                            }
+
                             maybe_tag.map(|tag| Matched {
                                 tag,
                                 offset: read_at,
@@ -124,7 +599,12 @@ impl<M: Matcher> MatcherFields<M> {
                                 FieldValue::Varint(x) => Value::Varint(*x),
                                 FieldValue::Fixed64(x) => Value::Fixed64(*x),
                                 FieldValue::Fixed32(x) => Value::Fixed32(*x),
-                                x => panic!("{:?} returned for a length delimited field which is invalid. Either Cont::ReadSlice or Skip the field.", x),
+                                // either Cont::ReadSlice or Skip a length delimited field
+                                FieldValue::DataLength(_) => return Err(invalid),
+                                #[cfg(feature = "groups")]
+                                FieldValue::StartGroup | FieldValue::EndGroup => {
+                                    return Err(invalid)
+                                }
                             };
 
                             Some(Matched {
@@ -136,15 +616,67 @@ impl<M: Matcher> MatcherFields<M> {
                         Action::Continue(Cont::ReadSlice(tag)) => {
                             self.state = State::Buffering(
                                 tag,
+                                Buffered::Slice,
+                                read_at,
+                                self.offset,
+                                read.field_len() as Offset,
+                            );
+                            return Ok(Ok(None));
+                        }
+                        Action::Continue(Cont::ReadString(tag)) => {
+                            self.state = State::Buffering(
+                                tag,
+                                Buffered::Str,
+                                read_at,
+                                self.offset,
+                                read.field_len() as Offset,
+                            );
+                            return Ok(Ok(None));
+                        }
+                        Action::Continue(Cont::ReadPartialSlice(tag)) => {
+                            self.state = State::Streaming(tag, read_at, read.field_len() as Offset);
+                            return Ok(Ok(None));
+                        }
+                        Action::Continue(Cont::ReadPacked(tag, wire_type)) => {
+                            self.state = State::Buffering(
+                                tag,
+                                Buffered::Packed(wire_type),
+                                read_at,
+                                self.offset,
+                                read.field_len() as Offset,
+                            );
+                            return Ok(Ok(None));
+                        }
+                        Action::Continue(Cont::CaptureRaw(tag)) => {
+                            let (resumed, amount) = (read.resumed(), read.field_len() as Offset);
+                            let mut raw = RawHeader::default();
+                            raw.extend(self.reader.resumed_bytes(resumed));
+                            header.contiguous(consumed, |bytes| raw.extend(bytes));
+
+                            self.state = State::Buffering(
+                                tag,
+                                Buffered::Raw(raw),
                                 read_at,
                                 self.offset,
-                                read.field_len() as u64,
+                                amount,
+                            );
+                            return Ok(Ok(None));
+                        }
+                        #[cfg(feature = "groups")]
+                        Action::Skip(tag) if group => {
+                            self.state = State::SkippingGroup(
+                                tag,
+                                read_at,
+                                self.offset,
+                                vec![read.field_id()],
+                                0,
                             );
                             return Ok(Ok(None));
                         }
                         Action::Skip(tag) => {
                             let total = read.field_len();
-                            self.state = State::Skipping(tag, read_at, self.offset, total as u64);
+                            self.state =
+                                State::Skipping(tag, read_at, self.offset, total as Offset);
                             return Ok(Ok(None));
                         }
                     };
@@ -155,12 +687,24 @@ impl<M: Matcher> MatcherFields<M> {
                 }
             },
             State::DecidingAfter => {
-                let (again, maybe_tag) = self.matcher.decide_after(self.offset as usize);
+                let offset = self.offset;
+                let ended = match self.nested.last() {
+                    Some((end, _)) if *end == offset => self.nested.pop().map(|(_, ended)| ended),
+                    _ => None,
+                };
+                if ended.is_some() {
+                    self.path.pop();
+                }
+                let again = ended.is_some()
+                    && matches!(self.nested.last(), Some((end, _)) if *end == offset);
 
-                if !again {
-                    // the `again` would had been true if multiple levels of nested messages ended
-                    // at the same byte
-                    self.state = State::Ready;
+                let maybe_tag = self
+                    .matcher
+                    .decide_after(crate::offset_to_usize(offset)?, ended);
+
+                if again {
+                    // multiple levels of nested messages ended at the same byte
+                    self.state = State::DecidingAfter;
                 }
 
                 if let Some(tag) = maybe_tag {
@@ -173,73 +717,212 @@ impl<M: Matcher> MatcherFields<M> {
                     Ok(Ok(None))
                 }
             }
-            State::Buffering(_, _, _, amount) => {
-                if (buf.len() as u64) < *amount {
-                    // TODO: it'd be great to tell how many we are expecting, a size hint, so that
-                    // the caller could bail out on too large payloads.
-                    return Ok(Err(Status::NeedMoreBytes));
+            State::Buffering(tag, buffered, read_at, start, amount) => {
+                let available = buf.remaining() as Offset;
+                if available < amount {
+                    self.state = State::Buffering(tag, buffered, read_at, start, amount);
+                    return Ok(Err(Status::need_more(crate::widen(amount - available))));
                 }
 
-                let amount = *amount;
+                match buffered {
+                    Buffered::Packed(wire_type) => {
+                        // validated once here so that iterating the values cannot fail
+                        buf.contiguous(amount as usize, |bytes| {
+                            Packed::new(wire_type, bytes).map(drop)
+                        })
+                        .map_err(|e| e.or_offset(crate::widen(read_at)))?;
+                    }
+                    Buffered::Str => {
+                        buf.contiguous(amount as usize, |bytes| to_str(bytes, start).map(drop))?;
+                    }
+                    Buffered::Slice | Buffered::Raw(_) => {}
+                }
 
-                let bytes = &buf[..amount as usize];
-                assert_eq!(bytes.len() as u64, amount);
+                buf.advance(amount as usize);
+                self.offset += amount;
 
-                *buf = &buf[amount as usize..];
-                self.offset += amount as u64;
+                self.state = State::DecidingAfter;
 
-                // this trick is needed to avoid Matcher::Tag: Copy
-                let (tag, read_at, start) =
-                    match std::mem::replace(&mut self.state, State::DecidingAfter) {
-                        State::Buffering(tag, read_at, start, _) => (tag, read_at, start),
-                        _ => unreachable!(),
-                    };
+                let range = start..self.offset;
 
-                let ret = Matched {
+                Ok(Ok(Some(Matched {
                     tag,
                     offset: read_at,
-                    value: Value::Slice(start..self.offset),
-                };
+                    value: match buffered {
+                        Buffered::Slice => Value::Slice(range),
+                        Buffered::Str => Value::Str(range),
+                        Buffered::Packed(wire_type) => Value::Packed(wire_type, range),
+                        Buffered::Raw(raw) => Value::Raw(raw, range),
+                    },
+                })))
+            }
+            State::Streaming(tag, read_at, remaining) => {
+                let taken = remaining.min(buf.remaining() as Offset);
 
-                // FIXME: this is extra since we already replaced with this value
-                self.state = State::DecidingAfter;
+                if taken == 0 && remaining > 0 {
+                    self.state = State::Streaming(tag, read_at, remaining);
+                    return Ok(Err(Status::need_more(crate::widen(remaining))));
+                }
 
-                Ok(Ok(Some(ret)))
-            }
-            State::Skipping(_, _, _, amount) => {
-                let amt = *amount;
+                let start = self.offset;
+                buf.advance(taken as usize);
+                self.offset += taken;
+
+                let remaining = remaining - taken;
+                let chunk_tag = if remaining == 0 {
+                    self.state = State::DecidingAfter;
+                    tag
+                } else {
+                    let chunk_tag = tag.clone();
+                    self.state = State::Streaming(tag, read_at, remaining);
+                    chunk_tag
+                };
 
-                let skipped = amt.min(buf.len() as u64);
+                Ok(Ok(Some(Matched {
+                    tag: chunk_tag,
+                    offset: read_at,
+                    value: Value::SliceChunk(start..self.offset, remaining),
+                })))
+            }
+            State::Skipping(tag, read_at, start, amount) => {
+                let skipped = amount.min(buf.remaining() as Offset);
 
-                self.offset += skipped as u64;
-                *buf = &buf[skipped as usize..];
+                self.offset += skipped;
+                buf.advance(skipped as usize);
 
-                let remaining = amt - skipped;
+                let remaining = amount - skipped;
 
                 if remaining == 0 {
-                    let (tag, read_at, start) =
-                        match std::mem::replace(&mut self.state, State::DecidingAfter) {
-                            State::Skipping(tag, read_at, start, _) => (tag, read_at, start),
-                            _ => unreachable!(),
-                        };
-                    let ret = Matched {
+                    self.state = State::DecidingAfter;
+                    return Ok(Ok(Some(Matched {
                         tag,
                         offset: read_at,
                         value: Value::Slice(start..self.offset),
-                    };
-                    return Ok(Ok(Some(ret)));
+                    })));
+                }
+
+                self.state = State::Skipping(tag, read_at, start, remaining);
+
+                Ok(Err(Status::CanSkip(crate::widen(remaining))))
+            }
+            #[cfg(feature = "groups")]
+            State::SkippingGroup(tag, read_at, start, mut open, mut remaining) => loop {
+                if remaining > 0 {
+                    let skipped = remaining.min(buf.remaining() as Offset);
+                    self.offset += skipped;
+                    buf.advance(skipped as usize);
+                    remaining -= skipped;
+
+                    if remaining > 0 {
+                        self.state = State::SkippingGroup(tag, read_at, start, open, remaining);
+                        return Ok(Err(Status::CanSkip(crate::widen(remaining))));
+                    }
+                }
+
+                self.reader.set_offset(crate::widen(self.offset));
+                let read = match buf.read_field(&mut self.reader)? {
+                    Ok(read) => read,
+                    Err(_) => {
+                        // a partial header was taken by the reader
+                        let taken = buf.remaining();
+                        self.offset = crate::advance_offset(self.offset, taken as u64)?;
+                        buf.advance(taken);
+
+                        self.state = State::SkippingGroup(tag, read_at, start, open, remaining);
+                        return Ok(Err(Status::NeedMoreBytes(None)));
+                    }
+                };
+
+                let (field, wire_type, consumed) =
+                    (read.field_id(), read.wire_type(), read.consumed());
+                remaining = read.field_len() as Offset;
+
+                let end = self.offset - read.resumed() as Offset;
+                field_end(self.offset, end, &read)?;
+                self.offset += consumed as Offset;
+                buf.advance(consumed);
+
+                match wire_type {
+                    WireType::StartGroup => open.push(field),
+                    WireType::EndGroup => {
+                        if open.pop() != Some(field) {
+                            return Err(DecodingError::from(DecodingErrorKind::UnmatchedEndGroup)
+                                .with_offset(crate::widen(end))
+                                .with_field(field));
+                        }
+
+                        if open.is_empty() {
+                            self.state = State::DecidingAfter;
+                            return Ok(Ok(Some(Matched {
+                                tag,
+                                offset: read_at,
+                                value: Value::Slice(start..end),
+                            })));
+                        }
+                    }
+                    _ => {}
+                }
+            },
+        }
+    }
+
+    /// Reads as many items as the buffer allows, up to `max`, appending them to `out` and
+    /// returning the number of the items appended. If no items could be read the status is
+    /// returned instead. On an error the items read before it are left in `out`.
+    ///
+    /// This amortizes the per call overhead for messages with a lot of small fields.
+    #[allow(clippy::type_complexity)]
+    pub fn next_batch(
+        &mut self,
+        buf: &mut &[u8],
+        out: &mut Vec<Matched<M::Tag>>,
+        max: usize,
+    ) -> Result<Result<usize, Status>, DecodingError> {
+        let mut count = 0;
+        while count < max {
+            match self.advance(buf)? {
+                Ok(Some(m)) => {
+                    out.push(m);
+                    count += 1;
+                    self.items += 1;
                 }
+                Ok(None) => continue,
+                Err(status) if count == 0 => return Ok(Err(status)),
+                Err(_) => break,
+            }
+        }
+        Ok(Ok(count))
+    }
 
-                *amount = remaining;
+    /// Reads the next item like `Reader::next` but from the two slices of a ring buffer, where
+    /// the second one continues where the first one ends. The slices are advanced like the single
+    /// slice would be; once the first one has been consumed the second one takes its place.
+    #[allow(clippy::type_complexity)]
+    pub fn next_split<'a>(
+        &mut self,
+        buf: &mut (&'a [u8], &'a [u8]),
+    ) -> Result<Result<Matched<M::Tag>, Status>, DecodingError> {
+        self.next_from(buf)
+    }
 
-                // TODO: again, a size hint wouldn't hurt, especially if the user is reading from
-                // std::io::Seek or similar; these could just be not read at all.
-                Ok(Err(Status::NeedMoreBytes))
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn next_from<'a, I: Input<'a>>(
+        &mut self,
+        buf: &mut I,
+    ) -> Result<Result<Matched<M::Tag>, Status>, DecodingError> {
+        loop {
+            match self.advance(buf)? {
+                Ok(Some(m)) => {
+                    self.items += 1;
+                    return Ok(Ok(m));
+                }
+                Ok(None) => continue,
+                Err(e) => return Ok(Err(e)),
             }
         }
     }
 
-    pub fn into_parts(self) -> (u64, M) {
+    pub fn into_parts(self) -> (Offset, M) {
         (self.offset, self.matcher)
     }
 
@@ -261,12 +944,19 @@ impl<'a, M: Matcher> crate::Reader<'a> for MatcherFields<M> {
         &mut self,
         buf: &mut &'a [u8],
     ) -> Result<Result<Matched<M::Tag>, Status>, DecodingError> {
-        loop {
-            match self.advance(buf)? {
-                Ok(Some(m)) => return Ok(Ok(m)),
-                Ok(None) => continue,
-                Err(e) => return Ok(Err(e)),
-            }
+        self.next_from(buf)
+    }
+}
+
+/// The buffer is owned by the caller, so `buffered` is always zero.
+impl<M: Matcher> Introspect for MatcherFields<M> {
+    fn stats(&self) -> Stats {
+        Stats {
+            offset: crate::widen(self.offset),
+            mid_field: !matches!(self.state, State::Ready | State::DecidingAfter)
+                || self.reader.pending() > 0,
+            buffered: 0,
+            items: self.items,
         }
     }
 }
@@ -276,6 +966,24 @@ pub struct SlicedMatcherFields<M: Matcher> {
     inner: MatcherFields<M>,
 }
 
+impl<M: Matcher> Introspect for SlicedMatcherFields<M> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<M: Matcher> crate::Skip for MatcherFields<M> {
+    fn skipped(&mut self, amount: u64) {
+        MatcherFields::skipped(self, amount)
+    }
+}
+
+impl<M: Matcher> crate::Skip for SlicedMatcherFields<M> {
+    fn skipped(&mut self, amount: u64) {
+        self.inner.skipped(amount)
+    }
+}
+
 impl<'a, M: Matcher> crate::Reader<'a> for SlicedMatcherFields<M> {
     type Returned = SlicedMatched<'a, M::Tag>;
 
@@ -284,22 +992,8 @@ impl<'a, M: Matcher> crate::Reader<'a> for SlicedMatcherFields<M> {
         buf: &mut &'a [u8],
     ) -> Result<Result<SlicedMatched<'a, M::Tag>, Status>, DecodingError> {
         // store for later slicing
-        let orig: &'a [u8] = *buf;
+        let orig: &'a [u8] = buf;
         match self.inner.next(buf)? {
-            Ok(Matched {
-                tag,
-                offset,
-                value: Value::Slice(range),
-            }) => {
-                let slicer = self.inner.slicer(&orig[..(orig.len() - buf.len())]);
-                let bytes = slicer.as_slice(&range);
-
-                Ok(Ok(SlicedMatched {
-                    tag,
-                    offset,
-                    value: SlicedValue::Slice(range, bytes),
-                }))
-            }
             Ok(Matched { tag, offset, value }) => Ok(Ok(SlicedMatched {
                 tag,
                 offset,
@@ -308,7 +1002,36 @@ impl<'a, M: Matcher> crate::Reader<'a> for SlicedMatcherFields<M> {
                     Value::Varint(x) => SlicedValue::Varint(x),
                     Value::Fixed64(x) => SlicedValue::Fixed64(x),
                     Value::Fixed32(x) => SlicedValue::Fixed32(x),
-                    Value::Slice(_) => unreachable!("already matched it in an earlier arm"),
+                    Value::Slice(range) => {
+                        let slicer = self.inner.slicer(&orig[..(orig.len() - buf.len())]);
+                        if slicer.contains(&range) {
+                            let bytes = slicer.as_slice(&range);
+                            SlicedValue::Slice(range, bytes)
+                        } else {
+                            // a skipped field which was advanced over in the earlier calls
+                            SlicedValue::Marker
+                        }
+                    }
+                    Value::Str(range) => {
+                        let slicer = self.inner.slicer(&orig[..(orig.len() - buf.len())]);
+                        let s = to_str(slicer.as_slice(&range), range.start)?;
+                        SlicedValue::Str(range, s)
+                    }
+                    Value::Packed(wire_type, range) => {
+                        let slicer = self.inner.slicer(&orig[..(orig.len() - buf.len())]);
+                        let bytes = slicer.as_slice(&range);
+                        SlicedValue::Packed(range, Packed { wire_type, bytes })
+                    }
+                    Value::Raw(raw, range) => {
+                        let slicer = self.inner.slicer(&orig[..(orig.len() - buf.len())]);
+                        let bytes = slicer.as_slice(&range);
+                        SlicedValue::Raw(range, raw, bytes)
+                    }
+                    Value::SliceChunk(range, remaining) => {
+                        let slicer = self.inner.slicer(&orig[..(orig.len() - buf.len())]);
+                        let bytes = slicer.as_slice(&range);
+                        SlicedValue::SliceChunk(range, remaining, bytes)
+                    }
                 },
             })),
             Err(e) => Ok(Err(e)),
@@ -319,23 +1042,26 @@ impl<'a, M: Matcher> crate::Reader<'a> for SlicedMatcherFields<M> {
 /// An item tagged by a [`Matcher`] from the stream of fields read by
 /// [`MatcherFields`].
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Matched<T> {
     pub tag: T,
-    pub offset: u64,
+    pub offset: Offset,
     pub value: Value,
 }
 
 /// An item tagged by a [`Matcher`] from the stream of fields read by
 /// [`MatcherFields`] with Value::Slice turned into a byte slice.
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct SlicedMatched<'a, T> {
     pub tag: T,
-    pub offset: u64,
+    pub offset: Offset,
     pub value: SlicedValue<'a>,
 }
 
 /// Represents a matched value.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Value {
     /// Value does not exist in the stream, but it represents a state change taken by the
     /// [`Matcher`].
@@ -347,11 +1073,22 @@ pub enum Value {
     /// Value read as a [`WireType::Fixed32`]
     Fixed32(u32),
     /// A length delimited field read as slice.
-    Slice(Range<u64>),
+    Slice(Range<Offset>),
+    /// A length delimited field read with `Cont::ReadString`, validated as UTF-8.
+    Str(Range<Offset>),
+    /// A length delimited field read as packed values of the wire type.
+    Packed(WireType, Range<Offset>),
+    /// A field captured with `Cont::CaptureRaw`: the header, and the range of the payload of a
+    /// length delimited field which follows it.
+    Raw(RawHeader, Range<Offset>),
+    /// A chunk of a length delimited field read with `Cont::ReadPartialSlice`, and the amount of
+    /// bytes of the field remaining after it, zero for the last chunk.
+    SliceChunk(Range<Offset>, Offset),
 }
 
 /// Represents a sliced matched value.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SlicedValue<'a> {
     /// Value does not exist in the stream, but it represents a state change taken by the
     /// [`Matcher`].
@@ -363,26 +1100,1277 @@ pub enum SlicedValue<'a> {
     /// Value read as a [`WireType::Fixed32`]
     Fixed32(u32),
     /// A length delimited field read as slice.
-    Slice(Range<u64>, &'a [u8]),
+    Slice(Range<Offset>, &'a [u8]),
+    /// A length delimited field read as a string.
+    Str(Range<Offset>, &'a str),
+    /// A length delimited field read as packed values.
+    Packed(Range<Offset>, Packed<'a>),
+    /// A field captured with `Cont::CaptureRaw`, the header followed by the payload.
+    Raw(Range<Offset>, RawHeader, &'a [u8]),
+    /// A chunk of a slice and the amount of bytes remaining after it.
+    SliceChunk(Range<Offset>, Offset, &'a [u8]),
 }
 
-impl From<SlicedValue<'_>> for Value {
-    fn from(sv: SlicedValue<'_>) -> Self {
-        match sv {
-            SlicedValue::Marker => Self::Marker,
-            SlicedValue::Varint(x) => Self::Varint(x),
-            SlicedValue::Fixed64(x) => Self::Fixed64(x),
-            SlicedValue::Fixed32(x) => Self::Fixed32(x),
-            SlicedValue::Slice(range, _) => Self::Slice(range),
+/// Iterator over the packed repeated values of a single wire type, returned as
+/// [`Value::Varint`], [`Value::Fixed64`] or [`Value::Fixed32`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Packed<'a> {
+    wire_type: WireType,
+    bytes: &'a [u8],
+}
+
+impl<'a> Packed<'a> {
+    /// Checks that the bytes consist of whole values of the wire type.
+    pub fn new(wire_type: WireType, bytes: &'a [u8]) -> Result<Self, DecodingError> {
+        let invalid = DecodingError::from(DecodingErrorKind::InvalidPacked { wire_type });
+        match wire_type {
+            WireType::Varint => {
+                let mut rest = bytes;
+                while !rest.is_empty() {
+                    match read_varint64(rest)? {
+                        Ok((consumed, _)) => rest = &rest[consumed..],
+                        Err(NeedMoreBytes) => return Err(invalid),
+                    }
+                }
+            }
+            WireType::Fixed64 if bytes.len().is_multiple_of(8) => {}
+            WireType::Fixed32 if bytes.len().is_multiple_of(4) => {}
+            _ => return Err(invalid),
         }
+        Ok(Packed { wire_type, bytes })
+    }
+
+    pub fn wire_type(&self) -> WireType {
+        self.wire_type
+    }
+
+    /// The remaining bytes of the values.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
     }
 }
 
-impl Value {
-    pub fn slice_len(&self) -> Result<usize, ()> {
-        match self {
-            Value::Slice(Range { start, end }) => Ok((end - start) as usize),
-            _ => Err(()),
+impl Iterator for Packed<'_> {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        if self.bytes.is_empty() {
+            return None;
         }
+
+        let (consumed, value) = match self.wire_type {
+            WireType::Varint => {
+                let (consumed, x) = read_varint64(self.bytes)
+                    .ok()?
+                    .expect("validated in Packed::new");
+                (consumed, Value::Varint(x))
+            }
+            WireType::Fixed64 => {
+                let (consumed, x) = read_fixed64(self.bytes).expect("validated in Packed::new");
+                (consumed, Value::Fixed64(x))
+            }
+            WireType::Fixed32 => {
+                let (consumed, x) = read_fixed32(self.bytes).expect("validated in Packed::new");
+                (consumed, Value::Fixed32(x))
+            }
+            _ => unreachable!("rejected in Packed::new"),
+        };
+
+        self.bytes = &self.bytes[consumed..];
+        Some(value)
+    }
+}
+
+/// The header of a field captured with [`Cont::CaptureRaw`]: the tag and the length prefix, or
+/// the tag and the value for the other wire types, as they were in the input.
+#[derive(Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawHeader {
+    bytes: [u8; MAX_HEADER],
+    len: u8,
+}
+
+impl RawHeader {
+    fn extend(&mut self, bytes: &[u8]) {
+        let len = self.len as usize;
+        self.bytes[len..len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len() as u8;
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl fmt::Debug for RawHeader {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "RawHeader({:02x?})", self.as_bytes())
+    }
+}
+
+/// An owned version of [`SlicedMatched`] which can outlive the buffer it was read from.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OwnedMatched<T> {
+    pub tag: T,
+    pub offset: Offset,
+    pub value: OwnedValue,
+}
+
+/// An owned version of [`SlicedValue`], which only differs by copying the slice.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OwnedValue {
+    /// Value does not exist in the stream, but it represents a state change taken by the
+    /// [`Matcher`].
+    Marker,
+    /// Number read as a [`WireType::Varint`]
+    Varint(u64),
+    /// Value read as a [`WireType::Fixed64`]
+    Fixed64(u64),
+    /// Value read as a [`WireType::Fixed32`]
+    Fixed32(u32),
+    /// A length delimited field copied from the buffer.
+    Slice(Range<Offset>, Vec<u8>),
+    /// A string copied from the buffer.
+    Str(Range<Offset>, String),
+    /// Packed values of the wire type copied from the buffer.
+    Packed(Range<Offset>, WireType, Vec<u8>),
+    /// A captured field with the payload copied from the buffer.
+    Raw(Range<Offset>, RawHeader, Vec<u8>),
+    /// A chunk of a slice copied from the buffer.
+    SliceChunk(Range<Offset>, Offset, Vec<u8>),
+}
+
+impl<'a, T> SlicedMatched<'a, T> {
+    /// Copies the possible slice out of the buffer.
+    pub fn into_owned(self) -> OwnedMatched<T> {
+        OwnedMatched {
+            tag: self.tag,
+            offset: self.offset,
+            value: self.value.into_owned(),
+        }
+    }
+}
+
+impl<T> From<SlicedMatched<'_, T>> for OwnedMatched<T> {
+    fn from(sm: SlicedMatched<'_, T>) -> Self {
+        sm.into_owned()
+    }
+}
+
+impl<T> OwnedMatched<T> {
+    /// Borrows the owned value as a [`SlicedMatched`].
+    pub fn as_sliced(&self) -> SlicedMatched<'_, &T> {
+        SlicedMatched {
+            tag: &self.tag,
+            offset: self.offset,
+            value: self.value.as_sliced(),
+        }
+    }
+}
+
+impl SlicedValue<'_> {
+    /// Copies the possible slice out of the buffer.
+    pub fn into_owned(self) -> OwnedValue {
+        match self {
+            SlicedValue::Marker => OwnedValue::Marker,
+            SlicedValue::Varint(x) => OwnedValue::Varint(x),
+            SlicedValue::Fixed64(x) => OwnedValue::Fixed64(x),
+            SlicedValue::Fixed32(x) => OwnedValue::Fixed32(x),
+            SlicedValue::Slice(range, bytes) => OwnedValue::Slice(range, bytes.to_vec()),
+            SlicedValue::Str(range, s) => OwnedValue::Str(range, s.to_owned()),
+            SlicedValue::Packed(range, packed) => {
+                OwnedValue::Packed(range, packed.wire_type, packed.bytes.to_vec())
+            }
+            SlicedValue::Raw(range, raw, bytes) => OwnedValue::Raw(range, raw, bytes.to_vec()),
+            SlicedValue::SliceChunk(range, remaining, bytes) => {
+                OwnedValue::SliceChunk(range, remaining, bytes.to_vec())
+            }
+        }
+    }
+
+    /// Decodes a varint as a zigzag encoded `sint64`.
+    pub fn as_sint64(&self) -> Option<i64> {
+        match self {
+            SlicedValue::Varint(x) => Some(decode_zigzag64(*x)),
+            _ => None,
+        }
+    }
+
+    /// Decodes a varint as a zigzag encoded `sint32`, which only uses the lower 32 bits.
+    pub fn as_sint32(&self) -> Option<i32> {
+        match self {
+            SlicedValue::Varint(x) => Some(decode_zigzag32(*x as u32)),
+            _ => None,
+        }
+    }
+
+    /// See [`Value::as_bool`].
+    pub fn as_bool(&self) -> Result<bool, ConversionError> {
+        Value::from(self.clone()).as_bool()
+    }
+
+    /// See [`Value::as_u32`].
+    pub fn as_u32(&self) -> Result<u32, ConversionError> {
+        Value::from(self.clone()).as_u32()
+    }
+
+    /// See [`Value::as_u64`].
+    pub fn as_u64(&self) -> Result<u64, ConversionError> {
+        Value::from(self.clone()).as_u64()
+    }
+
+    /// See [`Value::as_i64_zigzag`].
+    pub fn as_i64_zigzag(&self) -> Result<i64, ConversionError> {
+        Value::from(self.clone()).as_i64_zigzag()
+    }
+
+    /// See [`Value::as_f32`].
+    pub fn as_f32(&self) -> Result<f32, ConversionError> {
+        Value::from(self.clone()).as_f32()
+    }
+
+    /// See [`Value::as_f64`].
+    pub fn as_f64(&self) -> Result<f64, ConversionError> {
+        Value::from(self.clone()).as_f64()
+    }
+
+    /// See [`Value::as_enum`].
+    pub fn as_enum<T: TryFrom<u64>>(&self) -> Result<T, ConversionError> {
+        Value::from(self.clone()).as_enum()
+    }
+}
+
+impl From<SlicedValue<'_>> for OwnedValue {
+    fn from(sv: SlicedValue<'_>) -> Self {
+        sv.into_owned()
+    }
+}
+
+impl OwnedValue {
+    pub fn as_sliced(&self) -> SlicedValue<'_> {
+        match self {
+            OwnedValue::Marker => SlicedValue::Marker,
+            OwnedValue::Varint(x) => SlicedValue::Varint(*x),
+            OwnedValue::Fixed64(x) => SlicedValue::Fixed64(*x),
+            OwnedValue::Fixed32(x) => SlicedValue::Fixed32(*x),
+            OwnedValue::Slice(range, bytes) => SlicedValue::Slice(range.clone(), bytes),
+            OwnedValue::Str(range, s) => SlicedValue::Str(range.clone(), s),
+            OwnedValue::Packed(range, wire_type, bytes) => SlicedValue::Packed(
+                range.clone(),
+                Packed {
+                    wire_type: *wire_type,
+                    bytes,
+                },
+            ),
+            OwnedValue::Raw(range, raw, bytes) => SlicedValue::Raw(range.clone(), *raw, bytes),
+            OwnedValue::SliceChunk(range, remaining, bytes) => {
+                SlicedValue::SliceChunk(range.clone(), *remaining, bytes)
+            }
+        }
+    }
+
+    /// Converts a string or a slice value into a `String`, returning the value back if it was
+    /// neither or the slice was not valid UTF-8.
+    pub fn into_string(self) -> Result<String, OwnedValue> {
+        match self {
+            OwnedValue::Str(_, s) => Ok(s),
+            OwnedValue::Slice(range, bytes) => {
+                String::from_utf8(bytes).map_err(|e| OwnedValue::Slice(range, e.into_bytes()))
+            }
+            other => Err(other),
+        }
+    }
+}
+
+impl From<OwnedValue> for Value {
+    fn from(ov: OwnedValue) -> Self {
+        match ov {
+            OwnedValue::Marker => Self::Marker,
+            OwnedValue::Varint(x) => Self::Varint(x),
+            OwnedValue::Fixed64(x) => Self::Fixed64(x),
+            OwnedValue::Fixed32(x) => Self::Fixed32(x),
+            OwnedValue::Slice(range, _) => Self::Slice(range),
+            OwnedValue::Str(range, _) => Self::Str(range),
+            OwnedValue::Packed(range, wire_type, _) => Self::Packed(wire_type, range),
+            OwnedValue::Raw(range, raw, _) => Self::Raw(raw, range),
+            OwnedValue::SliceChunk(range, remaining, _) => Self::SliceChunk(range, remaining),
+        }
+    }
+}
+
+impl From<SlicedValue<'_>> for Value {
+    fn from(sv: SlicedValue<'_>) -> Self {
+        match sv {
+            SlicedValue::Marker => Self::Marker,
+            SlicedValue::Varint(x) => Self::Varint(x),
+            SlicedValue::Fixed64(x) => Self::Fixed64(x),
+            SlicedValue::Fixed32(x) => Self::Fixed32(x),
+            SlicedValue::Slice(range, _) => Self::Slice(range),
+            SlicedValue::Str(range, _) => Self::Str(range),
+            SlicedValue::Packed(range, packed) => Self::Packed(packed.wire_type, range),
+            SlicedValue::Raw(range, raw, _) => Self::Raw(raw, range),
+            SlicedValue::SliceChunk(range, remaining, _) => Self::SliceChunk(range, remaining),
+        }
+    }
+}
+
+impl Value {
+    #[allow(clippy::result_unit_err)]
+    pub fn slice_len(&self) -> Result<usize, ()> {
+        match self {
+            Value::Slice(Range { start, end }) | Value::Str(Range { start, end }) => {
+                Ok((end - start) as usize)
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Decodes a varint as a zigzag encoded `sint64`.
+    pub fn as_sint64(&self) -> Option<i64> {
+        match self {
+            Value::Varint(x) => Some(decode_zigzag64(*x)),
+            _ => None,
+        }
+    }
+
+    /// Decodes a varint as a zigzag encoded `sint32`, which only uses the lower 32 bits.
+    pub fn as_sint32(&self) -> Option<i32> {
+        match self {
+            Value::Varint(x) => Some(decode_zigzag32(*x as u32)),
+            _ => None,
+        }
+    }
+
+    /// A varint as a `bool`, anything but zero being true.
+    pub fn as_bool(&self) -> Result<bool, ConversionError> {
+        match self {
+            Value::Varint(x) => Ok(*x != 0),
+            _ => Err(ConversionError::UnexpectedValue { wanted: "bool" }),
+        }
+    }
+
+    /// A varint or a fixed32 as an `uint32` or a `fixed32`.
+    pub fn as_u32(&self) -> Result<u32, ConversionError> {
+        match self {
+            Value::Varint(x) => u32::try_from(*x).map_err(|_| ConversionError::OutOfRange {
+                wanted: "uint32",
+                value: *x,
+            }),
+            Value::Fixed32(x) => Ok(*x),
+            _ => Err(ConversionError::UnexpectedValue { wanted: "uint32" }),
+        }
+    }
+
+    /// Any of the numbers as an `uint64`, `fixed64` or one of the narrower unsigned types.
+    pub fn as_u64(&self) -> Result<u64, ConversionError> {
+        match self {
+            Value::Varint(x) | Value::Fixed64(x) => Ok(*x),
+            Value::Fixed32(x) => Ok(u64::from(*x)),
+            _ => Err(ConversionError::UnexpectedValue { wanted: "uint64" }),
+        }
+    }
+
+    /// Same as [`Value::as_sint64`] with an error instead of `None`.
+    pub fn as_i64_zigzag(&self) -> Result<i64, ConversionError> {
+        self.as_sint64()
+            .ok_or(ConversionError::UnexpectedValue { wanted: "sint64" })
+    }
+
+    /// A fixed32 as a `float`.
+    pub fn as_f32(&self) -> Result<f32, ConversionError> {
+        match self {
+            Value::Fixed32(x) => Ok(f32::from_bits(*x)),
+            _ => Err(ConversionError::UnexpectedValue { wanted: "float" }),
+        }
+    }
+
+    /// A fixed64 as a `double`.
+    pub fn as_f64(&self) -> Result<f64, ConversionError> {
+        match self {
+            Value::Fixed64(x) => Ok(f64::from_bits(*x)),
+            _ => Err(ConversionError::UnexpectedValue { wanted: "double" }),
+        }
+    }
+
+    /// A varint as an enum, or any other type convertible from the number. The negative `int32`
+    /// values are encoded as 64-bit numbers.
+    pub fn as_enum<T: TryFrom<u64>>(&self) -> Result<T, ConversionError> {
+        match self {
+            Value::Varint(x) => T::try_from(*x).map_err(|_| ConversionError::OutOfRange {
+                wanted: "enum",
+                value: *x,
+            }),
+            _ => Err(ConversionError::UnexpectedValue { wanted: "enum" }),
+        }
+    }
+}
+
+/// Why a [`Value`] could not be converted into the wanted type, see [`Value::as_bool`] and the
+/// other conversions.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConversionError {
+    /// The value was not of the wire type of the wanted type, or not a number at all
+    UnexpectedValue { wanted: &'static str },
+    /// The number did not fit the wanted type
+    OutOfRange { wanted: &'static str, value: u64 },
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnexpectedValue { wanted } => {
+                write!(fmt, "value cannot be read as {}", wanted)
+            }
+            ConversionError::OutOfRange { wanted, value } => {
+                write!(fmt, "{} is out of range for {}", value, wanted)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Validates the bytes of a string starting at `start`, the error pointing at the first invalid
+/// byte.
+pub(crate) fn to_str(bytes: &[u8], start: Offset) -> Result<&str, DecodingError> {
+    std::str::from_utf8(bytes).map_err(|e| {
+        DecodingError::from(DecodingErrorKind::InvalidUtf8)
+            .with_offset(crate::widen(start) + e.valid_up_to() as u64)
+    })
+}
+
+/// The offset after the field of which the header ends at `offset`, erroring with
+/// `DecodingErrorKind::OffsetOverflow` if it does not fit an [`Offset`].
+fn field_end(
+    offset: Offset,
+    read_at: Offset,
+    read: &ReadField<'_>,
+) -> Result<Offset, DecodingError> {
+    let overflow = || {
+        let kind = DecodingErrorKind::OffsetOverflow {
+            offset: crate::widen(read_at),
+            len: read.field_len() as u64,
+        };
+        DecodingError::from(kind)
+            .with_offset(crate::widen(read_at))
+            .with_field(read.field_id())
+    };
+
+    let len = (read.consumed() as u64)
+        .checked_add(read.field_len() as u64)
+        .ok_or_else(overflow)?;
+    crate::advance_offset(offset, len).map_err(|_| overflow())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Action, Cont, ConversionError, EndedMessage, Matcher, MatcherFields, OwnedValue,
+        SlicedValue, Value,
+    };
+    use crate::{
+        DecodingError, DecodingErrorKind, FieldId, Introspect, ReadField, Reader, Status, WireType,
+    };
+    use hex_literal::hex;
+
+    /// Reads every field as a value, which is only valid for the non-length delimited ones.
+    struct Values;
+
+    impl Matcher for Values {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            _read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(Action::Continue(Cont::ReadValue(())))
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn invalid_decision_is_an_error() {
+        let input = hex!("0801 1201ff");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Values);
+
+        assert!(fields.next(&mut buf).unwrap().is_ok());
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::InvalidDecision {
+                wire_type: WireType::LengthDelimited
+            }
+        ));
+        assert_eq!((e.offset(), e.field()), (Some(2), Some(2)));
+    }
+
+    #[test]
+    fn next_batch_reads_what_is_buffered() {
+        // the last varint is incomplete
+        let input = hex!("0801 0802 0803 0896");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Values);
+        let mut out = Vec::new();
+
+        assert_eq!(
+            fields.next_batch(&mut buf, &mut out, 2).unwrap().unwrap(),
+            2
+        );
+        assert_eq!(
+            fields.next_batch(&mut buf, &mut out, 8).unwrap().unwrap(),
+            1
+        );
+        assert!(matches!(
+            fields.next_batch(&mut buf, &mut out, 8).unwrap(),
+            Err(Status::NeedMoreBytes(_))
+        ));
+
+        let values = out.iter().map(|m| &m.value).collect::<Vec<_>>();
+        assert!(matches!(
+            values[..],
+            [Value::Varint(1), Value::Varint(2), Value::Varint(3)]
+        ));
+    }
+
+    #[test]
+    fn zigzag_values() {
+        assert_eq!(Value::Varint(0).as_sint64(), Some(0));
+        assert_eq!(Value::Varint(3).as_sint64(), Some(-2));
+        assert_eq!(Value::Varint(u64::MAX).as_sint64(), Some(i64::MIN));
+        assert_eq!(
+            Value::Varint(u64::from(u32::MAX) - 1).as_sint32(),
+            Some(i32::MAX)
+        );
+        assert_eq!(Value::Fixed64(3).as_sint64(), None);
+    }
+
+    #[test]
+    fn typed_conversions() {
+        use std::convert::TryFrom;
+
+        #[derive(Debug, PartialEq)]
+        enum Color {
+            Red,
+            Green,
+        }
+
+        impl TryFrom<u64> for Color {
+            type Error = ();
+
+            fn try_from(x: u64) -> Result<Self, ()> {
+                match x {
+                    0 => Ok(Color::Red),
+                    1 => Ok(Color::Green),
+                    _ => Err(()),
+                }
+            }
+        }
+
+        assert_eq!(Value::Varint(2).as_bool(), Ok(true));
+        assert_eq!(
+            Value::Varint(1 << 32).as_u32(),
+            Err(ConversionError::OutOfRange {
+                wanted: "uint32",
+                value: 1 << 32
+            })
+        );
+        assert_eq!(Value::Fixed32(7).as_u64(), Ok(7));
+        assert_eq!(Value::Varint(3).as_i64_zigzag(), Ok(-2));
+        assert_eq!(Value::Fixed32(1.5f32.to_bits()).as_f32(), Ok(1.5));
+        assert_eq!(
+            Value::Fixed32(0).as_f64(),
+            Err(ConversionError::UnexpectedValue { wanted: "double" })
+        );
+        assert_eq!(Value::Varint(1).as_enum::<Color>(), Ok(Color::Green));
+        assert!(Value::Varint(2).as_enum::<Color>().is_err());
+        assert!(Value::Marker.as_bool().is_err());
+    }
+
+    #[test]
+    fn strict_offsets_are_stream_offsets() {
+        let input = hex!("0801 0880 00");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Values);
+        fields.set_strict(true);
+
+        fields.next(&mut buf).unwrap().unwrap();
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(e.kind(), DecodingErrorKind::NonCanonicalVarint));
+        assert_eq!((e.offset(), e.field()), (Some(3), Some(1)));
+    }
+
+    #[test]
+    fn partial_headers_need_not_be_kept() {
+        // 1: 150, 3: 1, 4: 150
+        let input = hex!("089601 1801 209601");
+        let mut fields = MatcherFields::new(Values);
+        let mut buf = Vec::new();
+        let mut offsets = Vec::new();
+
+        for &b in &input[..] {
+            buf.push(b);
+            let mut slice = &buf[..];
+            while let Ok(m) = fields.next(&mut slice).unwrap() {
+                offsets.push(m.offset);
+            }
+            // only the bytes of an unfinished value are left for the next call
+            let consumed = buf.len() - slice.len();
+            buf.drain(..consumed);
+        }
+
+        assert_eq!(offsets, [0, 3, 5]);
+    }
+
+    #[test]
+    fn size_hints() {
+        // 1: "abcd"
+        let input = hex!("0a0461626364");
+
+        let mut fields = MatcherFields::new(Raw);
+        let mut buf = &input[..3];
+        let status = fields.next(&mut buf).unwrap().unwrap_err();
+        assert_eq!(status.size_hint().map(|n| n.get()), Some(3));
+
+        let mut fields = MatcherFields::new(Chunks);
+        let mut buf = &input[..3];
+        fields.next(&mut buf).unwrap().unwrap();
+        let status = fields.next(&mut buf).unwrap().unwrap_err();
+        assert_eq!(status.size_hint().map(|n| n.get()), Some(3));
+
+        let mut buf = &input[..1];
+        let status = MatcherFields::new(Raw).next(&mut buf).unwrap().unwrap_err();
+        assert!(matches!(status, Status::NeedMoreBytes(None)));
+    }
+
+    #[test]
+    fn nesting_tracker_exits_at_the_ends() {
+        use super::NestingTracker;
+        use crate::field_reader::FieldReader;
+
+        // 1: { 2: { 3: 1 } } as the headers at offsets 0 and 2
+        let input = hex!("0a04 1202 1801");
+        let (mut first, mut second) = (FieldReader::default(), FieldReader::default());
+        let outer = first.next(&input[..]).unwrap().unwrap();
+        let inner = second.next(&input[2..]).unwrap().unwrap();
+
+        let mut nesting = NestingTracker::default();
+        nesting.enter(0, &outer, "outer").unwrap();
+        nesting.enter(2, &inner, "inner").unwrap();
+        assert_eq!(nesting.depth(), 2);
+        assert!(nesting.check(4).is_ok());
+        assert!(nesting.check(6).is_err());
+
+        // the inner field cannot end after the outer one
+        assert!(nesting.clone().enter(4, &outer, "too long").is_err());
+
+        assert_eq!(nesting.exit(4), None);
+        assert_eq!(nesting.exit(6), Some("inner"));
+        assert_eq!(nesting.exit(6), Some("outer"));
+        assert_eq!(nesting.exit(6), None);
+    }
+
+    /// Enters every length delimited field as a message, tagged with the field id, and returns
+    /// the ended messages as markers of the negated field id.
+    struct Messages;
+
+    impl Matcher for Messages {
+        type Tag = i64;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<i64>, DecodingError> {
+            let id = i64::from(read.field_id());
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::Message(Some(id)))
+            } else {
+                Action::Continue(Cont::ReadValue(id))
+            })
+        }
+
+        fn decide_after(&mut self, offset: usize, ended: Option<EndedMessage<i64>>) -> Option<i64> {
+            ended.map(|ended| {
+                assert_eq!(ended.tag, Some(i64::from(ended.field)));
+                assert!(ended.offset < offset);
+                -i64::from(ended.field)
+            })
+        }
+    }
+
+    #[test]
+    fn ended_messages_innermost_first() {
+        // 1: { 2: { 3: 1 } }, 4: {}, 5: 1
+        let input = hex!("0a04 1202 1801 2200 2801");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Messages);
+
+        let tags = std::iter::from_fn(|| fields.next(&mut buf).unwrap().ok())
+            .map(|m| m.tag)
+            .collect::<Vec<_>>();
+        assert_eq!(tags, [1, 2, 3, -2, -1, 4, -4, 5]);
+
+        // the field 2 would end after the field 1
+        let input = hex!("0a04 1203 180161");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Messages);
+        fields.next(&mut buf).unwrap().unwrap();
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::FailedMatcherNesting(7, 6)
+        ));
+        assert_eq!((e.offset(), e.field()), (Some(2), Some(2)));
+    }
+
+    #[test]
+    fn hostile_message_length() {
+        // 1: 1, 2: a message of u64::MAX bytes
+        let input = hex!("0801 12ffffffffffffffffff01");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Messages);
+
+        fields.next(&mut buf).unwrap().unwrap();
+        let e = fields.next(&mut buf).unwrap_err();
+        // over the default maximum field length, which is limited by the offsets
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::FieldTooLarge { len: u64::MAX, .. }
+        ));
+        assert_eq!((e.offset(), e.field()), (Some(2), Some(2)));
+    }
+
+    #[cfg(feature = "offset32")]
+    #[test]
+    fn fields_past_4gib_with_offset32() {
+        // 1: almost 4GiB which the caller seeks over, 1: 1, 1: 8 bytes past the largest offset
+        let input = hex!("0af0ffffff0f 0801 0a08");
+        let mut buf = &input[..6];
+        let mut fields = MatcherFields::new(Skips);
+
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::CanSkip(0xffff_fff0)))
+        ));
+        fields.skipped(0xffff_fff0);
+
+        let mut buf = &input[6..];
+        let m = fields.next(&mut buf).unwrap().unwrap();
+        assert!(matches!(m.value, Value::Slice(ref r) if *r == (6..0xffff_fff6)));
+        let m = fields.next(&mut buf).unwrap().unwrap();
+        assert_eq!(m.offset, 0xffff_fff6);
+
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::OffsetOverflow {
+                offset: 0xffff_fff8,
+                len: 8
+            }
+        ));
+        assert_eq!(e.offset(), Some(0xffff_fff8));
+    }
+
+    #[cfg(feature = "offset32")]
+    #[test]
+    fn hostile_message_length_with_offset32() {
+        // 1: 1 eight times, then 2: a message of the default maximum length, which would end past
+        // the largest offset
+        let mut input = hex!("0801").repeat(8);
+        input.extend(&hex!("12f0ffffff0f"));
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Messages);
+
+        for _ in 0..8 {
+            fields.next(&mut buf).unwrap().unwrap();
+        }
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::OffsetOverflow {
+                offset: 16,
+                len: 0xffff_fff0
+            }
+        ));
+    }
+
+    /// Enters every length delimited field, tagging the fields with their message paths.
+    struct Paths;
+
+    impl Matcher for Paths {
+        type Tag = Vec<FieldId>;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            path: &[FieldId],
+        ) -> Result<Action<Vec<FieldId>>, DecodingError> {
+            let path = path.to_vec();
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::Message(Some(path)))
+            } else {
+                Action::Continue(Cont::ReadValue(path))
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<Vec<FieldId>>>,
+        ) -> Option<Vec<FieldId>> {
+            None
+        }
+    }
+
+    #[test]
+    fn message_paths() {
+        // 1: { 2: { 3: 1 } }, 4: {}, 5: 1
+        let input = hex!("0a04 1202 1801 2200 2801");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Paths);
+
+        let paths = std::iter::from_fn(|| fields.next(&mut buf).unwrap().ok())
+            .map(|m| m.tag)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [vec![], vec![1], vec![1, 2], vec![], vec![]]);
+    }
+
+    /// Skips every field.
+    struct Skips;
+
+    impl Matcher for Skips {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            _read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(Action::Skip(()))
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn skipped_bytes_can_be_seeked_over() {
+        // 1: "abcdef", 2: 1
+        let input = hex!("0a06616263646566 1001");
+
+        let mut fields = MatcherFields::new(Skips);
+        let mut buf = &input[..4];
+        assert!(matches!(fields.next(&mut buf), Ok(Err(Status::CanSkip(4)))));
+        assert!(buf.is_empty());
+
+        // the caller seeks over three of the bytes and reads the last one
+        fields.skipped(3);
+        let mut buf = &input[7..];
+        let m = fields.next(&mut buf).unwrap().unwrap();
+        assert!(matches!(m.value, Value::Slice(ref r) if *r == (2..8)));
+
+        let m = fields.next(&mut buf).unwrap().unwrap();
+        assert_eq!(m.offset, 8);
+        assert!(buf.is_empty());
+    }
+
+    /// Reads the length delimited fields in chunks.
+    struct Chunks;
+
+    impl Matcher for Chunks {
+        type Tag = u32;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<u32>, DecodingError> {
+            Ok(Action::Continue(Cont::ReadPartialSlice(read.field_id())))
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<u32>>,
+        ) -> Option<u32> {
+            None
+        }
+    }
+
+    #[test]
+    fn partial_slices_are_chunked() {
+        // 1: "abcd", 2: ""
+        let input = hex!("0a0461626364 1200");
+
+        let mut fields = MatcherFields::new(Chunks).into_sliced();
+        let mut out = Vec::new();
+
+        // the chunks end where the buffer ends
+        for part in [&input[..4], &input[4..]] {
+            let mut buf = part;
+            while let Ok(m) = fields.next(&mut buf).unwrap() {
+                out.push(m.into_owned());
+            }
+            assert!(buf.is_empty());
+        }
+
+        let chunks = out
+            .iter()
+            .map(|m| match &m.value {
+                OwnedValue::SliceChunk(_, remaining, bytes) => (m.tag, *remaining, &bytes[..]),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chunks,
+            [(1, 2, &b"ab"[..]), (1, 0, &b"cd"[..]), (2, 0, &b""[..])]
+        );
+    }
+
+    /// Captures every field.
+    struct Raw;
+
+    impl Matcher for Raw {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            _read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(Action::Continue(Cont::CaptureRaw(())))
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn captured_fields_are_byte_exact() {
+        // 1: 0 as an overlong varint, 2: "ab"
+        let input = hex!("088000 12026162");
+
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Raw).into_sliced();
+        let mut out = Vec::new();
+        while let Ok(m) = fields.next(&mut buf).unwrap() {
+            match m.value {
+                SlicedValue::Raw(_, raw, bytes) => {
+                    out.extend_from_slice(raw.as_bytes());
+                    out.extend_from_slice(bytes);
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(out, input);
+
+        // with the headers split over the calls
+        let mut fields = MatcherFields::new(Raw);
+        let mut pending = Vec::new();
+        let mut headers = Vec::new();
+        for &b in &input[..] {
+            pending.push(b);
+            let mut slice = &pending[..];
+            while let Ok(m) = fields.next(&mut slice).unwrap() {
+                if let Value::Raw(raw, range) = m.value {
+                    headers.push((raw.as_bytes().to_vec(), range));
+                }
+            }
+            let consumed = pending.len() - slice.len();
+            pending.drain(..consumed);
+        }
+        assert_eq!(
+            headers,
+            [(input[..3].to_vec(), 3..3), (input[3..5].to_vec(), 5..7)]
+        );
+    }
+
+    /// Reads the length delimited fields as packed varints.
+    struct PackedVarints;
+
+    impl Matcher for PackedVarints {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            _read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(Action::Continue(Cont::ReadPacked((), WireType::Varint)))
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn read_packed() {
+        // 4: [3, 270, 86942]
+        let input = hex!("2206 03 8e02 9ea705");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(PackedVarints).into_sliced();
+
+        let m = fields.next(&mut buf).unwrap().unwrap();
+        let values = match m.value {
+            SlicedValue::Packed(range, packed) => {
+                assert_eq!(range, 2..8);
+                packed.collect::<Vec<_>>()
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+        assert!(matches!(
+            values[..],
+            [Value::Varint(3), Value::Varint(270), Value::Varint(86942)]
+        ));
+
+        // the last varint is cut short by the length
+        let input = hex!("2202 038e");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(PackedVarints);
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::InvalidPacked {
+                wire_type: WireType::Varint
+            }
+        ));
+        assert_eq!(e.offset(), Some(0));
+    }
+
+    /// Reads the length delimited fields as strings.
+    #[derive(Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    struct Strings;
+
+    impl Matcher for Strings {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            _read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(Action::Continue(Cont::ReadString(())))
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn read_string() {
+        // 1: "abc"
+        let input = hex!("0a03 616263");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Strings).into_sliced();
+
+        let m = fields.next(&mut buf).unwrap().unwrap();
+        assert!(matches!(m.value, SlicedValue::Str(ref r, "abc") if *r == (2..5)));
+        assert_eq!(m.into_owned().value.into_string(), Ok(String::from("abc")));
+
+        // 1: "a\xffb", split over two calls
+        let input = hex!("0a03 61ff62");
+        let mut fields = MatcherFields::new(Strings);
+        let mut buf = &input[..3];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::NeedMoreBytes(_)))
+        ));
+        let mut buf = &input[2..];
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(e.kind(), DecodingErrorKind::InvalidUtf8));
+        assert_eq!(e.offset(), Some(3));
+    }
+
+    #[test]
+    fn stats_after_fields() {
+        // 1: "abc", 2: "de"
+        let input = hex!("0a03616263 12026465");
+        let mut fields = MatcherFields::new(Strings);
+
+        let mut buf = &input[..];
+        assert!(fields.next(&mut buf).unwrap().is_ok());
+        let stats = fields.stats();
+        assert_eq!(
+            (stats.offset, stats.mid_field, stats.buffered, stats.items),
+            (5, false, 0, 1)
+        );
+
+        // the header of the second field is read but the string is not complete
+        let mut buf = &input[5..8];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::NeedMoreBytes(_)))
+        ));
+        let stats = fields.stats();
+        assert_eq!(
+            (stats.offset, stats.mid_field, stats.buffered, stats.items),
+            (7, true, 0, 1)
+        );
+
+        let mut buf = &input[7..];
+        assert!(fields.next(&mut buf).unwrap().is_ok());
+        let stats = fields.into_sliced().stats();
+        assert_eq!(
+            (stats.offset, stats.mid_field, stats.buffered, stats.items),
+            (9, false, 0, 2)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn continue_from_a_checkpoint() {
+        // 1: "abc", 1: "d"
+        let input = hex!("0a03 616263 0a01 64");
+        let mut fields = MatcherFields::new(Strings);
+        fields.set_max_slice_len(3);
+
+        // the header and a part of the string
+        let mut buf = &input[..4];
+        assert!(matches!(
+            fields.next(&mut buf),
+            Ok(Err(Status::NeedMoreBytes(_)))
+        ));
+
+        let checkpoint = fields.checkpoint();
+        assert_eq!(checkpoint.offset(), 2);
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        drop(fields);
+
+        let checkpoint = serde_json::from_str(&json).unwrap();
+        let mut fields = MatcherFields::<Strings>::from_checkpoint(checkpoint).into_sliced();
+        let mut buf = &input[2..];
+        let mut strings = Vec::new();
+        while let Ok(m) = fields.next(&mut buf).unwrap() {
+            match m.value {
+                SlicedValue::Str(range, s) => strings.push((range, s)),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(strings, [(2..5, "abc"), (7..8, "d")]);
+    }
+
+    #[test]
+    fn buffered_slices_are_limited() {
+        // 1: "ab", 1: "abc", only the header of the latter
+        let input = hex!("0a02 6162 0a03");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Strings);
+        fields.set_max_slice_len(2);
+
+        assert!(matches!(fields.next(&mut buf), Ok(Ok(_))));
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            DecodingErrorKind::SliceLimitExceeded {
+                field: 1,
+                len: 3,
+                limit: 2
+            }
+        ));
+        assert_eq!(e.offset(), Some(4));
+        assert_eq!(e.field(), Some(1));
+    }
+
+    /// Enters the groups of field 1 and skips the others, reading the rest as values.
+    #[cfg(feature = "groups")]
+    struct Groups;
+
+    #[cfg(feature = "groups")]
+    impl Matcher for Groups {
+        type Tag = &'static str;
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<Self::Tag>, DecodingError> {
+            Ok(match (read.is_start_group(), read.field_id()) {
+                (true, 1) => Action::Continue(Cont::Message(Some("start"))),
+                (true, _) => Action::Skip("skipped"),
+                _ => Action::Continue(Cont::ReadValue("value")),
+            })
+        }
+
+        fn decide_after(
+            &mut self,
+            _offset: usize,
+            _ended: Option<EndedMessage<Self::Tag>>,
+        ) -> Option<Self::Tag> {
+            None
+        }
+
+        fn end_group(&mut self, _offset: usize, _field: crate::FieldId) -> Option<Self::Tag> {
+            Some("end")
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "groups")]
+    fn groups_are_entered_and_skipped() {
+        use std::ops::Range;
+
+        // 1: { 1: 1 }, 2: { 1: "a", 3: {} }, 3: 5
+        let input = hex!("0b 0801 0c 13 0a0161 1b 1c 14 1805");
+        let mut fields = MatcherFields::new(Groups);
+
+        // fed a byte at a time to resume in the middle of the skipped group
+        let mut items = Vec::new();
+        let (mut at, mut end) = (0, 0);
+        while at < input.len() {
+            let mut buf = &input[at..end];
+            let res = fields.next(&mut buf).unwrap();
+            at = end - buf.len();
+            match res {
+                Ok(m) => items.push((m.tag, m.offset, m.value)),
+                Err(_) => end += 1,
+            }
+        }
+
+        assert!(
+            matches!(
+                items[..],
+                [
+                    ("start", 0, Value::Marker),
+                    ("value", 1, Value::Varint(1)),
+                    ("end", 4, Value::Marker),
+                    ("skipped", 4, Value::Slice(Range { start: 5, end: 10 })),
+                    ("value", 11, Value::Varint(5)),
+                ]
+            ),
+            "{:?}",
+            items
+        );
+
+        let input = hex!("0801 0c");
+        let mut buf = &input[..];
+        let mut fields = MatcherFields::new(Groups);
+        fields.next(&mut buf).unwrap().unwrap();
+        let e = fields.next(&mut buf).unwrap_err();
+        assert!(matches!(e.kind(), DecodingErrorKind::UnmatchedEndGroup));
+        assert_eq!((e.offset(), e.field()), (Some(2), Some(1)));
     }
 }
@@ -0,0 +1,126 @@
+//! Reporting the memory held by the IO wrappers and [`crate::gather_fields::GatheredFields`],
+//! for the capacity planning of services which need to buffer large fields or retain large
+//! windows.
+
+/// Memory usage of a wrapper or a reader.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryUsage {
+    /// Bytes allocated for the buffer. Zero for the readers without a buffer of their own.
+    pub capacity: u64,
+    /// Bytes which are still needed: the unread bytes of a buffer, or the window of the caller's
+    /// buffer retained by a gatherer.
+    pub retained: u64,
+    /// The highest `capacity` since creation
+    pub peak_capacity: u64,
+    /// The highest `retained` since creation
+    pub peak_retained: u64,
+}
+
+/// Wrappers and readers which can report their [`MemoryUsage`].
+pub trait MemoryReport {
+    fn memory(&self) -> MemoryUsage;
+}
+
+type OnPeak = Box<dyn FnMut(&MemoryUsage) + Send>;
+
+/// The peak values and the callback to call when they grow.
+#[derive(Default)]
+pub(crate) struct Peaks {
+    capacity: u64,
+    retained: u64,
+    on_peak: Option<OnPeak>,
+}
+
+impl Peaks {
+    pub(crate) fn set_on_peak<F>(&mut self, on_peak: Option<F>)
+    where
+        F: FnMut(&MemoryUsage) + Send + 'static,
+    {
+        self.on_peak = on_peak.map(|f| Box::new(f) as Box<_>);
+    }
+
+    /// Records the current values, calling the callback if either of the peaks grew.
+    pub(crate) fn update(&mut self, capacity: u64, retained: u64) {
+        if capacity <= self.capacity && retained <= self.retained {
+            return;
+        }
+
+        self.capacity = self.capacity.max(capacity);
+        self.retained = self.retained.max(retained);
+
+        let usage = self.usage(capacity, retained);
+        if let Some(on_peak) = self.on_peak.as_mut() {
+            on_peak(&usage);
+        }
+    }
+
+    pub(crate) fn usage(&self, capacity: u64, retained: u64) -> MemoryUsage {
+        MemoryUsage {
+            capacity,
+            retained,
+            peak_capacity: self.capacity.max(capacity),
+            peak_retained: self.retained.max(retained),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryReport, MemoryUsage};
+    use crate::io_ext::read::ReadWrapper;
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields};
+    use crate::{DecodingError, FieldId, ReadField};
+    use hex_literal::hex;
+    use std::sync::{Arc, Mutex};
+
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn peaks_are_reported() {
+        // a long slice followed by a short one
+        let mut input = hex!("1220").to_vec();
+        input.extend_from_slice(&[b'a'; 32]);
+        input.extend_from_slice(&hex!("120161"));
+
+        let peaks = Arc::new(Mutex::new(Vec::new()));
+        let mut rw = ReadWrapper::new(&input[..], MatcherFields::new(Slices));
+        {
+            let peaks = Arc::clone(&peaks);
+            rw.set_on_peak(Some(move |usage: &MemoryUsage| {
+                peaks.lock().unwrap().push(usage.peak_capacity)
+            }));
+        }
+
+        while rw.read_next().unwrap().is_some() {}
+
+        let usage = rw.memory();
+        assert!(usage.peak_capacity >= 34, "{:?}", usage);
+        assert!(usage.peak_retained >= 34, "{:?}", usage);
+        assert!(usage.retained < usage.peak_retained, "{:?}", usage);
+
+        let peaks = peaks.lock().unwrap();
+        assert!(!peaks.is_empty());
+        assert!(peaks.windows(2).all(|w| w[0] <= w[1]), "{:?}", peaks);
+    }
+}
@@ -0,0 +1,198 @@
+//! Iterating the fields of a complete message held in memory.
+//!
+//! Unlike `MatcherFields` nothing here is incremental: the whole message must be in the buffer,
+//! and running out of bytes is an error.
+
+use crate::field_reader::FieldReader;
+use crate::{DecodingError, FieldId, FieldValue, WireType};
+use std::fmt;
+
+/// A single field of a complete message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Field<'a> {
+    pub id: FieldId,
+    /// Offset of the tag from the beginning of the message
+    pub offset: usize,
+    /// The whole field including the tag, for copying it verbatim
+    pub raw: &'a [u8],
+    pub value: FieldData<'a>,
+}
+
+/// The value of a [`Field`], with the payload of length delimited fields.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldData<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    Bytes(&'a [u8]),
+}
+
+impl FieldData<'_> {
+    pub fn wire_type(&self) -> WireType {
+        match self {
+            FieldData::Varint(_) => WireType::Varint,
+            FieldData::Fixed64(_) => WireType::Fixed64,
+            FieldData::Fixed32(_) => WireType::Fixed32,
+            FieldData::Bytes(_) => WireType::LengthDelimited,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MessageError {
+    Decoding(DecodingError),
+    /// The message ended in the middle of the field starting at the offset
+    Truncated {
+        offset: usize,
+    },
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::Decoding(e) => write!(fmt, "{}", e),
+            MessageError::Truncated { offset } => {
+                write!(fmt, "message was truncated in the field at {}", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+impl From<DecodingError> for MessageError {
+    fn from(e: DecodingError) -> Self {
+        MessageError::Decoding(e)
+    }
+}
+
+/// Iterator over the fields of a complete message. Nested messages are not entered, they are
+/// returned as [`FieldData::Bytes`] which can be iterated with another `Fields`.
+///
+/// The iterator is fused after the first error.
+pub struct Fields<'a> {
+    buf: &'a [u8],
+    at: usize,
+    reader: FieldReader,
+    failed: bool,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Fields {
+            buf,
+            at: 0,
+            reader: FieldReader::default(),
+            failed: false,
+        }
+    }
+
+    /// Offset of the next field.
+    pub fn offset(&self) -> usize {
+        self.at
+    }
+
+    fn read_next(&mut self) -> Result<Field<'a>, MessageError> {
+        let offset = self.at;
+        let read = match self.reader.next(&self.buf[offset..])? {
+            Ok(read) => read,
+            Err(_) => return Err(MessageError::Truncated { offset }),
+        };
+
+        let start = offset + read.consumed();
+        let end = match start.checked_add(read.field_len()) {
+            Some(end) if end <= self.buf.len() => end,
+            _ => return Err(MessageError::Truncated { offset }),
+        };
+
+        let value = match *read.value() {
+            FieldValue::Varint(x) => FieldData::Varint(x),
+            FieldValue::Fixed64(x) => FieldData::Fixed64(x),
+            FieldValue::Fixed32(x) => FieldData::Fixed32(x),
+            FieldValue::DataLength(_) => FieldData::Bytes(&self.buf[start..end]),
+            #[cfg(feature = "groups")]
+            FieldValue::StartGroup | FieldValue::EndGroup => {
+                return Err(read.unsupported_group().into())
+            }
+        };
+
+        let id = read.field_id();
+        self.at = end;
+
+        Ok(Field {
+            id,
+            offset,
+            raw: &self.buf[offset..end],
+            value,
+        })
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<Field<'a>, MessageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.at == self.buf.len() {
+            return None;
+        }
+
+        let ret = self.read_next();
+        self.failed = ret.is_err();
+        Some(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldData, Fields, MessageError};
+    use hex_literal::hex;
+
+    #[test]
+    fn iterates_fields() {
+        let input = hex!("0801 120161 1d01000000 2202");
+        let mut fields = Fields::new(&input);
+
+        let first = fields.next().unwrap().unwrap();
+        assert_eq!((first.id, first.offset, first.raw), (1, 0, &input[..2]));
+        assert_eq!(first.value, FieldData::Varint(1));
+
+        let second = fields.next().unwrap().unwrap();
+        assert_eq!(second.value, FieldData::Bytes(b"a"));
+        assert_eq!(second.raw, &input[2..5]);
+
+        let third = fields.next().unwrap().unwrap();
+        assert_eq!(third.value, FieldData::Fixed32(1));
+
+        assert!(matches!(
+            fields.next(),
+            Some(Err(MessageError::Truncated { offset: 10 }))
+        ));
+        assert!(fields.next().is_none());
+    }
+
+    #[test]
+    fn hostile_lengths() {
+        use crate::field_reader::DEFAULT_MAX_FIELD_LEN;
+        use crate::pb::encode_varint;
+        use crate::DecodingErrorKind;
+
+        // 1: u64::MAX - 1 bytes
+        let input = hex!("0a feffffffffffffffff01");
+        assert!(matches!(
+            Fields::new(&input).next(),
+            Some(Err(MessageError::Decoding(e))) if matches!(e.kind(), DecodingErrorKind::FieldTooLarge { .. })
+        ));
+
+        // 1: 1, 1: the longest field there can be
+        let mut input = hex!("0801 0a").to_vec();
+        let mut tmp = [0u8; 10];
+        let len = encode_varint(DEFAULT_MAX_FIELD_LEN, &mut tmp);
+        input.extend(&tmp[..len]);
+        let mut fields = Fields::new(&input);
+        assert!(fields.next().unwrap().is_ok());
+        assert!(matches!(
+            fields.next(),
+            Some(Err(MessageError::Truncated { offset: 2 }))
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+//! Support for the legacy MessageSet wire format.
+//!
+//! A MessageSet is a sequence of `Item` groups with the field id 1, each containing the `type_id`
+//! (field 2, varint) and the `message` (field 3, length delimited) fields:
+//!
+//! ```text
+//! message MessageSet {
+//!   repeated group Item = 1 {
+//!     required int32 type_id = 2;
+//!     required bytes message = 3;
+//!   }
+//! }
+//! ```
+//!
+//! As groups are otherwise not supported, [`MessageSetReader`] parses the items on its own. An item
+//! is returned only once it has been completely buffered.
+
+use crate::pb::{read_fixed32, read_fixed64, read_varint32, read_varint64};
+use crate::{DecodingError, DecodingErrorKind, FieldId, NeedMoreBytes, Offset, Reader, Status};
+use std::ops::Range;
+
+const ITEM_FIELD: FieldId = 1;
+const TYPE_ID_FIELD: FieldId = 2;
+const MESSAGE_FIELD: FieldId = 3;
+
+const START_GROUP: u32 = 3;
+const END_GROUP: u32 = 4;
+
+/// A single item of a MessageSet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageSetItem<'a> {
+    /// Offset of the beginning of the item group
+    pub offset: Offset,
+    /// Identifies the type of the `message`
+    pub type_id: u32,
+    /// Offsets of the `message` payload
+    pub range: Range<Offset>,
+    /// The serialized message
+    pub message: &'a [u8],
+}
+
+/// Reads [`MessageSetItem`]s out of a MessageSet. Top level fields other than the item groups are
+/// skipped.
+#[derive(Debug, Default)]
+pub struct MessageSetReader {
+    offset: Offset,
+}
+
+impl MessageSetReader {
+    pub fn offset(&self) -> Offset {
+        self.offset
+    }
+}
+
+macro_rules! launder {
+    ($x:expr) => {
+        match $x {
+            Ok(x) => x,
+            Err(NeedMoreBytes) => return Ok(Err(NeedMoreBytes)),
+        }
+    };
+}
+
+enum Parsed {
+    /// A field other than an item was skipped
+    Skipped,
+    Item(u32, Range<usize>),
+}
+
+impl<'a> Reader<'a> for MessageSetReader {
+    type Returned = MessageSetItem<'a>;
+
+    fn next(
+        &mut self,
+        buf: &mut &'a [u8],
+    ) -> Result<Result<Self::Returned, Status>, DecodingError> {
+        loop {
+            if buf.is_empty() {
+                return Ok(Err(Status::IdleAtEndOfBuffer));
+            }
+
+            let at = crate::widen(self.offset);
+            let (consumed, parsed) = match parse_one(buf).map_err(|e| e.or_offset(at))? {
+                Ok(x) => x,
+                Err(NeedMoreBytes) => return Ok(Err(Status::NeedMoreBytes(None))),
+            };
+
+            let offset = self.offset;
+            let data: &'a [u8] = buf;
+            *buf = &data[consumed..];
+            self.offset += consumed as Offset;
+
+            if let Parsed::Item(type_id, r) = parsed {
+                return Ok(Ok(MessageSetItem {
+                    offset,
+                    type_id,
+                    range: (offset + r.start as Offset)..(offset + r.end as Offset),
+                    message: &data[r],
+                }));
+            }
+        }
+    }
+}
+
+/// Parses a single top level field, returning the amount of bytes consumed.
+fn parse_one(data: &[u8]) -> Result<Result<(usize, Parsed), NeedMoreBytes>, DecodingError> {
+    let (mut at, tag) = launder!(read_varint32(data)?);
+
+    if tag >> 3 != ITEM_FIELD || tag & 0x7 != START_GROUP {
+        let len = launder!(skip_value(&data[at..], tag)?);
+        return Ok(Ok((at + len, Parsed::Skipped)));
+    }
+
+    let mut type_id = None;
+    let mut message = None;
+
+    loop {
+        let (consumed, tag) = launder!(read_varint32(&data[at..])?);
+        at += consumed;
+
+        match (tag >> 3, tag & 0x7) {
+            (ITEM_FIELD, END_GROUP) => break,
+            (_, END_GROUP) => return Err(DecodingErrorKind::InvalidMessageSetItem.into()),
+            (TYPE_ID_FIELD, 0) => {
+                let (consumed, val) = launder!(read_varint64(&data[at..])?);
+                type_id = Some(val as u32);
+                at += consumed;
+            }
+            (MESSAGE_FIELD, 2) => {
+                let (consumed, len) = launder!(read_varint32(&data[at..])?);
+                let start = at + consumed;
+                let end = start + len as usize;
+                if data.len() < end {
+                    return Ok(Err(NeedMoreBytes));
+                }
+                message = Some(start..end);
+                at = end;
+            }
+            _ => {
+                at += launder!(skip_value(&data[at..], tag)?);
+            }
+        }
+    }
+
+    match (type_id, message) {
+        (Some(type_id), Some(range)) => Ok(Ok((at, Parsed::Item(type_id, range)))),
+        _ => Err(DecodingErrorKind::InvalidMessageSetItem.into()),
+    }
+}
+
+/// Returns the length of value following the tag.
+fn skip_value(data: &[u8], tag: u32) -> Result<Result<usize, NeedMoreBytes>, DecodingError> {
+    Ok(Ok(match tag & 0x7 {
+        0 => launder!(read_varint64(data)?).0,
+        1 => launder!(read_fixed64(data)).0,
+        2 => {
+            let (consumed, len) = launder!(read_varint32(data)?);
+            let total = consumed + len as usize;
+            if data.len() < total {
+                return Ok(Err(NeedMoreBytes));
+            }
+            total
+        }
+        3 | 4 => return Err(DecodingErrorKind::UnsupportedGroupWireType(tag).into()),
+        5 => launder!(read_fixed32(data)).0,
+        _ => return Err(DecodingErrorKind::UnknownWireType(tag).into()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MessageSetItem, MessageSetReader};
+    use crate::{DecodingErrorKind, Reader, Status};
+    use hex_literal::hex;
+
+    #[test]
+    fn reads_items() {
+        // two items, the latter with the message before type_id and an unknown varint field
+        let input = hex!("0b 1001 1a03616263 0c 0b 1a00 2007 10ac02 0c");
+
+        let mut reader = MessageSetReader::default();
+
+        // all prefixes are either incomplete or contain the first item
+        for end in 1..9 {
+            let mut buf = &input[..end];
+            let ret = reader.next(&mut buf).unwrap();
+            assert!(matches!(ret, Err(Status::NeedMoreBytes(_))), "{}", end);
+        }
+
+        let mut buf = &input[..];
+        assert_eq!(
+            reader.next(&mut buf).unwrap().unwrap(),
+            MessageSetItem {
+                offset: 0,
+                type_id: 1,
+                range: 5..8,
+                message: b"abc",
+            }
+        );
+        assert_eq!(
+            reader.next(&mut buf).unwrap().unwrap(),
+            MessageSetItem {
+                offset: 9,
+                type_id: 300,
+                range: 12..12,
+                message: b"",
+            }
+        );
+        assert!(matches!(
+            reader.next(&mut buf).unwrap(),
+            Err(Status::IdleAtEndOfBuffer)
+        ));
+    }
+
+    #[test]
+    fn missing_message() {
+        let input = hex!("0b 1001 0c");
+        let mut buf = &input[..];
+        let e = MessageSetReader::default().next(&mut buf).unwrap_err();
+        assert!(
+            matches!(e.kind(), DecodingErrorKind::InvalidMessageSetItem),
+            "{:?}",
+            e
+        );
+        assert_eq!(e.offset(), Some(0));
+    }
+}
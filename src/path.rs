@@ -0,0 +1,297 @@
+//! Extracting fields by slash separated paths of field ids such as `/1/2/3::string`, which
+//! navigates fields 1 and 2 as nested messages and picks field 3, to be converted into a
+//! string.
+//!
+//! [`PathMatcher`] tags the matching leaf fields with [`Tag::Leaf`]; string leaves are validated
+//! as UTF-8 while reading, the other conversions into the [`LeafType`] are left to the caller, see
+//! the `extractor` example.
+
+use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher};
+use crate::{DecodingError, FieldId, ReadField, WireType};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The type the leaf field of a [`Path`] is wanted as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LeafType {
+    Slice,
+    Str,
+    U64,
+    I64,
+    F64,
+    F32,
+    Bool,
+    /// Any field, written as `any` or given when the type is left out
+    Debug,
+}
+
+impl LeafType {
+    /// True if the leaf is only matched when length delimited.
+    fn is_length_delimited(&self) -> bool {
+        matches!(self, LeafType::Slice | LeafType::Str)
+    }
+}
+
+impl TryFrom<&'_ str> for LeafType {
+    type Error = ();
+
+    fn try_from(s: &'_ str) -> Result<Self, Self::Error> {
+        Ok(match s {
+            "slice" | "bytes" => LeafType::Slice,
+            "str" | "string" => LeafType::Str,
+            "u64" | "uint64" | "u32" | "uint32" | "fixed32" | "fixed64" => LeafType::U64,
+            "i64" | "sint64" | "i32" | "sint32" | "sfixed32" | "sfixed64" => LeafType::I64,
+            "double" => LeafType::F64,
+            "float" => LeafType::F32,
+            "bool" => LeafType::Bool,
+            "any" => LeafType::Debug,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Parsed `/a/b/c::type` path, see [`Path::try_from`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    components: Vec<FieldId>,
+    leaf_type: LeafType,
+}
+
+impl Path {
+    /// The field ids from the outermost message to the leaf field, never empty.
+    pub fn components(&self) -> &[FieldId] {
+        &self.components
+    }
+
+    pub fn leaf_type(&self) -> LeafType {
+        self.leaf_type
+    }
+
+    pub fn into_components(self) -> Vec<FieldId> {
+        self.components
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PathParseError<'a> {
+    InvalidField(&'a str),
+    MissingLeafType,
+    UnsupportedLeafType(&'a str),
+    Empty,
+}
+
+impl fmt::Display for PathParseError<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use PathParseError::*;
+        match self {
+            InvalidField(field) => write!(fmt, "invalid field: {:?}", field),
+            MissingLeafType => write!(fmt, "path ended in double colon but the type is missing"),
+            UnsupportedLeafType(leaf_type) => write!(fmt, "unsupported leaf type: {:?}", leaf_type),
+            Empty => write!(fmt, "no path specified"),
+        }
+    }
+}
+
+impl std::error::Error for PathParseError<'_> {}
+
+/// Parses `/a/b/c::type` or `a/b/c::type`. Without the `::type` the leaf type is
+/// [`LeafType::Debug`].
+impl<'a> TryFrom<&'a str> for Path {
+    type Error = PathParseError<'a>;
+
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+        let mut split = s.split('/').enumerate().peekable();
+        let mut components = Vec::new();
+
+        while let Some((i, maybe_last)) = split.next() {
+            let is_last = split.peek().is_none();
+
+            if !is_last {
+                if i == 0 && maybe_last.is_empty() {
+                    // allow paths like `a/b/c` in addition to `/a/b/c`
+                    continue;
+                }
+                // this should only be an FieldId, no subscripts yet
+                let id = maybe_last
+                    .parse::<FieldId>()
+                    .map_err(|_| PathParseError::InvalidField(maybe_last))?;
+                components.push(id);
+            } else {
+                // there may be the "cast"
+                let mut split = maybe_last.split("::");
+                let last = split.next().expect("there is always the first element");
+
+                if last.is_empty() && components.is_empty() {
+                    return Err(PathParseError::Empty);
+                }
+
+                let last = last
+                    .parse::<FieldId>()
+                    .map_err(|_| PathParseError::InvalidField(last))?;
+
+                let leaf_type = match split.next() {
+                    Some("") => return Err(PathParseError::MissingLeafType),
+                    Some(leaf_type) => LeafType::try_from(leaf_type)
+                        .map_err(|_| PathParseError::UnsupportedLeafType(leaf_type))?,
+                    None => LeafType::Debug,
+                };
+
+                components.push(last);
+
+                return Ok(Path {
+                    components,
+                    leaf_type,
+                });
+            }
+        }
+
+        Err(PathParseError::Empty)
+    }
+}
+
+/// Marks the fields read by [`PathMatcher`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    /// A message on the path was entered
+    Start,
+    /// The leaf field, read as a slice or as a value
+    Leaf,
+    /// A message on the path ended
+    End,
+    /// A field not on the path was skipped
+    Ignored,
+    /// The leaf field was skipped as it was not of the wanted type
+    UnexpectedLeafType(WireType),
+}
+
+/// Matches the "fields delimited by slash" path syntax. Is not able to do the "to leaftype"
+/// conversion at least yet.
+pub struct PathMatcher {
+    /// What we want to find
+    path: Vec<FieldId>,
+    /// Leaf type we want to find, other parts of the path are submessages
+    leaf_type: LeafType,
+}
+
+impl PathMatcher {
+    /// # Panics
+    ///
+    /// If the path is empty.
+    pub fn new(path: Vec<FieldId>, leaf_type: LeafType) -> PathMatcher {
+        assert!(
+            !path.is_empty(),
+            "path needs to have at least the leaf field"
+        );
+        PathMatcher { path, leaf_type }
+    }
+}
+
+impl From<Path> for PathMatcher {
+    fn from(path: Path) -> Self {
+        PathMatcher::new(path.components, path.leaf_type)
+    }
+}
+
+impl Matcher for PathMatcher {
+    type Tag = Tag;
+
+    fn decide_before(
+        &mut self,
+        _offset: usize,
+        read: &ReadField<'_>,
+        path: &[FieldId],
+    ) -> Result<Action<Tag>, DecodingError> {
+        // only the messages on the path are entered
+        let depth = path.len();
+        let leaves = self.path.len() - 1;
+
+        let decision = if depth == leaves {
+            if read.field_id() == self.path[depth] {
+                match self.leaf_type {
+                    LeafType::Str if read.is_length_delimited() => {
+                        Action::Continue(Cont::ReadString(Tag::Leaf))
+                    }
+                    LeafType::Debug | LeafType::Slice if read.is_length_delimited() => {
+                        Action::Continue(Cont::ReadSlice(Tag::Leaf))
+                    }
+                    leaf_type
+                        if !read.is_length_delimited() && !leaf_type.is_length_delimited() =>
+                    {
+                        Action::Continue(Cont::ReadValue(Tag::Leaf))
+                    }
+                    _ => Action::Skip(Tag::UnexpectedLeafType(read.wire_type())),
+                }
+            } else {
+                Action::Skip(Tag::Ignored)
+            }
+        } else if read.field_id() == self.path[depth] && read.is_length_delimited() {
+            if read.field_len() > 0 {
+                Action::Continue(Cont::Message(Some(Tag::Start)))
+            } else {
+                Action::Skip(Tag::Ignored)
+            }
+        } else {
+            Action::Skip(Tag::Ignored)
+        };
+
+        Ok(decision)
+    }
+
+    fn decide_after(&mut self, _offset: usize, ended: Option<EndedMessage<Tag>>) -> Option<Tag> {
+        ended.map(|_| Tag::End)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LeafType, Path, PathMatcher, PathParseError, Tag};
+    use crate::matcher_fields::{MatcherFields, SlicedValue};
+    use crate::Reader;
+    use hex_literal::hex;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn parse_paths() {
+        let path = Path::try_from("/1/2/3::string").unwrap();
+        assert_eq!(path.components(), &[1, 2, 3]);
+        assert_eq!(path.leaf_type(), LeafType::Str);
+
+        let path = Path::try_from("4").unwrap();
+        assert_eq!(path.components(), &[4]);
+        assert_eq!(path.leaf_type(), LeafType::Debug);
+
+        assert_eq!(
+            Path::try_from("/1/x/3"),
+            Err(PathParseError::InvalidField("x"))
+        );
+        assert_eq!(Path::try_from("/1::"), Err(PathParseError::MissingLeafType));
+        assert_eq!(
+            Path::try_from("/1::foo"),
+            Err(PathParseError::UnsupportedLeafType("foo"))
+        );
+        assert_eq!(Path::try_from("/"), Err(PathParseError::Empty));
+    }
+
+    #[test]
+    fn leaves_of_nested_messages() {
+        // 1: { 2: "ab", 3: 5 }, 2: "x", 1: { 2: "cd" }
+        let input = hex!("0a06 12026162 1805 120178 0a04 12026364");
+        let path = Path::try_from("/1/2::string").unwrap();
+        let mut fields = MatcherFields::new(PathMatcher::from(path)).into_sliced();
+        let mut buf = &input[..];
+
+        let mut leaves = Vec::new();
+        let mut tags = Vec::new();
+        while let Ok(m) = fields.next(&mut buf).unwrap() {
+            if let (Tag::Leaf, SlicedValue::Str(_, s)) = (&m.tag, &m.value) {
+                leaves.push(s.to_string());
+            }
+            tags.push(m.tag);
+        }
+
+        assert_eq!(leaves, ["ab", "cd"]);
+        assert_eq!(tags.iter().filter(|t| **t == Tag::Start).count(), 2);
+        assert_eq!(tags.iter().filter(|t| **t == Tag::End).count(), 2);
+        assert!(buf.is_empty());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{DecodingError, NeedMoreBytes};
+//! Reading and writing the primitive values of the wire format.
+
+use crate::{DecodingError, DecodingErrorKind, FieldId, NeedMoreBytes, WireType};
 
 pub fn read_varint32(data: &[u8]) -> Result<Result<(usize, u32), NeedMoreBytes>, DecodingError> {
     match read_varint(data, 5)? {
@@ -11,6 +13,48 @@ pub fn read_varint64(data: &[u8]) -> Result<Result<(usize, u64), NeedMoreBytes>,
     read_varint(data, 10)
 }
 
+/// Reads a zigzag encoded `sint32` varint.
+pub fn read_sint32(data: &[u8]) -> Result<Result<(usize, i32), NeedMoreBytes>, DecodingError> {
+    match read_varint32(data)? {
+        Ok((bytes, val)) => Ok(Ok((bytes, decode_zigzag32(val)))),
+        Err(e) => Ok(Err(e)),
+    }
+}
+
+/// Reads a zigzag encoded `sint64` varint.
+pub fn read_sint64(data: &[u8]) -> Result<Result<(usize, i64), NeedMoreBytes>, DecodingError> {
+    match read_varint64(data)? {
+        Ok((bytes, val)) => Ok(Ok((bytes, decode_zigzag64(val)))),
+        Err(e) => Ok(Err(e)),
+    }
+}
+
+/// Returns true if the complete varint is in the canonical form: without trailing zero bytes and
+/// without any bits set above `max_bits`.
+pub fn is_canonical_varint(bytes: &[u8], max_bits: u32) -> bool {
+    let (last, rest) = match bytes.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+
+    if !rest.is_empty() && *last == 0 {
+        return false;
+    }
+
+    let bits = rest.len() as u32 * 7 + (8 - last.leading_zeros());
+    bits <= max_bits
+}
+
+/// Decodes the zigzag encoding of `sint32`, where the low bit holds the sign.
+pub fn decode_zigzag32(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+/// Decodes the zigzag encoding of `sint64`, where the low bit holds the sign.
+pub fn decode_zigzag64(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
 pub fn read_fixed32(data: &[u8]) -> Result<(usize, u32), NeedMoreBytes> {
     if data.len() < 4 {
         Err(NeedMoreBytes)
@@ -53,8 +97,102 @@ pub fn read_varint(
     if count < max_bytes {
         Ok(Err(NeedMoreBytes))
     } else if max_bytes == 4 {
-        Err(DecodingError::TooManyVarint32Bytes)
+        Err(DecodingErrorKind::TooManyVarint32Bytes.into())
     } else {
-        Err(DecodingError::TooManyVarint64Bytes)
+        Err(DecodingErrorKind::TooManyVarint64Bytes.into())
+    }
+}
+
+/// Encodes the value as a varint into the buffer, returning the number of bytes used.
+pub fn encode_varint(mut value: u64, buf: &mut [u8; 10]) -> usize {
+    let mut i = 0;
+    loop {
+        let b = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[i] = b;
+            return i + 1;
+        }
+        buf[i] = b | 0x80;
+        i += 1;
+    }
+}
+
+/// Appends the value encoded as a varint.
+pub fn write_varint(value: u64, out: &mut Vec<u8>) {
+    let mut tmp = [0u8; 10];
+    let len = encode_varint(value, &mut tmp);
+    out.extend_from_slice(&tmp[..len]);
+}
+
+/// Returns the value of the tag for the field id and wire type, to be encoded as a varint.
+pub fn tag(id: FieldId, wire_type: WireType) -> u64 {
+    let lowest_bits = match wire_type {
+        WireType::Varint => 0,
+        WireType::Fixed64 => 1,
+        WireType::LengthDelimited => 2,
+        #[cfg(feature = "groups")]
+        WireType::StartGroup => 3,
+        #[cfg(feature = "groups")]
+        WireType::EndGroup => 4,
+        WireType::Fixed32 => 5,
+    };
+    u64::from(id) << 3 | lowest_bits
+}
+
+/// Appends the tag of a field.
+pub fn write_tag(id: FieldId, wire_type: WireType, out: &mut Vec<u8>) {
+    write_varint(tag(id, wire_type), out);
+}
+
+/// Appends the value as 4 little-endian bytes.
+pub fn write_fixed32(value: u32, out: &mut Vec<u8>) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Appends the value as 8 little-endian bytes.
+pub fn write_fixed64(value: u64, out: &mut Vec<u8>) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Appends a complete length delimited field: the tag, the length prefix and the bytes.
+pub fn write_length_delimited(id: FieldId, bytes: &[u8], out: &mut Vec<u8>) {
+    write_tag(id, WireType::LengthDelimited, out);
+    write_varint(bytes.len() as u64, out);
+    out.extend_from_slice(bytes);
+}
+
+/// Returns the amount of bytes the value takes when encoded as varint.
+pub fn varint_len(value: u64) -> usize {
+    let bits = 64 - (value | 1).leading_zeros() as usize;
+    bits.div_ceil(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_varint64, write_length_delimited, write_tag, write_varint};
+    use crate::WireType;
+
+    #[test]
+    fn varints_roundtrip() {
+        for &x in &[0, 1, 127, 128, 227, 242, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(x, &mut out);
+            assert_eq!(read_varint64(&out).unwrap().unwrap(), (out.len(), x));
+        }
+
+        let mut out = Vec::new();
+        write_varint(227, &mut out);
+        write_varint(242, &mut out);
+        assert_eq!(out, [0xe3, 0x01, 0xf2, 0x01]);
+    }
+
+    #[test]
+    fn fields() {
+        let mut out = Vec::new();
+        write_tag(1, WireType::Varint, &mut out);
+        write_varint(150, &mut out);
+        write_length_delimited(2, b"ab", &mut out);
+        assert_eq!(out, [0x08, 0x96, 0x01, 0x12, 0x02, b'a', b'b']);
     }
 }
@@ -0,0 +1,578 @@
+//! The [protoscope] text format, a schema-less representation of the wire bytes which can be
+//! edited by hand and encoded back.
+//!
+//! [`write`] renders a message so that [`parse`] gives back the same bytes: overlong varint values
+//! are written with a `long-form:N` prefix, where N is the number of extra bytes, and anything
+//! which cannot be represented otherwise, such as overlong tags or trailing garbage, is written
+//! as a backtick bytes literal.
+//!
+//! The supported input is a subset of the protoscope language:
+//!
+//! * `N:TYPE` tags, where `TYPE` is one of `VARINT`, `I64`, `LEN`, `SGROUP`, `EGROUP`, `I32` or a
+//!   number, or `N:` to infer the type from the next token
+//! * integers in decimal or `0x` prefixed hex, written as varints, or with an `i32` or `i64`
+//!   suffix as fixed width integers, or with a `z` suffix as zigzag varints
+//! * floats, written as `I64` doubles or with an `i32` suffix as `I32` floats
+//! * `true` and `false`
+//! * `"strings"` with the `\n`, `\r`, `\t`, `\0`, `\xNN`, `\\`, `\"` and `\'` escapes and
+//!   `` `hex` `` bytes literals, written as is
+//! * `{ ... }` for the contents of a length delimited field, written with the length prefix
+//! * `long-form:N` before a varint, tag or `{` to encode it with N extra bytes
+//! * `#` comments until the end of the line
+//!
+//! [protoscope]: https://github.com/protocolbuffers/protoscope
+
+use crate::infer::{is_message, is_text};
+use crate::message::{FieldData, Fields};
+use crate::pb::{encode_varint, varint_len};
+use crate::FieldId;
+use std::convert::TryFrom;
+use std::fmt::{self, Write};
+
+/// How deep the nested messages are written as such, the deeper ones are written as bytes.
+const MAX_DEPTH: usize = 32;
+
+/// Writes the message in the protoscope format, guessing which of the length delimited fields
+/// are nested messages.
+pub fn write<W: Write>(buf: &[u8], out: &mut W) -> fmt::Result {
+    write_with(buf, out, |_, body| is_message(body))
+}
+
+/// Writes the message in the protoscope format, writing the length delimited fields for which
+/// `is_message` returns true, when called with the path of field ids and the bytes, as nested
+/// messages. The fields nested deeper than 32 levels are written as bytes literals.
+pub fn write_with<W, F>(buf: &[u8], out: &mut W, mut is_message: F) -> fmt::Result
+where
+    W: Write,
+    F: FnMut(&[FieldId], &[u8]) -> bool,
+{
+    let mut path = Vec::new();
+    write_message(buf, out, &mut is_message, &mut path, 0)
+}
+
+fn write_message(
+    buf: &[u8],
+    out: &mut dyn Write,
+    is_message: &mut dyn FnMut(&[FieldId], &[u8]) -> bool,
+    path: &mut Vec<FieldId>,
+    depth: usize,
+) -> fmt::Result {
+    let mut fields = Fields::new(buf);
+
+    loop {
+        let at = fields.offset();
+        let field = match fields.next() {
+            Some(Ok(field)) => field,
+            Some(Err(_)) => {
+                // groups, truncated fields and the like are kept verbatim
+                write!(out, "{:indent$}", "", indent = depth * 2)?;
+                write_bytes(out, &buf[at..])?;
+                return writeln!(out);
+            }
+            None => return Ok(()),
+        };
+
+        write!(out, "{:indent$}", "", indent = depth * 2)?;
+
+        let lowest_bits = match field.value {
+            FieldData::Varint(_) => 0,
+            FieldData::Fixed64(_) => 1,
+            FieldData::Bytes(_) => 2,
+            FieldData::Fixed32(_) => 5,
+        };
+        let tag_len = field.raw.iter().take_while(|&&b| b & 0x80 != 0).count() + 1;
+        let value_len = field.raw.len() - tag_len;
+
+        if tag_len != varint_len((u64::from(field.id) << 3) | lowest_bits) {
+            write_bytes(out, field.raw)?;
+            writeln!(out)?;
+            continue;
+        }
+
+        match field.value {
+            FieldData::Varint(x) => {
+                write!(out, "{}: ", field.id)?;
+                let extra = value_len - varint_len(x);
+                if extra > 0 {
+                    write!(out, "long-form:{} ", extra)?;
+                }
+                writeln!(out, "{}", x)?;
+            }
+            FieldData::Fixed64(x) => writeln!(out, "{}: {}i64", field.id, x as i64)?,
+            FieldData::Fixed32(x) => writeln!(out, "{}: {}i32", field.id, x as i32)?,
+            FieldData::Bytes(body) => {
+                let extra = value_len - body.len() - varint_len(body.len() as u64);
+                write!(out, "{}: ", field.id)?;
+                if extra > 0 {
+                    write!(out, "long-form:{} ", extra)?;
+                }
+
+                path.push(field.id);
+                if body.is_empty() {
+                    writeln!(out, "{{}}")?;
+                } else if depth < MAX_DEPTH && is_message(path, body) {
+                    writeln!(out, "{{")?;
+                    write_message(body, out, is_message, path, depth + 1)?;
+                    writeln!(out, "{:indent$}}}", "", indent = depth * 2)?;
+                } else if is_text(body) {
+                    write!(out, "{{")?;
+                    write_string(out, std::str::from_utf8(body).expect("checked by is_text"))?;
+                    writeln!(out, "}}")?;
+                } else {
+                    write!(out, "{{")?;
+                    write_bytes(out, body)?;
+                    writeln!(out, "}}")?;
+                }
+                path.pop();
+            }
+        }
+    }
+}
+
+fn write_string(out: &mut dyn Write, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+fn write_bytes(out: &mut dyn Write, bytes: &[u8]) -> fmt::Result {
+    out.write_char('`')?;
+    for b in bytes {
+        write!(out, "{:02x}", b)?;
+    }
+    out.write_char('`')
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtoscopeError {
+    /// Unexpected input at the given byte offset
+    Unexpected(usize),
+    /// The number, string or bytes literal at the given byte offset was invalid
+    InvalidLiteral(usize),
+    /// The brace at the given byte offset was not matched
+    UnbalancedBrace(usize),
+}
+
+impl fmt::Display for ProtoscopeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ProtoscopeError::*;
+        match self {
+            Unexpected(at) => write!(fmt, "unexpected input at {}", at),
+            InvalidLiteral(at) => write!(fmt, "invalid literal at {}", at),
+            UnbalancedBrace(at) => write!(fmt, "unbalanced brace at {}", at),
+        }
+    }
+}
+
+impl std::error::Error for ProtoscopeError {}
+
+/// Encodes the protoscope text into bytes.
+pub fn parse(text: &str) -> Result<Vec<u8>, ProtoscopeError> {
+    Parser {
+        text,
+        at: 0,
+        stack: vec![(0, 0, Vec::new())],
+        long_form: None,
+    }
+    .parse()
+}
+
+#[derive(Clone, Copy)]
+enum Token<'a> {
+    Open,
+    Close,
+    /// The contents of a string literal, escapes not yet processed
+    Str(&'a str),
+    /// The contents of a bytes literal
+    Bytes(&'a str),
+    Word(&'a str),
+}
+
+enum Number {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    at: usize,
+    /// Offset of the opening brace, the extra bytes for the length and the bytes of the open
+    /// length delimited fields, the outermost being the whole output
+    stack: Vec<(usize, usize, Vec<u8>)>,
+    /// Extra bytes and the offset of the `long-form:N` waiting for the varint it applies to
+    long_form: Option<(usize, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(mut self) -> Result<Vec<u8>, ProtoscopeError> {
+        while let Some((at, token)) = self.next_token()? {
+            match token {
+                Token::Open => {
+                    let extra = self.long_form.take().map(|(extra, _)| extra).unwrap_or(0);
+                    self.stack.push((at, extra, Vec::new()));
+                }
+                Token::Close => {
+                    if self.stack.len() == 1 {
+                        return Err(ProtoscopeError::UnbalancedBrace(at));
+                    }
+                    self.no_long_form()?;
+                    let (_, extra, body) = self.stack.pop().expect("checked above");
+                    self.varint(body.len() as u64, extra);
+                    self.output().extend_from_slice(&body);
+                }
+                Token::Str(s) => {
+                    self.no_long_form()?;
+                    let bytes = unescape(s).ok_or(ProtoscopeError::InvalidLiteral(at))?;
+                    self.output().extend_from_slice(&bytes);
+                }
+                Token::Bytes(hex) => {
+                    self.no_long_form()?;
+                    let bytes = unhex(hex).ok_or(ProtoscopeError::InvalidLiteral(at))?;
+                    self.output().extend_from_slice(&bytes);
+                }
+                Token::Word(word) => self.word(at, word)?,
+            }
+        }
+
+        self.no_long_form()?;
+
+        if self.stack.len() > 1 {
+            return Err(ProtoscopeError::UnbalancedBrace(self.stack[1].0));
+        }
+
+        Ok(self
+            .stack
+            .pop()
+            .expect("the output is always on the stack")
+            .2)
+    }
+
+    fn word(&mut self, at: usize, word: &'a str) -> Result<(), ProtoscopeError> {
+        if let Some(extra) = word.strip_prefix("long-form:") {
+            self.no_long_form()?;
+            let extra = extra
+                .parse()
+                .ok()
+                .filter(|&extra| extra < 10)
+                .ok_or(ProtoscopeError::InvalidLiteral(at))?;
+            self.long_form = Some((extra, at));
+            return Ok(());
+        }
+
+        if let Some((id, kind)) = word.split_once(':') {
+            let id = parse_int(id)
+                .filter(|&id| (0..1 << 29).contains(&id))
+                .ok_or(ProtoscopeError::InvalidLiteral(at))? as u64;
+
+            let kind = match kind {
+                "" => self.infer_kind(),
+                "VARINT" => 0,
+                "I64" => 1,
+                "LEN" => 2,
+                "SGROUP" => 3,
+                "EGROUP" => 4,
+                "I32" => 5,
+                other => other
+                    .parse()
+                    .ok()
+                    .filter(|&kind| kind < 8)
+                    .ok_or(ProtoscopeError::InvalidLiteral(at))?,
+            };
+
+            let extra = self.take_long_form();
+            self.varint((id << 3) | kind, extra);
+            return Ok(());
+        }
+
+        match parse_number(word).ok_or(ProtoscopeError::InvalidLiteral(at))? {
+            Number::Varint(x) => {
+                let extra = self.take_long_form();
+                self.varint(x, extra);
+            }
+            Number::Fixed64(x) => {
+                self.no_long_form()?;
+                self.output().extend_from_slice(&x.to_le_bytes());
+            }
+            Number::Fixed32(x) => {
+                self.no_long_form()?;
+                self.output().extend_from_slice(&x.to_le_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The wire type of an `N:` tag from the token following it.
+    fn infer_kind(&mut self) -> u64 {
+        let at = self.at;
+        let kind = match self.next_token() {
+            Ok(Some((_, Token::Open))) => 2,
+            Ok(Some((_, Token::Word(word)))) => match parse_number(word) {
+                Some(Number::Fixed64(_)) => 1,
+                Some(Number::Fixed32(_)) => 5,
+                _ => 0,
+            },
+            _ => 0,
+        };
+        self.at = at;
+        kind
+    }
+
+    fn take_long_form(&mut self) -> usize {
+        self.long_form.take().map(|(extra, _)| extra).unwrap_or(0)
+    }
+
+    fn no_long_form(&self) -> Result<(), ProtoscopeError> {
+        match self.long_form {
+            Some((_, at)) => Err(ProtoscopeError::Unexpected(at)),
+            None => Ok(()),
+        }
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        &mut self
+            .stack
+            .last_mut()
+            .expect("the output is always on the stack")
+            .2
+    }
+
+    fn varint(&mut self, value: u64, extra: usize) {
+        let mut tmp = [0u8; 10];
+        let len = encode_varint(value, &mut tmp);
+        let out = self.output();
+        if extra == 0 {
+            out.extend_from_slice(&tmp[..len]);
+        } else {
+            tmp[len - 1] |= 0x80;
+            out.extend_from_slice(&tmp[..len]);
+            out.extend(std::iter::repeat_n(0x80, extra - 1));
+            out.push(0x00);
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<(usize, Token<'a>)>, ProtoscopeError> {
+        let rest = &self.text[self.at..];
+        let trimmed = skip_whitespace(rest);
+        let at = self.at + (rest.len() - trimmed.len());
+
+        let mut chars = trimmed.chars();
+        let (token, len) = match chars.next() {
+            None => {
+                self.at = at;
+                return Ok(None);
+            }
+            Some('{') => (Token::Open, 1),
+            Some('}') => (Token::Close, 1),
+            Some('"') => {
+                let end = string_end(&trimmed[1..]).ok_or(ProtoscopeError::InvalidLiteral(at))?;
+                (Token::Str(&trimmed[1..1 + end]), end + 2)
+            }
+            Some('`') => {
+                let end = trimmed[1..]
+                    .find('`')
+                    .ok_or(ProtoscopeError::InvalidLiteral(at))?;
+                (Token::Bytes(&trimmed[1..1 + end]), end + 2)
+            }
+            Some(_) => {
+                let end = trimmed
+                    .find(|c: char| c.is_whitespace() || "{}\"`#".contains(c))
+                    .unwrap_or(trimmed.len());
+                if end == 0 {
+                    return Err(ProtoscopeError::Unexpected(at));
+                }
+                (Token::Word(&trimmed[..end]), end)
+            }
+        };
+
+        self.at = at + len;
+        Ok(Some((at, token)))
+    }
+}
+
+fn skip_whitespace(mut s: &str) -> &str {
+    loop {
+        s = s.trim_start();
+        match s.strip_prefix('#') {
+            Some(comment) => s = comment.find('\n').map(|end| &comment[end..]).unwrap_or(""),
+            None => return s,
+        }
+    }
+}
+
+/// Returns the offset of the closing quote.
+fn string_end(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut tmp = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut tmp).as_bytes());
+            continue;
+        }
+
+        out.push(match chars.next()? {
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            '0' => 0,
+            '\\' => b'\\',
+            '"' => b'"',
+            '\'' => b'\'',
+            'x' => {
+                let hex = [chars.next()?, chars.next()?].iter().collect::<String>();
+                u8::from_str_radix(&hex, 16).ok()?
+            }
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn parse_int(s: &str) -> Option<i128> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None if digits.bytes().all(|b| b.is_ascii_digit()) => digits.parse().ok()?,
+        None => return None,
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn parse_number(word: &str) -> Option<Number> {
+    let in_range = |x: i128, bits: u32| x >= -(1 << (bits - 1)) && x < 1 << bits;
+
+    if let Some(x) = word.strip_suffix("i64") {
+        return Some(Number::Fixed64(match parse_int(x) {
+            Some(x) if in_range(x, 64) => x as u64,
+            Some(_) => return None,
+            None => x.parse::<f64>().ok()?.to_bits(),
+        }));
+    }
+
+    if let Some(x) = word.strip_suffix("i32") {
+        return Some(Number::Fixed32(match parse_int(x) {
+            Some(x) if in_range(x, 32) => x as u32,
+            Some(_) => return None,
+            None => x.parse::<f32>().ok()?.to_bits(),
+        }));
+    }
+
+    if let Some(x) = word.strip_suffix('z') {
+        let x = i64::try_from(parse_int(x)?).ok()?;
+        return Some(Number::Varint(((x << 1) ^ (x >> 63)) as u64));
+    }
+
+    match word {
+        "true" => return Some(Number::Varint(1)),
+        "false" => return Some(Number::Varint(0)),
+        _ => {}
+    }
+
+    match parse_int(word) {
+        Some(x) if in_range(x, 64) => Some(Number::Varint(x as u64)),
+        Some(_) => None,
+        None => word
+            .parse::<f64>()
+            .ok()
+            .map(|x| Number::Fixed64(x.to_bits())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, write, ProtoscopeError};
+    use hex_literal::hex;
+
+    #[test]
+    fn roundtrips() {
+        // 1: 150 with an extra byte, 2: { 1: "a\n" }, 3: 0xff 0x00, 4: -1i32, trailing garbage
+        let input = hex!("08968100 1204 0a02610a 1a02ff00 25ffffffff 0a05");
+
+        let mut text = String::new();
+        write(&input, &mut text).unwrap();
+
+        assert_eq!(
+            text,
+            "1: long-form:1 150\n\
+             2: {\n  1: {\"a\\n\"}\n}\n\
+             3: {`ff00`}\n\
+             4: -1i32\n\
+             `0a05`\n"
+        );
+
+        assert_eq!(parse(&text).unwrap(), input);
+    }
+
+    #[test]
+    fn deep_nesting_is_written_as_bytes() {
+        // 1: { 1: { ... 1: 1 } } nested far deeper than the limit
+        let mut input = hex!("0801").to_vec();
+        for _ in 0..1000 {
+            let mut outer = vec![0x0a];
+            let mut tmp = [0u8; 10];
+            let len = crate::pb::encode_varint(input.len() as u64, &mut tmp);
+            outer.extend(&tmp[..len]);
+            outer.extend(&input);
+            input = outer;
+        }
+
+        let mut text = String::new();
+        write(&input, &mut text).unwrap();
+        assert_eq!(text.matches('{').count(), super::MAX_DEPTH + 1);
+        assert_eq!(parse(&text).unwrap(), input);
+    }
+
+    #[test]
+    fn parses_hand_written() {
+        let text = "
+            # a comment
+            1: -1z 2:I64 1.5 3: { \"\\x01\" 1:LEN {} } 4: 0x10i64 true
+            long-form:2 5:VARINT 1";
+
+        assert_eq!(
+            parse(text).unwrap(),
+            hex!("0801 11000000000000f83f 1a03 01 0a00 211000000000000000 01 a88000 01")
+        );
+
+        assert_eq!(parse("1: { 2: 3"), Err(ProtoscopeError::UnbalancedBrace(3)));
+        assert_eq!(parse("1: 1i33"), Err(ProtoscopeError::InvalidLiteral(3)));
+    }
+}
@@ -0,0 +1,657 @@
+//! A small jq-like query language over whole messages.
+//!
+//! Queries are pipelines of filters separated by `|`:
+//!
+//!  * `.` outputs the input as is
+//!  * `.2` outputs the last field 2 of the message, as with non-repeated fields
+//!  * `.2[]` outputs every field 2, as with repeated fields
+//!  * `.2[].1` navigates further into the nested messages
+//!  * `select(COND)` outputs the input only if `COND` holds
+//!
+//! A condition is a path, optionally compared to an integer or a string literal with one of `==`,
+//! `!=`, `<`, `<=`, `>` and `>=`, and combined with `and`, `or` and parentheses. A path alone
+//! tests for the presence of the field. If the path outputs multiple values the condition holds if
+//! any of them match.
+//!
+//! For example `.2[] | select(.3 > 100) | .1` outputs the field 1 of every field 2 message which
+//! has field 3 greater than 100.
+//!
+//! With a [`Schema`] the fields can also be named by their names or proto3 JSON names, as in
+//! `.lines[] | select(.count > 100) | .productId`, see [`Query::parse_with_schema`]. The names are
+//! resolved when parsing, so running the query works the same as with numbers.
+//!
+//! The fields are typeless on the wire, so comparisons are made on the wire values: varints and
+//! fixed values compare as unsigned integers against non-negative literals and as two's complement
+//! signed integers against negative literals, length delimited fields compare as bytes against
+//! string literals. Other combinations never match.
+
+use crate::field_reader::FieldReader;
+use crate::schema::{MessageDescriptor, Schema};
+use crate::{DecodingError, FieldId, FieldValue};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A parsed query, see the module documentation for the syntax.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Path(Vec<Step>),
+    Select(Condition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Step {
+    id: FieldId,
+    /// `true` for `.N[]`, `false` for `.N`
+    all: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Present(Vec<Step>),
+    Compare(Vec<Step>, Op, Literal),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Unsigned(u64),
+    Signed(i64),
+    Str(String),
+}
+
+/// A value output by the query, borrowing from the queried message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    /// Length delimited field, or the whole input message
+    Bytes(&'a [u8]),
+}
+
+impl QueryValue<'_> {
+    fn compare(&self, literal: &Literal) -> Option<Ordering> {
+        use QueryValue::*;
+        match (self, literal) {
+            (Varint(x), Literal::Unsigned(y)) | (Fixed64(x), Literal::Unsigned(y)) => {
+                Some(x.cmp(y))
+            }
+            (Fixed32(x), Literal::Unsigned(y)) => Some((*x as u64).cmp(y)),
+            (Varint(x), Literal::Signed(y)) | (Fixed64(x), Literal::Signed(y)) => {
+                Some((*x as i64).cmp(y))
+            }
+            (Fixed32(x), Literal::Signed(y)) => Some((*x as i32 as i64).cmp(y)),
+            (Bytes(x), Literal::Str(y)) => Some((*x).cmp(y.as_bytes())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QueryParseError<'a> {
+    /// The query ended while more was expected
+    UnexpectedEnd,
+    /// Unexpected input at the given byte offset
+    Unexpected(usize, &'a str),
+    InvalidField(&'a str),
+    /// The field name was not found in the schema
+    UnknownField(&'a str),
+    InvalidLiteral(&'a str),
+}
+
+impl fmt::Display for QueryParseError<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use QueryParseError::*;
+        match self {
+            UnexpectedEnd => write!(fmt, "unexpected end of query"),
+            Unexpected(at, rest) => write!(fmt, "unexpected {:?} at {}", rest, at),
+            InvalidField(field) => write!(fmt, "invalid field: {:?}", field),
+            UnknownField(field) => write!(fmt, "unknown field: {:?}", field),
+            InvalidLiteral(literal) => write!(fmt, "invalid literal: {:?}", literal),
+        }
+    }
+}
+
+impl std::error::Error for QueryParseError<'_> {}
+
+#[derive(Debug)]
+pub enum QueryError {
+    Decoding(DecodingError),
+    /// A navigated message ended in the middle of a field
+    Truncated,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Decoding(e) => write!(fmt, "{}", e),
+            QueryError::Truncated => write!(fmt, "message was truncated"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl From<DecodingError> for QueryError {
+    fn from(e: DecodingError) -> Self {
+        QueryError::Decoding(e)
+    }
+}
+
+impl Query {
+    pub fn parse(s: &str) -> Result<Query, QueryParseError<'_>> {
+        Self::parse_inner(s, None)
+    }
+
+    /// Parses a query over messages of the type `message`, allowing the fields to be referred to
+    /// by their names and proto3 JSON names as well as numbers.
+    pub fn parse_with_schema<'a>(
+        s: &'a str,
+        schema: &Schema,
+        message: &MessageDescriptor,
+    ) -> Result<Query, QueryParseError<'a>> {
+        Self::parse_inner(s, Some((schema, message)))
+    }
+
+    fn parse_inner<'a>(
+        s: &'a str,
+        schema: Option<(&Schema, &MessageDescriptor)>,
+    ) -> Result<Query, QueryParseError<'a>> {
+        let mut parser = Parser {
+            s,
+            at: 0,
+            schema: schema.map(|(schema, _)| schema),
+            current: schema.map(|(_, message)| message),
+        };
+        let query = parser.pipeline()?;
+        parser.skip_whitespace();
+        if parser.at < s.len() {
+            return Err(parser.unexpected());
+        }
+        Ok(query)
+    }
+
+    /// Runs the query over a single complete message, calling `output` for every result.
+    pub fn run<'a, F>(&self, message: &'a [u8], mut output: F) -> Result<(), QueryError>
+    where
+        F: FnMut(QueryValue<'a>),
+    {
+        run_filters(&self.filters, QueryValue::Bytes(message), &mut output)
+    }
+
+    /// Runs the query over a single complete message, collecting the results.
+    pub fn evaluate<'a>(&self, message: &'a [u8]) -> Result<Vec<QueryValue<'a>>, QueryError> {
+        let mut ret = Vec::new();
+        self.run(message, |v| ret.push(v))?;
+        Ok(ret)
+    }
+}
+
+fn run_filters<'a>(
+    filters: &[Filter],
+    input: QueryValue<'a>,
+    output: &mut dyn FnMut(QueryValue<'a>),
+) -> Result<(), QueryError> {
+    let (first, rest) = match filters.split_first() {
+        Some(x) => x,
+        None => {
+            output(input);
+            return Ok(());
+        }
+    };
+
+    match first {
+        Filter::Path(steps) => {
+            let mut ret = Ok(());
+            run_path(steps, input, &mut |v| {
+                if ret.is_ok() {
+                    ret = run_filters(rest, v, output);
+                }
+            })?;
+            ret
+        }
+        Filter::Select(cond) => {
+            if cond.holds(input)? {
+                run_filters(rest, input, output)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+fn run_path<'a>(
+    steps: &[Step],
+    input: QueryValue<'a>,
+    output: &mut dyn FnMut(QueryValue<'a>),
+) -> Result<(), QueryError> {
+    let (step, rest) = match steps.split_first() {
+        Some(x) => x,
+        None => {
+            output(input);
+            return Ok(());
+        }
+    };
+
+    let message = match input {
+        QueryValue::Bytes(message) => message,
+        // only messages can be navigated, other values have no fields
+        _ => return Ok(()),
+    };
+
+    let mut reader = FieldReader::default();
+    let mut at = 0;
+    let mut last = None;
+
+    while at < message.len() {
+        let read = match reader.next(&message[at..])? {
+            Ok(read) => read,
+            Err(_) => return Err(QueryError::Truncated),
+        };
+
+        let start = at + read.consumed();
+        let end = match start.checked_add(read.field_len()) {
+            Some(end) if end <= message.len() => end,
+            _ => return Err(QueryError::Truncated),
+        };
+
+        if read.field_id() == step.id {
+            let value = match read.value() {
+                FieldValue::Varint(x) => QueryValue::Varint(*x),
+                FieldValue::Fixed64(x) => QueryValue::Fixed64(*x),
+                FieldValue::Fixed32(x) => QueryValue::Fixed32(*x),
+                FieldValue::DataLength(_) => QueryValue::Bytes(&message[start..end]),
+                #[cfg(feature = "groups")]
+                FieldValue::StartGroup | FieldValue::EndGroup => {
+                    return Err(read.unsupported_group().into())
+                }
+            };
+
+            if step.all {
+                run_path(rest, value, output)?;
+            } else {
+                last = Some(value);
+            }
+        }
+
+        at = end;
+    }
+
+    match last {
+        Some(value) => run_path(rest, value, output),
+        None => Ok(()),
+    }
+}
+
+impl Condition {
+    fn holds(&self, input: QueryValue<'_>) -> Result<bool, QueryError> {
+        Ok(match self {
+            Condition::Present(steps) => {
+                let mut found = false;
+                run_path(steps, input, &mut |_| found = true)?;
+                found
+            }
+            Condition::Compare(steps, op, literal) => {
+                let mut found = false;
+                run_path(steps, input, &mut |v| {
+                    found |= v.compare(literal).map(|o| op.holds(o)).unwrap_or(false);
+                })?;
+                found
+            }
+            Condition::And(a, b) => a.holds(input)? && b.holds(input)?,
+            Condition::Or(a, b) => a.holds(input)? || b.holds(input)?,
+        })
+    }
+}
+
+struct Parser<'a, 's> {
+    s: &'a str,
+    at: usize,
+    schema: Option<&'s Schema>,
+    /// The type of the input of the next filter, if known
+    current: Option<&'s MessageDescriptor>,
+}
+
+impl<'a, 's> Parser<'a, 's> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.at..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.at += rest.len() - rest.trim_start().len();
+    }
+
+    fn unexpected(&self) -> QueryParseError<'a> {
+        if self.at == self.s.len() {
+            QueryParseError::UnexpectedEnd
+        } else {
+            QueryParseError::Unexpected(self.at, self.rest())
+        }
+    }
+
+    /// Consumes `token` after any whitespace if it is next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.at += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Like `eat` but requires that a keyword is not followed by more of an identifier.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let matches = rest.starts_with(keyword)
+            && !rest[keyword.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        if matches {
+            self.at += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), QueryParseError<'a>> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// Takes the longest prefix matching `pred`.
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !pred(c)).unwrap_or(rest.len());
+        self.at += len;
+        &rest[..len]
+    }
+
+    fn pipeline(&mut self) -> Result<Query, QueryParseError<'a>> {
+        let mut filters = vec![self.filter()?];
+        while self.eat("|") {
+            filters.push(self.filter()?);
+        }
+        // identity filters do nothing
+        filters.retain(|f| !matches!(f, Filter::Path(steps) if steps.is_empty()));
+        Ok(Query { filters })
+    }
+
+    fn filter(&mut self) -> Result<Filter, QueryParseError<'a>> {
+        if self.eat_keyword("select") {
+            self.expect("(")?;
+            let cond = self.or()?;
+            self.expect(")")?;
+            Ok(Filter::Select(cond))
+        } else {
+            let (steps, output) = self.path()?;
+            self.current = output;
+            Ok(Filter::Path(steps))
+        }
+    }
+
+    /// Parses `.`, `.N`, `.N[]` and any chain of the latter two, returning the steps and the
+    /// message type of the output if it is known.
+    fn path(&mut self) -> Result<(Vec<Step>, Option<&'s MessageDescriptor>), QueryParseError<'a>> {
+        self.skip_whitespace();
+        if !self.rest().starts_with('.') {
+            return Err(self.unexpected());
+        }
+
+        let mut steps = Vec::new();
+        let mut message = self.current;
+
+        while self.rest().starts_with('.') {
+            self.at += 1;
+            let digits = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            if digits.is_empty() {
+                if steps.is_empty() {
+                    // the identity
+                    break;
+                }
+                return Err(self.unexpected());
+            }
+
+            let field = match digits.parse::<FieldId>() {
+                Ok(id) => message.and_then(|m| m.field(id)),
+                Err(_) if self.schema.is_none() => {
+                    return Err(QueryParseError::InvalidField(digits))
+                }
+                Err(_) => Some(
+                    message
+                        .and_then(|m| m.field_by_name(digits))
+                        .ok_or(QueryParseError::UnknownField(digits))?,
+                ),
+            };
+
+            let id = match field {
+                Some(field) => field.number,
+                None => digits
+                    .parse::<FieldId>()
+                    .map_err(|_| QueryParseError::InvalidField(digits))?,
+            };
+
+            message = match (self.schema, field) {
+                (Some(schema), Some(field)) => schema.message_of(field),
+                _ => None,
+            };
+
+            let all = if self.rest().starts_with("[]") {
+                self.at += 2;
+                true
+            } else {
+                false
+            };
+
+            steps.push(Step { id, all });
+        }
+
+        Ok((steps, message))
+    }
+
+    fn or(&mut self) -> Result<Condition, QueryParseError<'a>> {
+        let mut cond = self.and()?;
+        while self.eat_keyword("or") {
+            cond = Condition::Or(Box::new(cond), Box::new(self.and()?));
+        }
+        Ok(cond)
+    }
+
+    fn and(&mut self) -> Result<Condition, QueryParseError<'a>> {
+        let mut cond = self.comparison()?;
+        while self.eat_keyword("and") {
+            cond = Condition::And(Box::new(cond), Box::new(self.comparison()?));
+        }
+        Ok(cond)
+    }
+
+    fn comparison(&mut self) -> Result<Condition, QueryParseError<'a>> {
+        if self.eat("(") {
+            let cond = self.or()?;
+            self.expect(")")?;
+            return Ok(cond);
+        }
+
+        let (steps, _) = self.path()?;
+
+        // the two character operators need to be tested first
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+
+        for (token, op) in ops.iter() {
+            if self.eat(token) {
+                let literal = self.literal()?;
+                return Ok(Condition::Compare(steps, *op, literal));
+            }
+        }
+
+        Ok(Condition::Present(steps))
+    }
+
+    fn literal(&mut self) -> Result<Literal, QueryParseError<'a>> {
+        self.skip_whitespace();
+        let start = self.at;
+
+        if self.eat("\"") {
+            let mut s = String::new();
+            let mut chars = self.rest().char_indices();
+            loop {
+                match chars.next() {
+                    Some((i, '"')) => {
+                        self.at += i + 1;
+                        return Ok(Literal::Str(s));
+                    }
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c @ '"')) | Some((_, c @ '\\')) => s.push(c),
+                        Some((_, 'n')) => s.push('\n'),
+                        Some((_, 't')) => s.push('\t'),
+                        Some(_) => return Err(QueryParseError::InvalidLiteral(&self.s[start..])),
+                        None => return Err(QueryParseError::UnexpectedEnd),
+                    },
+                    Some((_, c)) => s.push(c),
+                    None => return Err(QueryParseError::UnexpectedEnd),
+                }
+            }
+        }
+
+        let negative = self.eat("-");
+        let digits = self.take_while(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_');
+        let literal = &self.s[start..self.at];
+
+        if digits.is_empty() {
+            return Err(self.unexpected());
+        }
+
+        let invalid = |_| QueryParseError::InvalidLiteral(literal);
+
+        Ok(if negative {
+            Literal::Signed(literal.parse::<i64>().map_err(invalid)?)
+        } else {
+            Literal::Unsigned(digits.parse::<u64>().map_err(invalid)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Query, QueryParseError, QueryValue};
+    use crate::schema::{tests::order_schema, Schema};
+    use hex_literal::hex;
+
+    #[test]
+    fn select_from_repeated() {
+        // 2: { 1: "a", 3: 50 }, 2: { 1: "b", 3: 150 }, 2: { 1: "c", 3: 200 }
+        let input = hex!("1205 0a0161 1832 1206 0a0162 189601 1206 0a0163 18c801");
+
+        let query = Query::parse(".2[] | select(.3 > 100) | .1").unwrap();
+        let results = query.evaluate(&input).unwrap();
+        assert_eq!(
+            results,
+            vec![QueryValue::Bytes(b"b"), QueryValue::Bytes(b"c")]
+        );
+
+        let query = Query::parse(".2[] | select(.1 == \"a\" or .3 >= 200) | .3").unwrap();
+        let results = query.evaluate(&input).unwrap();
+        assert_eq!(
+            results,
+            vec![QueryValue::Varint(50), QueryValue::Varint(200)]
+        );
+
+        // non-repeated access takes the last one
+        let query = Query::parse(".2.1").unwrap();
+        assert_eq!(
+            query.evaluate(&input).unwrap(),
+            vec![QueryValue::Bytes(b"c")]
+        );
+
+        let query = Query::parse(". | select(.3)").unwrap();
+        assert!(query.evaluate(&input).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Query::parse(".2 |"), Err(QueryParseError::UnexpectedEnd));
+        assert_eq!(Query::parse(".a"), Err(QueryParseError::InvalidField("a")));
+        assert!(matches!(
+            Query::parse("select(.1 > x)"),
+            Err(QueryParseError::InvalidLiteral("x"))
+        ));
+        assert!(matches!(
+            Query::parse(".1 .2"),
+            Err(QueryParseError::Unexpected(3, ".2"))
+        ));
+    }
+
+    #[test]
+    fn field_names() {
+        let schema = Schema::decode(&order_schema()).unwrap();
+        let order = schema.message("test.Order").unwrap();
+
+        // lines: { product_id: "a", count: 50 }, lines: { product_id: "b", count: 150 }
+        let input = hex!("1205 0a0161 1832 1206 0a0162 189601");
+
+        let query = Query::parse_with_schema(
+            ".lines[] | select(.count > 100) | .productId",
+            &schema,
+            order,
+        )
+        .unwrap();
+        assert_eq!(query, Query::parse(".2[] | select(.3 > 100) | .1").unwrap());
+        assert_eq!(
+            query.evaluate(&input).unwrap(),
+            vec![QueryValue::Bytes(b"b")]
+        );
+
+        // numbers and names mix
+        assert!(Query::parse_with_schema(".2[].product_id", &schema, order).is_ok());
+        assert_eq!(
+            Query::parse_with_schema(".lines[].price", &schema, order),
+            Err(QueryParseError::UnknownField("price"))
+        );
+        // not a message
+        assert_eq!(
+            Query::parse_with_schema(".order_id.x", &schema, order),
+            Err(QueryParseError::UnknownField("x"))
+        );
+    }
+}
@@ -0,0 +1,492 @@
+//! Rewriting complete messages into a [`Sink`].
+//!
+//! The fields which are not affected by the rewrite are copied byte for byte.
+
+use crate::message::{Field, FieldData, Fields, MessageError};
+use crate::pb::{encode_varint, read_varint64, varint_len};
+use crate::schema::{FieldDescriptor, MessageDescriptor, Schema};
+use crate::sink::{Scalar, Sink};
+use crate::{FieldId, WireType};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// How deep the nested messages of a recursive message type are followed, like the default
+/// recursion limit of the protobuf implementations.
+pub const MAX_DEPTH: usize = 100;
+
+#[derive(Debug)]
+pub enum RewriteError<E> {
+    /// Reading the input message failed
+    Message(MessageError),
+    /// Writing to the sink failed
+    Sink(E),
+    /// The messages were nested deeper than [`MAX_DEPTH`], at the offset of the innermost nested
+    /// message field relative to its enclosing message
+    TooDeep { offset: usize },
+}
+
+impl<E: fmt::Display> fmt::Display for RewriteError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewriteError::Message(e) => write!(fmt, "{}", e),
+            RewriteError::Sink(e) => write!(fmt, "writing failed: {}", e),
+            RewriteError::TooDeep { offset } => write!(
+                fmt,
+                "messages nested deeper than {} at offset {}",
+                MAX_DEPTH, offset
+            ),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for RewriteError<E> {}
+
+impl<E> From<MessageError> for RewriteError<E> {
+    fn from(e: MessageError) -> Self {
+        RewriteError::Message(e)
+    }
+}
+
+/// Sorts the occurrences of a repeated submessage field by the value of the `key` field within
+/// them, writing the rewritten message into `sink`.
+///
+/// `path` navigates the nested messages with every element but the last, and the last element is
+/// the repeated field to sort. The sorted occurrences are written where the first one was, and
+/// the sort is stable. Integer keys compare by their value and length delimited keys as bytes; if
+/// the key field occurs multiple times the last one is used, and occurrences without the key sort
+/// first.
+pub fn sort_repeated<S: Sink>(
+    buf: &[u8],
+    path: &[FieldId],
+    key: FieldId,
+    sink: &mut S,
+) -> Result<(), RewriteError<S::Error>> {
+    assert!(
+        !path.is_empty(),
+        "path must contain at least the field to sort"
+    );
+
+    let (&id, rest) = path.split_first().unwrap();
+
+    if !rest.is_empty() {
+        for field in Fields::new(buf) {
+            let field = field?;
+            match field.value {
+                FieldData::Bytes(payload) if field.id == id => {
+                    // the length does not change, so the header can be copied as is
+                    let header = &field.raw[..field.raw.len() - payload.len()];
+                    sink.write_raw(header).map_err(RewriteError::Sink)?;
+                    sort_repeated(payload, rest, key, sink)?;
+                }
+                _ => sink.write_raw(field.raw).map_err(RewriteError::Sink)?,
+            }
+        }
+        return Ok(());
+    }
+
+    let mut sorted = Vec::new();
+    for field in Fields::new(buf) {
+        let field = field?;
+        if let FieldData::Bytes(payload) = field.value {
+            if field.id == id {
+                sorted.push((key_of(payload, key)?, field));
+            }
+        }
+    }
+
+    sorted.sort_by(|a, b| compare_keys(&a.0, &b.0));
+
+    let mut sorted = Some(sorted);
+
+    for field in Fields::new(buf) {
+        let field = field?;
+        let is_sorted = field.id == id && matches!(field.value, FieldData::Bytes(_));
+
+        if !is_sorted {
+            sink.write_raw(field.raw).map_err(RewriteError::Sink)?;
+        } else if let Some(sorted) = sorted.take() {
+            for (_, field) in sorted {
+                sink.write_raw(field.raw).map_err(RewriteError::Sink)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the fields not declared in `message`, recursively for the nested messages, writing the
+/// rest into `sink`.
+///
+/// The declared fields are copied verbatim, except for the nested messages which are rewritten. A
+/// declared field with a different wire type than in the schema is removed as well, unless it is
+/// a packed repeated scalar. Messages nested deeper than [`MAX_DEPTH`] are an error.
+pub fn strip_unknown<S: Sink>(
+    buf: &[u8],
+    schema: &Schema,
+    message: &MessageDescriptor,
+    sink: &mut S,
+) -> Result<(), RewriteError<S::Error>> {
+    strip_message(buf, schema, message, sink, 0)
+}
+
+fn strip_message<S: Sink>(
+    buf: &[u8],
+    schema: &Schema,
+    message: &MessageDescriptor,
+    sink: &mut S,
+    depth: usize,
+) -> Result<(), RewriteError<S::Error>> {
+    for field in Fields::new(buf) {
+        let field = field?;
+
+        let descriptor = match message.field(field.id) {
+            Some(descriptor) => descriptor,
+            None => continue,
+        };
+
+        let wire_type = field.value.wire_type();
+        let packed = descriptor.is_repeated()
+            && descriptor.kind.is_packable()
+            && matches!(field.value, FieldData::Bytes(_));
+
+        if !packed && descriptor.kind.wire_type() != Some(wire_type) {
+            continue;
+        }
+
+        match (schema.message_of(descriptor), field.value) {
+            (Some(_), FieldData::Bytes(_)) if depth == MAX_DEPTH => {
+                return Err(RewriteError::TooDeep {
+                    offset: field.offset,
+                })
+            }
+            (Some(nested), FieldData::Bytes(payload)) => {
+                sink.begin_message(field.id).map_err(RewriteError::Sink)?;
+                strip_message(payload, schema, nested, sink, depth + 1)?;
+                sink.end_message().map_err(RewriteError::Sink)?;
+            }
+            _ => sink.write_raw(field.raw).map_err(RewriteError::Sink)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// The sizes before and after [`compact`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compaction {
+    pub before: u64,
+    pub after: u64,
+}
+
+impl Compaction {
+    pub fn saved(&self) -> u64 {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+/// Re-encodes the message of type `message` into `sink` as compactly as the schema allows:
+///
+///  * the occurrences of a repeated scalar field are packed into a single field where the first
+///    occurrence was, unless the field has `packed = false`
+///  * the non-repeated proto3 scalars with default values are removed, when the field does not
+///    track presence
+///  * all varints, including the tags and the lengths, are written with the minimal encoding
+///
+/// Nested messages are compacted as well, up to [`MAX_DEPTH`] after which they are an error.
+/// Length delimited fields not in the schema are copied as is, as they could be messages, strings
+/// or bytes.
+pub fn compact<S: Sink>(
+    buf: &[u8],
+    schema: &Schema,
+    message: &MessageDescriptor,
+    sink: &mut S,
+) -> Result<Compaction, RewriteError<S::Error>> {
+    let after = compact_message(buf, schema, message, sink, 0)?;
+    Ok(Compaction {
+        before: buf.len() as u64,
+        after,
+    })
+}
+
+/// Returns the number of bytes written.
+fn compact_message<S: Sink>(
+    buf: &[u8],
+    schema: &Schema,
+    message: &MessageDescriptor,
+    sink: &mut S,
+    depth: usize,
+) -> Result<u64, RewriteError<S::Error>> {
+    let fields = Fields::new(buf).collect::<Result<Vec<_>, _>>()?;
+    let mut packed_already = Vec::new();
+    let mut written = 0;
+
+    for field in &fields {
+        let descriptor = match message.field(field.id) {
+            Some(descriptor) => descriptor,
+            None => {
+                written += write_minimal(field, sink)?;
+                continue;
+            }
+        };
+
+        if packable(descriptor, &fields) {
+            if !packed_already.contains(&field.id) {
+                packed_already.push(field.id);
+                written += write_packed(descriptor, &fields, sink)?;
+            }
+            continue;
+        }
+
+        match (schema.message_of(descriptor), field.value) {
+            (Some(_), FieldData::Bytes(_)) if depth == MAX_DEPTH => {
+                return Err(RewriteError::TooDeep {
+                    offset: field.offset,
+                })
+            }
+            (Some(nested), FieldData::Bytes(payload)) => {
+                sink.begin_message(field.id).map_err(RewriteError::Sink)?;
+                let len = compact_message(payload, schema, nested, sink, depth + 1)?;
+                sink.end_message().map_err(RewriteError::Sink)?;
+                written += header_len(field.id) + varint_len(len) as u64 + len;
+            }
+            (_, value) if !message.has_presence(descriptor) && is_default(&value) => {}
+            _ => written += write_minimal(field, sink)?,
+        }
+    }
+
+    Ok(written)
+}
+
+/// Returns true if the field is a repeated scalar which can be packed and all of its occurrences
+/// are either packed or have the wire type of the scalar.
+fn packable(descriptor: &FieldDescriptor, fields: &[Field<'_>]) -> bool {
+    if !descriptor.is_repeated()
+        || !descriptor.kind.is_packable()
+        || descriptor.packed == Some(false)
+    {
+        return false;
+    }
+
+    let wire_type = descriptor.kind.wire_type();
+    fields
+        .iter()
+        .filter(|f| f.id == descriptor.number)
+        .all(|f| {
+            let actual = f.value.wire_type();
+            actual == WireType::LengthDelimited || Some(actual) == wire_type
+        })
+}
+
+fn write_packed<S: Sink>(
+    descriptor: &FieldDescriptor,
+    fields: &[Field<'_>],
+    sink: &mut S,
+) -> Result<u64, RewriteError<S::Error>> {
+    let mut payload = Vec::new();
+    let mut tmp = [0u8; 10];
+
+    for field in fields.iter().filter(|f| f.id == descriptor.number) {
+        let truncated = || MessageError::Truncated {
+            offset: field.offset,
+        };
+
+        match (field.value, descriptor.kind.wire_type()) {
+            (FieldData::Varint(x), _) => {
+                let len = encode_varint(x, &mut tmp);
+                payload.extend_from_slice(&tmp[..len]);
+            }
+            (FieldData::Fixed64(x), _) => payload.extend_from_slice(&x.to_le_bytes()),
+            (FieldData::Fixed32(x), _) => payload.extend_from_slice(&x.to_le_bytes()),
+            (FieldData::Bytes(mut packed), Some(WireType::Varint)) => {
+                while !packed.is_empty() {
+                    let (consumed, x) = read_varint64(packed)
+                        .map_err(MessageError::from)?
+                        .map_err(|_| truncated())?;
+                    let len = encode_varint(x, &mut tmp);
+                    payload.extend_from_slice(&tmp[..len]);
+                    packed = &packed[consumed..];
+                }
+            }
+            (FieldData::Bytes(packed), Some(WireType::Fixed64)) if packed.len() % 8 == 0 => {
+                payload.extend_from_slice(packed)
+            }
+            (FieldData::Bytes(packed), Some(WireType::Fixed32)) if packed.len() % 4 == 0 => {
+                payload.extend_from_slice(packed)
+            }
+            (FieldData::Bytes(_), _) => return Err(truncated().into()),
+        }
+    }
+
+    if payload.is_empty() {
+        return Ok(0);
+    }
+
+    sink.write_slice(descriptor.number, &payload)
+        .map_err(RewriteError::Sink)?;
+    let len = payload.len() as u64;
+    Ok(header_len(descriptor.number) + varint_len(len) as u64 + len)
+}
+
+fn write_minimal<S: Sink>(field: &Field<'_>, sink: &mut S) -> Result<u64, RewriteError<S::Error>> {
+    let (scalar, len) = match field.value {
+        FieldData::Varint(x) => (Scalar::Varint(x), varint_len(x) as u64),
+        FieldData::Fixed64(x) => (Scalar::Fixed64(x), 8),
+        FieldData::Fixed32(x) => (Scalar::Fixed32(x), 4),
+        FieldData::Bytes(bytes) => {
+            sink.write_slice(field.id, bytes)
+                .map_err(RewriteError::Sink)?;
+            let len = bytes.len() as u64;
+            return Ok(header_len(field.id) + varint_len(len) as u64 + len);
+        }
+    };
+
+    sink.write_scalar(field.id, scalar)
+        .map_err(RewriteError::Sink)?;
+    Ok(header_len(field.id) + len)
+}
+
+/// The length of the tag does not depend on the wire type.
+fn header_len(id: FieldId) -> u64 {
+    varint_len((id as u64) << 3) as u64
+}
+
+/// Default values are the zero bits, so a negative zero float is kept.
+fn is_default(value: &FieldData<'_>) -> bool {
+    match value {
+        FieldData::Varint(x) | FieldData::Fixed64(x) => *x == 0,
+        FieldData::Fixed32(x) => *x == 0,
+        FieldData::Bytes(bytes) => bytes.is_empty(),
+    }
+}
+
+fn key_of(message: &[u8], key: FieldId) -> Result<Option<FieldData<'_>>, MessageError> {
+    let mut ret = None;
+    for field in Fields::new(message) {
+        let field = field?;
+        if field.id == key {
+            ret = Some(field.value);
+        }
+    }
+    Ok(ret)
+}
+
+fn compare_keys(a: &Option<FieldData<'_>>, b: &Option<FieldData<'_>>) -> Ordering {
+    use FieldData::*;
+
+    fn rank(x: &Option<FieldData<'_>>) -> u8 {
+        match x {
+            None => 0,
+            Some(Varint(_)) => 1,
+            Some(Fixed32(_)) => 2,
+            Some(Fixed64(_)) => 3,
+            Some(Bytes(_)) => 4,
+        }
+    }
+
+    match (a, b) {
+        (Some(Varint(a)), Some(Varint(b))) | (Some(Fixed64(a)), Some(Fixed64(b))) => a.cmp(b),
+        (Some(Fixed32(a)), Some(Fixed32(b))) => a.cmp(b),
+        (Some(Bytes(a)), Some(Bytes(b))) => a.cmp(b),
+        // different wire types for the same key are unlikely, but the order must be total
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compact, sort_repeated, strip_unknown, RewriteError, MAX_DEPTH};
+    use crate::schema::{
+        tests::{node_schema, order_schema},
+        Schema,
+    };
+    use crate::sink::{Sink, VecSink};
+    use hex_literal::hex;
+
+    /// `Node`s nested `depth` times with a value in the innermost one.
+    fn nested_nodes(depth: usize) -> Vec<u8> {
+        let mut sink = VecSink::default();
+        for _ in 0..depth {
+            sink.begin_message(1).unwrap();
+        }
+        sink.write_field_header(2, crate::WireType::Varint).unwrap();
+        sink.write_varint(1).unwrap();
+        for _ in 0..depth {
+            sink.end_message().unwrap();
+        }
+        sink.into_inner()
+    }
+
+    #[test]
+    fn sorts_nested_repeated() {
+        // 1: { 2: { 1: 3 }, 5: 0, 2: {}, 2: { 1: 1, 9: "x" } }, 2: { 1: 0 }
+        let input = hex!("0a0f 12020803 2800 1200 12050801 4a0178 12020800");
+        let mut sink = VecSink::default();
+        sort_repeated(&input, &[1, 2], 1, &mut sink).unwrap();
+
+        // the keyless occurrence sorts first; top level field 2 is not touched
+        let expected = hex!("0a0f 1200 12050801 4a0178 12020803 2800 12020800");
+        assert_eq!(sink.into_inner(), expected);
+    }
+
+    #[test]
+    fn strips_undeclared_fields() {
+        let schema = Schema::decode(&order_schema()).unwrap();
+        let order = schema.message("test.Order").unwrap();
+
+        // order_id: "x", 9: 1, lines: { product_id: "a", 2: "secret", count: 2 }, total_cents as a
+        // string
+        let input = hex!("0a0178 4801 120d 0a0161 1206736563726574 1802 1a0161");
+        let mut sink = VecSink::default();
+        strip_unknown(&input, &schema, order, &mut sink).unwrap();
+
+        assert_eq!(sink.into_inner(), hex!("0a0178 1205 0a0161 1802"));
+    }
+
+    #[test]
+    fn strip_unknown_is_limited_in_depth() {
+        let schema = Schema::decode(&node_schema()).unwrap();
+        let node = schema.message("test.Node").unwrap();
+
+        let input = nested_nodes(MAX_DEPTH);
+        let mut sink = VecSink::default();
+        strip_unknown(&input, &schema, node, &mut sink).unwrap();
+        assert_eq!(sink.into_inner(), input);
+
+        let input = nested_nodes(MAX_DEPTH + 1);
+        let e = strip_unknown(&input, &schema, node, &mut VecSink::default()).unwrap_err();
+        assert!(matches!(e, RewriteError::TooDeep { offset: 0 }), "{:?}", e);
+    }
+
+    #[test]
+    fn compacts() {
+        let schema = Schema::decode(&order_schema()).unwrap();
+        let order = schema.message("test.Order").unwrap();
+
+        // order_id: "", tags: 1, total_cents: -1 as an overlong varint, tags: [2, 3],
+        // lines: { count: 0, product_id: "a" }, 9: 0 as an overlong varint
+        let input = hex!("0a00 2001 188100 2202 0203 1205 1800 0a0161 488000");
+        let mut sink = VecSink::default();
+        let compaction = compact(&input, &schema, order, &mut sink).unwrap();
+
+        let expected = hex!("2203 010203 1801 1203 0a0161 4800");
+        assert_eq!(sink.into_inner(), expected);
+        assert_eq!(compaction.after, expected.len() as u64);
+        assert_eq!(compaction.saved(), (input.len() - expected.len()) as u64);
+    }
+
+    #[test]
+    fn compact_is_limited_in_depth() {
+        let schema = Schema::decode(&node_schema()).unwrap();
+        let node = schema.message("test.Node").unwrap();
+
+        let input = nested_nodes(MAX_DEPTH);
+        let mut sink = VecSink::default();
+        let compaction = compact(&input, &schema, node, &mut sink).unwrap();
+        assert_eq!(compaction.saved(), 0);
+        assert_eq!(sink.into_inner(), input);
+
+        let input = nested_nodes(MAX_DEPTH + 1);
+        let e = compact(&input, &schema, node, &mut VecSink::default()).unwrap_err();
+        assert!(matches!(e, RewriteError::TooDeep { offset: 0 }), "{:?}", e);
+    }
+}
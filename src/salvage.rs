@@ -0,0 +1,111 @@
+//! Finding how much of a truncated or corrupted input can be kept, for example of a file left
+//! behind by a crashed producer.
+//!
+//! The input is kept up to the end of the last complete top level field, or of the last complete
+//! frame of a length prefixed stream, before the first error. Writing out the kept prefix gives a
+//! cleanly truncated copy.
+
+use crate::framing::Framing;
+use crate::message::Fields;
+use std::convert::TryFrom;
+
+/// How much of the input was found to be intact.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Salvage {
+    /// Length of the intact prefix of the input
+    pub kept: usize,
+    /// Amount of the trailing bytes which need to be dropped
+    pub dropped: usize,
+    /// Fields or frames in the intact prefix
+    pub items: u64,
+}
+
+impl Salvage {
+    fn new(buf: &[u8], kept: usize, items: u64) -> Self {
+        Salvage {
+            kept,
+            dropped: buf.len() - kept,
+            items,
+        }
+    }
+
+    /// Returns true if nothing needs to be dropped.
+    pub fn is_intact(&self) -> bool {
+        self.dropped == 0
+    }
+}
+
+/// Finds the end of the last complete top level field of a message.
+pub fn salvage_message(buf: &[u8]) -> Salvage {
+    let mut fields = Fields::new(buf);
+    let mut items = 0;
+    let mut kept = 0;
+
+    while let Some(Ok(_)) = fields.next() {
+        items += 1;
+        kept = fields.offset();
+    }
+
+    Salvage::new(buf, kept, items)
+}
+
+/// Finds the end of the last complete frame of a length prefixed stream. The uncompressed frames
+/// need to contain a complete message to be kept, so that a frame with a corrupted length prefix
+/// is not kept just because the following bytes happen to be enough for it.
+pub fn salvage_frames(buf: &[u8], framing: Framing) -> Salvage {
+    let mut items = 0;
+    let mut kept = 0;
+
+    while let Ok(Ok((prefix, header))) = framing.read_prefix(&buf[kept..]) {
+        let start = kept + prefix;
+        let end = match usize::try_from(header.len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+        {
+            Some(end) if end <= buf.len() => end,
+            _ => break,
+        };
+
+        if !header.compressed && Fields::new(&buf[start..end]).any(|field| field.is_err()) {
+            break;
+        }
+
+        items += 1;
+        kept = end;
+    }
+
+    Salvage::new(buf, kept, items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{salvage_frames, salvage_message, Salvage};
+    use crate::framing::Framing;
+    use hex_literal::hex;
+
+    #[test]
+    fn keeps_complete_fields() {
+        // 1: 1, 2: "ab", and the beginning of 3: "abc"
+        let input = hex!("0801 12026162 1a0361");
+        assert_eq!(
+            salvage_message(&input),
+            Salvage {
+                kept: 6,
+                dropped: 3,
+                items: 2
+            }
+        );
+        assert!(salvage_message(&input[..6]).is_intact());
+    }
+
+    #[test]
+    fn keeps_complete_frames() {
+        // { 1: 1 }, { 1: 2 }, then a frame of garbage and a truncated frame
+        let input = hex!("02 0801 02 0802 02 ffff 04 0803");
+        let salvage = salvage_frames(&input, Framing::Varint);
+        assert_eq!((salvage.kept, salvage.items), (6, 2));
+
+        let salvage = salvage_frames(&input[..6], Framing::Varint);
+        assert!(salvage.is_intact());
+    }
+}
@@ -0,0 +1,472 @@
+//! Message descriptors read from a serialized `google.protobuf.FileDescriptorSet`, as written by
+//! `protoc --include_imports --descriptor_set_out=FILE`.
+//!
+//! Only the parts needed to resolve field names and types are kept; options other than `packed`,
+//! services and the rest of the descriptor are ignored. The set is decoded with [`Fields`], so no
+//! generated code is needed.
+
+use crate::message::{FieldData, Fields, MessageError};
+use crate::{FieldId, WireType};
+use std::convert::TryFrom;
+use std::fmt;
+
+pub mod detect;
+pub mod lint;
+
+/// The messages of a descriptor set, looked up by their fully qualified names.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    messages: Vec<MessageDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageDescriptor {
+    /// Fully qualified name without the leading dot, for example `google.protobuf.Timestamp`
+    pub full_name: String,
+    pub fields: Vec<FieldDescriptor>,
+    /// True if the message was declared in a file with `syntax = "proto3"`
+    pub proto3: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDescriptor {
+    pub name: String,
+    /// The proto3 JSON name, either given in the descriptor or derived from the name
+    pub json_name: String,
+    pub number: FieldId,
+    pub label: Label,
+    pub kind: FieldType,
+    /// Fully qualified name of the message or enum type without the leading dot
+    pub type_name: Option<String>,
+    /// The `packed` option if it was given
+    pub packed: Option<bool>,
+    /// Index of the `oneof` the field is a member of
+    pub oneof_index: Option<u32>,
+    /// The field was declared `optional` in proto3
+    pub proto3_optional: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Label {
+    Optional,
+    Required,
+    Repeated,
+}
+
+/// The type of the field, as in `FieldDescriptorProto.Type`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    Double,
+    Float,
+    Int64,
+    UInt64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Group,
+    Message,
+    Bytes,
+    UInt32,
+    Enum,
+    SFixed32,
+    SFixed64,
+    SInt32,
+    SInt64,
+}
+
+impl TryFrom<u64> for FieldType {
+    type Error = SchemaError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        use FieldType::*;
+        Ok(match value {
+            1 => Double,
+            2 => Float,
+            3 => Int64,
+            4 => UInt64,
+            5 => Int32,
+            6 => Fixed64,
+            7 => Fixed32,
+            8 => Bool,
+            9 => String,
+            10 => Group,
+            11 => Message,
+            12 => Bytes,
+            13 => UInt32,
+            14 => Enum,
+            15 => SFixed32,
+            16 => SFixed64,
+            17 => SInt32,
+            18 => SInt64,
+            _ => return Err(SchemaError::InvalidType(value)),
+        })
+    }
+}
+
+impl FieldType {
+    /// The wire type of a single non-packed value, or None for groups which are not supported.
+    pub fn wire_type(&self) -> Option<WireType> {
+        use FieldType::*;
+        Some(match self {
+            Double | Fixed64 | SFixed64 => WireType::Fixed64,
+            Float | Fixed32 | SFixed32 => WireType::Fixed32,
+            Int64 | UInt64 | Int32 | Bool | UInt32 | Enum | SInt32 | SInt64 => WireType::Varint,
+            String | Message | Bytes => WireType::LengthDelimited,
+            Group => return None,
+        })
+    }
+
+    /// Returns true for the scalar types which can be packed.
+    pub fn is_packable(&self) -> bool {
+        !matches!(
+            self,
+            FieldType::String | FieldType::Bytes | FieldType::Message | FieldType::Group
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Message(MessageError),
+    /// A name was not valid UTF-8
+    InvalidUtf8,
+    InvalidLabel(u64),
+    InvalidType(u64),
+    /// A field had no name or number
+    IncompleteField,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SchemaError::*;
+        match self {
+            Message(e) => write!(fmt, "invalid descriptor set: {}", e),
+            InvalidUtf8 => write!(fmt, "invalid descriptor set: name was not utf-8"),
+            InvalidLabel(x) => write!(fmt, "invalid descriptor set: unknown label {}", x),
+            InvalidType(x) => write!(fmt, "invalid descriptor set: unknown type {}", x),
+            IncompleteField => write!(fmt, "invalid descriptor set: field without name or number"),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl From<MessageError> for SchemaError {
+    fn from(e: MessageError) -> Self {
+        SchemaError::Message(e)
+    }
+}
+
+impl Schema {
+    /// Decodes a serialized `FileDescriptorSet`.
+    pub fn decode(file_descriptor_set: &[u8]) -> Result<Schema, SchemaError> {
+        let mut schema = Schema::default();
+        for field in Fields::new(file_descriptor_set) {
+            let field = field?;
+            if let (1, FieldData::Bytes(file)) = (field.id, field.value) {
+                schema.add_file(file)?;
+            }
+        }
+        Ok(schema)
+    }
+
+    fn add_file(&mut self, file: &[u8]) -> Result<(), SchemaError> {
+        let mut package = String::new();
+        let mut messages = Vec::new();
+        let mut proto3 = false;
+
+        for field in Fields::new(file) {
+            let field = field?;
+            match (field.id, field.value) {
+                (2, FieldData::Bytes(bytes)) => package = string(bytes)?,
+                (4, FieldData::Bytes(bytes)) => messages.push(bytes),
+                (12, FieldData::Bytes(bytes)) => proto3 = bytes == b"proto3",
+                _ => {}
+            }
+        }
+
+        // the package and syntax can come after the messages
+        for message in messages {
+            self.add_message(&package, proto3, message)?;
+        }
+        Ok(())
+    }
+
+    fn add_message(
+        &mut self,
+        scope: &str,
+        proto3: bool,
+        message: &[u8],
+    ) -> Result<(), SchemaError> {
+        let mut name = String::new();
+        let mut fields = Vec::new();
+        let mut nested = Vec::new();
+
+        for field in Fields::new(message) {
+            let field = field?;
+            match (field.id, field.value) {
+                (1, FieldData::Bytes(bytes)) => name = string(bytes)?,
+                (2, FieldData::Bytes(bytes)) => fields.push(FieldDescriptor::decode(bytes)?),
+                (3, FieldData::Bytes(bytes)) => nested.push(bytes),
+                _ => {}
+            }
+        }
+
+        let full_name = if scope.is_empty() {
+            name
+        } else {
+            format!("{}.{}", scope, name)
+        };
+
+        for message in nested {
+            self.add_message(&full_name, proto3, message)?;
+        }
+
+        self.messages.push(MessageDescriptor {
+            full_name,
+            fields,
+            proto3,
+        });
+        Ok(())
+    }
+
+    /// Looks up a message by its fully qualified name, with or without the leading dot.
+    pub fn message(&self, full_name: &str) -> Option<&MessageDescriptor> {
+        let full_name = full_name.strip_prefix('.').unwrap_or(full_name);
+        self.messages.iter().find(|m| m.full_name == full_name)
+    }
+
+    pub fn messages(&self) -> impl Iterator<Item = &MessageDescriptor> {
+        self.messages.iter()
+    }
+
+    /// Returns the message type of the field, if it is a message field.
+    pub fn message_of(&self, field: &FieldDescriptor) -> Option<&MessageDescriptor> {
+        match field.kind {
+            FieldType::Message | FieldType::Group => self.message(field.type_name.as_ref()?),
+            _ => None,
+        }
+    }
+}
+
+impl MessageDescriptor {
+    pub fn field(&self, number: FieldId) -> Option<&FieldDescriptor> {
+        self.fields.iter().find(|f| f.number == number)
+    }
+
+    /// Looks up a field by its name or its proto3 JSON name.
+    pub fn field_by_name(&self, name: &str) -> Option<&FieldDescriptor> {
+        self.fields
+            .iter()
+            .find(|f| f.name == name)
+            .or_else(|| self.fields.iter().find(|f| f.json_name == name))
+    }
+
+    /// Returns false for the non-repeated fields for which a default value on the wire means the
+    /// same as the field being absent, as with the proto3 scalars outside of a `oneof`.
+    pub fn has_presence(&self, field: &FieldDescriptor) -> bool {
+        !self.proto3
+            || field.is_repeated()
+            || field.oneof_index.is_some()
+            || field.proto3_optional
+            || matches!(field.kind, FieldType::Message | FieldType::Group)
+    }
+}
+
+impl FieldDescriptor {
+    fn decode(buf: &[u8]) -> Result<FieldDescriptor, SchemaError> {
+        let mut name = None;
+        let mut json_name = None;
+        let mut number = None;
+        let mut label = Label::Optional;
+        let mut kind = None;
+        let mut type_name = None;
+        let mut packed = None;
+        let mut oneof_index = None;
+        let mut proto3_optional = false;
+
+        for field in Fields::new(buf) {
+            let field = field?;
+            match (field.id, field.value) {
+                (1, FieldData::Bytes(bytes)) => name = Some(string(bytes)?),
+                (3, FieldData::Varint(x)) => number = Some(x as FieldId),
+                (4, FieldData::Varint(x)) => {
+                    label = match x {
+                        1 => Label::Optional,
+                        2 => Label::Required,
+                        3 => Label::Repeated,
+                        _ => return Err(SchemaError::InvalidLabel(x)),
+                    }
+                }
+                (5, FieldData::Varint(x)) => kind = Some(FieldType::try_from(x)?),
+                (6, FieldData::Bytes(bytes)) => {
+                    let s = string(bytes)?;
+                    type_name = Some(s.strip_prefix('.').map(str::to_owned).unwrap_or(s));
+                }
+                (8, FieldData::Bytes(options)) => {
+                    for option in Fields::new(options) {
+                        let option = option?;
+                        if let (2, FieldData::Varint(x)) = (option.id, option.value) {
+                            packed = Some(x != 0);
+                        }
+                    }
+                }
+                (9, FieldData::Varint(x)) => oneof_index = Some(x as u32),
+                (10, FieldData::Bytes(bytes)) => json_name = Some(string(bytes)?),
+                (17, FieldData::Varint(x)) => proto3_optional = x != 0,
+                _ => {}
+            }
+        }
+
+        let (name, number) = match (name, number) {
+            (Some(name), Some(number)) => (name, number),
+            _ => return Err(SchemaError::IncompleteField),
+        };
+
+        // protoc leaves out the type when the type_name is not yet resolved
+        let kind = match kind {
+            Some(kind) => kind,
+            None if type_name.is_some() => FieldType::Message,
+            None => return Err(SchemaError::IncompleteField),
+        };
+
+        Ok(FieldDescriptor {
+            json_name: json_name.unwrap_or_else(|| to_json_name(&name)),
+            name,
+            number,
+            label,
+            kind,
+            type_name,
+            packed,
+            oneof_index,
+            proto3_optional,
+        })
+    }
+
+    pub fn is_repeated(&self) -> bool {
+        self.label == Label::Repeated
+    }
+}
+
+fn string(bytes: &[u8]) -> Result<String, SchemaError> {
+    std::str::from_utf8(bytes)
+        .map(str::to_owned)
+        .map_err(|_| SchemaError::InvalidUtf8)
+}
+
+/// Converts a field name into lowerCamelCase like protoc does: underscores are removed and the
+/// following letters uppercased.
+pub fn to_json_name(name: &str) -> String {
+    let mut ret = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            ret.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{to_json_name, FieldType, Label, Schema};
+    use crate::sink::{Sink, VecSink};
+
+    fn field(sink: &mut VecSink, name: &str, number: u64, label: u64, kind: u64, type_name: &str) {
+        sink.begin_message(2).unwrap();
+        sink.write_slice(1, name.as_bytes()).unwrap();
+        sink.write_field_header(3, crate::WireType::Varint).unwrap();
+        sink.write_varint(number).unwrap();
+        sink.write_field_header(4, crate::WireType::Varint).unwrap();
+        sink.write_varint(label).unwrap();
+        sink.write_field_header(5, crate::WireType::Varint).unwrap();
+        sink.write_varint(kind).unwrap();
+        if !type_name.is_empty() {
+            sink.write_slice(6, type_name.as_bytes()).unwrap();
+        }
+        sink.end_message().unwrap();
+    }
+
+    /// A descriptor set for:
+    ///
+    /// ```text
+    /// syntax = "proto3";
+    /// package test;
+    /// message Order {
+    ///   message Line { string product_id = 1; uint64 count = 3; }
+    ///   string order_id = 1;
+    ///   repeated Line lines = 2;
+    ///   sint64 total_cents = 3;
+    ///   repeated uint32 tags = 4;
+    /// }
+    /// ```
+    pub(crate) fn order_schema() -> Vec<u8> {
+        let mut sink = VecSink::default();
+        sink.begin_message(1).unwrap();
+        sink.write_slice(2, b"test").unwrap();
+        sink.begin_message(4).unwrap();
+        sink.write_slice(1, b"Order").unwrap();
+        field(&mut sink, "order_id", 1, 1, 9, "");
+        field(&mut sink, "lines", 2, 3, 11, ".test.Order.Line");
+        field(&mut sink, "total_cents", 3, 1, 18, "");
+        field(&mut sink, "tags", 4, 3, 13, "");
+        sink.begin_message(3).unwrap();
+        sink.write_slice(1, b"Line").unwrap();
+        field(&mut sink, "product_id", 1, 1, 9, "");
+        field(&mut sink, "count", 3, 1, 4, "");
+        sink.end_message().unwrap();
+        sink.end_message().unwrap();
+        sink.write_slice(12, b"proto3").unwrap();
+        sink.end_message().unwrap();
+        sink.into_inner()
+    }
+
+    /// A descriptor set for:
+    ///
+    /// ```text
+    /// syntax = "proto3";
+    /// package test;
+    /// message Node { Node child = 1; uint64 value = 2; }
+    /// ```
+    pub(crate) fn node_schema() -> Vec<u8> {
+        let mut sink = VecSink::default();
+        sink.begin_message(1).unwrap();
+        sink.write_slice(2, b"test").unwrap();
+        sink.begin_message(4).unwrap();
+        sink.write_slice(1, b"Node").unwrap();
+        field(&mut sink, "child", 1, 1, 11, ".test.Node");
+        field(&mut sink, "value", 2, 1, 4, "");
+        sink.end_message().unwrap();
+        sink.write_slice(12, b"proto3").unwrap();
+        sink.end_message().unwrap();
+        sink.into_inner()
+    }
+
+    #[test]
+    fn decodes_nested_messages() {
+        let schema = Schema::decode(&order_schema()).unwrap();
+        let order = schema.message(".test.Order").unwrap();
+
+        let lines = order.field_by_name("lines").unwrap();
+        assert_eq!((lines.number, lines.label), (2, Label::Repeated));
+
+        let line = schema.message_of(lines).unwrap();
+        assert_eq!(line.full_name, "test.Order.Line");
+        assert_eq!(line.field_by_name("productId").unwrap().number, 1);
+
+        let total = order.field(3).unwrap();
+        assert_eq!(
+            (total.kind, total.json_name.as_str()),
+            (FieldType::SInt64, "totalCents")
+        );
+
+        assert_eq!(to_json_name("a_b_c1"), "aBC1");
+    }
+}
@@ -0,0 +1,209 @@
+//! Guessing which of the candidate message types a payload is, for example for a stream of
+//! messages which has lost its type tags.
+//!
+//! Every field of the payload is checked against every candidate: a field agrees if the candidate
+//! has a field with the number and the wire type matches, conflicts if the wire type does not match
+//! and is unknown if the candidate has no such field. Nested messages are checked against the
+//! field's message type and their fields counted in as well. Required fields missing from the
+//! payload count against the candidate. Ties are broken by preferring the candidate with the
+//! fewest fields absent from the payload.
+
+use super::{FieldDescriptor, Label, MessageDescriptor, Schema};
+use crate::message::{FieldData, Fields, MessageError};
+use crate::FieldId;
+use std::cmp::Ordering;
+
+/// How deep nested messages are checked.
+const MAX_DEPTH: usize = 16;
+
+/// The counts of how well a payload conforms to a message type.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Score {
+    pub agreeing: u64,
+    pub conflicting: u64,
+    pub unknown: u64,
+    pub missing_required: u64,
+    /// Fields of the top level message which were not present in the payload. This does not
+    /// affect the confidence but breaks ties: the closer fit is preferred.
+    pub absent: u64,
+}
+
+impl Score {
+    /// The share of agreeing fields from all of the counted, between 0 and 1. An empty payload
+    /// conforms to every message type without required fields.
+    pub fn confidence(&self) -> f64 {
+        let total = self.agreeing + self.conflicting + self.unknown + self.missing_required;
+        if total == 0 {
+            1.0
+        } else {
+            self.agreeing as f64 / total as f64
+        }
+    }
+
+    fn add(&mut self, other: &Score) {
+        self.agreeing += other.agreeing;
+        self.conflicting += other.conflicting;
+        self.unknown += other.unknown;
+        self.missing_required += other.missing_required;
+    }
+}
+
+/// A candidate with its score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification<'s> {
+    pub message: &'s MessageDescriptor,
+    pub score: Score,
+}
+
+/// Scores the payload against all of the `candidates`, returning them ordered from the most
+/// plausible to the least. Fails only if the payload is not a message at all.
+pub fn classify<'s>(
+    payload: &[u8],
+    schema: &'s Schema,
+    candidates: &[&'s MessageDescriptor],
+) -> Result<Vec<Classification<'s>>, MessageError> {
+    let mut ret = candidates
+        .iter()
+        .map(|&message| Classification {
+            message,
+            score: Score::default(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut seen = vec![Vec::new(); ret.len()];
+
+    // the payload is read once, checking each field against all of the candidates
+    for field in Fields::new(payload) {
+        let field = field?;
+        for (c, seen) in ret.iter_mut().zip(seen.iter_mut()) {
+            let descriptor = c.message.field(field.id);
+            check(schema, descriptor, &field.value, &mut c.score, 0);
+            if descriptor.is_some() {
+                seen.push(field.id);
+            }
+        }
+    }
+
+    for (c, seen) in ret.iter_mut().zip(seen.iter()) {
+        c.score.missing_required += missing_required(c.message, seen);
+        c.score.absent = c
+            .message
+            .fields
+            .iter()
+            .filter(|f| !seen.contains(&f.number))
+            .count() as u64;
+    }
+
+    ret.sort_by(|a, b| {
+        b.score
+            .confidence()
+            .partial_cmp(&a.score.confidence())
+            .unwrap_or(Ordering::Equal)
+            .then(a.score.conflicting.cmp(&b.score.conflicting))
+            .then(a.score.absent.cmp(&b.score.absent))
+    });
+
+    Ok(ret)
+}
+
+fn check(
+    schema: &Schema,
+    descriptor: Option<&FieldDescriptor>,
+    value: &FieldData<'_>,
+    score: &mut Score,
+    depth: usize,
+) {
+    let descriptor = match descriptor {
+        Some(descriptor) => descriptor,
+        None => {
+            score.unknown += 1;
+            return;
+        }
+    };
+
+    let wire_type = value.wire_type();
+    let packed = descriptor.is_repeated()
+        && descriptor.kind.is_packable()
+        && matches!(value, FieldData::Bytes(_));
+
+    if !packed && descriptor.kind.wire_type() != Some(wire_type) {
+        score.conflicting += 1;
+        return;
+    }
+
+    score.agreeing += 1;
+
+    if let (Some(message), FieldData::Bytes(bytes)) = (schema.message_of(descriptor), value) {
+        if depth < MAX_DEPTH {
+            match score_message(schema, message, bytes, depth + 1) {
+                Some(nested) => score.add(&nested),
+                // the bytes did not parse as a message
+                None => {
+                    score.agreeing -= 1;
+                    score.conflicting += 1;
+                }
+            }
+        }
+    }
+}
+
+fn score_message(
+    schema: &Schema,
+    message: &MessageDescriptor,
+    buf: &[u8],
+    depth: usize,
+) -> Option<Score> {
+    let mut score = Score::default();
+    let mut seen = Vec::new();
+
+    for field in Fields::new(buf) {
+        let field = field.ok()?;
+        let descriptor = message.field(field.id);
+        check(schema, descriptor, &field.value, &mut score, depth);
+        if descriptor.is_some() {
+            seen.push(field.id);
+        }
+    }
+
+    score.missing_required += missing_required(message, &seen);
+    Some(score)
+}
+
+fn missing_required(message: &MessageDescriptor, seen: &[FieldId]) -> u64 {
+    message
+        .fields
+        .iter()
+        .filter(|f| f.label == Label::Required && !seen.contains(&f.number))
+        .count() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::classify;
+    use crate::schema::{tests::order_schema, Schema};
+    use hex_literal::hex;
+
+    #[test]
+    fn picks_the_conforming_type() {
+        let schema = Schema::decode(&order_schema()).unwrap();
+        let order = schema.message("test.Order").unwrap();
+        let line = schema.message("test.Order.Line").unwrap();
+
+        // an Order: order_id: "x", lines: { product_id: "a", count: 2 }, total_cents: -1
+        let input = hex!("0a0178 1205 0a0161 1802 1801");
+        let ret = classify(&input, &schema, &[line, order]).unwrap();
+        assert_eq!(ret[0].message.full_name, "test.Order");
+        assert_eq!(ret[0].score.confidence(), 1.0);
+        // Line has no field 2, and field 3 is a varint in both
+        assert_eq!(ret[1].score.agreeing, 2);
+        assert_eq!(ret[1].score.unknown, 1);
+
+        // a Line
+        let input = hex!("0a0161 1802");
+        let ret = classify(&input, &schema, &[order, line]).unwrap();
+        assert_eq!(ret[0].message.full_name, "test.Order.Line");
+        // for an Order field 3 is a sint64 which is a varint as well
+        assert_eq!(ret[1].score.confidence(), 1.0);
+        assert_eq!((ret[1].score.agreeing, ret[1].score.absent), (2, 2));
+    }
+}
@@ -0,0 +1,115 @@
+//! Validations of messages against the schema, for finding producer bugs which the decoders
+//! silently hide.
+
+use super::{MessageDescriptor, Schema};
+use crate::message::{FieldData, Fields, MessageError};
+use crate::FieldId;
+
+/// How deep nested messages are checked.
+const MAX_DEPTH: usize = 64;
+
+/// A non-repeated field which occurred more than once in a single message instance. Decoders keep
+/// the last one of scalars and merge the messages, so the earlier occurrences are easily lost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duplicate {
+    /// Field ids from the outermost message down to the duplicated field
+    pub path: Vec<FieldId>,
+    /// Offsets of the tags of each occurrence, from the beginning of the outermost message
+    pub offsets: Vec<usize>,
+}
+
+/// Finds the fields declared non-repeated which occur multiple times within a message instance,
+/// including in all of the nested messages.
+pub fn find_duplicates(
+    buf: &[u8],
+    schema: &Schema,
+    message: &MessageDescriptor,
+) -> Result<Vec<Duplicate>, MessageError> {
+    let mut ret = Vec::new();
+    let mut path = Vec::new();
+    visit(buf, 0, schema, message, &mut path, &mut ret)?;
+    Ok(ret)
+}
+
+fn visit(
+    buf: &[u8],
+    base: usize,
+    schema: &Schema,
+    message: &MessageDescriptor,
+    path: &mut Vec<FieldId>,
+    ret: &mut Vec<Duplicate>,
+) -> Result<(), MessageError> {
+    // occurrences of the non-repeated fields in the order of the first occurrence
+    let mut seen: Vec<(FieldId, Vec<usize>)> = Vec::new();
+
+    for field in Fields::new(buf) {
+        let field = field?;
+        let descriptor = match message.field(field.id) {
+            Some(descriptor) => descriptor,
+            None => continue,
+        };
+
+        let offset = base + field.offset;
+
+        if !descriptor.is_repeated() {
+            match seen.iter_mut().find(|(id, _)| *id == field.id) {
+                Some((_, offsets)) => offsets.push(offset),
+                None => seen.push((field.id, vec![offset])),
+            }
+        }
+
+        if let (Some(nested), FieldData::Bytes(payload)) =
+            (schema.message_of(descriptor), field.value)
+        {
+            if path.len() < MAX_DEPTH {
+                let payload_start = offset + field.raw.len() - payload.len();
+                path.push(field.id);
+                let res = visit(payload, payload_start, schema, nested, path, ret);
+                path.pop();
+                res?;
+            }
+        }
+    }
+
+    for (id, offsets) in seen {
+        if offsets.len() > 1 {
+            let mut path = path.clone();
+            path.push(id);
+            ret.push(Duplicate { path, offsets });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_duplicates, Duplicate};
+    use crate::schema::{tests::order_schema, Schema};
+    use hex_literal::hex;
+
+    #[test]
+    fn finds_duplicates_per_instance() {
+        let schema = Schema::decode(&order_schema()).unwrap();
+        let order = schema.message("test.Order").unwrap();
+
+        // order_id: "a", lines: { count: 1, count: 2 }, lines: { count: 3 }, tags: 1, tags: 2,
+        // order_id: "b"
+        let input = hex!("0a0161 1204 1801 1802 1202 1803 2001 2002 0a0162");
+        let duplicates = find_duplicates(&input, &schema, order).unwrap();
+
+        assert_eq!(
+            duplicates,
+            vec![
+                Duplicate {
+                    path: vec![2, 3],
+                    offsets: vec![5, 7],
+                },
+                Duplicate {
+                    path: vec![1],
+                    offsets: vec![0, 17],
+                },
+            ]
+        );
+    }
+}
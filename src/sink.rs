@@ -0,0 +1,292 @@
+//! Output side abstraction for anything emitting protobuf wire bytes.
+//!
+//! The [`Sink`] trait only requires raw byte writing and the nested message bookkeeping, other
+//! methods are provided in terms of those. Implementations interested in the semantic events
+//! (for example writing into a columnar format) can override the provided methods.
+//!
+//! The rewrites of [`crate::rewrite`] and [`crate::transcode`] write into any sink.
+
+use crate::pb::{encode_varint, tag, varint_len};
+use crate::{FieldId, WireType};
+use std::fmt;
+use std::io;
+
+/// Non-length delimited value to be written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scalar {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+}
+
+impl Scalar {
+    pub fn wire_type(&self) -> WireType {
+        match self {
+            Scalar::Varint(_) => WireType::Varint,
+            Scalar::Fixed64(_) => WireType::Fixed64,
+            Scalar::Fixed32(_) => WireType::Fixed32,
+        }
+    }
+}
+
+/// Destination for encoded fields.
+pub trait Sink {
+    type Error;
+
+    /// Writes already encoded bytes as is.
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Starts a nested, length delimited message with the given field id. All writes up until the
+    /// matching `end_message` make up the nested message.
+    fn begin_message(&mut self, id: FieldId) -> Result<(), Self::Error>;
+
+    /// Ends the latest message started with `begin_message`.
+    fn end_message(&mut self) -> Result<(), Self::Error>;
+
+    /// Writes the tag of a field: field id and the wire type.
+    fn write_field_header(&mut self, id: FieldId, kind: WireType) -> Result<(), Self::Error> {
+        self.write_varint(tag(id, kind))
+    }
+
+    /// Writes a varint without any field header, for example a length prefix.
+    fn write_varint(&mut self, value: u64) -> Result<(), Self::Error> {
+        let mut tmp = [0u8; 10];
+        let len = encode_varint(value, &mut tmp);
+        self.write_raw(&tmp[..len])
+    }
+
+    /// Writes a complete non-length delimited field.
+    fn write_scalar(&mut self, id: FieldId, value: Scalar) -> Result<(), Self::Error> {
+        self.write_field_header(id, value.wire_type())?;
+        match value {
+            Scalar::Varint(x) => self.write_varint(x),
+            Scalar::Fixed64(x) => self.write_raw(&x.to_le_bytes()),
+            Scalar::Fixed32(x) => self.write_raw(&x.to_le_bytes()),
+        }
+    }
+
+    /// Writes a complete length delimited field.
+    fn write_slice(&mut self, id: FieldId, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_field_header(id, WireType::LengthDelimited)?;
+        self.write_varint(bytes.len() as u64)?;
+        self.write_raw(bytes)
+    }
+}
+
+/// Errors from the provided [`Sink`] implementations.
+#[derive(Debug)]
+pub enum SinkError {
+    /// `end_message` was called without a matching `begin_message`
+    UnbalancedEndMessage,
+    /// Writing to the underlying `std::io::Write` failed
+    IO(io::Error),
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::UnbalancedEndMessage => {
+                write!(fmt, "end_message called without begin_message")
+            }
+            SinkError::IO(e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+impl From<io::Error> for SinkError {
+    fn from(e: io::Error) -> Self {
+        SinkError::IO(e)
+    }
+}
+
+/// Appends to a `Vec<u8>`. Nested messages are written in place and the length prefix is inserted
+/// once the message ends, which moves the bytes of the message once per nesting level.
+#[derive(Debug, Default)]
+pub struct VecSink {
+    buffer: Vec<u8>,
+    /// Start offsets of the nested messages bodies
+    open: Vec<usize>,
+}
+
+impl VecSink {
+    pub fn new(buffer: Vec<u8>) -> Self {
+        Self {
+            buffer,
+            open: Vec::new(),
+        }
+    }
+
+    /// Returns the written bytes. Any messages still open will be missing their length prefix.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    pub fn open_messages(&self) -> usize {
+        self.open.len()
+    }
+}
+
+impl Sink for VecSink {
+    type Error = SinkError;
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.buffer.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn begin_message(&mut self, id: FieldId) -> Result<(), Self::Error> {
+        self.write_field_header(id, WireType::LengthDelimited)?;
+        self.open.push(self.buffer.len());
+        Ok(())
+    }
+
+    fn end_message(&mut self) -> Result<(), Self::Error> {
+        let start = self.open.pop().ok_or(SinkError::UnbalancedEndMessage)?;
+        let len = self.buffer.len() - start;
+        let mut tmp = [0u8; 10];
+        let prefix = encode_varint(len as u64, &mut tmp);
+        self.buffer
+            .splice(start..start, tmp[..prefix].iter().copied());
+        Ok(())
+    }
+}
+
+/// Writes to an `std::io::Write`. Nested messages are buffered until the outermost message ends,
+/// as their length needs to be known before the body can be written.
+pub struct WriteSink<W> {
+    inner: W,
+    /// Field id and the buffered body of the open messages, the outermost first
+    open: Vec<(FieldId, Vec<u8>)>,
+}
+
+impl<W: io::Write> WriteSink<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            open: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns the inner writer, discarding any still open messages.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub fn open_messages(&self) -> usize {
+        self.open.len()
+    }
+}
+
+impl<W: io::Write> Sink for WriteSink<W> {
+    type Error = SinkError;
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        match self.open.last_mut() {
+            Some((_, body)) => body.extend_from_slice(bytes),
+            None => self.inner.write_all(bytes)?,
+        }
+        Ok(())
+    }
+
+    fn begin_message(&mut self, id: FieldId) -> Result<(), Self::Error> {
+        self.open.push((id, Vec::new()));
+        Ok(())
+    }
+
+    fn end_message(&mut self) -> Result<(), Self::Error> {
+        let (id, body) = self.open.pop().ok_or(SinkError::UnbalancedEndMessage)?;
+        self.write_slice(id, &body)
+    }
+}
+
+/// Only counts the bytes which would be written.
+#[derive(Debug, Default)]
+pub struct CountingSink {
+    count: u64,
+    /// Counts at the start of each open messages body
+    open: Vec<u64>,
+}
+
+impl CountingSink {
+    /// Returns the number of bytes written so far, not including length prefixes of the open
+    /// messages.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl Sink for CountingSink {
+    type Error = SinkError;
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.count += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn begin_message(&mut self, id: FieldId) -> Result<(), Self::Error> {
+        self.write_field_header(id, WireType::LengthDelimited)?;
+        self.open.push(self.count);
+        Ok(())
+    }
+
+    fn end_message(&mut self) -> Result<(), Self::Error> {
+        let start = self.open.pop().ok_or(SinkError::UnbalancedEndMessage)?;
+        self.count += varint_len(self.count - start) as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CountingSink, Scalar, Sink, SinkError, VecSink, WriteSink};
+    use hex_literal::hex;
+
+    fn write_nested<S: Sink<Error = SinkError>>(sink: &mut S) {
+        sink.write_scalar(1, Scalar::Varint(150)).unwrap();
+        sink.begin_message(2).unwrap();
+        sink.write_slice(1, b"abc").unwrap();
+        sink.begin_message(3).unwrap();
+        sink.write_scalar(4, Scalar::Fixed32(1)).unwrap();
+        sink.end_message().unwrap();
+        sink.end_message().unwrap();
+        sink.write_scalar(5, Scalar::Fixed64(2)).unwrap();
+    }
+
+    const EXPECTED: [u8; 26] = hex!("089601 120c 0a03616263 1a05 2501000000 290200000000000000");
+
+    #[test]
+    fn vec_sink_nested() {
+        let mut sink = VecSink::default();
+        write_nested(&mut sink);
+        assert_eq!(sink.into_inner(), &EXPECTED[..]);
+    }
+
+    #[test]
+    fn write_sink_nested() {
+        let mut sink = WriteSink::new(Vec::new());
+        write_nested(&mut sink);
+        assert_eq!(sink.into_inner(), &EXPECTED[..]);
+    }
+
+    #[test]
+    fn counting_sink_nested() {
+        let mut sink = CountingSink::default();
+        write_nested(&mut sink);
+        assert_eq!(sink.count(), EXPECTED.len() as u64);
+    }
+
+    #[test]
+    fn unbalanced_end() {
+        let mut sink = VecSink::default();
+        assert!(matches!(
+            sink.end_message(),
+            Err(SinkError::UnbalancedEndMessage)
+        ));
+    }
+}
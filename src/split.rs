@@ -0,0 +1,201 @@
+//! The inputs the readers can be advanced over: a single slice or the two halves of a ring buffer.
+//!
+//! With the two slices the second one continues where the first one ends. Only the field headers
+//! straddling the two are copied, into a small scratch buffer; the length delimited fields are
+//! never copied by the readers.
+
+use crate::field_reader::{FieldReader, MAX_HEADER};
+use crate::gather_fields::Slicer;
+use crate::{DecodingError, Offset, ReadField, Status};
+
+pub(crate) trait Input<'a>: Copy {
+    /// Bytes available in total.
+    fn remaining(&self) -> usize;
+
+    /// Drops the first `n` bytes, which need to be available.
+    fn advance(&mut self, n: usize);
+
+    /// Reads a header with `FieldReader::next_resumable`, so on `Status::NeedMoreBytes` all of the
+    /// remaining bytes have been taken by the reader.
+    fn read_field<'r>(
+        &self,
+        reader: &'r mut FieldReader,
+    ) -> Result<Result<ReadField<'r>, Status>, DecodingError>;
+
+    /// Calls `f` with the first `n` bytes as a single slice, copying them only if needed.
+    fn contiguous<R>(&self, n: usize, f: impl FnOnce(&[u8]) -> R) -> R;
+
+    /// Slicer over the first `len` bytes, which end at `last_offset`.
+    fn slicer(&self, len: usize, last_offset: Offset) -> Slicer<'a>;
+}
+
+impl<'a> Input<'a> for &'a [u8] {
+    fn remaining(&self) -> usize {
+        self.len()
+    }
+
+    fn advance(&mut self, n: usize) {
+        *self = &self[n..];
+    }
+
+    fn read_field<'r>(
+        &self,
+        reader: &'r mut FieldReader,
+    ) -> Result<Result<ReadField<'r>, Status>, DecodingError> {
+        reader.next_resumable(self)
+    }
+
+    fn contiguous<R>(&self, n: usize, f: impl FnOnce(&[u8]) -> R) -> R {
+        f(&self[..n])
+    }
+
+    fn slicer(&self, len: usize, last_offset: Offset) -> Slicer<'a> {
+        Slicer::wrap(&self[..len], last_offset)
+    }
+}
+
+impl<'a> Input<'a> for (&'a [u8], &'a [u8]) {
+    fn remaining(&self) -> usize {
+        self.0.len() + self.1.len()
+    }
+
+    fn advance(&mut self, n: usize) {
+        if n < self.0.len() {
+            self.0 = &self.0[n..];
+        } else {
+            let rest = n - self.0.len();
+            *self = (&self.1[rest..], &[]);
+        }
+    }
+
+    fn read_field<'r>(
+        &self,
+        reader: &'r mut FieldReader,
+    ) -> Result<Result<ReadField<'r>, Status>, DecodingError> {
+        if self.1.is_empty() || self.0.len() >= MAX_HEADER {
+            return reader.next_resumable(self.0);
+        }
+
+        let mut scratch = [0u8; MAX_HEADER];
+        let len = MAX_HEADER.min(self.remaining());
+        let (head, tail) = scratch[..len].split_at_mut(self.0.len());
+        head.copy_from_slice(self.0);
+        tail.copy_from_slice(&self.1[..tail.len()]);
+
+        reader.next_resumable(&scratch[..len])
+    }
+
+    fn contiguous<R>(&self, n: usize, f: impl FnOnce(&[u8]) -> R) -> R {
+        if n <= self.0.len() {
+            return f(&self.0[..n]);
+        }
+
+        let mut joined = Vec::with_capacity(n);
+        joined.extend_from_slice(self.0);
+        joined.extend_from_slice(&self.1[..n - self.0.len()]);
+        f(&joined)
+    }
+
+    fn slicer(&self, len: usize, last_offset: Offset) -> Slicer<'a> {
+        if len <= self.0.len() {
+            Slicer::wrap(&self.0[..len], last_offset)
+        } else {
+            Slicer::wrap_split(self.0, &self.1[..len - self.0.len()], last_offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gather_fields::{GatheredFields, Gatherer, Slicer};
+    use crate::matcher_fields::{
+        Action, Cont, EndedMessage, Matched, Matcher, MatcherFields, Value,
+    };
+    use crate::{DecodingError, FieldId, ReadField, Reader};
+    use hex_literal::hex;
+
+    struct Slices;
+
+    impl Matcher for Slices {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(if read.is_length_delimited() {
+                Action::Continue(Cont::ReadSlice(()))
+            } else {
+                Action::Continue(Cont::ReadValue(()))
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    /// Returns the bytes of the slices and nothing for the other values.
+    struct Bytes;
+
+    impl<'a> Gatherer<'a> for Bytes {
+        type Tag = ();
+        type Returned = Vec<u8>;
+
+        fn update(
+            &mut self,
+            matched: Matched<()>,
+            slicer: Slicer<'a>,
+        ) -> Result<Option<Vec<u8>>, DecodingError> {
+            Ok(match matched.value {
+                Value::Slice(range) => Some(slicer.get(&range).into_owned()),
+                _ => None,
+            })
+        }
+
+        fn min_offset(&self) -> Option<crate::Offset> {
+            None
+        }
+    }
+
+    // 1: 150, 2: "abcdef", 1: 300, 2: "gh"
+    const INPUT: [u8; 18] = hex!("089601 1206616263646566 08ac02 12026768");
+
+    #[test]
+    fn matcher_fields_over_every_split() {
+        let expected = {
+            let mut buf = &INPUT[..];
+            let mut fields = MatcherFields::new(Slices);
+            std::iter::from_fn(|| fields.next(&mut buf).unwrap().ok())
+                .map(|m| format!("{:?}", m))
+                .collect::<Vec<_>>()
+        };
+
+        for split in 0..=INPUT.len() {
+            let (first, second) = INPUT.split_at(split);
+            let mut buf = (first, second);
+            let mut fields = MatcherFields::new(Slices);
+            let actual = std::iter::from_fn(|| fields.next_split(&mut buf).unwrap().ok())
+                .map(|m| format!("{:?}", m))
+                .collect::<Vec<_>>();
+
+            assert_eq!(actual, expected, "split at {}", split);
+            assert_eq!(buf.0.len() + buf.1.len(), 0);
+        }
+    }
+
+    #[test]
+    fn gathered_fields_over_every_split() {
+        for split in 0..=INPUT.len() {
+            let (first, second) = INPUT.split_at(split);
+            let mut buf = (first, second);
+            let mut fields = GatheredFields::new(Slices, Bytes);
+            let actual = std::iter::from_fn(|| fields.next_split(&mut buf).unwrap().ok())
+                .collect::<Vec<_>>();
+
+            assert_eq!(actual, [&b"abcdef"[..], b"gh"], "split at {}", split);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Where the size of the messages goes: the counts and the sizes of the fields per field path.
+//!
+//! The length delimited fields which parse as messages are walked into, the same guess as in
+//! [`crate::infer`], so the sizes of the nested fields are included in the sizes of the fields
+//! containing them.
+
+use crate::infer::is_message;
+use crate::message::{FieldData, Fields, MessageError};
+use crate::FieldId;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How deep the nested messages are walked into.
+const MAX_DEPTH: usize = 32;
+
+/// The sizes of the occurrences of a single field. The size is of the whole field, including the
+/// tag and the length prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldSize {
+    pub count: u64,
+    pub total_bytes: u64,
+    pub min: u64,
+    pub max: u64,
+}
+
+impl FieldSize {
+    fn add(&mut self, size: u64) {
+        self.count += 1;
+        self.total_bytes += size;
+        self.min = self.min.min(size);
+        self.max = self.max.max(size);
+    }
+}
+
+impl Default for FieldSize {
+    fn default() -> Self {
+        FieldSize {
+            count: 0,
+            total_bytes: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+/// Collects the [`FieldSize`]s of the fields of messages by the path of the field ids.
+#[derive(Debug, Default)]
+pub struct FieldStats {
+    messages: u64,
+    bytes: u64,
+    fields: BTreeMap<Vec<FieldId>, FieldSize>,
+}
+
+impl FieldStats {
+    /// Adds a complete message. On an error the fields before it have been recorded.
+    pub fn add_message(&mut self, message: &[u8]) -> Result<(), MessageError> {
+        self.messages += 1;
+        self.bytes += message.len() as u64;
+        let mut path = Vec::new();
+        self.walk(message, &mut path)
+    }
+
+    fn walk(&mut self, buf: &[u8], path: &mut Vec<FieldId>) -> Result<(), MessageError> {
+        for field in Fields::new(buf) {
+            let field = field?;
+
+            path.push(field.id);
+            self.fields
+                .entry(path.clone())
+                .or_default()
+                .add(field.raw.len() as u64);
+
+            if let FieldData::Bytes(bytes) = field.value {
+                if path.len() <= MAX_DEPTH && is_message(bytes) {
+                    self.walk(bytes, path)?;
+                }
+            }
+            path.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Amount of messages added.
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    /// The fields ordered by their paths, the nested fields following the field containing them.
+    pub fn fields(&self) -> impl Iterator<Item = (&[FieldId], &FieldSize)> + '_ {
+        self.fields.iter().map(|(path, size)| (&path[..], size))
+    }
+
+    /// The sizes of the field at the path, for example `&[2, 1]` for the field 1 of the messages
+    /// in the field 2.
+    pub fn get(&self, path: &[FieldId]) -> Option<&FieldSize> {
+        self.fields.get(path)
+    }
+}
+
+/// Writes a line per field path, with the share of the total bytes of the messages.
+impl fmt::Display for FieldStats {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            fmt,
+            "{} messages of {} bytes in total",
+            self.messages, self.bytes
+        )?;
+
+        for (path, size) in &self.fields {
+            let indent = 2 * (path.len() - 1);
+            let id = path.last().expect("paths are never empty");
+            let share = 100.0 * size.total_bytes as f64 / self.bytes.max(1) as f64;
+
+            writeln!(
+                fmt,
+                "{:indent$}{}: {} times, {} bytes ({:.1}%), {}..={} bytes each",
+                "",
+                id,
+                size.count,
+                size.total_bytes,
+                share,
+                size.min,
+                size.max,
+                indent = indent
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldSize, FieldStats};
+    use hex_literal::hex;
+
+    #[test]
+    fn nested_sizes() {
+        // 1: 150, 2: { 1: "ab" }, 2: { 1: "" }, 3: "a b"
+        let input = hex!("089601 1204 0a026162 1202 0a00 1a03612062");
+        let mut stats = FieldStats::default();
+        stats.add_message(&input).unwrap();
+
+        assert_eq!(
+            stats.get(&[2]),
+            Some(&FieldSize {
+                count: 2,
+                total_bytes: 10,
+                min: 4,
+                max: 6,
+            })
+        );
+        assert_eq!(stats.get(&[2, 1]).map(|s| s.total_bytes), Some(6));
+        assert_eq!(stats.get(&[3]).map(|s| s.count), Some(1));
+
+        let paths = stats.fields().map(|(p, _)| p.to_vec()).collect::<Vec<_>>();
+        assert_eq!(paths, [vec![1], vec![2], vec![2, 1], vec![3]]);
+    }
+
+    #[test]
+    fn truncated_is_an_error() {
+        let mut stats = FieldStats::default();
+        assert!(stats.add_message(&hex!("0896")).is_err());
+        assert_eq!(stats.get(&[1]), None);
+    }
+}
@@ -0,0 +1,235 @@
+//! Writing the items returned by the matcher as newline delimited JSON, one object per item, for
+//! offline analysis of what the matcher saw with for example `jq`.
+//!
+//! Every line has the running number of the item as `seq`, the `Debug` rendering of the tag as
+//! `tag`, the `offset` and the `type` of the value, which is one of `marker`, `varint`, `fixed64`,
+//! `fixed32`, `slice` or `packed`. The numeric values are in `value`, the 64-bit ones as decimal strings like
+//! in the proto3 JSON mapping so that they survive the double precision numbers of the JSON tools.
+//! Slices and packed values have the `start` and `end` of the range and, when written from a
+//! [`SlicedMatched`], the `bytes` as hex. Packed values also have the `wire_type` of the values.
+//!
+//! ```text
+//! {"seq":0,"tag":"Name","offset":0,"type":"slice","start":2,"end":5,"bytes":"616263"}
+//! ```
+
+use crate::matcher_fields::{Matched, SlicedMatched, SlicedValue, Value};
+use crate::{Offset, WireType};
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// Writes the matched items as newline delimited JSON to an `io::Write`.
+pub struct EventWriter<W> {
+    inner: W,
+    seq: u64,
+    /// Reused for the tags
+    tmp: String,
+}
+
+impl<W: Write> EventWriter<W> {
+    pub fn new(inner: W) -> Self {
+        EventWriter {
+            inner,
+            seq: 0,
+            tmp: String::new(),
+        }
+    }
+
+    /// The number of items written so far.
+    pub fn written(&self) -> u64 {
+        self.seq
+    }
+
+    /// Writes an item of [`crate::matcher_fields::MatcherFields`], which has only the ranges of
+    /// the slices.
+    pub fn write<T: Debug>(&mut self, item: &Matched<T>) -> io::Result<()> {
+        self.begin(&item.tag, item.offset)?;
+        match &item.value {
+            Value::Marker => write!(self.inner, r#""type":"marker""#)?,
+            Value::Varint(x) => write!(self.inner, r#""type":"varint","value":"{}""#, x)?,
+            Value::Fixed64(x) => write!(self.inner, r#""type":"fixed64","value":"{}""#, x)?,
+            Value::Fixed32(x) => write!(self.inner, r#""type":"fixed32","value":{}"#, x)?,
+            Value::Slice(range) => self.range(range)?,
+            Value::Str(range) => self.str_range(range)?,
+            Value::Packed(wire_type, range) => self.packed(*wire_type, range)?,
+            Value::Raw(raw, range) => self.raw(raw.as_bytes(), range)?,
+            Value::SliceChunk(range, remaining) => self.chunk(range, *remaining)?,
+        }
+        self.end()
+    }
+
+    /// Writes an item of [`crate::matcher_fields::SlicedMatcherFields`], including the bytes of
+    /// the slices.
+    pub fn write_sliced<T: Debug>(&mut self, item: &SlicedMatched<'_, T>) -> io::Result<()> {
+        self.begin(&item.tag, item.offset)?;
+        match &item.value {
+            SlicedValue::Marker => write!(self.inner, r#""type":"marker""#)?,
+            SlicedValue::Varint(x) => write!(self.inner, r#""type":"varint","value":"{}""#, x)?,
+            SlicedValue::Fixed64(x) => write!(self.inner, r#""type":"fixed64","value":"{}""#, x)?,
+            SlicedValue::Fixed32(x) => write!(self.inner, r#""type":"fixed32","value":{}"#, x)?,
+            SlicedValue::Slice(range, bytes) => {
+                self.range(range)?;
+                self.bytes(bytes)?;
+            }
+            SlicedValue::Str(range, s) => {
+                self.str_range(range)?;
+                write!(self.inner, r#","value":""#)?;
+                write_escaped(&mut self.inner, s)?;
+                write!(self.inner, "\"")?;
+            }
+            SlicedValue::Packed(range, packed) => {
+                self.packed(packed.wire_type(), range)?;
+                self.bytes(packed.as_bytes())?;
+            }
+            SlicedValue::Raw(range, raw, bytes) => {
+                self.raw(raw.as_bytes(), range)?;
+                self.bytes(bytes)?;
+            }
+            SlicedValue::SliceChunk(range, remaining, bytes) => {
+                self.chunk(range, *remaining)?;
+                self.bytes(bytes)?;
+            }
+        }
+        self.end()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn begin<T: Debug>(&mut self, tag: &T, offset: Offset) -> io::Result<()> {
+        use std::fmt::Write as _;
+
+        self.tmp.clear();
+        write!(self.tmp, "{:?}", tag).expect("writing to a String cannot fail");
+
+        write!(self.inner, r#"{{"seq":{},"tag":""#, self.seq)?;
+        write_escaped(&mut self.inner, &self.tmp)?;
+        write!(self.inner, r#"","offset":{},"#, offset)
+    }
+
+    fn range(&mut self, range: &Range<Offset>) -> io::Result<()> {
+        write!(
+            self.inner,
+            r#""type":"slice","start":{},"end":{}"#,
+            range.start, range.end
+        )
+    }
+
+    fn str_range(&mut self, range: &Range<Offset>) -> io::Result<()> {
+        write!(
+            self.inner,
+            r#""type":"str","start":{},"end":{}"#,
+            range.start, range.end
+        )
+    }
+
+    fn packed(&mut self, wire_type: WireType, range: &Range<Offset>) -> io::Result<()> {
+        let wire_type = match wire_type {
+            WireType::Varint => "varint",
+            WireType::Fixed64 => "fixed64",
+            WireType::Fixed32 => "fixed32",
+            WireType::LengthDelimited => "length_delimited",
+            #[cfg(feature = "groups")]
+            WireType::StartGroup => "start_group",
+            #[cfg(feature = "groups")]
+            WireType::EndGroup => "end_group",
+        };
+        write!(
+            self.inner,
+            r#""type":"packed","wire_type":"{}","start":{},"end":{}"#,
+            wire_type, range.start, range.end
+        )
+    }
+
+    fn raw(&mut self, header: &[u8], range: &Range<Offset>) -> io::Result<()> {
+        write!(
+            self.inner,
+            r#""type":"raw","start":{},"end":{}"#,
+            range.start, range.end
+        )?;
+        self.hex("header", header)
+    }
+
+    fn chunk(&mut self, range: &Range<Offset>, remaining: Offset) -> io::Result<()> {
+        write!(
+            self.inner,
+            r#""type":"chunk","start":{},"end":{},"remaining":{}"#,
+            range.start, range.end, remaining
+        )
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.hex("bytes", bytes)
+    }
+
+    fn hex(&mut self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        write!(self.inner, r#","{}":""#, key)?;
+        for b in bytes {
+            write!(self.inner, "{:02x}", b)?;
+        }
+        write!(self.inner, "\"")
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.seq += 1;
+        writeln!(self.inner, "}}")
+    }
+}
+
+fn write_escaped(out: &mut impl Write, s: &str) -> io::Result<()> {
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\""),
+            '\\' => out.write_all(b"\\\\"),
+            '\n' => out.write_all(b"\\n"),
+            '\r' => out.write_all(b"\\r"),
+            '\t' => out.write_all(b"\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32),
+            c => write!(out, "{}", c),
+        }?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventWriter;
+    use crate::matcher_fields::{Matched, SlicedMatched, SlicedValue, Value};
+
+    #[test]
+    fn writes_lines() {
+        let mut writer = EventWriter::new(Vec::new());
+
+        writer
+            .write(&Matched {
+                tag: "a\"b",
+                offset: 0,
+                value: Value::Varint(u64::MAX),
+            })
+            .unwrap();
+
+        writer
+            .write_sliced(&SlicedMatched {
+                tag: Some(3),
+                offset: 11,
+                value: SlicedValue::Slice(13..15, b"hi"),
+            })
+            .unwrap();
+
+        assert_eq!(writer.written(), 2);
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            concat!(
+                r#"{"seq":0,"tag":"\"a\\\"b\"","offset":0,"type":"varint","value":"18446744073709551615"}"#,
+                "\n",
+                r#"{"seq":1,"tag":"Some(3)","offset":11,"type":"slice","start":13,"end":15,"bytes":"6869"}"#,
+                "\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,838 @@
+//! Streaming rewrite of a message from an `std::io::Read` into a [`Sink`], directed by a
+//! [`Matcher`].
+//!
+//! The fields the matcher skips are copied through byte for byte as they are read, so that the
+//! memory used does not depend on their size. The fields the matcher reads in any way are given to
+//! a callback which decides with an [`Edit`] whether they are kept, dropped or replaced, and the
+//! messages entered with `Cont::Message` are transcoded field by field in the same way.
+//!
+//! Only the matched fields are buffered. The entered messages are written with
+//! `Sink::begin_message` and `Sink::end_message` as their length can change, so how much of them
+//! is buffered depends on the sink; [`crate::sink::WriteSink`] for example buffers the outermost
+//! entered message until it ends.
+//!
+//! [`FieldEditor`] is the declarative version for the common edits of the fields at given paths.
+//!
+//! With the `groups` feature the group tags are copied as they are without asking the matcher, and
+//! the fields of the groups are decided as fields of the enclosing message.
+
+use crate::field_reader::{FieldReader, MAX_HEADER};
+use crate::field_writer::FieldWriter;
+use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, Packed};
+use crate::message::{Field, FieldData};
+use crate::pb::read_varint64;
+use crate::sink::{Scalar, Sink};
+use crate::{
+    DecodingError, DecodingErrorKind, FieldId, FieldValue, ReadError, ReadField, WireType,
+};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Read;
+
+/// What to do with a matched field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Write the field as it was
+    Keep,
+    /// Leave the field out
+    Drop,
+    /// Write these encoded fields in place of the field, for example ones written with
+    /// [`crate::field_writer::FieldWriter`]
+    Replace(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum TranscodeError<E> {
+    /// Reading the input failed
+    Read(ReadError),
+    /// Writing to the sink failed
+    Sink(E),
+    /// The matcher decided `Cont::ReadPartialSlice`, which cannot be edited as the edits are of
+    /// whole fields
+    PartialSlice { field: FieldId, offset: u64 },
+}
+
+impl<E: fmt::Display> fmt::Display for TranscodeError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscodeError::Read(e) => write!(fmt, "{}", e),
+            TranscodeError::Sink(e) => write!(fmt, "writing failed: {}", e),
+            TranscodeError::PartialSlice { field, offset } => write!(
+                fmt,
+                "partial slice of field {} at offset {} cannot be transcoded",
+                field, offset
+            ),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for TranscodeError<E> {}
+
+impl<E> From<ReadError> for TranscodeError<E> {
+    fn from(e: ReadError) -> Self {
+        TranscodeError::Read(e)
+    }
+}
+
+impl<E> From<DecodingError> for TranscodeError<E> {
+    fn from(e: DecodingError) -> Self {
+        TranscodeError::Read(ReadError::Decoding(e))
+    }
+}
+
+/// Transcodes the message read from `input` into `sink` with the default [`Transcoder`].
+///
+/// `edit` is called with the tag of the matched field and the whole field, of which the
+/// `Field::offset` is the offset in the input.
+pub fn transcode<M, F, IO, S>(
+    matcher: &mut M,
+    input: IO,
+    sink: &mut S,
+    edit: F,
+) -> Result<(), TranscodeError<S::Error>>
+where
+    M: Matcher,
+    F: FnMut(&M::Tag, &Field<'_>) -> Edit,
+    IO: Read,
+    S: Sink,
+{
+    Transcoder::default().transcode(matcher, input, sink, edit)
+}
+
+/// The configuration of [`transcode`].
+#[derive(Clone, Default)]
+pub struct Transcoder {
+    reader: FieldReader,
+}
+
+impl Transcoder {
+    /// The matched fields longer than `max` are rejected with `DecodingErrorKind::FieldTooLarge`
+    /// before they are buffered, see `FieldReader::set_max_field_len`. The limit applies to all
+    /// length delimited fields, so it needs to allow the entered and skipped messages as well.
+    pub fn set_max_field_len(&mut self, max: u64) {
+        self.reader.set_max_field_len(max);
+    }
+
+    /// See [`transcode`].
+    pub fn transcode<M, F, IO, S>(
+        &mut self,
+        matcher: &mut M,
+        input: IO,
+        sink: &mut S,
+        edit: F,
+    ) -> Result<(), TranscodeError<S::Error>>
+    where
+        M: Matcher,
+        F: FnMut(&M::Tag, &Field<'_>) -> Edit,
+        IO: Read,
+        S: Sink,
+    {
+        run(&mut self.reader, matcher, input, sink, edit)
+    }
+}
+
+fn run<M, F, IO, S>(
+    reader: &mut FieldReader,
+    matcher: &mut M,
+    input: IO,
+    sink: &mut S,
+    mut edit: F,
+) -> Result<(), TranscodeError<S::Error>>
+where
+    M: Matcher,
+    F: FnMut(&M::Tag, &Field<'_>) -> Edit,
+    IO: Read,
+    S: Sink,
+{
+    let mut input = Input::new(input);
+    let mut open: Vec<Open<M::Tag>> = Vec::new();
+    let mut path = Vec::new();
+
+    loop {
+        if !input.fill(MAX_HEADER)? && input.available().is_empty() {
+            if open.is_empty() {
+                return Ok(());
+            }
+            return Err(ReadError::UnexpectedEndOfFile.into());
+        }
+
+        let offset = input.offset;
+        reader.set_offset(offset);
+        let read = match reader.next(input.available())? {
+            Ok(read) => read,
+            Err(_) => return Err(ReadError::UnexpectedEndOfFile.into()),
+        };
+
+        let at = offset_to_usize(offset)?;
+        let len = match *read.value() {
+            FieldValue::DataLength(len) => len,
+            _ => 0,
+        };
+        let end = offset
+            .checked_add(read.consumed() as u64)
+            .and_then(|end| end.checked_add(len))
+            .and_then(|end| usize::try_from(end).ok())
+            .ok_or_else(|| overflow(offset, len, &read))?;
+        if let Some(limit) = open.last().map(|open| open.end) {
+            if end > limit {
+                let e = DecodingError::from(DecodingErrorKind::FailedMatcherNesting(end, limit));
+                return Err(e.with_offset(offset).with_field(read.field_id()).into());
+            }
+        }
+
+        #[cfg(feature = "groups")]
+        if !matches!(
+            read.value(),
+            FieldValue::Varint(_)
+                | FieldValue::Fixed64(_)
+                | FieldValue::Fixed32(_)
+                | FieldValue::DataLength(_)
+        ) {
+            let consumed = read.consumed();
+            sink.write_raw(&input.available()[..consumed])
+                .map_err(TranscodeError::Sink)?;
+            input.consume(consumed);
+            continue;
+        }
+
+        let decision = matcher
+            .decide_before(at, &read, &path)
+            .map_err(|e| e.with_offset(offset).with_field(read.field_id()))?;
+
+        let id = read.field_id();
+        let consumed = read.consumed();
+
+        match decision {
+            Action::Skip(_) => {
+                sink.write_raw(&input.available()[..consumed])
+                    .map_err(TranscodeError::Sink)?;
+                input.consume(consumed);
+
+                let mut remaining = read.field_len();
+                while remaining > 0 {
+                    if !input.fill(1)? && input.available().is_empty() {
+                        return Err(ReadError::UnexpectedEndOfFile.into());
+                    }
+                    let chunk = remaining.min(input.available().len());
+                    sink.write_raw(&input.available()[..chunk])
+                        .map_err(TranscodeError::Sink)?;
+                    input.consume(chunk);
+                    remaining -= chunk;
+                }
+            }
+            Action::Continue(Cont::Message(tag)) => {
+                if !read.is_length_delimited() {
+                    let e = DecodingError::from(DecodingErrorKind::InvalidDecision {
+                        wire_type: read.wire_type(),
+                    });
+                    return Err(e.with_offset(offset).with_field(id).into());
+                }
+
+                sink.begin_message(id).map_err(TranscodeError::Sink)?;
+                open.push(Open {
+                    id,
+                    offset: at,
+                    end,
+                    tag,
+                });
+                input.consume(consumed);
+                path.push(id);
+
+                // an empty message ends right away
+                end_messages(&mut open, &mut path, matcher, sink, at + consumed)?;
+                continue;
+            }
+            Action::Continue(cont) => {
+                let invalid = DecodingError::from(DecodingErrorKind::InvalidDecision {
+                    wire_type: read.wire_type(),
+                })
+                .with_offset(offset)
+                .with_field(id);
+                let length_delimited = read.is_length_delimited();
+
+                let (tag, check) = match cont {
+                    Cont::ReadValue(_) if length_delimited => return Err(invalid.into()),
+                    Cont::ReadSlice(_) | Cont::ReadString(_) | Cont::ReadPacked(..)
+                        if !length_delimited =>
+                    {
+                        return Err(invalid.into())
+                    }
+                    Cont::ReadPacked(_, WireType::LengthDelimited) => return Err(invalid.into()),
+                    Cont::ReadPartialSlice(_) => {
+                        return Err(TranscodeError::PartialSlice { field: id, offset })
+                    }
+                    Cont::ReadValue(tag) | Cont::ReadSlice(tag) | Cont::CaptureRaw(tag) => {
+                        (tag, None)
+                    }
+                    Cont::ReadString(tag) => (tag, Some(Check::Utf8)),
+                    Cont::ReadPacked(tag, wire_type) => (tag, Some(Check::Packed(wire_type))),
+                    Cont::Message(_) => unreachable!("handled above"),
+                };
+
+                let len = read.bytes_to_skip();
+                let value = read.value().clone();
+                if !input.fill(len)? && input.available().len() < len {
+                    return Err(ReadError::UnexpectedEndOfFile.into());
+                }
+
+                let raw = &input.available()[..len];
+                let payload_at = offset + consumed as u64;
+                match check {
+                    Some(Check::Utf8) => {
+                        std::str::from_utf8(&raw[consumed..]).map_err(|e| {
+                            DecodingError::from(DecodingErrorKind::InvalidUtf8)
+                                .with_offset(payload_at + e.valid_up_to() as u64)
+                                .with_field(id)
+                        })?;
+                    }
+                    Some(Check::Packed(wire_type)) => {
+                        Packed::new(wire_type, &raw[consumed..])
+                            .map_err(|e| e.or_offset(offset).or_field(id))?;
+                    }
+                    None => {}
+                }
+
+                let value = match value {
+                    FieldValue::Varint(x) => FieldData::Varint(x),
+                    FieldValue::Fixed64(x) => FieldData::Fixed64(x),
+                    FieldValue::Fixed32(x) => FieldData::Fixed32(x),
+                    _ => FieldData::Bytes(&raw[consumed..]),
+                };
+                let field = Field {
+                    id,
+                    offset: at,
+                    raw,
+                    value,
+                };
+
+                match edit(&tag, &field) {
+                    Edit::Keep => sink.write_raw(raw),
+                    Edit::Drop => Ok(()),
+                    Edit::Replace(bytes) => sink.write_raw(&bytes),
+                }
+                .map_err(TranscodeError::Sink)?;
+                input.consume(len);
+            }
+        }
+
+        if !end_messages(&mut open, &mut path, matcher, sink, end)? {
+            matcher.decide_after(end, None);
+        }
+    }
+}
+
+fn offset_to_usize(offset: u64) -> Result<usize, DecodingError> {
+    usize::try_from(offset).map_err(|_| {
+        let kind = DecodingErrorKind::OffsetOverflow { offset, len: 0 };
+        DecodingError::from(kind).with_offset(offset)
+    })
+}
+
+fn overflow(offset: u64, len: u64, read: &ReadField<'_>) -> DecodingError {
+    let kind = DecodingErrorKind::OffsetOverflow { offset, len };
+    DecodingError::from(kind)
+        .with_offset(offset)
+        .with_field(read.field_id())
+}
+
+/// An edit of [`FieldEditor`], applied to every occurrence of the field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldEdit {
+    Delete,
+    /// Replaces the value of a non-length delimited field, which can also change its wire type
+    ReplaceScalar(Scalar),
+    /// Replaces the payload of a length delimited field, or the value of any field with a length
+    /// delimited one
+    ReplaceBytes(Vec<u8>),
+    /// Changes the field id, keeping the value as it was
+    Renumber(FieldId),
+}
+
+/// Edits the fields at the paths of field ids, where every id but the last is a message entered
+/// on the way to the field to edit. The fields not on any of the paths are copied as they are.
+///
+/// When there are multiple edits for the same path the first one added is applied.
+#[derive(Debug, Clone, Default)]
+pub struct FieldEditor {
+    edits: Vec<(Vec<FieldId>, FieldEdit)>,
+}
+
+impl FieldEditor {
+    /// # Panics
+    ///
+    /// If the path is empty.
+    pub fn add(&mut self, path: &[FieldId], edit: FieldEdit) -> &mut Self {
+        assert!(
+            !path.is_empty(),
+            "path needs to have at least the edited field"
+        );
+        self.edits.push((path.to_vec(), edit));
+        self
+    }
+
+    /// Transcodes the message read from `input` with the edits into `sink`.
+    pub fn apply<IO: Read, S: Sink>(
+        &self,
+        input: IO,
+        sink: &mut S,
+    ) -> Result<(), TranscodeError<S::Error>> {
+        let mut matcher = EditMatcher { edits: &self.edits };
+        transcode(&mut matcher, input, sink, |&index, field| {
+            edited(&self.edits[index].1, field)
+        })
+    }
+}
+
+fn edited(edit: &FieldEdit, field: &Field<'_>) -> Edit {
+    let mut out = Vec::new();
+    let mut writer = FieldWriter::new(&mut out);
+    match edit {
+        FieldEdit::Delete => return Edit::Drop,
+        FieldEdit::ReplaceScalar(Scalar::Varint(x)) => writer.write_varint(field.id, *x),
+        FieldEdit::ReplaceScalar(Scalar::Fixed64(x)) => writer.write_fixed64(field.id, *x),
+        FieldEdit::ReplaceScalar(Scalar::Fixed32(x)) => writer.write_fixed32(field.id, *x),
+        FieldEdit::ReplaceBytes(bytes) => writer.write_bytes(field.id, bytes),
+        FieldEdit::Renumber(id) => {
+            let tag_len = match read_varint64(field.raw) {
+                Ok(Ok((len, _))) => len,
+                _ => unreachable!("the tag was read already"),
+            };
+            writer.write_header(*id, field.value.wire_type());
+            out.extend_from_slice(&field.raw[tag_len..]);
+        }
+    }
+    Edit::Replace(out)
+}
+
+/// Matches the fields at the paths of the edits, tagged with the index of the edit.
+struct EditMatcher<'a> {
+    edits: &'a [(Vec<FieldId>, FieldEdit)],
+}
+
+impl Matcher for EditMatcher<'_> {
+    type Tag = usize;
+
+    fn decide_before(
+        &mut self,
+        _offset: usize,
+        read: &ReadField<'_>,
+        path: &[FieldId],
+    ) -> Result<Action<usize>, DecodingError> {
+        let id = read.field_id();
+        let mut on_path = false;
+
+        for (index, (edit_path, _)) in self.edits.iter().enumerate() {
+            let (last, parents) = edit_path.split_last().expect("paths are not empty");
+            if parents == path && *last == id {
+                #[cfg(feature = "groups")]
+                if read.is_start_group() {
+                    // only the tags of the groups could be edited, not the groups
+                    return Ok(Action::Skip(index));
+                }
+
+                return Ok(Action::Continue(if read.is_length_delimited() {
+                    Cont::ReadSlice(index)
+                } else {
+                    Cont::ReadValue(index)
+                }));
+            }
+            on_path |= edit_path.len() > path.len() + 1
+                && edit_path[..path.len()] == *path
+                && edit_path[path.len()] == id;
+        }
+
+        Ok(if on_path && read.is_length_delimited() {
+            Action::Continue(Cont::Message(None))
+        } else {
+            Action::Skip(0)
+        })
+    }
+
+    fn decide_after(
+        &mut self,
+        _offset: usize,
+        _ended: Option<EndedMessage<usize>>,
+    ) -> Option<usize> {
+        None
+    }
+}
+
+/// The validation of the matched fields like with [`crate::matcher_fields::MatcherFields`].
+enum Check {
+    /// For `Cont::ReadString`
+    Utf8,
+    /// For `Cont::ReadPacked`
+    Packed(WireType),
+}
+
+/// A message entered with `Cont::Message`.
+struct Open<T> {
+    id: FieldId,
+    /// Offset of the tag
+    offset: usize,
+    /// Offset after the body
+    end: usize,
+    tag: Option<T>,
+}
+
+/// Ends the entered messages ending at `offset`, innermost first, returning true if any did.
+fn end_messages<M: Matcher, S: Sink>(
+    open: &mut Vec<Open<M::Tag>>,
+    path: &mut Vec<FieldId>,
+    matcher: &mut M,
+    sink: &mut S,
+    offset: usize,
+) -> Result<bool, TranscodeError<S::Error>> {
+    let mut ended = false;
+    while open.last().map(|open| open.end) == Some(offset) {
+        let open = open.pop().expect("checked above");
+        path.pop();
+        sink.end_message().map_err(TranscodeError::Sink)?;
+
+        matcher.decide_after(
+            offset,
+            Some(EndedMessage {
+                field: open.id,
+                offset: open.offset,
+                tag: open.tag,
+            }),
+        );
+        ended = true;
+    }
+    Ok(ended)
+}
+
+/// The most the buffer of [`Input`] grows by on one read.
+const CHUNK: usize = 8192;
+
+/// The bytes read from the input but not yet transcoded.
+struct Input<IO> {
+    inner: IO,
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+    /// Stream offset of `buffer[start]`
+    offset: u64,
+    eof: bool,
+}
+
+impl<IO: Read> Input<IO> {
+    fn new(inner: IO) -> Self {
+        Input {
+            inner,
+            buffer: vec![0; CHUNK],
+            start: 0,
+            end: 0,
+            offset: 0,
+            eof: false,
+        }
+    }
+
+    fn available(&self) -> &[u8] {
+        &self.buffer[self.start..self.end]
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.start += amount;
+        self.offset += amount as u64;
+    }
+
+    /// Reads until at least `min` bytes are available, returning false if the input ended before.
+    ///
+    /// The buffer grows by at most [`CHUNK`] bytes per read, so that a hostile length of a field
+    /// allocates only as much as the input has.
+    fn fill(&mut self, min: usize) -> Result<bool, ReadError> {
+        while self.end - self.start < min {
+            if self.eof {
+                return Ok(false);
+            }
+
+            if self.buffer.len() - self.start < min {
+                self.buffer.copy_within(self.start..self.end, 0);
+                self.end -= self.start;
+                self.start = 0;
+            }
+
+            if self.end == self.buffer.len() {
+                let missing = min - (self.end - self.start);
+                self.buffer.resize(self.end + missing.min(CHUNK), 0);
+            }
+
+            match self.inner.read(&mut self.buffer[self.end..]) {
+                Ok(bytes) => {
+                    self.end += bytes;
+                    self.eof = bytes == 0;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        transcode, Edit, EditMatcher, FieldEdit, FieldEditor, Input, TranscodeError, Transcoder,
+    };
+    use crate::field_writer::FieldWriter;
+    use crate::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields, Value};
+    use crate::message::FieldData;
+    use crate::sink::{Scalar, VecSink, WriteSink};
+    use crate::{
+        DecodingError, DecodingErrorKind, FieldId, ReadError, ReadField, Reader, WireType,
+    };
+    use hex_literal::hex;
+
+    /// Enters the messages of field 1 and matches the fields 2 within them.
+    struct Twos;
+
+    impl Matcher for Twos {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(match (path, read.field_id()) {
+                ([], 1) => Action::Continue(Cont::Message(None)),
+                ([1], 2) => Action::Continue(Cont::ReadValue(())),
+                _ => Action::Skip(()),
+            })
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    /// Doubles the varints, drops the zeroes and keeps the others.
+    fn double(_: &(), field: &crate::message::Field<'_>) -> Edit {
+        match field.value {
+            FieldData::Varint(0) => Edit::Drop,
+            FieldData::Varint(x) => {
+                let mut out = Vec::new();
+                FieldWriter::new(&mut out).write_varint(field.id, x * 2);
+                Edit::Replace(out)
+            }
+            _ => Edit::Keep,
+        }
+    }
+
+    #[test]
+    fn matched_fields_are_edited() {
+        // 3: "abc", 1: { 2: 100, 2: 0, 4: 1, 2: 1 }, 1: {}, 2: 7
+        let input = hex!("1a03616263 0a0d 1064 1000 2001 1001 2d07000000 0a00 1007");
+        let mut sink = VecSink::default();
+
+        transcode(&mut Twos, &input[..], &mut sink, double).unwrap();
+
+        assert_eq!(
+            sink.into_inner(),
+            hex!("1a03616263 0a0c 10c801 2001 1002 2d07000000 0a00 1007")
+        );
+    }
+
+    #[test]
+    fn skipped_fields_are_copied_in_chunks() {
+        // 3: 20000 bytes, which do not fit the buffer at once
+        let mut input = Vec::new();
+        FieldWriter::new(&mut input).write_bytes(3, &[0xaa; 20000]);
+        let mut sink = WriteSink::new(Vec::new());
+
+        transcode(&mut Twos, &input[..], &mut sink, double).unwrap();
+        assert_eq!(sink.into_inner(), input);
+
+        let truncated = &input[..10000];
+        assert!(matches!(
+            transcode(&mut Twos, truncated, &mut VecSink::default(), double),
+            Err(TranscodeError::Read(ReadError::UnexpectedEndOfFile))
+        ));
+    }
+
+    #[test]
+    fn edits_at_paths() {
+        // 1: { 2: 5, 3: "ab", 4: 1 }, 2: 6, 5: "x", 2: 7
+        let input = hex!("0a08 1005 1a026162 2001 1006 2a0178 1007");
+        let mut editor = FieldEditor::default();
+        editor
+            .add(&[1, 2], FieldEdit::ReplaceScalar(Scalar::Fixed32(9)))
+            .add(&[1, 3], FieldEdit::ReplaceBytes(b"abcd".to_vec()))
+            .add(&[1, 4], FieldEdit::Delete)
+            .add(&[2], FieldEdit::Renumber(6))
+            .add(&[2], FieldEdit::Delete);
+
+        let mut sink = VecSink::default();
+        editor.apply(&input[..], &mut sink).unwrap();
+        let out = sink.into_inner();
+
+        assert_eq!(out, hex!("0a0b 1509000000 1a0461626364 3006 2a0178 3007"));
+    }
+
+    #[test]
+    fn edit_matcher_is_valid_for_matcher_fields() {
+        // 1: { 2: 5, 3: "ab" }, 2: "c"
+        let input = hex!("0a06 1005 1a026162 1201 63");
+        let edits = [
+            (vec![1, 2], FieldEdit::Delete),
+            (vec![1, 3], FieldEdit::Delete),
+            (vec![2], FieldEdit::Delete),
+        ];
+        let mut fields = MatcherFields::new(EditMatcher { edits: &edits });
+
+        let mut buf = &input[..];
+        let values = std::iter::from_fn(|| fields.next(&mut buf).unwrap().ok())
+            .map(|m| (m.tag, m.value))
+            .collect::<Vec<_>>();
+        assert!(
+            matches!(
+                &values[..],
+                [
+                    (0, Value::Varint(5)),
+                    (1, Value::Slice(r1)),
+                    (2, Value::Slice(r2)),
+                ] if *r1 == (6..8) && *r2 == (10..11)
+            ),
+            "{:?}",
+            values
+        );
+    }
+
+    /// Reads the field 1 as a string, the field 2 as packed varints and the field 3 in chunks.
+    struct Conts;
+
+    impl Matcher for Conts {
+        type Tag = ();
+
+        fn decide_before(
+            &mut self,
+            _offset: usize,
+            read: &ReadField<'_>,
+            _path: &[FieldId],
+        ) -> Result<Action<()>, DecodingError> {
+            Ok(Action::Continue(match read.field_id() {
+                1 => Cont::ReadString(()),
+                2 => Cont::ReadPacked((), WireType::Varint),
+                3 => Cont::ReadPartialSlice(()),
+                _ => Cont::ReadValue(()),
+            }))
+        }
+
+        fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+            None
+        }
+    }
+
+    #[test]
+    fn matched_fields_are_validated() {
+        let run = |input: &[u8]| {
+            transcode(&mut Conts, input, &mut VecSink::default(), |_, _| {
+                Edit::Keep
+            })
+        };
+
+        // 1: "ab", 2: [1, 150], 4: 1
+        assert!(run(&hex!("0a026162 1203019601 2001")).is_ok());
+
+        // the b of "ab" is not UTF-8
+        let e = run(&hex!("2001 0a0261ff"));
+        assert!(
+            matches!(&e, Err(TranscodeError::Read(ReadError::Decoding(e))) if matches!(e.kind(), DecodingErrorKind::InvalidUtf8) && e.offset() == Some(5)),
+            "{:?}",
+            e
+        );
+
+        // the last varint is incomplete
+        let e = run(&hex!("12020196"));
+        assert!(
+            matches!(&e, Err(TranscodeError::Read(ReadError::Decoding(e))) if matches!(e.kind(), DecodingErrorKind::InvalidPacked { .. })),
+            "{:?}",
+            e
+        );
+
+        // a value read of a length delimited field
+        let e = run(&hex!("2201ff"));
+        assert!(
+            matches!(&e, Err(TranscodeError::Read(ReadError::Decoding(e))) if matches!(e.kind(), DecodingErrorKind::InvalidDecision { .. })),
+            "{:?}",
+            e
+        );
+
+        assert!(matches!(
+            run(&hex!("2001 1a0161")),
+            Err(TranscodeError::PartialSlice {
+                field: 3,
+                offset: 2
+            })
+        ));
+    }
+
+    /// Returns `Interrupted` before every byte.
+    struct Interrupting<'a>(&'a [u8], bool);
+
+    impl std::io::Read for Interrupting<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.1 = !self.1;
+            if self.1 {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn hostile_field_length() {
+        // 1: a string of 4GiB - 1 of which 3 bytes are there
+        let input = hex!("0affffffff0f 616263");
+        let mut input = Input::new(&input[..]);
+        assert!(!input.fill(0xffff_ffff).unwrap());
+        assert_eq!(input.available().len(), 9);
+        assert!(input.buffer.len() <= 2 * super::CHUNK);
+
+        let input = hex!("2001 0a04616263 64");
+        let mut transcoder = Transcoder::default();
+        transcoder.set_max_field_len(3);
+        let e = transcoder.transcode(&mut Conts, &input[..], &mut VecSink::default(), |_, _| {
+            Edit::Keep
+        });
+        assert!(
+            matches!(&e, Err(TranscodeError::Read(ReadError::Decoding(e))) if matches!(e.kind(), DecodingErrorKind::FieldTooLarge { len: 4, limit: 3 }) && e.offset() == Some(2)),
+            "{:?}",
+            e
+        );
+    }
+
+    #[test]
+    fn hostile_length_is_an_error() {
+        // 4: 1, 1: u64::MAX - 1 bytes
+        let input = hex!("2001 0afeffffffffffffffff01");
+        let e = transcode(&mut Conts, &input[..], &mut VecSink::default(), |_, _| {
+            Edit::Keep
+        });
+        assert!(
+            matches!(&e, Err(TranscodeError::Read(ReadError::Decoding(e))) if matches!(e.kind(), DecodingErrorKind::FieldTooLarge { .. }) && e.offset() == Some(2)),
+            "{:?}",
+            e
+        );
+    }
+
+    #[test]
+    fn interrupted_reads_are_retried() {
+        let input = hex!("2001 0a03616263");
+        let mut sink = VecSink::default();
+        transcode(
+            &mut Conts,
+            Interrupting(&input, false),
+            &mut sink,
+            |_, _| Edit::Keep,
+        )
+        .unwrap();
+        assert_eq!(sink.into_inner(), input);
+    }
+}
@@ -3,8 +3,8 @@ use minipb::{Status, DecodingError, Reader, io_ext::read::ReadWrapper};
 use std::borrow::Cow;
 
 /// Making sure that Reader<'static> cannot be passed over to ReadWrapper. If it could be, the
-/// returned lifetime would not be valid after mutation but luckily the read_next() binding `'a` to
-/// `&mut self` stops it.
+/// returned lifetime would not be valid after mutation, but ReadWrapper needs readers for any
+/// lifetime of the buffer.
 struct BadReader;
 
 impl Reader<'static> for BadReader {
@@ -15,9 +15,13 @@ impl Reader<'static> for BadReader {
     }
 }
 
+fn next_of<IO: std::io::Read, R: for<'a> Reader<'a>>(rw: &mut ReadWrapper<IO, R>) {
+    let _ = rw.with_next(|_| ());
+}
+
 fn test(read: impl std::io::Read + 'static) {
     let mut rw = ReadWrapper::new(read, BadReader);
-    rw.read_next();
+    next_of(&mut rw);
 }
 
 fn main() {}
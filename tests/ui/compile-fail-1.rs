@@ -1,21 +1,21 @@
 use minipb::{DecodingError, Status, Reader};
 use minipb::io_ext::read::ReadWrapper;
 
-#[derive(Default)]
-struct NoopReader<'a>(std::marker::PhantomData<&'a ()>);
+/// Returns the whole buffer, borrowing it.
+struct BorrowingReader;
 
-impl<'a> Reader<'a> for NoopReader<'a> {
-    type Returned = (); // it doesn't matter if Returned is 'static
-    fn next(&mut self, _buf: &mut &'a [u8]) -> Result<Result<(), Status>, DecodingError> {
-        todo!()
+impl<'a> Reader<'a> for BorrowingReader {
+    type Returned = &'a [u8];
+    fn next(&mut self, buf: &mut &'a [u8]) -> Result<Result<&'a [u8], Status>, DecodingError> {
+        Ok(Ok(std::mem::take(buf)))
     }
 }
 
 fn test(read: impl std::io::Read) {
-    let mut items = ReadWrapper::new(read, NoopReader::default());
+    let mut items = ReadWrapper::new(read, BorrowingReader);
     let first = items.read_next();
     let second = items.read_next();
-    // with NoopReader<'a> as in, if it would store a reference to the buffer this should fail
+    // if the items could borrow the buffer the first would be overwritten by the second read
     println!("{:?}, {:?}", first, second);
 }
 
@@ -0,0 +1,32 @@
+use minipb::io_ext::read::ReadWrapper;
+use minipb::matcher_fields::{Action, Cont, EndedMessage, Matcher, MatcherFields};
+use minipb::{DecodingError, FieldId, ReadField};
+
+struct Slices;
+
+impl Matcher for Slices {
+    type Tag = ();
+
+    fn decide_before(
+        &mut self,
+        _offset: usize,
+        _read: &ReadField<'_>,
+        _path: &[FieldId],
+    ) -> Result<Action<()>, DecodingError> {
+        Ok(Action::Continue(Cont::ReadSlice(())))
+    }
+
+    fn decide_after(&mut self, _offset: usize, _ended: Option<EndedMessage<()>>) -> Option<()> {
+        None
+    }
+}
+
+/// The items borrowing the buffer cannot be returned out of `with_next`, as the buffer can be
+/// overwritten by the next read.
+fn test(read: impl std::io::Read) {
+    let mut rw = ReadWrapper::new(read, MatcherFields::new(Slices).into_sliced());
+    let first = rw.with_next(|m| m);
+    println!("{:?}", first.is_ok());
+}
+
+fn main() {}